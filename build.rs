@@ -6,7 +6,64 @@ use clap_mangen::Man;
 
 include!("src/cli.rs");
 
+/// Generates the Rust telemetry payload struct from the `Telemetry` message of
+/// `proto/cave_telem.proto`, so the JSON payload and the proto schema cannot drift.
+///
+/// Only the scalar types used by the schema are supported.
+fn generate_telemetry_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let proto = fs::read_to_string("proto/cave_telem.proto")?;
+    let body = proto
+        .split("message Telemetry {")
+        .nth(1)
+        .and_then(|rest| rest.split('}').next())
+        .ok_or("message Telemetry not found in proto/cave_telem.proto")?;
+
+    let mut fields = String::new();
+    for line in body.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with("//")) {
+        let decl = line.split('=').next().unwrap_or_default();
+        let tokens: Vec<&str> = decl.split_whitespace().collect();
+        let (optional, ty, name) = match tokens.as_slice() {
+            ["optional", ty, name] => (true, *ty, *name),
+            [ty, name] => (false, *ty, *name),
+            _ => return Err(format!("unsupported proto field: {}", line).into()),
+        };
+        let rust_ty = match ty {
+            "string" => "String",
+            "bool" => "bool",
+            "int32" => "i32",
+            "int64" => "i64",
+            "uint32" => "u32",
+            "uint64" => "u64",
+            "double" => "f64",
+            _ => return Err(format!("unsupported proto type: {}", ty).into()),
+        };
+        let rust_name = if name == "type" { "r#type".to_string() } else { name.to_string() };
+        if optional {
+            fields.push_str("    #[serde(skip_serializing_if = \"Option::is_none\")]\n");
+            fields.push_str(&format!("    pub {}: Option<{}>,\n", rust_name, rust_ty));
+        } else {
+            fields.push_str(&format!("    pub {}: {},\n", rust_name, rust_ty));
+        }
+    }
+
+    let code = format!(
+        "/// Telemetry payload, generated from `proto/cave_telem.proto`.\n\
+         #[derive(Debug, Clone, Default, serde::Serialize)]\n\
+         pub struct Telemetry {{\n{}}}\n",
+        fields
+    );
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    fs::write(out_dir.join("cave_telem.rs"), code)?;
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/cli.rs");
+    println!("cargo:rerun-if-changed=proto/cave_telem.proto");
+
+    generate_telemetry_schema()?;
+
     let out_dir = PathBuf::from("target/completions");
     fs::create_dir_all(&out_dir).expect("failed to create completion dir");
 
@@ -20,4 +77,4 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     man.render(&mut file).unwrap();
 
     Ok(())
-}
\ No newline at end of file
+}
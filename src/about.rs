@@ -0,0 +1,134 @@
+//! Metadata of a code_aster version (`cave about`).
+//!
+//! The Docker Hub metadata of the tag (push date, digest, size) is combined
+//! with the labels of the image (source commit, build date, changelog). The
+//! labels are read from the local image when the version is installed, and
+//! otherwise from its registry without pulling it. The translations of the
+//! `cave run` flags for the version (see [`profiles`]) are listed too.
+
+use crate::docker::{exists_locally, hub_tag, image_digest, image_labels, remote_labels};
+use crate::format;
+use crate::manage::{resolve_version, CaveError};
+use crate::mpi::MPI_LABEL;
+use crate::profiles;
+use crate::progress::Spinner;
+use crate::sources;
+use colored::*;
+use std::collections::BTreeMap;
+
+/// Labels shown first, with their title.
+const KNOWN_LABELS: [(&str, &str); 6] = [
+    ("org.opencontainers.image.version", "Aster version"),
+    ("org.opencontainers.image.revision", "Commit"),
+    ("org.opencontainers.image.created", "Built"),
+    ("org.opencontainers.image.source", "Source code"),
+    ("org.code-aster.changelog", "Changelog"),
+    (MPI_LABEL, "MPI"),
+];
+
+fn unavailable(e: &CaveError) -> ColoredString {
+    format!("unknown ({})", e).dimmed()
+}
+
+/// Handler of `cave about`: prints the registry metadata and the labels of
+/// a version, installed or not.
+///
+/// # Errors
+/// - [`CaveError::InvalidFormat`] if the version cannot be an image tag.
+/// - [`CaveError::VersionNotAvailable`] if the version is neither installed nor published.
+pub fn about(version: &str) -> Result<(), CaveError> {
+    let (version, _) = resolve_version(version)?;
+    let installed = exists_locally(&version).unwrap_or(false);
+    let source = match sources::installed()?.get(&version) {
+        Some(source) if installed => Some(source.clone()),
+        _ => sources::find(&version),
+    };
+    if !installed && source.is_none() {
+        return Err(CaveError::VersionNotAvailable(version));
+    }
+
+    println!("{:<16}{}", "Version", version.bold());
+    println!(
+        "{:<16}{}",
+        "Installed",
+        if installed {
+            "yes".green()
+        } else {
+            "no".normal()
+        }
+    );
+    if let Some(source) = &source {
+        println!("{:<16}{}", "Source", source);
+    }
+    if let Some(digest) = installed.then(|| image_digest(&version)).flatten() {
+        println!("{:<16}{}", "Local digest", digest);
+    }
+    let flags: Vec<String> = profiles::profile(&version)?
+        .into_iter()
+        .map(|(flag, arg)| {
+            if arg.scale == 1.0 {
+                format!("{} -> {}", flag, arg.option)
+            } else {
+                format!("{} -> {} (x{})", flag, arg.option, arg.scale)
+            }
+        })
+        .collect();
+    if !flags.is_empty() {
+        println!("{:<16}{}", "run_aster flags", flags.join(", "));
+    }
+
+    // Registry metadata, only published by Docker Hub
+    if let Some(repo) = source.as_deref().and_then(sources::hub_repository) {
+        let tag = {
+            let _spinner = Spinner::start(format!("Reading {} on Docker Hub", version));
+            hub_tag(&repo, &version)
+        };
+        match tag {
+            Ok(Some(tag)) => {
+                if let Some(pushed) = tag.tag_last_pushed {
+                    println!("{:<16}{}", "Pushed", pushed);
+                }
+                if let Some(digest) = tag.digest {
+                    println!("{:<16}{}", "Digest", digest);
+                }
+                if let Some(size) = tag.full_size {
+                    println!("{:<16}{} (compressed)", "Size", format::size(size));
+                }
+            }
+            Ok(None) => println!("{:<16}{}", "Docker Hub", "tag not found".dimmed()),
+            Err(e) => println!("{:<16}{}", "Docker Hub", unavailable(&e)),
+        }
+    }
+
+    let labels = if installed {
+        image_labels(&version)
+    } else {
+        let (host, repo) = sources::registry(source.as_deref().unwrap_or(sources::DEFAULT_SOURCE));
+        let _spinner = Spinner::start(format!("Reading the labels of {} on {}", version, host));
+        remote_labels(&host, &repo, &version, None)
+    };
+    let labels: BTreeMap<String, String> = match labels {
+        Ok(labels) => labels.into_iter().collect(),
+        Err(e) => {
+            println!("{:<16}{}", "Labels", unavailable(&e));
+            return Ok(());
+        }
+    };
+    for (label, title) in KNOWN_LABELS {
+        if let Some(value) = labels.get(label) {
+            println!("{:<16}{}", title, value);
+        }
+    }
+    let others: Vec<_> = labels
+        .iter()
+        .filter(|(label, _)| !KNOWN_LABELS.iter().any(|(known, _)| known == label))
+        .collect();
+    if !others.is_empty() {
+        println!();
+        println!("{}", "Labels".bold());
+        for (label, value) in others {
+            println!("  {} = {}", label, value);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Answers to the interactive prompts, for unattended use.
+//!
+//! Provisioning scripts give an answers file with `cave --answers <file>`
+//! (or the `CAVE_ANSWERS` environment variable), in YAML:
+//!
+//! ```yaml
+//! download: true               # download missing versions (use, pin, updated channels)
+//! missing_version: download    # pinned version missing at run time: download, pin-installed or abort
+//! license: true                # accept the license notice of a downloaded version
+//! remove: true                 # confirm unpin and prune
+//! stop: false                  # confirm jobs kill and kill-all
+//! defaults: true               # take the default of the other questions (cave init-export)
+//! usage_tracking: false        # telemetry settings, for this invocation only
+//! resource_tracking: false
+//! ```
+//!
+//! A prompt with an answer is printed with it and not asked; the other
+//! prompts are asked as usual. The telemetry settings override the
+//! configuration without changing it.
+
+use crate::manage::CaveError;
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+static ANSWERS: OnceLock<Answers> = OnceLock::new();
+
+/// Serializes the prompts of concurrent pulls.
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Action taken when the pinned version is missing at run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingVersion {
+    /// Download the pinned version again.
+    Download,
+    /// Pin the most recent installed version instead.
+    PinInstalled,
+    /// Fail the run.
+    Abort,
+}
+
+/// Content of an answers file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Answers {
+    /// Whether missing versions are downloaded.
+    download: Option<bool>,
+    /// What to do when the pinned version is missing at run time.
+    missing_version: Option<MissingVersion>,
+    /// Whether license notices are accepted.
+    license: Option<bool>,
+    /// Whether removals are confirmed.
+    remove: Option<bool>,
+    /// Whether stopping containers is confirmed.
+    stop: Option<bool>,
+    /// Whether the other questions take their default.
+    defaults: Option<bool>,
+    /// Usage tracking setting.
+    usage_tracking: Option<bool>,
+    /// Resource usage tracking setting.
+    resource_tracking: Option<bool>,
+}
+
+/// Kind of confirmation asked to the user.
+#[derive(Debug, Clone, Copy)]
+pub enum Prompt {
+    /// Downloading a version.
+    Download,
+    /// Accepting the license notice of a version.
+    License,
+    /// Removing a pin or versions.
+    Remove,
+    /// Stopping containers.
+    Stop,
+}
+
+/// Loads the answers file.
+///
+/// # Errors
+/// - [`CaveError::IoError`] if the file cannot be read.
+/// - [`CaveError::InvalidAnswers`] if the file is not valid.
+pub fn load(path: &str) -> Result<(), CaveError> {
+    let content = fs::read_to_string(path)?;
+    let answers: Answers = serde_yaml::from_str(&content)
+        .map_err(|e| CaveError::InvalidAnswers(format!("{}: {}", path, e)))?;
+    let _ = ANSWERS.set(answers);
+    Ok(())
+}
+
+fn answers() -> Option<&'static Answers> {
+    ANSWERS.get()
+}
+
+/// Asks a yes/no `question`, unless the answers file answers it.
+///
+/// # Errors
+/// Returns [`CaveError::IoError`] if the answer cannot be read.
+pub fn confirm(prompt: Prompt, question: &str) -> Result<bool, CaveError> {
+    let answer = answers().and_then(|a| match prompt {
+        Prompt::Download => a.download,
+        Prompt::License => a.license,
+        Prompt::Remove => a.remove,
+        Prompt::Stop => a.stop,
+    });
+    if let Some(answer) = answer {
+        println!("{} (y/n): {} (answers file)", question, if answer { "y" } else { "n" });
+        return Ok(answer);
+    }
+    Ok(read(&format!("{} (y/n):", question))?.to_lowercase() == "y")
+}
+
+/// Asks `question`, the empty answer giving `default`. With `defaults: true`
+/// in the answers file, the default is taken without asking.
+///
+/// # Errors
+/// Returns [`CaveError::IoError`] if the answer cannot be read.
+pub fn ask(question: &str, default: Option<&str>) -> Result<String, CaveError> {
+    if let (Some(default), Some(true)) = (default, answers().and_then(|a| a.defaults)) {
+        println!("{} [{}]: {} (answers file)", question, default, default);
+        return Ok(default.to_string());
+    }
+    let input = match default {
+        Some(default) => read(&format!("{} [{}]:", question, default))?,
+        None => read(&format!("{}:", question))?,
+    };
+    Ok(if input.is_empty() {
+        default.unwrap_or_default().to_string()
+    } else {
+        input
+    })
+}
+
+/// Prints `question` and reads the answer of the user, trimmed.
+fn read(question: &str) -> Result<String, CaveError> {
+    let _prompt = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+    print!("{} ", question);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+/// Returns the action of the answers file for a pinned version missing at run time.
+pub fn missing_version() -> Option<MissingVersion> {
+    answers().and_then(|a| a.missing_version)
+}
+
+/// Returns the usage tracking setting of the answers file.
+pub fn usage_tracking() -> Option<bool> {
+    answers().and_then(|a| a.usage_tracking)
+}
+
+/// Returns the resource usage tracking setting of the answers file.
+pub fn resource_tracking() -> Option<bool> {
+    answers().and_then(|a| a.resource_tracking)
+}
@@ -0,0 +1,248 @@
+//! Apptainer (Singularity) backend, for HPC hosts without Docker or Podman.
+//!
+//! Versions are converted from the `simvia/code_aster` Docker images into SIF
+//! files with `apptainer pull docker://...`, cached in `~/.cave.d/sif/`, and
+//! run with `apptainer exec`, the study directory being bind-mounted at the
+//! working directory of the images. Version resolution is shared with the
+//! other runtimes.
+
+use crate::cmdlog::Logged;
+use crate::config::{state_dir, Registry};
+use crate::manage::CaveError;
+use crate::progress;
+use crate::signals::{self, Operation};
+use crate::sources;
+use colored::*;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+const SIF_PREFIX: &str = "code_aster_";
+const SIF_EXTENSION: &str = "sif";
+
+static BINARY: OnceLock<Option<&'static str>> = OnceLock::new();
+
+/// Returns the Apptainer binary, `apptainer` or its former name `singularity`.
+pub fn binary() -> Option<&'static str> {
+    *BINARY.get_or_init(|| {
+        ["apptainer", "singularity"].into_iter().find(|binary| {
+            Command::new(binary)
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success())
+        })
+    })
+}
+
+fn command() -> Result<Command, CaveError> {
+    binary().map(Command::new).ok_or(CaveError::NoDocker)
+}
+
+/// Returns the directory caching the SIF images.
+pub fn sif_dir() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("sif"))
+}
+
+/// Returns the SIF image of a version.
+pub fn sif_path(version: &str) -> Result<PathBuf, CaveError> {
+    Ok(sif_dir()?.join(format!("{}{}.{}", SIF_PREFIX, version, SIF_EXTENSION)))
+}
+
+/// Returns the versions with a cached SIF image.
+pub fn local_versions() -> Result<Vec<String>, CaveError> {
+    let entries = match fs::read_dir(sif_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let version = name.strip_prefix(SIF_PREFIX)?.strip_suffix(".sif")?;
+            Some(version.to_string())
+        })
+        .collect())
+}
+
+/// Returns the disk space of the cached SIF images, in bytes.
+pub fn images_size() -> Result<u64, CaveError> {
+    let entries = match fs::read_dir(sif_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum())
+}
+
+/// Converts the Docker image of a version into a SIF image, `image` being
+/// its Docker reference. The credentials of `registry` are used, if given.
+///
+/// # Errors
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::DockerError`] if `apptainer pull` fails.
+pub fn pull(version: &str, image: &str, registry: Option<&Registry>) -> Result<(), CaveError> {
+    let target = sif_path(version)?;
+    fs::create_dir_all(sif_dir()?)?;
+    // Pulled next to the cache, so that an interrupted pull leaves no truncated image
+    let partial = target.with_extension("sif.partial");
+    let source = format!("docker://{}", sources::qualified(image));
+    let mut cmd = command()?;
+    if let Some(registry) = registry {
+        for prefix in ["APPTAINER", "SINGULARITY"] {
+            cmd.env(format!("{}_DOCKER_USERNAME", prefix), &registry.user)
+                .env(format!("{}_DOCKER_PASSWORD", prefix), &registry.token);
+        }
+    }
+    let mut child = cmd
+        .args(progress::suppressed().then_some("--quiet"))
+        .arg("pull")
+        .arg("--force")
+        .arg(&partial)
+        .arg(source)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .logged()
+        .spawn()?;
+
+    let guard = signals::register(Operation::Process(child.id()));
+    let status = child.wait()?;
+    drop(guard);
+
+    if signals::cancelled() || !status.success() {
+        let _ = fs::remove_file(&partial);
+        if signals::cancelled() {
+            return Err(CaveError::Cancelled);
+        }
+        return Err(CaveError::DockerError(format!(
+            "Failed to convert version {} to a SIF image",
+            version
+        )));
+    }
+    fs::rename(&partial, &target)?;
+    Ok(())
+}
+
+/// Removes the SIF image of a version.
+pub fn remove(version: &str) -> Result<(), CaveError> {
+    let path = sif_path(version)?;
+    fs::remove_file(&path)?;
+    let _ = fs::remove_file(path.with_extension("sha256"));
+    Ok(())
+}
+
+/// Returns a short identifier of the SIF image of a version: the start of
+/// its SHA-256, computed once and cached next to the image.
+pub fn image_id(version: &str) -> Result<String, CaveError> {
+    let path = sif_path(version)?;
+    let cache = path.with_extension("sha256");
+    if let Ok(id) = fs::read_to_string(&cache) {
+        return Ok(id.trim().to_string());
+    }
+    debug!("Calcul de l'empreinte de {}", path.display());
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+    let id = format!("{:x}", hasher.finalize())[..12].to_string();
+    fs::write(&cache, &id)?;
+    Ok(id)
+}
+
+/// Translates the `docker run` arguments of a run: environment variables and
+/// files are kept, volumes become binds, GPUs become `--nv`, the other
+/// options have no Apptainer equivalent and are ignored. Returns the
+/// arguments with the number of ignored ones.
+fn translate_args(docker_args: &[String]) -> (Vec<String>, usize) {
+    let mut args = Vec::new();
+    let mut ignored = 0;
+    let mut iter = docker_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-e" | "--env" => {
+                // Apptainer has no form passing the host value of a variable
+                let value = iter.next().and_then(|value| {
+                    if value.contains('=') {
+                        Some(value.clone())
+                    } else {
+                        env::var(value).ok().map(|host| format!("{}={}", value, host))
+                    }
+                });
+                if let Some(value) = value {
+                    args.push("--env".to_string());
+                    args.push(value);
+                }
+            }
+            "--env-file" => {
+                if let Some(file) = iter.next() {
+                    args.push("--env-file".to_string());
+                    args.push(file.clone());
+                }
+            }
+            _ if arg.starts_with("--env=") => args.push(arg.clone()),
+            "-v" | "--volume" => {
+                if let Some(value) = iter.next() {
+                    args.push("--bind".to_string());
+                    args.push(value.clone());
+                }
+            }
+            // Apptainer binds all the NVIDIA GPUs of the host, whatever the selection
+            "--gpus" => {
+                iter.next();
+                args.push("--nv".to_string());
+            }
+            _ if arg.starts_with("--gpus=") => args.push("--nv".to_string()),
+            _ => {
+                debug!("Option docker ignorée avec Apptainer: {}", arg);
+                ignored += 1;
+            }
+        }
+    }
+    (args, ignored)
+}
+
+/// Returns the command running `script` (or an interactive shell) with the
+/// SIF image of a version, `host_dir` being the working directory.
+pub fn exec_command(
+    version: &str,
+    host_dir: &Path,
+    docker_args: &[String],
+    script: Option<&str>,
+) -> Result<Command, CaveError> {
+    let sif = sif_path(version)?;
+    if !sif.exists() {
+        return Err(CaveError::VersionNotInstalled(version.to_string()));
+    }
+    let (args, ignored) = translate_args(docker_args);
+    if ignored > 0 {
+        eprintln!(
+            "{} {} docker option argument(s) ignored with Apptainer.",
+            "Note:".cyan().bold(),
+            ignored
+        );
+    }
+    let mut cmd = command()?;
+    cmd.arg("exec")
+        .arg("--cleanenv")
+        .arg("--bind")
+        .arg(format!("{}:/home/user/data", host_dir.display()))
+        .arg("--pwd")
+        .arg("/home/user/data")
+        .args(args)
+        .arg(&sif)
+        .arg("/bin/bash");
+    if let Some(script) = script {
+        cmd.arg("-c").arg(script);
+    }
+    Ok(cmd)
+}
@@ -0,0 +1,229 @@
+//! Batch runs of several studies (`cave run a.export b.export --jobs 2`).
+//!
+//! The export files are run from the current directory by the
+//! [`Orchestrator`], at most `jobs` containers at a time, with the options
+//! of the command line. Their versions are resolved and installed first,
+//! once each. The batch holds the study lock for all its runs, and refuses
+//! studies writing the same output files, which would corrupt each other. It
+//! ends with a pass/fail summary of the studies.
+
+use crate::docker::RunOptions;
+use crate::export::Export;
+use crate::format;
+use crate::history;
+use crate::lock;
+use crate::manage::{
+    ensure_installed, ensure_present, export_version, find_export_file, read_cave_version, run_aster, CaveError,
+};
+use crate::orchestrator::{Orchestrator, Outcome};
+use crate::output;
+use crate::signals;
+use crate::table::{Cell, Table};
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Arguments of `cave run`, split for a batch.
+#[derive(Debug, Default)]
+pub struct BatchArgs {
+    /// Arguments given to run_aster.
+    pub args: Vec<String>,
+    pub exports: Vec<String>,
+    pub jobs: Option<usize>,
+}
+
+impl BatchArgs {
+    /// Returns the arguments of a single run: the run_aster arguments, then
+    /// the export file, without `--jobs`.
+    pub fn run_args(&self) -> Vec<String> {
+        self.args.iter().chain(&self.exports).cloned().collect()
+    }
+}
+
+/// Splits the arguments of `cave run` into the run_aster arguments, the
+/// export files and the number of jobs, which can follow the export files
+/// (`cave run a.export b.export --jobs 2`).
+///
+/// # Errors
+/// Returns [`CaveError::CodeAsterError`] if the number of jobs is not a positive integer.
+pub fn split_args(args: &[String], jobs: Option<usize>) -> Result<BatchArgs, CaveError> {
+    let mut rest = Vec::new();
+    let mut exports = Vec::new();
+    let mut jobs = jobs;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--jobs" | "-j" => args.next().map(String::as_str),
+            _ => match arg.strip_prefix("--jobs=") {
+                Some(value) => Some(value),
+                None if arg.ends_with(".export") => {
+                    exports.push(arg.clone());
+                    continue;
+                }
+                None => {
+                    rest.push(arg.clone());
+                    continue;
+                }
+            },
+        };
+        jobs = Some(value.and_then(|v| v.parse().ok()).filter(|n| *n > 0).ok_or_else(|| {
+            CaveError::CodeAsterError("`--jobs` needs a positive number of studies".to_string())
+        })?);
+    }
+    Ok(BatchArgs { args: rest, exports, jobs })
+}
+
+/// Checks that the studies of a batch do not write the same output files.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidExport`] for the second export writing an output of another one.
+fn check_outputs(exports: &[String]) -> Result<(), CaveError> {
+    let mut writers: HashMap<String, &str> = HashMap::new();
+    for export in exports {
+        let (parsed, _) = Export::parse(&fs::read_to_string(export)?);
+        let dir = Path::new(export).parent().unwrap_or(Path::new(""));
+        for entry in parsed.entries.iter().filter(|e| e.output) {
+            let path = dir.join(&entry.path).display().to_string();
+            match writers.get(&path) {
+                Some(other) if *other != export.as_str() => {
+                    return Err(CaveError::InvalidExport {
+                        file: export.clone(),
+                        problems: vec![format!(
+                            "line {}: output '{}' is also written by {}, run them separately",
+                            entry.line, entry.path, other
+                        )],
+                    })
+                }
+                _ => {
+                    writers.insert(path, export);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handler of `cave run` with several export files: runs the studies with
+/// the run_aster arguments `args`, `jobs` at a time, and prints a summary.
+///
+/// With several jobs, the output of the runs only goes to their run logs,
+/// unless a `--filter` is given.
+///
+/// # Errors
+/// - [`CaveError::CodeAsterError`] if `--name` or `--detach` is given, or an export is given twice.
+/// - [`CaveError::FileNotFound`] if an export file does not exist.
+/// - [`CaveError::InvalidExport`] if two studies write the same output file.
+/// - [`CaveError::RunInProgress`] if another run holds the study lock.
+/// - [`CaveError::BatchFailed`] if any study fails.
+pub fn run(exports: &[String], args: &[String], jobs: usize, mut options: RunOptions) -> Result<(), CaveError> {
+    if options.name.is_some() || options.detach {
+        return Err(CaveError::CodeAsterError(
+            "`--name` and `--detach` cannot be used with several export files".to_string(),
+        ));
+    }
+    for (i, export) in exports.iter().enumerate() {
+        if exports[..i].contains(export) {
+            return Err(CaveError::CodeAsterError(format!("{} is given twice", export)));
+        }
+        find_export_file(export)?;
+    }
+    check_outputs(exports)?;
+
+    // The versions are resolved and installed before the runs start, so
+    // that their prompts are not interleaved
+    let mut pinned: Option<String> = None;
+    let mut versions = HashMap::new();
+    for export in exports {
+        let version = match options.version.clone() {
+            Some(version) => version,
+            None => match export_version(std::slice::from_ref(export))? {
+                Some(version) => version,
+                None => match &pinned {
+                    Some(version) => version.clone(),
+                    None => pinned.insert(ensure_installed(read_cave_version()?, options.yes)?).clone(),
+                },
+            },
+        };
+        if Some(&version) != pinned.as_ref() {
+            ensure_present(&version, true)?;
+        }
+        versions.insert(export, version);
+    }
+
+    let current_dir = env::current_dir()?;
+    let _lock = lock::acquire(&current_dir, "cave-batch", options.force)?;
+    if jobs > 1 && options.filter.is_none() {
+        options.filter = Some(output::silent_filter());
+    }
+    println!(
+        "{} {} studies, {} at a time",
+        "Running".bold(),
+        exports.len(),
+        jobs.min(exports.len())
+    );
+
+    let last_runs: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+    let outcomes = Orchestrator::new(jobs).run(
+        exports,
+        |export| export.clone(),
+        |export, _| {
+            let run_id = history::new_run_id();
+            last_runs
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(export.clone(), run_id.clone());
+            let mut args = args.to_vec();
+            args.push(export.clone());
+            run_aster(
+                &args,
+                RunOptions {
+                    run_id: Some(run_id),
+                    version: versions.get(export).cloned(),
+                    ..options.clone()
+                },
+            )
+        },
+    );
+    let last_runs = last_runs.into_inner().unwrap_or_else(|e| e.into_inner());
+
+    let mut table = Table::new(&["Export", "Status", "Duration", "Run"]);
+    for (export, outcome) in exports.iter().zip(&outcomes) {
+        let status = match outcome.outcome {
+            Outcome::Done(()) => Cell::from("pass").color(Color::Green),
+            Outcome::Failed(_) => Cell::from("fail").color(Color::Red),
+            Outcome::Skipped => Cell::from("not run").color(Color::Yellow),
+        };
+        let run = last_runs.get(export).filter(|_| outcome.attempts > 0);
+        let duration = run
+            .and_then(|id| history::find(id).ok())
+            .map(|r| format::duration_ms(r.duration_ms))
+            .unwrap_or_default();
+        table.push(vec![
+            export.as_str().into(),
+            status,
+            duration.into(),
+            run.map(String::as_str).unwrap_or_default().into(),
+        ]);
+    }
+    println!();
+    table.print();
+
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    let failed = outcomes.iter().filter(|o| !o.succeeded()).count();
+    println!();
+    println!(
+        "{} studies run, {} passed, {} failed.",
+        exports.len(),
+        exports.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        return Err(CaveError::BatchFailed(failed));
+    }
+    Ok(())
+}
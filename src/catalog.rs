@@ -0,0 +1,214 @@
+//! Catalog of the published code_aster versions.
+//!
+//! The tags of the image sources (see [`crate::sources`]) are cached in
+//! `~/.cave.d/catalog.json` and refreshed after [`max_age`], an hour unless
+//! set with `cave config set-cache-ttl`; `--refresh` bypasses the cache of
+//! the tags and of the channel resolutions (see [`force_refresh`]). Versions
+//! given on the command line are checked against this catalog rather than
+//! against a fixed `xx.x.xx` format, so that a change of the upstream naming
+//! scheme (`17.3.10`, four components, suffixes, ...) keeps working; only the
+//! strings that cannot be an image tag at all are rejected upfront.
+//!
+//! A version missing from the cache is looked up with a request on its tag
+//! only, so that checking a new version does not fetch every page of tags.
+
+use crate::config::{offline, read_config, state_dir};
+use crate::docker::remote_versions;
+use crate::format;
+use crate::manage::{version_cmp, CaveError};
+use crate::sources;
+use chrono::Utc;
+use colored::*;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Age after which the catalog is fetched again, in seconds, unless configured.
+pub const MAX_AGE_SECS: i64 = 3600;
+
+static REFRESH: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize)]
+struct Catalog {
+    /// When the tags were fetched, as a Unix timestamp.
+    fetched: i64,
+    /// Tags with their push date.
+    versions: Vec<(String, String)>,
+}
+
+/// Returns `true` if `version` can be an image tag: up to 128 letters,
+/// digits, `_`, `.` and `-`, not starting with `.` or `-`.
+pub fn is_tag(version: &str) -> bool {
+    let mut chars = version.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric() || c == '_')
+        && version.len() <= 128
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+}
+
+fn path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("catalog.json"))
+}
+
+fn read() -> Option<Catalog> {
+    let content = fs::read_to_string(path().ok()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Returns the age after which the catalog and the channel resolutions are
+/// fetched again, in seconds: the configured TTL or [`MAX_AGE_SECS`].
+pub fn max_age() -> i64 {
+    read_config()
+        .ok()
+        .and_then(|cfg| cfg.cache_ttl)
+        .map_or(MAX_AGE_SECS, |minutes| i64::from(minutes) * 60)
+}
+
+/// Makes the cached tags and channel resolutions outdated for the rest of
+/// the invocation, for `--refresh`.
+pub fn force_refresh() {
+    REFRESH.store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` after [`force_refresh`].
+pub fn refresh_forced() -> bool {
+    REFRESH.load(Ordering::Relaxed)
+}
+
+fn is_fresh(catalog: &Catalog) -> bool {
+    !refresh_forced() && Utc::now().timestamp() - catalog.fetched < max_age()
+}
+
+/// Returns the age of the cached catalog in seconds, `None` if nothing is cached.
+pub fn age() -> Option<i64> {
+    read().map(|catalog| Utc::now().timestamp() - catalog.fetched)
+}
+
+/// Fetches the tags of the image sources and caches them.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if no source can be reached.
+pub fn refresh() -> Result<Vec<(String, String)>, CaveError> {
+    let budget = read_config().map(|c| c.hub_budget).unwrap_or_default();
+    let versions = remote_versions(&budget.unbounded())?;
+    let catalog = Catalog {
+        fetched: Utc::now().timestamp(),
+        versions,
+    };
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    match serde_json::to_string(&catalog) {
+        Ok(content) => fs::write(path, content)?,
+        Err(e) => debug!("Catalogue non enregistré: {}", e),
+    }
+    Ok(catalog.versions)
+}
+
+/// Returns the published tags with their push date, from the cache while it
+/// is recent, or whatever its age when offline. When no source can be
+/// reached, an outdated cache is used with a warning.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if no source can be reached and nothing is cached.
+pub fn versions() -> Result<Vec<(String, String)>, CaveError> {
+    let cached = read();
+    if let Some(catalog) = cached.as_ref() {
+        let age = Utc::now().timestamp() - catalog.fetched;
+        if is_fresh(catalog) {
+            debug!("Catalogue en cache depuis {}s", age);
+            return Ok(catalog.versions.clone());
+        }
+        if offline() {
+            eprintln!("{} offline, showing the versions fetched {}.", "Note:".cyan().bold(), format::age(age));
+            return Ok(catalog.versions.clone());
+        }
+    }
+    match (refresh(), cached) {
+        (Ok(versions), _) => Ok(versions),
+        (Err(e), Some(catalog)) => Ok(outdated(&e, catalog)),
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Returns the tags of an outdated catalog, with a warning that the image
+/// sources cannot be reached.
+fn outdated(e: &CaveError, catalog: Catalog) -> Vec<(String, String)> {
+    eprintln!(
+        "{} the image sources cannot be reached ({}), showing the versions fetched {}.",
+        "Warning:".yellow().bold(),
+        e,
+        format::age(Utc::now().timestamp() - catalog.fetched)
+    );
+    catalog.versions
+}
+
+/// Returns the tags listed by `cave available`: the catalog while it is
+/// recent, or offline, else the tags fetched within the `hub_budget` setting
+/// (`cave config set-hub-budget`). A bounded listing is not cached, the
+/// catalog holding every tag for [`latest`] and [`contains`].
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if no source can be reached and nothing is cached.
+pub fn listing() -> Result<Vec<(String, String)>, CaveError> {
+    let budget = read_config().map(|c| c.hub_budget).unwrap_or_default();
+    let cached = read();
+    if !budget.is_bounded() || offline() || cached.as_ref().is_some_and(is_fresh) {
+        return versions();
+    }
+    match (remote_versions(&budget), cached) {
+        (Ok(versions), _) => Ok(versions),
+        (Err(e), Some(catalog)) => Ok(outdated(&e, catalog)),
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Returns the highest numeric version published, such as `17.4.0`, for the
+/// `latest` channel. Flavors such as `17.4.0-mpi` and named tags are left out.
+///
+/// # Errors
+/// - [`CaveError::HttpError`] if no source can be reached and nothing is cached.
+/// - [`CaveError::VersionNotAvailable`] if no numeric version is published.
+pub fn latest() -> Result<String, CaveError> {
+    versions()?
+        .into_iter()
+        .map(|(tag, _)| tag)
+        .filter(|tag| tag.starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|tag| tag.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .max_by(|a, b| version_cmp(a, b))
+        .ok_or_else(|| CaveError::VersionNotAvailable("latest".to_string()))
+}
+
+/// Returns `true` if `version` is published. A version missing from a recent
+/// cache is looked up in the sources with a request on its tag only, in case
+/// it was published in the meantime; an outdated cache is used when no
+/// source can be reached.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if no source can be reached and nothing is cached.
+pub fn contains(version: &str) -> Result<bool, CaveError> {
+    let cached = read();
+    let listed = |catalog: &Catalog| catalog.versions.iter().any(|(tag, _)| tag == version);
+    if cached.as_ref().is_some_and(|c| is_fresh(c) && listed(c)) {
+        return Ok(true);
+    }
+    let mut reached = false;
+    let mut error = None;
+    for source in sources::configured() {
+        match sources::has(&source, version) {
+            Ok(true) => return Ok(true),
+            Ok(false) => reached = true,
+            Err(e) => {
+                debug!("Source {} injoignable: {}", source, e);
+                error = Some(e);
+            }
+        }
+    }
+    match (reached, cached, error) {
+        (false, Some(catalog), _) => Ok(listed(&catalog)),
+        (false, None, Some(e)) => Err(e),
+        _ => Ok(false),
+    }
+}
@@ -0,0 +1,163 @@
+//! cgroup v2 resource accounting for code_aster runs.
+//!
+//! On hosts using the unified cgroup hierarchy, the kernel keeps exact
+//! per-container counters (`memory.peak`, `cpu.stat`). This module samples
+//! them from the host side while the container runs, so that the peak memory
+//! and CPU time of a run are known once it exits, without relying on
+//! `docker stats` sampling.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Resource usage of a container, as reported by its cgroup.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Peak memory usage in bytes (`memory.peak`, or the highest sampled `memory.current`).
+    pub peak_memory_bytes: Option<u64>,
+    /// Total CPU time consumed, in microseconds (`usage_usec` of `cpu.stat`).
+    pub cpu_usage_usec: Option<u64>,
+    /// User CPU time, in microseconds.
+    pub cpu_user_usec: Option<u64>,
+    /// System CPU time, in microseconds.
+    pub cpu_system_usec: Option<u64>,
+}
+
+impl ResourceUsage {
+    fn merge(&mut self, sample: ResourceUsage) {
+        self.peak_memory_bytes = match (self.peak_memory_bytes, sample.peak_memory_bytes) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.cpu_usage_usec = sample.cpu_usage_usec.or(self.cpu_usage_usec);
+        self.cpu_user_usec = sample.cpu_user_usec.or(self.cpu_user_usec);
+        self.cpu_system_usec = sample.cpu_system_usec.or(self.cpu_system_usec);
+    }
+}
+
+/// Returns `true` if the host uses the unified cgroup v2 hierarchy.
+pub fn is_cgroup_v2() -> bool {
+    Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+/// Locates the cgroup directory of a container, for both the `systemd`
+/// and the `cgroupfs` Docker cgroup drivers, and for rootful Podman.
+fn container_cgroup_dir(container_id: &str) -> Option<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    [
+        root.join("system.slice")
+            .join(format!("docker-{}.scope", container_id)),
+        root.join("docker").join(container_id),
+        root.join("machine.slice")
+            .join(format!("libpod-{}.scope", container_id)),
+    ]
+    .into_iter()
+    .find(|p| p.is_dir())
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Reads the current counters of a cgroup directory.
+pub fn read_usage(dir: &Path) -> ResourceUsage {
+    let mut usage = ResourceUsage {
+        peak_memory_bytes: read_u64(&dir.join("memory.peak"))
+            .or_else(|| read_u64(&dir.join("memory.current"))),
+        ..Default::default()
+    };
+
+    if let Ok(content) = fs::read_to_string(dir.join("cpu.stat")) {
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let value = value.parse().ok();
+            match key {
+                "usage_usec" => usage.cpu_usage_usec = value,
+                "user_usec" => usage.cpu_user_usec = value,
+                "system_usec" => usage.cpu_system_usec = value,
+                _ => {}
+            }
+        }
+    }
+    usage
+}
+
+/// Background sampler following the cgroup of a running container.
+///
+/// The container ID is read from the file given to `docker run --cidfile`.
+/// Counters are sampled until [`CgroupSampler::finish`] is called or the
+/// cgroup disappears, and the last values are kept.
+pub struct CgroupSampler {
+    stop: Arc<AtomicBool>,
+    usage: Arc<Mutex<Option<ResourceUsage>>>,
+    handle: JoinHandle<()>,
+}
+
+impl CgroupSampler {
+    /// Starts sampling, or returns `None` when the host is not on cgroup v2.
+    pub fn start(cidfile: PathBuf) -> Option<Self> {
+        if !is_cgroup_v2() {
+            debug!("cgroup v2 non disponible, pas de comptabilité des ressources");
+            return None;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let usage = Arc::new(Mutex::new(None));
+
+        let handle = {
+            let stop = Arc::clone(&stop);
+            let usage = Arc::clone(&usage);
+            thread::spawn(move || sample_loop(&cidfile, &stop, &usage))
+        };
+
+        Some(Self {
+            stop,
+            usage,
+            handle,
+        })
+    }
+
+    /// Stops sampling and returns the last known resource usage.
+    pub fn finish(self) -> Option<ResourceUsage> {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+        let usage = *self.usage.lock().ok()?;
+        usage
+    }
+}
+
+fn sample_loop(cidfile: &Path, stop: &AtomicBool, usage: &Mutex<Option<ResourceUsage>>) {
+    let mut dir = None;
+    while !stop.load(Ordering::Relaxed) {
+        if dir.is_none() {
+            dir = fs::read_to_string(cidfile)
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .and_then(|id| container_cgroup_dir(&id));
+            if let Some(d) = &dir {
+                debug!("cgroup du conteneur: {}", d.display());
+            }
+        }
+        if let Some(d) = &dir {
+            if !d.is_dir() {
+                break;
+            }
+            let sample = read_usage(d);
+            if let Ok(mut guard) = usage.lock() {
+                guard.get_or_insert_with(ResourceUsage::default).merge(sample);
+            }
+        }
+        thread::sleep(SAMPLE_INTERVAL);
+    }
+}
@@ -3,74 +3,802 @@ use clap::{Parser, Subcommand};
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"), version = env!("CARGO_PKG_VERSION"))]
 pub struct Cli {
+    ///Answer the interactive prompts from this YAML file, for unattended use
+    #[arg(long, global = true, value_name = "FILE", env = "CAVE_ANSWERS")]
+    pub answers: Option<String>,
+    ///Do not show activity indicators during long operations
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    ///Work without the network: no update check, tag listing, download or HTTP telemetry
+    #[arg(long, global = true)]
+    pub offline: bool,
     #[command(subcommand)]
     pub command: Command,
 }
 
+// Parsed once per invocation, the size of `Run` does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Command {
     ///Define the default version
     Use {
-        ///Code aster version : stable, testing or under this format : 1x.x.xx
+        ///Code aster version : stable, testing, latest or under this format : 1x.x.xx
         version: String,
+        ///Pin the version without downloading it, `cave run` downloads it when needed
+        #[arg(long)]
+        no_download: bool,
+        ///Resolve stable, testing and latest again, ignoring the cached resolution
+        #[arg(long)]
+        refresh: bool,
     },
     ///Define the directory version
     Pin {
-        ///Code aster version : stable, testing or under this format : 1x.x.xx
+        ///Code aster version : stable, testing, latest or under this format : 1x.x.xx
+        #[arg(required_unless_present = "from_global")]
+        version: Option<String>,
+        ///Copy the global version into the directory, without resolving or downloading it
+        #[arg(long, conflicts_with = "version")]
+        from_global: bool,
+        ///Pin the version without downloading it, `cave run` downloads it when needed
+        #[arg(long, conflicts_with = "from_global")]
+        no_download: bool,
+        ///Resolve stable, testing and latest again, ignoring the cached resolution
+        #[arg(long, conflicts_with = "from_global")]
+        refresh: bool,
+    },
+    ///Remove the directory version, falling back to the global one
+    Unpin {
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Print the version used in the current directory and where it comes from
+    Current {
+        ///Print a JSON object, for scripts
+        #[arg(long)]
+        json: bool,
+    },
+    ///Show the registry metadata and image labels of a version, installed or not
+    About {
+        ///Version, ex : 17.1.4, stable, testing or latest
         version: String,
     },
+    ///Check Docker, the network, the configuration and the selected version
+    Doctor,
+    ///Summarize the selected versions, runtime, jobs, images, caches and telemetry
+    Status,
+    ///Download one or several versions, in parallel
+    Pull {
+        ///Code aster versions : stable, testing, latest or under this format : 1x.x.xx
+        #[arg(required_unless_present = "from_list")]
+        versions: Vec<String>,
+        ///Pull the versions listed in FILE, one per line, such as written by `cave images export-list`
+        #[arg(long, value_name = "FILE", conflicts_with = "versions")]
+        from_list: Option<String>,
+        ///Number of versions pulled at the same time
+        #[arg(short, long, default_value_t = 3)]
+        jobs: usize,
+        ///Do not ask for confirmation before pulling the versions of a list
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Share the list of installed versions between machines
+    Images {
+        #[command(subcommand)]
+        action: ImagesAction,
+    },
+    ///Remove a downloaded image
+    Remove {
+        ///Code aster version, under this format : 1x.x.xx
+        version: String,
+        ///Remove the version even if it is pinned
+        #[arg(short, long)]
+        force: bool,
+    },
+    ///Remove the downloaded images that are not pinned
+    Prune {
+        ///Keep the N most recent images
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        keep_last: usize,
+        ///Only remove images created more than DAYS days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u32>,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
     ///Run code_aster
-    #[command(override_usage = "cave run -- [ARGS]")]
+    #[command(override_usage = "cave run [OPTIONS] -- [ARGS]")]
     Run {
-        ///Optional args followed by export file
+        ///Copy the study to a local scratch directory and copy the results back
+        #[arg(long)]
+        stage_local: bool,
+        ///Name of the run, used in the container name and as history lookup key
+        #[arg(long)]
+        name: Option<String>,
+        ///Run even if another run is in progress in this directory
+        #[arg(long)]
+        force: bool,
+        ///Run with N MPI processes (needs an MPI build of code_aster, or its -mpi flavor)
+        #[arg(long, visible_alias = "np", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        mpi: Option<u32>,
+        ///Run with MPI on the hosts declared in the [cluster] section of cave.toml (experimental)
+        #[arg(long, conflicts_with_all = ["mpi", "stage_local"])]
+        cluster: bool,
+        ///Pin the container to these CPUs, e.g. 0-7,16-23
+        #[arg(long, value_name = "CPUS")]
+        cpuset: Option<String>,
+        ///Pin the container to the cores and memory of the NUMA node with the most free memory
+        #[arg(long, conflicts_with_all = ["cpuset", "cluster"])]
+        numa: bool,
+        ///Run with low CPU and disk I/O priority
+        #[arg(long)]
+        nice: bool,
+        ///Restart from databases produced by another code_aster version
+        #[arg(long)]
+        allow_version_mismatch: bool,
+        ///Only print the output lines matching this regex, the full output goes to the run log
+        #[arg(long, value_name = "REGEX")]
+        filter: Option<String>,
+        ///Also write the output, with the time of each line, to .cave-logs/<run id>.log in the study directory
+        #[arg(long, conflicts_with_all = ["detach", "remote", "backend"])]
+        log_file: bool,
+        ///Write the whole output to the run log, past the size limit of the logs
+        #[arg(long)]
+        full_log: bool,
+        ///Keep the files given to code_aster in place of the study ones, such as rewritten export files, in .cave-tmp/<run id>
+        #[arg(long)]
+        keep_temp: bool,
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+        ///Number of CPUs the container may use
+        #[arg(long)]
+        cpus: Option<f64>,
+        ///Memory limit of the container, ex : 16g
+        #[arg(long)]
+        memory: Option<String>,
+        ///Memory plus swap limit of the container, -1 for unlimited swap
+        #[arg(long, allow_hyphen_values = true)]
+        memory_swap: Option<String>,
+        ///Set an environment variable in the container, repeatable; NAME alone passes the host value
+        #[arg(short, long = "env", value_name = "NAME=VALUE")]
+        envs: Vec<String>,
+        ///Read environment variables from a file of NAME=VALUE lines, repeatable
+        #[arg(long = "env-file", value_name = "FILE")]
+        env_files: Vec<String>,
+        ///Mount a host directory in the container, repeatable, ex : /mnt/meshes:/meshes:ro
+        #[arg(long = "mount", value_name = "HOST:CONTAINER[:ro]")]
+        mounts: Vec<String>,
+        ///GPUs given to the container, ex : all, device=0 ; none to override the configured default
+        #[arg(long, value_name = "SPEC")]
+        gpus: Option<String>,
+        ///Apply the resource preset of this name, see `cave config set-preset`
+        #[arg(long, value_name = "NAME")]
+        preset: Option<String>,
+        ///Start the run in the background and return immediately
+        #[arg(short, long, conflicts_with_all = ["stage_local", "mpi", "cluster", "filter"])]
+        detach: bool,
+        ///Pass the piped standard input to code_aster
+        #[arg(long, conflicts_with = "detach")]
+        stdin: bool,
+        ///Number of studies run at the same time, with several export files (default: 1)
+        #[arg(short, long, conflicts_with = "detach")]
+        jobs: Option<usize>,
+        ///Run again whenever the export file or one of its input files changes, until Ctrl-C
+        #[arg(short, long, conflicts_with_all = ["detach", "stdin", "jobs"])]
+        watch: bool,
+        ///Run on the host of this [remote.<NAME>] section of cave.toml, copying the study there and the results back
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["detach", "stdin", "watch", "stage_local", "mpi", "cluster", "mounts", "cpuset", "numa"])]
+        remote: Option<String>,
+        ///Where the container runs: local, or k8s for a Job of the [k8s] cluster of cave.toml (needs the k8s feature)
+        #[arg(long, value_name = "BACKEND", value_parser = ["local", "k8s"], conflicts_with_all = ["detach", "stdin", "watch", "stage_local", "mpi", "cluster", "mounts", "cpuset", "numa", "remote"])]
+        backend: Option<String>,
+        ///Optional args followed by the export file, or several export files for a batch
         #[arg(trailing_var_arg = true)]
         #[arg(value_name = "ARGS")]
         args: Vec<String>,
     },
+    ///Submit a run to Slurm with sbatch, the resources being requested from the export file
+    #[command(
+        args_conflicts_with_subcommands = true,
+        override_usage = "cave submit [OPTIONS] -- [ARGS] FILE.export\n       cave submit status [JOB]"
+    )]
+    Submit {
+        #[command(subcommand)]
+        action: Option<SubmitAction>,
+        ///Slurm partition of the job
+        #[arg(short, long)]
+        partition: Option<String>,
+        ///Account charged for the job
+        #[arg(short = 'A', long)]
+        account: Option<String>,
+        ///Name of the job (default: named after the export file)
+        #[arg(short = 'J', long, value_name = "NAME")]
+        job_name: Option<String>,
+        ///Option given to sbatch, overriding the script ones, repeatable, ex : --sbatch=--qos=debug
+        #[arg(long = "sbatch", value_name = "OPTION", allow_hyphen_values = true)]
+        sbatch_args: Vec<String>,
+        ///Print the sbatch script without submitting it
+        #[arg(long)]
+        dry_run: bool,
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+        ///Optional run_aster args followed by the export file
+        #[arg(trailing_var_arg = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    ///Check an export file and the input files it references
+    Check {
+        ///Export file to check
+        #[arg(value_name = "FILE")]
+        export: String,
+    },
+    ///Check the syntax, imports and names of command files, without running the solver
+    CheckComm {
+        ///Command files (default: the .comm files of the current directory)
+        #[arg(value_name = "FILE")]
+        files: Vec<String>,
+    },
+    ///Write a starter export file for a study of the current directory
+    InitExport {
+        ///Name of the export file (default: named after the command file)
+        #[arg(value_name = "FILE")]
+        output: Option<String>,
+        ///Command file (default: the only .comm file of the directory)
+        #[arg(long, value_name = "FILE")]
+        comm: Option<String>,
+        ///Mesh file, .med or .mail (default: the only mesh file of the directory)
+        #[arg(long, value_name = "FILE")]
+        mesh: Option<String>,
+        ///Memory limit in MiB (default: 2048)
+        #[arg(long, value_name = "MIB", value_parser = clap::value_parser!(u32).range(1..))]
+        memory: Option<u32>,
+        ///Time limit in seconds (default: 3600)
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u32).range(1..))]
+        time: Option<u32>,
+        ///Replace an existing export file
+        #[arg(long)]
+        force: bool,
+    },
+    ///Run again the last run of the current directory, or the given run
+    Rerun {
+        ///Run name, identifier, or an unambiguous prefix of the identifier
+        run: Option<String>,
+        ///Run with this code_aster version instead of the version of the repeated run
+        #[arg(long, value_name = "VERSION")]
+        with: Option<String>,
+    },
+    ///Run coupled simulations with the auxiliary containers declared in cave.toml
+    Compose {
+        #[command(subcommand)]
+        action: ComposeAction,
+    },
     ///Start an interactive shell in the container
-    Shell,
+    Shell {
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Run a command in the container, ex : cave exec -- as_run --version
+    Exec {
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+        ///Command and its arguments
+        #[arg(trailing_var_arg = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    ///Print the environment of the current version's image, for bug reports
+    Env {
+        ///Inspect the image again instead of using the cached result
+        #[arg(long)]
+        refresh: bool,
+    },
+    ///Write a zip of the environment and of a failed run, secrets redacted, to attach to bug reports
+    SupportBundle {
+        ///Run name, identifier or prefix (default: the last failed run of the current directory)
+        run: Option<String>,
+        ///Zip file to write (default: cave-support-<date>.zip)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        ///Replace an existing file
+        #[arg(long)]
+        force: bool,
+    },
     ///List downloaded images
     List {
         ///Optionnal Expression to match, ex : "cave list 16"
         prefix: Option<String>,
+        ///Do not print the header line
+        #[arg(long)]
+        no_header: bool,
+        ///Print a JSON array, for scripts
+        #[arg(long, conflicts_with = "no_header")]
+        json: bool,
     },
     ///List available images on dockerhub
     Available {
         ///Optionnal Expression to match, ex : "cave list 16"
         prefix: Option<String>,
+        ///Do not print the header line
+        #[arg(long)]
+        no_header: bool,
+        ///Print a JSON array, for scripts
+        #[arg(long, conflicts_with = "no_header")]
+        json: bool,
+        ///Fetch the tags again, ignoring the cached ones
+        #[arg(long)]
+        refresh: bool,
+    },
+    ///Write a .gitignore for code_aster artifacts and check the git index for result files
+    Ignore {
+        ///Only check the staged files, do not modify .gitignore
+        #[arg(long)]
+        check: bool,
+        ///Size in MB above which a staged file is reported
+        #[arg(long, default_value_t = 10)]
+        max_size: u64,
+    },
+    ///List and inspect past runs
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+        ///Only list the failed and cancelled runs
+        #[arg(long)]
+        failed: bool,
+        ///Only list the runs of this code_aster version
+        #[arg(long)]
+        version: Option<String>,
+        ///Only list the runs started from the current directory
+        #[arg(long)]
+        here: bool,
+        ///Number of runs listed, the most recent ones
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    ///Compare the results of two runs of a study against tolerances
+    DiffResults {
+        ///Reference run: name, identifier, or an unambiguous prefix of the identifier
+        run_a: String,
+        ///Compared run: name, identifier, or an unambiguous prefix of the identifier
+        run_b: String,
+        ///Relative tolerance on result values
+        #[arg(long, default_value_t = 1e-6)]
+        rtol: f64,
+        ///Absolute tolerance on result values, for values close to zero
+        #[arg(long, default_value_t = 0.0)]
+        atol: f64,
+        ///Relative tolerance on the run duration
+        #[arg(long, default_value_t = 0.2)]
+        time_rtol: f64,
+        ///List every compared value, not only the differences
+        #[arg(long)]
+        all: bool,
+    },
+    ///List, follow and stop the running cave containers
+    Jobs {
+        #[command(subcommand)]
+        action: Option<JobsAction>,
+    },
+    ///Stop every running cave container
+    KillAll {
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Write a cave-freeze.json manifest of the project environment, for archival
+    Freeze {
+        ///Write the manifest to this file instead of cave-freeze.json in the project directory
+        #[arg(long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    ///Restore the environment described by a cave-freeze.json manifest
+    Thaw {
+        ///Manifest to restore (default: cave-freeze.json in the project directory)
+        manifest: Option<String>,
+        ///Replace an existing cave.toml that differs from the frozen one
+        #[arg(long)]
+        force: bool,
+    },
+    ///Explain a code_aster or cave error and suggest a fix
+    Explain {
+        ///Error code, or error message to search (default: list the known errors)
+        query: Option<String>,
+    },
+    ///Run the non-regression cases declared in cave.toml
+    #[command(args_conflicts_with_subcommands = true)]
+    Nrt {
+        #[command(subcommand)]
+        action: Option<NrtAction>,
+        ///Cases to run (default: all)
+        cases: Vec<String>,
+        ///Write a JUnit XML report to this file
+        #[arg(long, value_name = "FILE")]
+        junit: Option<String>,
+    },
+    ///Run a study for each combination of parameter values, ex : cave sweep beam.export -p thickness=1,2,5
+    Sweep {
+        ///Export file of the study, whose .export and .comm files hold {{ name }} placeholders
+        export: Option<String>,
+        ///Parameter and its values, as NAME=V1,V2,... (can be repeated)
+        #[arg(short, long = "param", value_name = "NAME=VALUES")]
+        params: Vec<String>,
+        ///YAML sweep file giving the export file, the parameters and the options
+        #[arg(short, long, value_name = "FILE")]
+        file: Option<String>,
+        ///Number of cases run at the same time (default: 1)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        ///Number of times a failed case is run again (default: 0)
+        #[arg(long)]
+        retries: Option<u32>,
+        ///Stop starting cases after the first failure
+        #[arg(long)]
+        fail_fast: bool,
+        ///Directory of the cases (default: sweep)
+        #[arg(short, long, value_name = "DIR")]
+        output: Option<String>,
+        ///Replace the existing case directories
+        #[arg(long)]
+        force: bool,
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Manage run_aster/as_run shims forwarding to `cave run`
+    Shim {
+        #[command(subcommand)]
+        action: ShimAction,
+    },
+    ///Manage the cave man pages
+    Man {
+        #[command(subcommand)]
+        action: ManAction,
+    },
+    ///Manage the external `cave-<name>` subcommands
+    Plugin {
+        #[command(subcommand)]
+        action: PluginAction,
     },
     ///Configurate cave
     Config {
         #[command(subcommand)]
         action: ConfigAction,
     },
+    ///Run the `cave-<name>` plugin found on the PATH
+    #[command(external_subcommand)]
+    External(Vec<String>),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryAction {
+    ///Show every detail of a past run
+    Show {
+        ///Run name, identifier, or an unambiguous prefix of the identifier
+        id: String,
+    },
+    ///Show the solver-hours and the concurrent runs per day or week, to size the compute node
+    Stats {
+        ///Length of the periods
+        #[arg(long, value_parser = ["day", "week"], default_value = "day")]
+        by: String,
+        ///Number of days covered, up to today
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+        ///Print CSV, for a spreadsheet
+        #[arg(long)]
+        csv: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImagesAction {
+    ///Print the installed versions, one per line
+    ExportList {
+        ///Write the list to FILE instead of printing it
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    ///Pull the versions of a list that are not installed, as `cave pull --from-list`
+    ImportList {
+        ///List of versions, one per line
+        file: String,
+        ///Number of versions pulled at the same time
+        #[arg(short, long, default_value_t = 3)]
+        jobs: usize,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ComposeAction {
+    ///Start the services of the [compose] section, run code_aster alongside them, then remove them
+    Run {
+        ///Name of the run, used in the container name and as history lookup key
+        #[arg(long)]
+        name: Option<String>,
+        ///Run even if another run is in progress in this directory
+        #[arg(long)]
+        force: bool,
+        ///Download the pinned version again without asking if its image was removed
+        #[arg(short, long)]
+        yes: bool,
+        ///Optional args followed by export file
+        #[arg(trailing_var_arg = true)]
+        #[arg(value_name = "ARGS")]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JobsAction {
+    ///Follow the output of a running job, Ctrl-C detaches without stopping it
+    Attach {
+        ///Run ID, run name, container name or container ID
+        id: String,
+    },
+    ///Stop a running job
+    Kill {
+        ///Run ID, run name, container name or container ID
+        id: String,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubmitAction {
+    ///Print the state of the submitted jobs, from squeue or sacct
+    Status {
+        ///Job ID or name (default: the recently submitted jobs)
+        job: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NrtAction {
+    ///Record the reference values of cases from their latest run
+    Bless {
+        ///Cases to bless (default: all)
+        cases: Vec<String>,
+        ///Run to take the values from, instead of the latest run of the case
+        #[arg(long)]
+        run: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ShimAction {
+    ///Install run_aster and as_run shims calling `cave run`
+    Install {
+        ///Directory to write the shims to (default: ~/.local/bin)
+        #[arg(long)]
+        dir: Option<String>,
+    },
+    ///Remove the shims installed by cave
+    Uninstall {
+        ///Directory holding the shims (default: ~/.local/bin)
+        #[arg(long)]
+        dir: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ManAction {
+    ///Install the man pages of cave and its subcommands
+    Install {
+        ///Directory to write the pages to (default: /usr/local/share/man/man1 if writable, else ~/.local/share/man/man1)
+        #[arg(long)]
+        dir: Option<String>,
+        ///Install in ~/.local/share/man/man1
+        #[arg(long)]
+        user: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PluginAction {
+    ///List the `cave-<name>` plugins found on the PATH
+    List,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum ConfigAction {
-    /// Activate auto update for stable/testing versions
+    /// Activate auto update for stable/testing/latest versions
     EnableAutoUpdate,
-    /// Deactivate auto update for stable/testing versions (default)
+    /// Deactivate auto update for stable/testing/latest versions (default)
     DisableAutoUpdate,
     /// Enable automatic new cave release check (default)
     EnableUpdateCheck,
     /// Disable automatic new cave release check
     DisableUpdateCheck,
-    // TODO : uncomment to have registry option
-    //
-    // ///Define a personnal registry
-    // SetRegistry {
-    //     ///Repository
-    //     repo : String,
-    //     ///Username
-    //     user : String,
-    //     ///Personal Access Token (PAT)
-    //     token : String
-    // },
-    // ///Erase the personal registry
-    // EraseRegistry,
+    ///Define a personnal registry, preferred to Docker Hub for the versions it has
+    SetRegistry {
+        ///Repository, ex : "mygroup/code_aster"
+        repo: String,
+        ///Username
+        user: String,
+        ///Personal Access Token (PAT)
+        token: String,
+        ///Host of the registry
+        #[arg(long, default_value = "registry.gitlab.com")]
+        host: String,
+    },
+    ///Erase the personal registry
+    EraseRegistry,
+    ///Set the repositories versions are looked up and pulled from, in order (default: simvia/code_aster)
+    SetImageSources {
+        ///Repositories, ex : "harbor.internal/code_aster simvia/code_aster"
+        #[arg(required = true)]
+        sources: Vec<String>,
+    },
+    ///Set the environment variables passed to every run, none without arguments
+    SetForwardEnv {
+        ///NAME to pass the value of the host, or NAME=VALUE, ex : HTTPS_PROXY OMP_NUM_THREADS=1
+        variables: Vec<String>,
+    },
+    ///Set where the telemetry of runs goes, none without arguments (default: http)
+    SetTelemetrySinks {
+        ///http for Simvia's endpoint, file:PATH to append JSON lines, command:COMMAND to pipe each payload to a command
+        sinks: Vec<String>,
+    },
     ///Enable version usage tracking (default)
     EnableUsageTracking,
     ///Disable version usage tracking
     DisableUsageTracking,
+    ///Include peak memory and CPU time of runs in usage tracking
+    EnableResourceTracking,
+    ///Do not include resource usage in usage tracking (default)
+    DisableResourceTracking,
+    ///Print a notice when a local pin shadows a different global version (default)
+    EnablePinNotice,
+    ///Do not print the local pin notice
+    DisablePinNotice,
+    ///Run the version declared by the export file (`P version`) instead of the pinned one
+    EnableExportVersion,
+    ///Run the pinned version, whatever the export file declares (default)
+    DisableExportVersion,
+    ///Write a .cave-run.json provenance file in the study directory after each run
+    EnableRunMetadata,
+    ///Do not write the .cave-run.json provenance file (default)
+    DisableRunMetadata,
+    ///Run with low priority, as with `cave run --nice`, when started without a terminal (scripts, schedulers)
+    EnableNiceBatch,
+    ///Run with normal priority when started without a terminal (default)
+    DisableNiceBatch,
+    ///Warn when a run prints nothing for this long (default 900 s)
+    SetHeartbeat {
+        ///Silence in seconds, 0 to disable
+        seconds: u64,
+    },
+    ///Capture the solver stacks when a run prints nothing for too long
+    EnableStackCapture,
+    ///Do not capture the solver stacks (default)
+    DisableStackCapture,
+    ///Keep the container commands of each run in the history and the run log, to replay them outside cave
+    EnableCommandLog,
+    ///Do not log the container commands of runs (default)
+    DisableCommandLog,
+    ///Also write the output of every run to .cave-logs/<run id>.log in the study directory, as with `cave run --log-file`
+    EnableLogFile,
+    ///Only write the output of runs to .cave-logs with `cave run --log-file` (default)
+    DisableLogFile,
+    ///Work without the network in every invocation, as with `cave --offline`
+    EnableOffline,
+    ///Use the network when needed (default)
+    DisableOffline,
+    ///Set the container runtime
+    SetRuntime {
+        ///auto (default) uses Docker if its daemon answers, then Podman, then Apptainer
+        #[arg(value_parser = ["auto", "docker", "podman", "apptainer"])]
+        runtime: String,
+    },
+    ///Set the user the run containers run as
+    SetContainerUser {
+        ///host (default) for the owner of the study directory, image for the user of the image, or UID:GID
+        user: String,
+    },
+    ///Set a license server environment variable injected into every run
+    SetLicenseEnv {
+        ///Assignment, ex : "LM_LICENSE_FILE=27000@licsrv"
+        #[arg(value_name = "NAME=VALUE")]
+        assignment: String,
+    },
+    ///Remove a license server environment variable
+    UnsetLicenseEnv {
+        ///Name of the variable
+        name: String,
+    },
+    ///Translate a `cave run` flag into a run_aster option, from a code_aster major version on
+    SetRunArg {
+        ///First major version, ex : 16
+        major: u32,
+        ///Flag given to `cave run`, ex : --memory-limit
+        #[arg(allow_hyphen_values = true)]
+        flag: String,
+        ///run_aster option, ex : --memory_limit
+        #[arg(allow_hyphen_values = true)]
+        option: String,
+        ///Factor applied to the value, ex : 0.125 from MiB to megawords
+        #[arg(long, default_value_t = 1.0)]
+        scale: f64,
+    },
+    ///Remove a translation set with set-run-arg, restoring the built-in one
+    UnsetRunArg {
+        ///Major version
+        major: u32,
+        ///Flag given to `cave run`
+        #[arg(allow_hyphen_values = true)]
+        flag: String,
+    },
+    ///Set the default CPU and memory limits of runs, none to remove them
+    SetLimits {
+        ///Number of CPUs the container may use
+        #[arg(long)]
+        cpus: Option<f64>,
+        ///Memory limit of the container, ex : 16g
+        #[arg(long)]
+        memory: Option<String>,
+        ///Memory plus swap limit of the container, -1 for unlimited swap
+        #[arg(long, allow_hyphen_values = true)]
+        memory_swap: Option<String>,
+    },
+    ///Bound the Docker Hub tag listings of `cave available`, for slow links; no limit without options
+    SetHubBudget {
+        ///Tags requested per page
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=100))]
+        page_size: u32,
+        ///Maximum number of pages fetched
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_pages: Option<u32>,
+        ///Maximum number of tags listed
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_tags: Option<u32>,
+    },
+    ///Set how long the published tags are cached, an hour without a value
+    SetCacheTtl {
+        ///Minutes, 0 to fetch them every time
+        minutes: Option<u32>,
+    },
+    ///Set the size limit of the run logs, 64 MiB without a value
+    SetLogLimit {
+        ///MiB, 0 to keep the whole output
+        mib: Option<u64>,
+    },
+    ///Set the GPUs given to runs by default, none without a value
+    SetGpus {
+        ///GPUs as for `docker run --gpus`, ex : all, device=0,1
+        spec: Option<String>,
+    },
+    ///Define a resource preset of runs, selected with `cave run --preset`
+    SetPreset {
+        ///Name of the preset, ex : big
+        name: String,
+        ///Number of CPUs the container may use
+        #[arg(long)]
+        cpus: Option<f64>,
+        ///Memory limit of the container, ex : 120g
+        #[arg(long)]
+        memory: Option<String>,
+        ///Memory plus swap limit of the container, -1 for unlimited swap
+        #[arg(long, allow_hyphen_values = true)]
+        memory_swap: Option<String>,
+        ///Copy the study to a local scratch directory, as with `cave run --stage-local`
+        #[arg(long)]
+        stage_local: bool,
+        ///Run with low CPU and disk I/O priority, as with `cave run --nice`
+        #[arg(long)]
+        nice: bool,
+    },
+    ///Remove a resource preset of runs
+    RemovePreset {
+        ///Name of the preset
+        name: String,
+    },
 }
@@ -0,0 +1,134 @@
+//! Experimental multi-node MPI runs (`cave run --cluster`).
+//!
+//! The hosts are declared in the `[cluster]` section of `cave.toml`. The
+//! study directory must be shared between them at the same path (NFS, ...).
+//! cave starts a worker container on every host but the first through
+//! `docker -H ssh://<host>`, running the image's `sshd` on the host network,
+//! then runs code_aster on the local host with a hostfile listing every host:
+//! `mpirun` reaches the workers through SSH, as on a hand-built cluster.
+//! The workers are stopped when the run ends.
+
+use crate::cmdlog::Logged;
+use crate::docker::runtime;
+use crate::manage::CaveError;
+use crate::project::ClusterConfig;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Name of the hostfile written in the study directory.
+pub const HOSTFILE: &str = ".cave-hostfile";
+
+fn remote_docker(host: &str) -> Command {
+    runtime().remote_command(host)
+}
+
+/// Worker containers of a cluster run, stopped when dropped.
+pub struct Workers {
+    containers: Vec<(String, String)>,
+    hostfile: PathBuf,
+}
+
+impl Workers {
+    /// Writes the hostfile in `dir` and starts a worker container for every
+    /// host of `cluster` but the first, which runs the MPI launcher.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::DockerError`] if the image is missing on a host
+    /// or a worker cannot be started.
+    pub fn start(cluster: &ClusterConfig, image: &str, dir: &Path, run_id: &str) -> Result<Self, CaveError> {
+        let hostfile = dir.join(HOSTFILE);
+        let content: String = cluster
+            .hosts
+            .iter()
+            .map(|h| format!("{} slots={}\n", h.host, h.slots))
+            .collect();
+        fs::write(&hostfile, content)?;
+
+        let mut workers = Workers {
+            containers: Vec::new(),
+            hostfile,
+        };
+        let ssh_dir = dirs::home_dir().ok_or(CaveError::HomeNotFound)?.join(".ssh");
+        for (i, host) in cluster.hosts.iter().enumerate().skip(1) {
+            let name = format!("cave-{}-worker{}", run_id, i);
+            debug!("Démarrage du worker {} sur {}", name, host.host);
+            let output = remote_docker(&host.host)
+                .args(["run", "-d", "--rm", "--network", "host", "--name", &name])
+                .arg("-v")
+                .arg(format!("{}:/home/user/data", dir.display()))
+                .arg("-v")
+                .arg(format!("{}:/home/user/.ssh:ro", ssh_dir.display()))
+                .arg(image)
+                .args(["/usr/sbin/sshd", "-D", "-p", &cluster.ssh_port.to_string()])
+                .logged_output()?;
+            if !output.status.success() {
+                return Err(CaveError::DockerError(format!(
+                    "cannot start the worker on {}: {}",
+                    host.host,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            workers.containers.push((host.host.clone(), name));
+        }
+        Ok(workers)
+    }
+}
+
+/// Returns the `docker run` arguments of the launcher container.
+pub fn launcher_args(cluster: &ClusterConfig) -> Result<Vec<String>, CaveError> {
+    let ssh_dir = dirs::home_dir().ok_or(CaveError::HomeNotFound)?.join(".ssh");
+    let hostfile = format!("/home/user/data/{}", HOSTFILE);
+    Ok(vec![
+        "--network".to_string(),
+        "host".to_string(),
+        "-v".to_string(),
+        format!("{}:/home/user/.ssh:ro", ssh_dir.display()),
+        "-e".to_string(),
+        format!("OMPI_MCA_plm_rsh_args=-p {}", cluster.ssh_port),
+        "-e".to_string(),
+        format!("OMPI_MCA_orte_default_hostfile={}", hostfile),
+        "-e".to_string(),
+        format!("PRTE_MCA_prte_default_hostfile={}", hostfile),
+    ])
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for (host, name) in &self.containers {
+            debug!("Arrêt du worker {} sur {}", name, host);
+            let _ = remote_docker(host)
+                .args(["stop", name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .logged()
+                .status();
+        }
+        let _ = fs::remove_file(&self.hostfile);
+    }
+}
+
+/// Checks that every remote host of `cluster` has `image`.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] naming the first host without the image.
+pub fn check_images(cluster: &ClusterConfig, image: &str) -> Result<(), CaveError> {
+    for host in cluster.hosts.iter().skip(1) {
+        let found = remote_docker(&host.host)
+            .args(["image", "inspect", image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .logged()
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if !found {
+            return Err(CaveError::DockerError(format!(
+                "{} is not available on {}. Run `docker -H ssh://{} pull {}` first.",
+                image, host.host, host.host, image
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! Log of the container commands run by cave.
+//!
+//! Every container command built by cave (pulls, inspections, runs, `exec`
+//! and `kill` calls, ...) is recorded in memory with its date. Operations done
+//! through the Docker Engine API are recorded as the equivalent docker CLI
+//! command. When enabled (`cave config enable-command-log`), the commands of
+//! a run are kept in its history record and appended to its run log, so that
+//! they can be replayed by hand outside cave.
+//!
+//! The runs of a batch or a sweep run at the same time on several threads:
+//! each run records its commands in its own [`CommandLog`], entered by the
+//! threads working for it (see [`scope`]). Commands recorded outside a run go
+//! to a log of the process.
+
+use crate::manage::{CaveError, ErrorContext};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+
+static LOG: Mutex<Vec<LoggedCommand>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static CURRENT: RefCell<Option<CommandLog>> = const { RefCell::new(None) };
+}
+
+/// Commands of a run, shared by the threads working for it.
+#[derive(Debug, Clone, Default)]
+pub struct CommandLog(Arc<Mutex<Vec<LoggedCommand>>>);
+
+impl CommandLog {
+    /// Records the commands of the current thread in this log until the
+    /// returned guard is dropped.
+    pub fn enter(&self) -> Entered {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        Entered { previous }
+    }
+}
+
+/// Guard of an entered [`CommandLog`], restoring the previous log of the thread when dropped.
+pub struct Entered {
+    previous: Option<CommandLog>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Returns the log of the run the current thread works for, if any.
+pub fn current() -> Option<CommandLog> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Enters a new log for a run started on the current thread, or keeps the
+/// log already entered when the run is part of an outer one.
+pub fn scope() -> Entered {
+    current().unwrap_or_default().enter()
+}
+
+/// A command run by cave.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggedCommand {
+    /// When the command was run, RFC 3339 in UTC.
+    pub date: String,
+    /// Command line, quoted for a POSIX shell.
+    pub command: String,
+}
+
+/// Quotes an argument for a POSIX shell, when needed.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@,+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+fn quote_args<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    args.into_iter()
+        .map(|a| shell_quote(a.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the command line of a command, quoted for a POSIX shell.
+pub fn command_line(cmd: &Command) -> String {
+    quote_args(
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().into_owned()),
+    )
+}
+
+fn push(command: String) {
+    let command = LoggedCommand {
+        date: chrono::Utc::now().to_rfc3339(),
+        command,
+    };
+    let current = current();
+    let log = match &current {
+        Some(log) => &log.0,
+        None => &LOG,
+    };
+    if let Ok(mut log) = log.lock() {
+        log.push(command);
+    };
+}
+
+/// Records a command line.
+pub fn record_args<I, S>(args: I)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    push(quote_args(args));
+}
+
+/// Records a command about to be run.
+pub fn record(cmd: &Command) {
+    push(command_line(cmd));
+}
+
+/// Records the commands before running them, in a call chain.
+pub trait Logged {
+    /// Records the command and returns it.
+    fn logged(&mut self) -> &mut Self;
+    /// Records the command and runs it as [`Command::output`], an error
+    /// naming the command if it cannot be started.
+    fn logged_output(&mut self) -> Result<Output, CaveError>;
+    /// Records the command and runs it as [`Command::status`], an error
+    /// naming the command if it cannot be started.
+    fn logged_status(&mut self) -> Result<ExitStatus, CaveError>;
+}
+
+impl Logged for Command {
+    fn logged(&mut self) -> &mut Self {
+        record(self);
+        self
+    }
+
+    fn logged_output(&mut self) -> Result<Output, CaveError> {
+        record(self);
+        self.output().with_context(|| format!("Running `{}`", command_line(self)))
+    }
+
+    fn logged_status(&mut self) -> Result<ExitStatus, CaveError> {
+        record(self);
+        self.status().with_context(|| format!("Running `{}`", command_line(self)))
+    }
+}
+
+/// Returns the recorded commands of the current run, or of the process
+/// outside a run, and clears them.
+pub fn take() -> Vec<LoggedCommand> {
+    let current = current();
+    let log = match &current {
+        Some(log) => &log.0,
+        None => &LOG,
+    };
+    let commands = log.lock().map(|mut log| std::mem::take(&mut *log)).unwrap_or_default();
+    commands
+}
@@ -0,0 +1,205 @@
+//! Fast checks of command files (`cave check-comm`).
+//!
+//! A typo in a `.comm` file is otherwise found when code_aster reaches it,
+//! possibly hours into a run. The command files are checked with the Python
+//! of the selected image instead, without starting the solver: the syntax,
+//! the imported modules, and the names used, which must be code_aster
+//! commands, Python builtins or defined in the file. Names are only warned
+//! about in files using `INCLUDE`, `POURSUITE` or `exec`, which define names
+//! the check cannot see.
+
+use crate::apptainer;
+use crate::cmdlog::{shell_quote, Logged};
+use crate::docker::{ensure_docker, exists_locally, runtime, uses_apptainer};
+use crate::export::{files_with, study_path};
+use crate::manage::{read_cave_version, CaveError};
+use crate::progress::Spinner;
+use colored::*;
+use std::env;
+
+/// Python script run in the container, printing one `kind\tpath\tline\tmessage`
+/// line per problem.
+const CHECK_SCRIPT: &str = r#"import ast, builtins, importlib, sys
+
+def report(kind, path, line, message):
+    print("\t".join((kind, path, str(line), message)))
+
+def names_of(module):
+    try:
+        return set(dir(importlib.import_module(module)))
+    except Exception:
+        return None
+
+commands = names_of("code_aster.Commands")
+if commands is None:
+    report("warning", "-", 0, "code_aster.Commands cannot be imported, names are not checked")
+for path in sys.argv[1:]:
+    try:
+        with open(path) as f:
+            tree = ast.parse(f.read(), path)
+    except SyntaxError as e:
+        report("error", path, e.lineno or 0, "syntax error: " + e.msg)
+        continue
+    known = set(dir(builtins)) | {"__file__", "__name__"}
+    dynamic = False
+    used = []
+    for node in ast.walk(tree):
+        if isinstance(node, ast.Import):
+            for alias in node.names:
+                if names_of(alias.name) is None:
+                    report("error", path, node.lineno, "cannot import " + alias.name)
+                known.add((alias.asname or alias.name).split(".")[0])
+        elif isinstance(node, ast.ImportFrom):
+            names = names_of(node.module) if node.level == 0 and node.module else None
+            if names is None:
+                if node.level == 0:
+                    report("error", path, node.lineno, "cannot import " + str(node.module))
+                dynamic = dynamic or any(a.name == "*" for a in node.names)
+            elif any(a.name == "*" for a in node.names):
+                known |= names
+            for alias in node.names:
+                if alias.name == "*":
+                    continue
+                if names is not None and alias.name not in names \
+                        and names_of(node.module + "." + alias.name) is None:
+                    report("error", path, node.lineno,
+                           "cannot import " + alias.name + " from " + node.module)
+                known.add(alias.asname or alias.name)
+        elif isinstance(node, ast.Name):
+            if isinstance(node.ctx, ast.Load):
+                used.append(node)
+            else:
+                known.add(node.id)
+        elif isinstance(node, (ast.FunctionDef, ast.AsyncFunctionDef, ast.ClassDef)):
+            known.add(node.name)
+        elif isinstance(node, ast.arg):
+            known.add(node.arg)
+        elif isinstance(node, ast.ExceptHandler) and node.name:
+            known.add(node.name)
+        elif isinstance(node, (ast.Global, ast.Nonlocal)):
+            known.update(node.names)
+        elif isinstance(node, ast.Call) and isinstance(node.func, ast.Name) \
+                and node.func.id in ("INCLUDE", "POURSUITE", "exec"):
+            dynamic = True
+    if commands is None:
+        continue
+    reported = set()
+    for node in sorted(used, key=lambda n: (n.lineno, n.col_offset)):
+        if node.id in known or node.id in commands or node.id in reported:
+            continue
+        reported.add(node.id)
+        report("warning" if dynamic else "error", path, node.lineno,
+               "name '" + node.id + "' is not defined")
+"#;
+
+/// A problem reported by [`CHECK_SCRIPT`].
+struct Problem {
+    error: bool,
+    location: String,
+    message: String,
+}
+
+fn parse(line: &str) -> Option<Problem> {
+    let mut fields = line.splitn(4, '\t');
+    let (kind, path, line, message) = (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+    let location = match (path, line) {
+        ("-", _) => String::new(),
+        (path, "0") => format!("{}: ", path),
+        (path, line) => format!("{}:{}: ", path, line),
+    };
+    Some(Problem {
+        error: kind == "error",
+        location,
+        message: message.to_string(),
+    })
+}
+
+/// Handler of `cave check-comm`: checks command files with the Python of
+/// the selected version, all the `.comm` files of the current directory
+/// when none is given.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the selected version is not installed.
+/// - [`CaveError::FileNotFound`] if a file is missing or outside the current directory.
+/// - [`CaveError::DockerError`] if the check cannot be run in the container.
+/// - [`CaveError::CommCheckFailed`] if errors are found.
+pub fn check_comm(files: &[String]) -> Result<(), CaveError> {
+    let files = if files.is_empty() {
+        files_with(&["comm"])
+    } else {
+        files.iter().map(|f| study_path(f)).collect::<Result<_, _>>()?
+    };
+    if files.is_empty() {
+        return Err(CaveError::FileNotFound(
+            "No .comm file in the current directory.".to_string(),
+        ));
+    }
+    ensure_docker()?;
+    let version = read_cave_version()?;
+    if !exists_locally(&version)? {
+        return Err(CaveError::VersionNotInstalled(version));
+    }
+
+    let dir = env::current_dir()?;
+    let quoted: Vec<String> = files.iter().map(|f| shell_quote(f)).collect();
+    let script = format!(
+        "source /opt/activate.sh >/dev/null 2>&1\nexec python3 -c {} {}",
+        shell_quote(CHECK_SCRIPT),
+        quoted.join(" ")
+    );
+    let mut cmd = if uses_apptainer() {
+        apptainer::exec_command(&version, &dir, &[], Some(&script))?
+    } else {
+        // Nothing is written: the study is mounted read-only, without network
+        let mut cmd = runtime().command();
+        cmd.arg("run")
+            .arg("--rm")
+            .arg("--network")
+            .arg("none")
+            .arg("-v")
+            .arg(format!("{}:/home/user/data:ro", dir.display()))
+            .arg("-w")
+            .arg("/home/user/data")
+            .arg(format!("simvia/code_aster:{}", version))
+            .arg("/bin/bash")
+            .arg("-c")
+            .arg(&script);
+        cmd
+    };
+    let output = {
+        let _spinner = Spinner::start(format!(
+            "Checking {} command file(s) with code_aster {}",
+            files.len(),
+            version
+        ));
+        cmd.logged_output()?
+    };
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to check the command files with code_aster {}: {}",
+            version,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let mut errors = 0;
+    for problem in String::from_utf8_lossy(&output.stdout).lines().filter_map(parse) {
+        let label = if problem.error {
+            errors += 1;
+            "error".red().bold()
+        } else {
+            "warn".yellow().bold()
+        };
+        println!("{:<6}{}{}", label, problem.location, problem.message);
+    }
+    if errors > 0 {
+        return Err(CaveError::CommCheckFailed(errors));
+    }
+    println!(
+        "{:<6}{} checked with code_aster {}",
+        "ok".green().bold(),
+        files.join(", "),
+        version
+    );
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Coupled runs (`cave compose run`).
+//!
+//! The auxiliary containers of a coupled simulation (an external thermal or
+//! fluid code exchanging files or sockets with code_aster, ...) are declared
+//! in the `[compose]` section of `cave.toml`. For a coupled run, cave creates
+//! a network named after the run, starts every service on it with the study
+//! directory mounted at `/home/user/data` like in the code_aster container,
+//! then runs code_aster on the same network, where each service is reachable
+//! by its name. code_aster starts once the `ready` command of every service
+//! succeeds in its container. The services and the network are removed when
+//! the run ends.
+
+use crate::cmdlog::Logged;
+use crate::docker::runtime;
+use crate::jobs;
+use crate::manage::CaveError;
+use crate::project::{ComposeConfig, ComposeService};
+use crate::signals;
+use log::debug;
+use std::path::Path;
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Delay between two runs of the `ready` command of a service.
+const READY_POLL: Duration = Duration::from_secs(1);
+
+/// Services of a coupled run and their network, removed when dropped.
+pub struct Services {
+    network: String,
+    containers: Vec<String>,
+}
+
+impl Services {
+    /// Creates the network of the run and starts the services of `config`,
+    /// with `dir` as shared volume, then waits for them to be ready.
+    ///
+    /// # Errors
+    /// - [`CaveError::DockerError`] if the network or a service cannot be
+    ///   created, or a service is not ready in time; the services already
+    ///   started are stopped.
+    /// - [`CaveError::Cancelled`] if interrupted while waiting.
+    pub fn start(config: &ComposeConfig, dir: &Path, run_id: &str) -> Result<Self, CaveError> {
+        let network = format!("cave-{}", run_id);
+        let output = runtime()
+            .command()
+            .args(["network", "create", "--label", jobs::LABEL, &network])
+            .logged_output()?;
+        if !output.status.success() {
+            return Err(CaveError::DockerError(format!(
+                "cannot create the network {}: {}",
+                network,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let mut services = Services {
+            network,
+            containers: Vec::new(),
+        };
+        for (name, service) in &config.services {
+            let container = format!("cave-{}-{}", run_id, name);
+            debug!("Démarrage du service {} ({})", name, service.image);
+            let mut cmd = runtime().command();
+            cmd.args(["run", "-d", "--rm", "--name", &container])
+                .args(["--network", &services.network, "--network-alias", name])
+                .args(["--label", jobs::LABEL])
+                .arg("--label")
+                .arg(format!("{}={}", jobs::RUN_ID_LABEL, run_id))
+                .arg("--label")
+                .arg(format!("{}={}", jobs::SERVICE_LABEL, name))
+                .arg("--label")
+                .arg(format!("{}={}", jobs::DIR_LABEL, dir.display()))
+                .arg("-v")
+                .arg(format!("{}:/home/user/data", dir.display()))
+                .args(["-w", "/home/user/data"]);
+            for (key, value) in &service.env {
+                cmd.arg("-e").arg(format!("{}={}", key, value));
+            }
+            let output = cmd
+                .args(&service.docker_args)
+                .arg(&service.image)
+                .args(&service.command)
+                .logged_output()?;
+            if !output.status.success() {
+                return Err(CaveError::DockerError(format!(
+                    "cannot start the service {}: {}",
+                    name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            services.containers.push(container);
+        }
+        for (name, service) in &config.services {
+            wait_ready(&format!("cave-{}-{}", run_id, name), name, service)?;
+        }
+        Ok(services)
+    }
+
+    /// Returns the `docker run` arguments joining the code_aster container to the services.
+    pub fn network_args(&self) -> Vec<String> {
+        vec!["--network".to_string(), self.network.clone()]
+    }
+}
+
+/// Runs the `ready` command of a service in its container until it succeeds.
+fn wait_ready(container: &str, name: &str, service: &ComposeService) -> Result<(), CaveError> {
+    if service.ready.is_empty() {
+        return Ok(());
+    }
+    let start = Instant::now();
+    loop {
+        let output = runtime()
+            .command()
+            .args(["exec", container])
+            .args(&service.ready)
+            .logged_output()?;
+        if output.status.success() {
+            debug!("Service {} prêt après {:?}", name, start.elapsed());
+            return Ok(());
+        }
+        if signals::cancelled() {
+            return Err(CaveError::Cancelled);
+        }
+        if start.elapsed() >= Duration::from_secs(service.ready_timeout) {
+            return Err(CaveError::DockerError(format!(
+                "the service {} is not ready after {}s: {}",
+                name,
+                service.ready_timeout,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        thread::sleep(READY_POLL);
+    }
+}
+
+impl Drop for Services {
+    fn drop(&mut self) {
+        for container in &self.containers {
+            debug!("Arrêt du service {}", container);
+            let _ = runtime()
+                .command()
+                .args(["stop", container])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .logged()
+                .status();
+        }
+        let _ = runtime()
+            .command()
+            .args(["network", "rm", &self.network])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .logged()
+            .status();
+    }
+}
@@ -3,21 +3,56 @@
 //! This module handles reading, writing, and updating the global
 //! configuration file located at `~/.caveconfig.json`.
 //!
+//! # Read-only mode
+//! On shared accounts and login nodes whose home directory is read-only,
+//! `CAVE_READ_ONLY=1` or `read_only_home = true` in the system policy file
+//! [`POLICY_FILE`] keep cave from writing to the home directory: the
+//! configuration is read but its changes only last for the invocation, and
+//! the state ([`state_dir`]) lives in a temporary directory removed when cave
+//! exits.
+//!
 //! # Adding a new configuration option
 //! 1. **Add a field** to the [`Config`] struct (and update [`Default::default`])
 //! 2. **Add a public setter function** following the pattern of [`set_auto_update`],
 //! 3. **Add the option to the cli** (in ConfigAction in `cli.rs`)
 //! 4. **Update the CLI command handler** in `main.rs`
 
-use crate::manage::CaveError;
+use crate::manage::{CaveError, ErrorContext};
+use log::debug;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 use uuid::Uuid;
 
+/// System policy file, where administrators can enable the read-only mode.
+pub const POLICY_FILE: &str = "/etc/cave/policy.toml";
+
+/// Settings of the system policy file.
+#[derive(Debug, Default, Deserialize)]
+struct Policy {
+    /// Never write to the home directory.
+    #[serde(default)]
+    read_only_home: bool,
+}
+
+/// State directory of the invocation in read-only mode.
+static INVOCATION_STATE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set by `--offline`, for the invocation.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+/// Configuration of the invocation in read-only mode, changes included.
+static INVOCATION_CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
 /// Stores Docker registry credentials and repository information.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Registry {
+    /// Host of the registry, e.g. `registry.gitlab.com`.
+    #[serde(default = "default_registry_host")]
+    pub host: String,
     /// Name of the Docker repository.
     pub repo: String,
     /// Username for authentication.
@@ -26,10 +61,179 @@ pub struct Registry {
     pub token: String,
 }
 
+/// Resource limits of the run containers.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResourceLimits {
+    /// Number of CPUs the container may use (`docker run --cpus`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// Memory limit of the container (`docker run --memory`), e.g. `8g`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// Memory plus swap limit of the container (`docker run --memory-swap`), `-1` for unlimited swap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_swap: Option<String>,
+}
+
+impl ResourceLimits {
+    /// Returns these limits, completed by `other` where they are not set.
+    pub fn or(self, other: ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            cpus: self.cpus.or(other.cpus),
+            memory: self.memory.or(other.memory),
+            memory_swap: self.memory_swap.or(other.memory_swap),
+        }
+    }
+
+    /// Returns the `docker run` arguments of the limits.
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cpus) = self.cpus {
+            args.extend(["--cpus".to_string(), cpus.to_string()]);
+        }
+        if let Some(memory) = &self.memory {
+            args.extend(["--memory".to_string(), memory.clone()]);
+        }
+        if let Some(memory_swap) = &self.memory_swap {
+            args.extend(["--memory-swap".to_string(), memory_swap.clone()]);
+        }
+        args
+    }
+}
+
+/// User the run containers run as (`cave config set-container-user`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerUser {
+    /// The invoking user, so that the files written in the study belong to them.
+    Host,
+    /// The user declared by the image.
+    Image,
+    /// A given UID and GID.
+    Fixed(u32, u32),
+}
+
+impl ContainerUser {
+    /// Parses `host`, `image` or `UID:GID`.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::InvalidContainerUser`] for any other value.
+    pub fn parse(user: &str) -> Result<Self, CaveError> {
+        match user {
+            "host" => Ok(ContainerUser::Host),
+            "image" => Ok(ContainerUser::Image),
+            _ => user
+                .split_once(':')
+                .and_then(|(uid, gid)| Some(ContainerUser::Fixed(uid.parse().ok()?, gid.parse().ok()?)))
+                .ok_or_else(|| CaveError::InvalidContainerUser(user.to_string())),
+        }
+    }
+}
+
+/// Destination of the telemetry of runs (`cave config set-telemetry-sinks`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelemetrySink {
+    /// The telemetry endpoint of Simvia, when usage tracking is enabled.
+    Http,
+    /// A JSON Lines file the payloads are appended to.
+    File(PathBuf),
+    /// A shell command receiving each payload as JSON on its standard input.
+    Command(String),
+}
+
+impl TelemetrySink {
+    /// Parses `http`, `file:PATH` or `command:COMMAND`.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::InvalidTelemetrySink`] for any other value.
+    pub fn parse(sink: &str) -> Result<Self, CaveError> {
+        match sink.split_once(':') {
+            None if sink == "http" => Ok(TelemetrySink::Http),
+            Some(("file", path)) if !path.is_empty() => Ok(TelemetrySink::File(PathBuf::from(path))),
+            Some(("command", command)) if !command.trim().is_empty() => {
+                Ok(TelemetrySink::Command(command.to_string()))
+            }
+            _ => Err(CaveError::InvalidTelemetrySink(sink.to_string())),
+        }
+    }
+}
+
+/// Bounds of the Docker Hub tag listings, for slow links.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HubBudget {
+    /// Tags requested per page, at most 100.
+    #[serde(default = "default_page_size")]
+    pub page_size: u32,
+    /// Maximum number of pages fetched per repository, unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<u32>,
+    /// Maximum number of tags listed per repository, unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tags: Option<u32>,
+}
+
+fn default_page_size() -> u32 {
+    100
+}
+
+impl HubBudget {
+    /// Returns `true` if the listings are cut after some pages or tags.
+    pub fn is_bounded(&self) -> bool {
+        self.max_pages.is_some() || self.max_tags.is_some()
+    }
+
+    /// Returns the budget listing every tag, with the same page size.
+    pub fn unbounded(&self) -> Self {
+        Self {
+            page_size: self.page_size,
+            max_pages: None,
+            max_tags: None,
+        }
+    }
+}
+
+impl Default for HubBudget {
+    fn default() -> Self {
+        Self {
+            page_size: default_page_size(),
+            max_pages: None,
+            max_tags: None,
+        }
+    }
+}
+
+/// A named resource envelope of runs, selected with `cave run --preset`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Preset {
+    /// CPU and memory limits.
+    #[serde(flatten)]
+    pub limits: ResourceLimits,
+    /// Whether the study is copied to a local scratch directory, as with `cave run --stage-local`.
+    #[serde(default)]
+    pub stage_local: bool,
+    /// Whether runs get a low priority, as with `cave run --nice`.
+    #[serde(default)]
+    pub nice: bool,
+}
+
+/// The run_aster option a user-friendly `cave run` flag is translated to,
+/// for a code_aster major version (see [`crate::profiles`]).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunArg {
+    /// run_aster option, ex : `--memjeveux`.
+    pub option: String,
+    /// Factor applied to the value, ex : 0.125 from MiB to megawords; 1 keeps the value as is.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
 /// Global configuration for the `cave` CLI.
 ///
 /// The configuration is stored in `~/.caveconfig.json`
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     /// Whether automatic update checks are enabled.
     pub auto_update: bool,
@@ -38,6 +242,76 @@ pub struct Config {
     pub auto_release_check: bool,
     /// Whether version tracking is enabled.
     pub version_tracking: bool,
+    /// Whether the peak memory and CPU time of runs are included in telemetry.
+    #[serde(default)]
+    pub share_resource_usage: bool,
+    /// Whether to print a notice when a local pin shadows a different global version.
+    #[serde(default = "default_pin_notice")]
+    pub pin_notice: bool,
+    /// Whether `cave run` uses the version declared by the export file (`P version`) instead of the pinned one.
+    #[serde(default)]
+    pub export_version: bool,
+    /// Whether to write a `.cave-run.json` provenance file in the study directory after each run.
+    #[serde(default)]
+    pub run_metadata: bool,
+    /// Whether runs started without a terminal (scripts, schedulers) get a low priority, as with `cave run --nice`.
+    #[serde(default)]
+    pub nice_batch: bool,
+    /// Silence in seconds after which a run is reported as possibly hung, 0 to disable.
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// Whether the solver stacks are captured when a run is reported as possibly hung.
+    #[serde(default)]
+    pub capture_stack: bool,
+    /// Whether the container commands of a run are kept in its history record and run log.
+    #[serde(default)]
+    pub log_commands: bool,
+    /// Whether the output of runs is also written to `.cave-logs/<run id>.log` in the study directory.
+    #[serde(default)]
+    pub log_file: bool,
+    /// Whether cave works without the network, as with `--offline`.
+    #[serde(default)]
+    pub offline: bool,
+    /// Container runtime: `docker`, `podman`, `apptainer`, or `auto` to detect it.
+    #[serde(default = "default_runtime")]
+    pub runtime: String,
+    /// User the run containers run as: `host`, `image` or `UID:GID`.
+    #[serde(default = "default_container_user")]
+    pub container_user: String,
+    /// Environment variables passed to every run: `NAME` for the host value, or `NAME=VALUE`.
+    #[serde(default)]
+    pub forward_env: Vec<String>,
+    /// Environment variables of license servers, injected into every run.
+    #[serde(default)]
+    pub license_env: BTreeMap<String, String>,
+    /// Repositories the versions are looked up and pulled from, in order.
+    #[serde(default = "default_image_sources")]
+    pub image_sources: Vec<String>,
+    /// Destinations of the telemetry of runs: `http`, `file:PATH` or `command:COMMAND`.
+    #[serde(default = "default_telemetry_sinks")]
+    pub telemetry_sinks: Vec<String>,
+    /// Default resource limits of runs.
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Resource presets of runs, keyed by name.
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    /// Translations of run_aster flags overriding the built-in ones, keyed by
+    /// the first major version they apply to, then by flag.
+    #[serde(default)]
+    pub run_args: BTreeMap<u32, BTreeMap<String, RunArg>>,
+    /// Bounds of the Docker Hub tag listings.
+    #[serde(default)]
+    pub hub_budget: HubBudget,
+    /// Minutes the published tags and channel resolutions are cached, an hour if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u32>,
+    /// Size limit of the run logs in MiB, 64 if not set and none if 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_limit_mb: Option<u64>,
+    /// GPUs given to runs, as for `docker run --gpus` (`all`, `device=0,1`, ...).
+    #[serde(default)]
+    pub gpus: Option<String>,
     /// Optional registry configuration for private Docker images.
     pub registry: Option<Registry>,
     ///User_id used for telemetry, generated randomly
@@ -48,26 +322,149 @@ fn default_enable_auto_update() -> bool {
     true
 }
 
+fn default_pin_notice() -> bool {
+    true
+}
+
+/// Registry used when none is given, kept for the configurations written
+/// before the host was configurable.
+pub fn default_registry_host() -> String {
+    "registry.gitlab.com".to_string()
+}
+
+fn default_image_sources() -> Vec<String> {
+    vec!["simvia/code_aster".to_string()]
+}
+
+fn default_telemetry_sinks() -> Vec<String> {
+    vec!["http".to_string()]
+}
+
+fn default_heartbeat_secs() -> u64 {
+    900
+}
+
+fn default_runtime() -> String {
+    "auto".to_string()
+}
+
+fn default_container_user() -> String {
+    "host".to_string()
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             auto_update: false,
             auto_release_check: true,
             version_tracking: true,
+            share_resource_usage: false,
+            pin_notice: true,
+            export_version: false,
+            run_metadata: false,
+            nice_batch: false,
+            heartbeat_secs: default_heartbeat_secs(),
+            capture_stack: false,
+            log_commands: false,
+            log_file: false,
+            offline: false,
+            runtime: default_runtime(),
+            container_user: default_container_user(),
+            forward_env: Vec::new(),
+            license_env: BTreeMap::new(),
+            image_sources: default_image_sources(),
+            telemetry_sinks: default_telemetry_sinks(),
+            limits: ResourceLimits::default(),
+            presets: BTreeMap::new(),
+            run_args: BTreeMap::new(),
+            hub_budget: HubBudget::default(),
+            cache_ttl: None,
+            log_limit_mb: None,
+            gpus: None,
             registry: None,
             user_id: Uuid::new_v4().to_string(),
         }
     }
 }
 
-fn config_path() -> Result<PathBuf, CaveError> {
+/// Returns the path of the user configuration file.
+pub fn config_path() -> Result<PathBuf, CaveError> {
     let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
     Ok(home.join(".caveconfig.json"))
 }
 
+/// Makes cave work offline for the rest of the invocation, for `--offline`.
+pub fn go_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether cave works offline, with `--offline` or `offline = true`
+/// in the configuration: the update check, the tag listings, the downloads
+/// and the HTTP telemetry are skipped, and versions are only resolved
+/// against the installed images and the caches.
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed) || read_config().is_ok_and(|cfg| cfg.offline)
+}
+
+/// Fails when cave works offline, `operation` telling what needed the network.
+///
+/// # Errors
+/// Returns [`CaveError::Offline`] in offline mode.
+pub fn ensure_online(operation: &str) -> Result<(), CaveError> {
+    if offline() {
+        return Err(CaveError::Offline(operation.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns whether cave runs in read-only mode, never writing to the home
+/// directory: with `CAVE_READ_ONLY` set to `1`, `true` or `yes`, or with
+/// `read_only_home = true` in [`POLICY_FILE`], which users cannot override.
+pub fn read_only_home() -> bool {
+    static READ_ONLY: OnceLock<bool> = OnceLock::new();
+    *READ_ONLY.get_or_init(|| {
+        let from_env = env::var("CAVE_READ_ONLY")
+            .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"));
+        let from_policy = match fs::read_to_string(POLICY_FILE) {
+            Ok(content) => match toml::from_str::<Policy>(&content) {
+                Ok(policy) => policy.read_only_home,
+                Err(e) => {
+                    debug!("Politique système {} ignorée: {}", POLICY_FILE, e);
+                    false
+                }
+            },
+            Err(_) => false,
+        };
+        from_env || from_policy
+    })
+}
+
+/// Returns the directory holding cave's state (run history, caches, ...).
+///
+/// `~/.cave` being the global version file, the state lives in `~/.cave.d`;
+/// in read-only mode, in a temporary directory of the invocation.
+pub fn state_dir() -> Result<PathBuf, CaveError> {
+    if read_only_home() {
+        return Ok(INVOCATION_STATE
+            .get_or_init(|| env::temp_dir().join(format!("cave-{}", Uuid::new_v4())))
+            .clone());
+    }
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    Ok(home.join(".cave.d"))
+}
+
+/// Removes the state directory of the invocation in read-only mode, before cave exits.
+pub fn remove_invocation_state() {
+    if let Some(dir) = INVOCATION_STATE.get() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
 /// Reads the user configuration from `~/.caveconfig.json`.
 ///
-/// If the file does not exist, a default configuration is returned.
+/// If the file does not exist, a default configuration is returned. In
+/// read-only mode, the configuration of the invocation is returned, with the
+/// changes made by [`write_config`].
 ///
 /// # Example
 /// ```
@@ -77,17 +474,29 @@ fn config_path() -> Result<PathBuf, CaveError> {
 /// println!("Auto update: {}", cfg.auto_update);
 /// ```
 pub fn read_config() -> Result<Config, CaveError> {
+    let read_only = read_only_home();
+    if read_only {
+        if let Some(config) = INVOCATION_CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+            return Ok(config);
+        }
+    }
     let path = config_path()?;
     if !path.exists() {
         let config = Config::default();
         write_config(&config)?;
         return Ok(config);
     }
-    let content = fs::read_to_string(path)?;
-    Ok(serde_json::from_str(&content).map_err(CaveError::SerdeError)?)
+    let context = || format!("Reading {}", path.display());
+    let content = fs::read_to_string(&path).with_context(context)?;
+    let config: Config = serde_json::from_str(&content).with_context(context)?;
+    if read_only {
+        *INVOCATION_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config.clone());
+    }
+    Ok(config)
 }
 
-/// Writes the given configuration to `~/.caveconfig.json`.
+/// Writes the given configuration to `~/.caveconfig.json`, or keeps it for
+/// the invocation in read-only mode.
 ///
 /// # Example
 /// ```
@@ -97,10 +506,13 @@ pub fn read_config() -> Result<Config, CaveError> {
 /// write_config(&cfg).expect("Failed to write config");
 /// ```
 pub fn write_config(config: &Config) -> Result<(), CaveError> {
+    if read_only_home() {
+        *INVOCATION_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config.clone());
+        return Ok(());
+    }
     let path = config_path()?;
     let content = serde_json::to_string_pretty(config).map_err(CaveError::SerdeError)?;
-    fs::write(path, content)?;
-    Ok(())
+    fs::write(&path, content).with_context(|| format!("Writing {}", path.display()))
 }
 
 /// Enables or disables automatic update checks globally.
@@ -145,28 +557,460 @@ pub fn set_version_tracking(value: bool) -> Result<(), CaveError> {
     write_config(&cfg)
 }
 
-// TODO : uncomment to have registry option
-//
-// /// Sets the Docker registry configuration.
-// ///
-// /// Pass `None` to remove any existing registry settings.
-// ///
-// /// # Example
-// /// ```
-// /// use cave::config::{set_registry, Registry};
-// ///
-// /// let registry = Registry {
-// ///     repo: "docker.io/myrepo".to_string(),
-// ///     user: "username".to_string(),
-// ///     token: "mytoken".to_string(),
-// /// };
-// /// set_registry(Some(registry)).expect("Failed to set registry");
-// /// ```
-// pub fn set_registry(registry: Option<Registry>) -> Result<(), CaveError> {
-//     let mut cfg = read_config()?;
-//     cfg.registry = registry;
-//     write_config(&cfg)
-// }
+/// Enables or disables the sharing of run resource usage (peak memory, CPU time) in telemetry.
+///
+/// # Example
+/// ```
+/// use cave::config::set_share_resource_usage;
+///
+/// set_share_resource_usage(true).expect("Failed to update setting");
+/// ```
+pub fn set_share_resource_usage(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.share_resource_usage = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the notice printed when a local pin shadows the global version.
+///
+/// # Example
+/// ```
+/// use cave::config::set_pin_notice;
+///
+/// set_pin_notice(false).expect("Failed to update setting");
+/// ```
+pub fn set_pin_notice(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.pin_notice = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the use of the version declared by the export file of a run.
+///
+/// # Example
+/// ```
+/// use cave::config::set_export_version;
+///
+/// set_export_version(true).expect("Failed to update setting");
+/// ```
+pub fn set_export_version(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.export_version = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the `.cave-run.json` provenance file written next to the results.
+///
+/// # Example
+/// ```
+/// use cave::config::set_run_metadata;
+///
+/// set_run_metadata(true).expect("Failed to update setting");
+/// ```
+pub fn set_run_metadata(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.run_metadata = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the low priority of runs started without a terminal.
+///
+/// # Example
+/// ```
+/// use cave::config::set_nice_batch;
+///
+/// set_nice_batch(true).expect("Failed to update setting");
+/// ```
+pub fn set_nice_batch(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.nice_batch = value;
+    write_config(&cfg)
+}
+
+/// Sets the silence, in seconds, after which a run is reported as possibly hung (0 disables the check).
+///
+/// # Example
+/// ```
+/// use cave::config::set_heartbeat;
+///
+/// set_heartbeat(1800).expect("Failed to update setting");
+/// ```
+pub fn set_heartbeat(seconds: u64) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.heartbeat_secs = seconds;
+    write_config(&cfg)
+}
+
+/// Enables or disables the capture of the solver stacks when a run is reported as possibly hung.
+///
+/// # Example
+/// ```
+/// use cave::config::set_capture_stack;
+///
+/// set_capture_stack(true).expect("Failed to update setting");
+/// ```
+pub fn set_capture_stack(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.capture_stack = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the log of the container commands of runs.
+///
+/// # Example
+/// ```
+/// use cave::config::set_log_commands;
+///
+/// set_log_commands(true).expect("Failed to update setting");
+/// ```
+pub fn set_log_commands(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.log_commands = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the log of the output of every run in its study directory.
+///
+/// # Example
+/// ```
+/// use cave::config::set_log_file;
+///
+/// set_log_file(true).expect("Failed to update setting");
+/// ```
+pub fn set_log_file(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.log_file = value;
+    write_config(&cfg)
+}
+
+/// Enables or disables the offline mode for every invocation, as with `--offline`.
+///
+/// # Example
+/// ```
+/// use cave::config::set_offline;
+///
+/// set_offline(true).expect("Failed to update setting");
+/// ```
+pub fn set_offline(value: bool) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.offline = value;
+    write_config(&cfg)
+}
+
+/// Sets the container runtime: `docker`, `podman`, `apptainer`, or `auto` to detect it.
+///
+/// # Example
+/// ```
+/// use cave::config::set_runtime;
+///
+/// set_runtime("podman").expect("Failed to update setting");
+/// ```
+pub fn set_runtime(runtime: &str) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.runtime = runtime.to_string();
+    write_config(&cfg)
+}
+
+/// Sets the user the run containers run as: `host` (default), `image` or `UID:GID`.
+///
+/// # Example
+/// ```
+/// use cave::config::set_container_user;
+///
+/// set_container_user("image").expect("Failed to update setting");
+/// ```
+pub fn set_container_user(user: &str) -> Result<(), CaveError> {
+    ContainerUser::parse(user)?;
+    let mut cfg = read_config()?;
+    cfg.container_user = user.to_string();
+    write_config(&cfg)
+}
+
+/// Sets a license server environment variable injected into every run, from a `NAME=VALUE` assignment.
+///
+/// # Example
+/// ```
+/// use cave::config::set_license_env;
+///
+/// set_license_env("LM_LICENSE_FILE=27000@licsrv").expect("Failed to update setting");
+/// ```
+pub fn set_license_env(assignment: &str) -> Result<(), CaveError> {
+    let (name, value) = assignment
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| CaveError::InvalidAssignment(assignment.to_string()))?;
+    let mut cfg = read_config()?;
+    cfg.license_env.insert(name.to_string(), value.to_string());
+    write_config(&cfg)
+}
+
+/// Removes a license server environment variable.
+///
+/// # Example
+/// ```
+/// use cave::config::unset_license_env;
+///
+/// unset_license_env("LM_LICENSE_FILE").expect("Failed to update setting");
+/// ```
+pub fn unset_license_env(name: &str) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.license_env.remove(name);
+    write_config(&cfg)
+}
+
+/// Translates a run_aster flag into `option` for code_aster `major` and the
+/// later versions, scaling its value by `scale`.
+///
+/// # Example
+/// ```
+/// use cave::config::set_run_arg;
+///
+/// set_run_arg(15, "--memory-limit", "--memjeveux", 0.125).expect("Failed to update setting");
+/// ```
+pub fn set_run_arg(major: u32, flag: &str, option: &str, scale: f64) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.run_args.entry(major).or_default().insert(
+        format!("--{}", flag.trim_start_matches('-')),
+        RunArg {
+            option: option.to_string(),
+            scale,
+        },
+    );
+    write_config(&cfg)
+}
+
+/// Removes the translation of a run_aster flag set for code_aster `major`,
+/// restoring the built-in one.
+///
+/// # Example
+/// ```
+/// use cave::config::unset_run_arg;
+///
+/// unset_run_arg(15, "--memory-limit").expect("Failed to update setting");
+/// ```
+pub fn unset_run_arg(major: u32, flag: &str) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    let flag = format!("--{}", flag.trim_start_matches('-'));
+    if let Some(args) = cfg.run_args.get_mut(&major) {
+        args.remove(&flag);
+        if args.is_empty() {
+            cfg.run_args.remove(&major);
+        }
+    }
+    write_config(&cfg)
+}
+
+/// Sets the default resource limits of runs; `cave run` options and presets take precedence.
+///
+/// # Example
+/// ```
+/// use cave::config::{set_limits, ResourceLimits};
+///
+/// let limits = ResourceLimits { cpus: Some(8.0), memory: Some("16g".to_string()), ..Default::default() };
+/// set_limits(limits).expect("Failed to update setting");
+/// ```
+pub fn set_limits(limits: ResourceLimits) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.limits = limits;
+    write_config(&cfg)
+}
+
+/// Sets the bounds of the Docker Hub tag listings of `cave available`.
+///
+/// # Example
+/// ```
+/// use cave::config::{set_hub_budget, HubBudget};
+///
+/// let budget = HubBudget { page_size: 50, max_pages: Some(2), ..Default::default() };
+/// set_hub_budget(budget).expect("Failed to update setting");
+/// ```
+pub fn set_hub_budget(budget: HubBudget) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.hub_budget = budget;
+    write_config(&cfg)
+}
+
+/// Sets the GPUs given to runs by default, `None` for none; `cave run --gpus` takes precedence.
+///
+/// # Example
+/// ```
+/// use cave::config::set_gpus;
+///
+/// set_gpus(Some("all".to_string())).expect("Failed to update setting");
+/// ```
+pub fn set_gpus(spec: Option<String>) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.gpus = spec;
+    write_config(&cfg)
+}
+
+/// Sets how long the published tags and channel resolutions are cached, in
+/// minutes; `None` restores the default of an hour and `0` disables the cache.
+///
+/// # Example
+/// ```
+/// use cave::config::set_cache_ttl;
+///
+/// set_cache_ttl(Some(1440)).expect("Failed to update setting");
+/// ```
+pub fn set_cache_ttl(minutes: Option<u32>) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.cache_ttl = minutes;
+    write_config(&cfg)
+}
+
+/// Sets the size limit of the run logs, in MiB; `None` restores the default
+/// of 64 MiB and `0` removes the limit.
+///
+/// # Example
+/// ```
+/// use cave::config::set_log_limit;
+///
+/// set_log_limit(Some(256)).expect("Failed to update setting");
+/// ```
+pub fn set_log_limit(mb: Option<u64>) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.log_limit_mb = mb;
+    write_config(&cfg)
+}
+
+/// Defines or replaces a resource preset of runs.
+///
+/// # Example
+/// ```
+/// use cave::config::{set_preset, Preset, ResourceLimits};
+///
+/// let limits = ResourceLimits { cpus: Some(32.0), memory: Some("120g".to_string()), ..Default::default() };
+/// let preset = Preset { limits, ..Default::default() };
+/// set_preset("big", preset).expect("Failed to update setting");
+/// ```
+pub fn set_preset(name: &str, preset: Preset) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.presets.insert(name.to_string(), preset);
+    write_config(&cfg)
+}
+
+/// Removes a resource preset of runs.
+///
+/// # Example
+/// ```
+/// use cave::config::remove_preset;
+///
+/// remove_preset("big").expect("Failed to update setting");
+/// ```
+pub fn remove_preset(name: &str) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.presets.remove(name);
+    write_config(&cfg)
+}
+
+/// Returns a resource preset of runs.
+///
+/// # Errors
+/// Returns [`CaveError::UnknownPreset`] if no preset has this name.
+pub fn preset(name: &str) -> Result<Preset, CaveError> {
+    read_config()?
+        .presets
+        .remove(name)
+        .ok_or_else(|| CaveError::UnknownPreset(name.to_string()))
+}
+
+/// Sets the environment variables passed to every run: `NAME` for the value
+/// of the host, or `NAME=VALUE`.
+///
+/// # Example
+/// ```
+/// use cave::config::set_forward_env;
+///
+/// set_forward_env(vec!["HTTPS_PROXY".to_string(), "OMP_NUM_THREADS=1".to_string()])
+///     .expect("Failed to update setting");
+/// ```
+pub fn set_forward_env(variables: Vec<String>) -> Result<(), CaveError> {
+    env_args(&variables)?;
+    let mut cfg = read_config()?;
+    cfg.forward_env = variables;
+    write_config(&cfg)
+}
+
+/// Returns the `docker run` arguments passing environment variables given
+/// as `NAME=VALUE`, or `NAME` for the value of the host.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidAssignment`] if a name is empty or contains spaces.
+pub fn env_args(variables: &[String]) -> Result<Vec<String>, CaveError> {
+    let mut args = Vec::new();
+    for variable in variables {
+        let name = variable.split_once('=').map_or(variable.as_str(), |(name, _)| name);
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(CaveError::InvalidAssignment(variable.clone()));
+        }
+        args.extend(["-e".to_string(), variable.clone()]);
+    }
+    Ok(args)
+}
+
+/// Sets the repositories the versions are looked up and pulled from, in order.
+///
+/// # Example
+/// ```
+/// use cave::config::set_image_sources;
+///
+/// set_image_sources(vec!["harbor.internal/code_aster".to_string(), "simvia/code_aster".to_string()])
+///     .expect("Failed to update setting");
+/// ```
+pub fn set_image_sources(sources: Vec<String>) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.image_sources = sources;
+    write_config(&cfg)
+}
+
+/// Sets the destinations of the telemetry of runs (see [`TelemetrySink`]),
+/// none to keep it from leaving cave.
+///
+/// # Example
+/// ```
+/// use cave::config::set_telemetry_sinks;
+///
+/// set_telemetry_sinks(vec!["file:/var/log/cave/runs.jsonl".to_string()])
+///     .expect("Failed to update setting");
+/// ```
+pub fn set_telemetry_sinks(sinks: Vec<String>) -> Result<(), CaveError> {
+    for sink in &sinks {
+        TelemetrySink::parse(sink)?;
+    }
+    let mut cfg = read_config()?;
+    cfg.telemetry_sinks = sinks;
+    write_config(&cfg)
+}
+
+/// Sets the Docker registry configuration.
+///
+/// Pass `None` to remove any existing registry settings. As the file then
+/// holds the access token, it is made readable by its owner only.
+///
+/// # Example
+/// ```
+/// use cave::config::{set_registry, Registry};
+///
+/// let registry = Registry {
+///     host: "registry.gitlab.com".to_string(),
+///     repo: "mygroup/code_aster".to_string(),
+///     user: "username".to_string(),
+///     token: "mytoken".to_string(),
+/// };
+/// set_registry(Some(registry)).expect("Failed to set registry");
+/// ```
+pub fn set_registry(registry: Option<Registry>) -> Result<(), CaveError> {
+    let mut cfg = read_config()?;
+    cfg.registry = registry;
+    write_config(&cfg)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(config_path()?, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
 
 pub fn read_user_id() -> Result<String, CaveError> {
     let mut config = read_config()?;
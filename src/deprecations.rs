@@ -0,0 +1,166 @@
+//! Deprecated and end-of-life code_aster versions.
+//!
+//! The list is published in `deprecations.json` at the root of the cave
+//! repository, fetched from [`URL`] and cached in
+//! `~/.cave.d/deprecations.json` for [`MAX_AGE_SECS`]. When it cannot be
+//! fetched, the cached list is used, or no version is reported: the
+//! warnings never keep a version from being pinned or run.
+//!
+//! ```json
+//! {"versions": [
+//!   {"version": "15", "end_of_support": "2026-06-30", "replacement": "17.2.4"},
+//!   {"version": "16.4.2", "replacement": "16.4.12"}
+//! ]}
+//! ```
+
+use crate::config::{offline, state_dir};
+use crate::manage::CaveError;
+use chrono::Utc;
+use colored::*;
+use log::debug;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Published list of the deprecated versions.
+pub const URL: &str = "https://raw.githubusercontent.com/simvia-tech/cave/main/deprecations.json";
+/// Age after which the list is fetched again, in seconds.
+pub const MAX_AGE_SECS: i64 = 86400;
+
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// A deprecated version, or series of versions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deprecation {
+    /// Tag, or series such as `15` or `16.4` covering the tags it starts.
+    pub version: String,
+    /// Last day of support (`YYYY-MM-DD`), after which the version is end-of-life.
+    pub end_of_support: Option<String>,
+    /// Version to migrate to.
+    pub replacement: Option<String>,
+}
+
+impl Deprecation {
+    /// Returns `true` once the end of support is past.
+    pub fn end_of_life(&self) -> bool {
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        self.end_of_support.as_ref().is_some_and(|date| *date < today)
+    }
+
+    /// Describes the support of the version, such as `deprecated until 2026-06-30`.
+    pub fn status(&self) -> String {
+        match &self.end_of_support {
+            Some(date) if self.end_of_life() => format!("end of life since {}", date),
+            Some(date) => format!("deprecated until {}", date),
+            None => "deprecated".to_string(),
+        }
+    }
+
+    fn covers(&self, version: &str) -> bool {
+        // Flavors such as 17.2.0-mpi follow their version
+        let base = version.split('-').next().unwrap_or(version);
+        base == self.version || base.starts_with(&format!("{}.", self.version))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct List {
+    versions: Vec<Deprecation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cache {
+    /// When the list was last fetched, or its fetch attempted, as a Unix timestamp.
+    fetched: i64,
+    versions: Vec<Deprecation>,
+}
+
+fn path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("deprecations.json"))
+}
+
+fn read() -> Option<Cache> {
+    let content = fs::read_to_string(path().ok()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn fetch() -> Result<Vec<Deprecation>, reqwest::Error> {
+    let client = Client::builder().timeout(Duration::from_secs(2)).build()?;
+    let list: List = client.get(URL).send()?.error_for_status()?.json()?;
+    Ok(list.versions)
+}
+
+/// Returns the deprecated versions, from the cache while it is recent, or
+/// whatever its age when offline.
+pub fn list() -> Vec<Deprecation> {
+    let cached = read();
+    if offline() {
+        return cached.map(|c| c.versions).unwrap_or_default();
+    }
+    if let Some(cache) = cached.as_ref().filter(|c| Utc::now().timestamp() - c.fetched < MAX_AGE_SECS) {
+        return cache.versions.clone();
+    }
+    let versions = match fetch() {
+        Ok(versions) => versions,
+        Err(e) => {
+            debug!("Liste des versions dépréciées indisponible: {}", e);
+            cached.map(|c| c.versions).unwrap_or_default()
+        }
+    };
+    // A failed fetch is also recorded, so that offline runs do not wait for it each time
+    let cache = Cache {
+        fetched: Utc::now().timestamp(),
+        versions,
+    };
+    let written = path().and_then(|path| {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&cache)?)?;
+        Ok(())
+    });
+    if let Err(e) = written {
+        debug!("Liste des versions dépréciées non enregistrée: {}", e);
+    }
+    cache.versions
+}
+
+/// Returns the deprecation of `version` in `list`, the most specific entry
+/// winning.
+pub fn find<'a>(list: &'a [Deprecation], version: &str) -> Option<&'a Deprecation> {
+    list.iter()
+        .filter(|d| d.covers(version))
+        .max_by_key(|d| d.version.len())
+}
+
+/// Warns on stderr, once per invocation, when `version` is deprecated.
+pub fn warn(version: &str) {
+    if WARNED.load(Ordering::Relaxed) {
+        return;
+    }
+    let list = list();
+    let Some(deprecation) = find(&list, version) else {
+        return;
+    };
+    WARNED.store(true, Ordering::Relaxed);
+    let support = match &deprecation.end_of_support {
+        Some(date) if deprecation.end_of_life() => format!("reached its end of life on {}", date),
+        Some(date) => format!("is deprecated, supported until {}", date),
+        None => "is deprecated".to_string(),
+    };
+    let replacement = deprecation
+        .replacement
+        .as_ref()
+        .map(|r| format!(" Consider migrating to {}.", r))
+        .unwrap_or_default();
+    eprintln!(
+        "{} code_aster {} {}.{}",
+        "Warning:".yellow().bold(),
+        version,
+        support,
+        replacement
+    );
+}
@@ -3,29 +3,290 @@
 //! This module handles interacting with Docker images, checking for local
 //! and remote versions of code_aster, pulling images, running
 //! images, and managing registry authentication.
+//!
+//! Containers are run with Docker or Podman, behind the [`ContainerRuntime`]
+//! trait; see [`runtime`] for the selection. On HPC hosts with neither, the
+//! images are run with Apptainer (see [`uses_apptainer`]).
+//!
+//! With Docker, images are managed through the Engine API (see
+//! [`crate::engine`]); containers are run with the CLI.
 
-use crate::manage::CaveError;
+use crate::manage::{CaveError, ErrorContext};
 use std::process::{Command, Stdio};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use std::io::ErrorKind;
-use chrono::{Local, Offset};
-use crate::config::{read_user_id};
-use crate::telemetry::{send_execution_data, ExecutionData};
+use std::io::{self, ErrorKind, IsTerminal};
+use chrono::{DateTime, FixedOffset, Local, Offset};
+use crate::answers::{self, Prompt};
+use crate::apptainer;
+use crate::catalog;
+use crate::cgroup::CgroupSampler;
+use crate::cmdlog::{self, Logged};
+use crate::config::{ensure_online, read_config, read_user_id, ContainerUser, HubBudget, Registry};
+use crate::engine;
+use crate::heartbeat::Heartbeat;
+use crate::history::{self, Invocation, RunRecord};
+use crate::explain;
+use crate::extractors;
+use crate::jobs;
+use crate::output;
+use crate::placement::Placement;
+use crate::progress::{self, Spinner};
+use crate::project::ExtractorConfig;
+use crate::results;
+use crate::signals::{self, Operation};
+use crate::sources;
+use crate::telemetry::{self, ExecutionData};
+use colored::*;
+use uuid::Uuid;
 use log::debug;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 
 #[cfg(unix)]
-use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+
+/// A container runtime running the code_aster images.
+///
+/// Both runtimes share the Docker command line; the trait covers the
+/// differences.
+pub trait ContainerRuntime: Send + Sync {
+    /// Name of the runtime binary.
+    fn binary(&self) -> &'static str;
+
+    /// Returns a command running the runtime.
+    fn command(&self) -> Command {
+        Command::new(self.binary())
+    }
+
+    /// Returns a command running the runtime of a remote host, over SSH.
+    fn remote_command(&self, host: &str) -> Command;
+
+    /// Returns a command running `subcommand` with the registry credentials
+    /// kept in `dir`, instead of the configuration of the user.
+    fn auth_command(&self, dir: &Path, subcommand: &str) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("--config").arg(dir).arg(subcommand);
+        cmd
+    }
+
+    /// Arguments of the `info` command printing the server version.
+    fn info_args(&self) -> &'static [&'static str];
+
+    /// Returns the reference an image is pulled by.
+    fn pull_reference(&self, image: &str) -> String {
+        image.to_string()
+    }
+
+    /// Returns the `run` arguments making files created in the container
+    /// belong to the given user.
+    fn user_args(&self, uid: u32, gid: u32) -> Vec<String> {
+        vec!["--user".to_string(), format!("{}:{}", uid, gid)]
+    }
+
+    /// Returns the `ps --format` template of a label value.
+    fn label_template(&self, label: &str) -> String {
+        format!("{{{{.Label \"{}\"}}}}", label)
+    }
+}
+
+/// The Docker runtime (default).
+pub struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn binary(&self) -> &'static str {
+        "docker"
+    }
+
+    fn remote_command(&self, host: &str) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("-H").arg(format!("ssh://{}", host));
+        cmd
+    }
+
+    fn info_args(&self) -> &'static [&'static str] {
+        &["info", "--format", "{{.ServerVersion}}"]
+    }
+}
+
+/// The daemonless Podman runtime, usually rootless.
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn binary(&self) -> &'static str {
+        "podman"
+    }
+
+    fn auth_command(&self, dir: &Path, subcommand: &str) -> Command {
+        let mut cmd = self.command();
+        cmd.arg(subcommand).arg("--authfile").arg(dir.join("auth.json"));
+        cmd
+    }
+
+    fn remote_command(&self, host: &str) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("--url").arg(format!("ssh://{}", host));
+        cmd
+    }
 
-// TODO : uncomment to have registry option
-// use regex::Regex;
-// use crate::config::Registry;
+    fn info_args(&self) -> &'static [&'static str] {
+        &["info", "--format", "{{.Version.Version}}"]
+    }
 
+    // Podman does not resolve short names without a configured registry
+    fn pull_reference(&self, image: &str) -> String {
+        sources::qualified(image)
+    }
+
+    // In rootless mode, the host user is only kept with its own user namespace
+    fn user_args(&self, uid: u32, gid: u32) -> Vec<String> {
+        vec![
+            "--userns=keep-id".to_string(),
+            "--user".to_string(),
+            format!("{}:{}", uid, gid),
+        ]
+    }
+
+    fn label_template(&self, label: &str) -> String {
+        format!("{{{{index .Labels \"{}\"}}}}", label)
+    }
+}
+
+static RUNTIME: OnceLock<&'static dyn ContainerRuntime> = OnceLock::new();
+static DOCKER_ANSWERS: OnceLock<bool> = OnceLock::new();
+
+/// Returns `true` if the Docker daemon answers, probed once for the runtime detection.
+fn docker_answers() -> bool {
+    *DOCKER_ANSWERS.get_or_init(|| matches!(probe(&Docker), DockerHealth::Ok))
+}
+
+fn binary_works(binary: &str) -> bool {
+    Command::new(binary)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Returns the container runtime, chosen once per invocation.
+///
+/// The `runtime` setting (`cave config set-runtime`) selects Docker or
+/// Podman; with `auto` (the default), Docker is used if its daemon answers,
+/// Podman if it is installed, Docker otherwise.
+pub fn runtime() -> &'static dyn ContainerRuntime {
+    *RUNTIME.get_or_init(|| {
+        let setting = read_config().map(|c| c.runtime).unwrap_or_default();
+        let runtime: &'static dyn ContainerRuntime = match setting.as_str() {
+            "docker" => &Docker,
+            "podman" => &Podman,
+            _ if docker_answers() => &Docker,
+            _ if binary_works("podman") => &Podman,
+            _ => &Docker,
+        };
+        debug!("Runtime de conteneurs: {}", runtime.binary());
+        runtime
+    })
+}
+
+/// Returns `true` when images are managed through the Docker Engine API:
+/// with Docker, unless its endpoint is only reachable by the CLI.
+fn uses_engine() -> bool {
+    !uses_apptainer() && runtime().binary() == Docker.binary() && engine::supported()
+}
+
+static APPTAINER: OnceLock<bool> = OnceLock::new();
+
+/// Returns `true` when versions are run with Apptainer (see [`crate::apptainer`])
+/// rather than a Docker-compatible runtime: when the `runtime` setting is
+/// `apptainer`, or with `auto` when neither Docker nor Podman is usable.
+pub fn uses_apptainer() -> bool {
+    *APPTAINER.get_or_init(|| {
+        match read_config().map(|c| c.runtime).unwrap_or_default().as_str() {
+            "apptainer" => true,
+            "auto" => {
+                !docker_answers()
+                    && !binary_works("podman")
+                    && apptainer::binary().is_some()
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Outcome of the Docker connection probe.
+#[derive(Debug, Clone, Copy)]
+enum DockerHealth {
+    Ok,
+    Missing,
+    DaemonNotRunning,
+    PermissionDenied,
+}
+
+static DOCKER_HEALTH: OnceLock<DockerHealth> = OnceLock::new();
+
+fn probe(runtime: &dyn ContainerRuntime) -> DockerHealth {
+    let _spinner = Spinner::start(format!("Waiting for {}", runtime.binary()));
+    if runtime.binary() == Docker.binary() && engine::supported() {
+        return match engine::ping() {
+            Ok(()) => DockerHealth::Ok,
+            Err(CaveError::NoDocker) => DockerHealth::Missing,
+            Err(CaveError::DockerPermissionDenied) => DockerHealth::PermissionDenied,
+            Err(e) => {
+                debug!("Le démon Docker ne répond pas: {}", e);
+                DockerHealth::DaemonNotRunning
+            }
+        };
+    }
+    let output = match runtime.command().args(runtime.info_args()).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => return DockerHealth::Missing,
+        Err(e) => {
+            debug!("Impossible de lancer `docker info`: {}", e);
+            return DockerHealth::DaemonNotRunning;
+        }
+    };
+    if output.status.success() {
+        return DockerHealth::Ok;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+    debug!("`docker info` a échoué: {}", stderr);
+    if stderr.contains("permission denied") {
+        DockerHealth::PermissionDenied
+    } else {
+        DockerHealth::DaemonNotRunning
+    }
+}
+
+/// Checks, once per invocation, that Docker is installed and its daemon reachable.
+///
+/// # Errors
+/// - [`CaveError::NoDocker`] if the `docker` binary is missing.
+/// - [`CaveError::DockerDaemonNotRunning`] if the daemon cannot be reached.
+/// - [`CaveError::DockerPermissionDenied`] if the user cannot access the docker socket.
+pub fn ensure_docker() -> Result<(), CaveError> {
+    if uses_apptainer() {
+        return apptainer::binary().map(|_| ()).ok_or(CaveError::NoDocker);
+    }
+    match *DOCKER_HEALTH.get_or_init(|| probe(runtime())) {
+        DockerHealth::Ok => Ok(()),
+        DockerHealth::Missing => Err(CaveError::NoDocker),
+        DockerHealth::DaemonNotRunning => Err(CaveError::DockerDaemonNotRunning),
+        DockerHealth::PermissionDenied => Err(CaveError::DockerPermissionDenied),
+    }
+}
 
 /// Returns a list of locally code_aster Docker image tags.
 ///
 /// # Errors
 /// Returns [`CaveError::NoDocker`] if Docker is not installed,
+/// the errors of [`ensure_docker`] if the daemon is unreachable,
 /// [`CaveError::DockerError`] if the `docker images` command fails.
 ///
 /// # Example
@@ -34,12 +295,23 @@ use std::os::unix::fs::MetadataExt;
 /// println!("Local versions: {:?}", versions);
 /// ```
 pub fn local_versions() -> Result<Vec<String>, CaveError> {
-    let output = Command::new("docker")
+    ensure_docker()?;
+    if uses_apptainer() {
+        return apptainer::local_versions();
+    }
+    if uses_engine() {
+        return Ok(engine::local_versions_created()?
+            .into_iter()
+            .map(|(version, _)| version)
+            .collect());
+    }
+    let output = runtime().command()
         .arg("images")
         .arg("--filter")
         .arg("reference=simvia/code_aster")
         .arg("--format")
         .arg("{{.Tag}}")
+        .logged()
         .output()
         .map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
@@ -50,9 +322,10 @@ pub fn local_versions() -> Result<Vec<String>, CaveError> {
         })?;
 
     if !output.status.success() {
-        return Err(CaveError::DockerError(
-            "Failed to run `docker images`.".into(),
-        ));
+        return Err(CaveError::DockerError(format!(
+            "Failed to run `docker images`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -68,6 +341,96 @@ pub fn local_versions() -> Result<Vec<String>, CaveError> {
 }
 
 
+/// Returns the local versions with the creation date of their image.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if `docker images` fails.
+pub fn local_versions_created() -> Result<Vec<(String, DateTime<FixedOffset>)>, CaveError> {
+    ensure_docker()?;
+    if uses_apptainer() {
+        return Ok(apptainer::local_versions()?
+            .into_iter()
+            .filter_map(|version| {
+                let modified = std::fs::metadata(apptainer::sif_path(&version).ok()?).ok()?.modified().ok()?;
+                Some((version, DateTime::<chrono::Utc>::from(modified).fixed_offset()))
+            })
+            .collect());
+    }
+    if uses_engine() {
+        return engine::local_versions_created();
+    }
+    let output = runtime().command()
+        .arg("images")
+        .arg("--filter")
+        .arg("reference=simvia/code_aster")
+        .arg("--format")
+        .arg("{{.Tag}}\t{{.CreatedAt}}")
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to run `docker images`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // CreatedAt looks like "2025-03-12 10:41:07 +0100 CET"
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (tag, created) = line.split_once('\t')?;
+            let created = created.rsplit_once(' ').map_or(created, |(date, _)| date);
+            let created = DateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S %z").ok()?;
+            Some((tag.trim().to_string(), created))
+        })
+        .collect())
+}
+
+/// Returns the disk space of the local code_aster images, in bytes.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if the images cannot be inspected.
+pub fn images_size() -> Result<u64, CaveError> {
+    ensure_docker()?;
+    if uses_apptainer() {
+        return apptainer::images_size();
+    }
+    if uses_engine() {
+        return engine::images_size();
+    }
+    let output = runtime().command()
+        .args(["images", "-q", "--filter", "reference=simvia/code_aster"])
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to run `docker images`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let mut ids: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let output = runtime().command()
+        .args(["image", "inspect", "--format", "{{.Size}}"])
+        .args(&ids)
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to run `docker image inspect`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.trim().parse::<u64>().ok())
+        .sum())
+}
 
 /// Checks if a specific version exists locally.
 ///
@@ -100,62 +463,171 @@ struct TagsResponse {
     next: Option<String>,
 }
 
-/// Returns a list of remote `simvia/code_aster` Docker image tags.
-/// 
-/// If there is a registry in the user's config, we return additionnaly those in the registry
+/// Returns a list of remote code_aster Docker image tags, from the image
+/// sources (see [`crate::sources`]) in order.
+///
+/// A tag found in several sources is listed once, with the date of the
+/// first one. Unreachable sources are skipped. The versions of the private
+/// registry are listed by [`registry_versions`].
 ///
 /// # Errors
-/// Returns [`CaveError::HttpError`] if the request fails or cannot be parsed.
+/// Returns [`CaveError::HttpError`] if no source can be reached.
 ///
 /// # Example
 /// ```
-/// let versions = remote_versions().expect("Failed to fetch remote versions");
+/// let versions = remote_versions(&HubBudget::default()).expect("Failed to fetch remote versions");
 /// for (tag, date) in versions {
 ///     println!("{} pushed on {}", tag, date);
 /// }
 /// ```
-pub fn remote_versions() -> Result<Vec<(String, String)>, CaveError> {
-    let mut versions = Vec::new();
-    let mut url = "https://hub.docker.com/v2/repositories/simvia/code_aster/tags?page_size=100".to_string();
-
-    loop {
-        let resp = reqwest::blocking::get(&url)
-            .map_err(|e| CaveError::HttpError(e.to_string()))?;
+pub fn remote_versions(budget: &HubBudget) -> Result<Vec<(String, String)>, CaveError> {
+    let mut versions: Vec<(String, String)> = Vec::new();
+    let mut error = None;
+    let mut reached = false;
+    for source in sources::configured() {
+        match sources::versions(&source, budget) {
+            Ok(tags) => {
+                reached = true;
+                for (tag, date) in tags {
+                    if !versions.iter().any(|(t, _)| *t == tag) {
+                        versions.push((tag, date));
+                    }
+                }
+            }
+            Err(e) => {
+                debug!("Source {} injoignable: {}", source, e);
+                error = Some(e);
+            }
+        }
+    }
+    match error {
+        Some(e) if !reached => Err(e),
+        _ => Ok(versions),
+    }
+}
 
+/// Fetches a page of the Docker Hub API, the error naming the URL.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the request fails or cannot be parsed.
+fn hub_get<T: DeserializeOwned>(url: &str) -> Result<T, CaveError> {
+    ensure_online("Querying Docker Hub")?;
+    let http_error = |e: reqwest::Error| CaveError::HttpError(e.without_url().to_string());
+    let fetch = || {
+        let resp = reqwest::blocking::get(url).map_err(http_error)?;
         if !resp.status().is_success() {
-            return Err(CaveError::HttpError(format!(
-                "Failed to fetch Docker tags: {}",
-                resp.status()
-            )));
+            return Err(CaveError::HttpError(format!("Docker Hub answered {}", resp.status())));
         }
+        resp.json().map_err(http_error)
+    };
+    fetch().with_context(|| format!("Fetching {}", url))
+}
+
+/// Returns the tags of a Docker Hub repository, such as `simvia/code_aster`,
+/// with their push date, most recent first.
+///
+/// The listing is bounded by `budget` (see `cave config set-hub-budget`); a
+/// note is printed when it is truncated.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the request fails or cannot be parsed.
+pub fn hub_versions(repo: &str, budget: &HubBudget) -> Result<Vec<(String, String)>, CaveError> {
+    let mut versions = Vec::new();
+    let mut url = format!(
+        "https://hub.docker.com/v2/repositories/{}/tags?page_size={}&ordering=last_updated",
+        repo, budget.page_size
+    );
+    let mut pages = 0;
+    let mut truncated = false;
+    let spinner = Spinner::start(format!("Listing the tags of {}", repo));
 
-        let tags_response: TagsResponse =
-            resp.json().map_err(|e| CaveError::HttpError(e.to_string()))?;
+    loop {
+        let tags_response: TagsResponse = hub_get(&url)?;
+        pages += 1;
+        spinner.set_message(format!("Listing the tags of {} (page {})", repo, pages + 1));
 
         for tag in tags_response.results {
             let last_pushed = tag
                 .images
-                .get(0)
+                .first()
                 .and_then(|img| img.last_pushed.clone())
                 .unwrap_or_else(|| "unknown".to_string());
 
             versions.push((tag.name, last_pushed));
         }
 
-        if let Some(next_url) = tags_response.next {
-            url = next_url;
-        } else {
-            break;
+        if let Some(max) = budget.max_tags.map(|max| max as usize) {
+            if versions.len() >= max {
+                truncated = versions.len() > max || tags_response.next.is_some();
+                versions.truncate(max);
+                break;
+            }
+        }
+        match tags_response.next {
+            Some(_) if budget.max_pages.is_some_and(|max| pages >= max) => {
+                truncated = true;
+                break;
+            }
+            Some(next_url) => url = next_url,
+            None => break,
         }
     }
 
+    drop(spinner);
+    if truncated {
+        debug!("Liste des tags de {} tronquée après {} pages", repo, pages);
+        progress::clear();
+        eprintln!(
+            "{} listing of {} truncated to its {} most recent tags (see `cave config set-hub-budget`).",
+            "Note:".cyan().bold(),
+            repo,
+            versions.len()
+        );
+    }
     Ok(versions)
 }
 
-/// Checks if a specific version exists on the Simvia Docker hub or in the private registry.
-/// 
-/// # TO DO :
-/// If there is a registry in the user's config, we look firstly in the private registry
+/// Metadata of a Docker Hub tag.
+#[derive(Debug, Deserialize)]
+pub struct HubTag {
+    /// Date of the last push of the tag.
+    pub tag_last_pushed: Option<String>,
+    /// Digest of the image, or of its index for multi-platform images.
+    pub digest: Option<String>,
+    /// Compressed size of the image, in bytes.
+    pub full_size: Option<u64>,
+}
+
+/// Returns the metadata of a tag of a Docker Hub repository, querying this
+/// tag only; `None` if the tag does not exist.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if Docker Hub cannot be reached.
+pub fn hub_tag(repo: &str, tag: &str) -> Result<Option<HubTag>, CaveError> {
+    ensure_online("Querying Docker Hub")?;
+    let url = format!("https://hub.docker.com/v2/repositories/{}/tags/{}", repo, tag);
+    let http_error = |e: reqwest::Error| CaveError::HttpError(e.without_url().to_string());
+    let fetch = || {
+        let resp = reqwest::blocking::get(&url).map_err(http_error)?;
+        match resp.status() {
+            status if status.is_success() => resp.json().map(Some).map_err(http_error),
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status => Err(CaveError::HttpError(format!("Docker Hub answered {}", status))),
+        }
+    };
+    fetch().with_context(|| format!("Fetching {}", url))
+}
+
+/// Checks if a Docker Hub repository has a tag, querying this tag only.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if Docker Hub cannot be reached.
+pub fn hub_has_tag(repo: &str, tag: &str) -> Result<bool, CaveError> {
+    Ok(hub_tag(repo, tag)?.is_some())
+}
+
+/// Checks if a specific version exists in the private registry, if one is
+/// configured, or in one of the image sources (see [`catalog::contains`]).
 ///
 /// # Example
 /// ```
@@ -163,16 +635,20 @@ pub fn remote_versions() -> Result<Vec<(String, String)>, CaveError> {
 /// println!("Version exists remotely? {}", exists);
 /// ```
 pub fn exists_remotely(version: &str) -> Result<bool, CaveError> {
-    let versions = remote_versions()?;
-    Ok(versions.iter().any(|(tag, _date)| tag == version))
+    if private_source(version).is_some() {
+        return Ok(true);
+    }
+    catalog::contains(version)
 }
 
 
 /// Pulls a specific version of `simvia/code_aster` from the Simvia Docker Hub or in the private registry.
 ///
-/// # TO DO :
-/// If there is a registry in the user's config, we pull firstly in the private registry
-/// 
+/// When a private registry is configured and has the version, it is
+/// preferred; otherwise the image sources are tried in order. Images pulled
+/// from elsewhere than Docker Hub are tagged as `simvia/code_aster:<version>`
+/// like the Docker Hub images, and the source is recorded.
+///
 /// # Errors
 /// Returns [`CaveError::DockerError`] if the pull fails.
 ///
@@ -181,14 +657,102 @@ pub fn exists_remotely(version: &str) -> Result<bool, CaveError> {
 /// pull_version("22.0").expect("Failed to pull version");
 /// ```
 pub fn pull_version(version: &str) -> Result<(), CaveError> {
-    let image = format!("simvia/code_aster:{}", version);
+    ensure_online(&format!("Downloading version {}", version))?;
+    let (registry, source) = version_source(version);
+    debug!("Version {} prise dans {}", version, source);
+    let image = format!("{}:{}", source, version);
+    if uses_apptainer() {
+        apptainer::pull(version, &image, registry.as_ref())?;
+    } else {
+        pull_image(&image)?;
+        if source != sources::DEFAULT_SOURCE {
+            tag_image(&image, version)?;
+            // The image stays referenced by the simvia/code_aster tag
+            untag(&image)?;
+        }
+    }
+    if let Err(e) = sources::record(version, &source) {
+        debug!("Impossible d'enregistrer la source de {}: {}", version, e);
+    }
+    accept_license(version)
+}
+
+/// Image label carrying a license notice to accept before using the version.
+const LICENSE_LABEL: &str = "org.code-aster.license-notice";
 
-    let output = Command::new("docker")
-        .arg("pull")
-        .arg(&image)
+/// Shows the license notice of a pulled version, if its image declares one,
+/// and removes the version when the notice is not accepted.
+///
+/// # Errors
+/// - [`CaveError::Cancelled`] if the notice is not accepted.
+/// - The errors of [`image_labels`] and [`remove_image`].
+fn accept_license(version: &str) -> Result<(), CaveError> {
+    let Some(notice) = image_labels(version)?.remove(LICENSE_LABEL) else {
+        return Ok(());
+    };
+    println!("{}", notice);
+    if answers::confirm(Prompt::License, &format!("Accept the license of version {}?", version))? {
+        return Ok(());
+    }
+    remove_image(version)?;
+    Err(CaveError::Cancelled)
+}
+
+/// Returns the source a version is pulled from: the private registry when
+/// it has the version, else the first image source having it.
+fn version_source(version: &str) -> (Option<Registry>, String) {
+    let registry = private_source(version);
+    let source = match &registry {
+        Some(registry) => format!("{}/{}", registry.host, registry.repo),
+        None => sources::find(version).unwrap_or_else(|| sources::DEFAULT_SOURCE.to_string()),
+    };
+    (registry, source)
+}
+
+/// Returns the image another host pulls a version from, looked up in the
+/// sources like [`pull_version`] does, such as `harbor.internal/code_aster:17.1.0`.
+/// That host must be logged in to the private registry itself.
+pub fn source_image(version: &str) -> String {
+    format!("{}:{}", version_source(version).1, version)
+}
+
+/// Pulls an image by reference (`name:tag` or `name@digest`). Images of the
+/// private registry are pulled with its credentials, logged in to a
+/// temporary configuration so that the credentials of the user are left
+/// untouched (see [`RegistryAuth`]).
+///
+/// # Errors
+/// - [`CaveError::Offline`] in offline mode.
+/// - [`CaveError::NoDocker`] if Docker is not installed.
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::DockerError`] if `docker pull` fails.
+pub fn pull_image(image: &str) -> Result<(), CaveError> {
+    ensure_online(&format!("Downloading {}", image))?;
+    ensure_docker()?;
+    let registry = configured_registry().filter(|r| image.starts_with(&format!("{}/", r.host)));
+    if uses_engine() {
+        return engine::pull(image, registry.as_ref());
+    }
+    let auth = registry.as_ref().map(RegistryAuth::login).transpose()?;
+    pull_with_cli(image, auth.as_ref())
+}
+
+fn pull_with_cli(image: &str, auth: Option<&RegistryAuth>) -> Result<(), CaveError> {
+    let mut cmd = match auth {
+        Some(auth) => runtime().auth_command(&auth.dir, "pull"),
+        None => {
+            let mut cmd = runtime().command();
+            cmd.arg("pull");
+            cmd
+        }
+    };
+    let mut child = cmd
+        .args(progress::suppressed().then_some("--quiet"))
+        .arg(runtime().pull_reference(image))
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
-        .output()
+        .logged()
+        .spawn()
         .map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
                 CaveError::NoDocker
@@ -197,74 +761,290 @@ pub fn pull_version(version: &str) -> Result<(), CaveError> {
             }
         })?;
 
+    let guard = signals::register(Operation::Process(child.id()));
+    let status = child.wait().map_err(CaveError::IoError)?;
+    drop(guard);
+
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    if !status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to pull image: {}",
+            image
+        )));
+    }
+    Ok(())
+}
+
+/// Tags a local image as the given code_aster version.
+pub fn tag_image(image: &str, version: &str) -> Result<(), CaveError> {
+    let status = runtime().command()
+        .arg("tag")
+        .arg(image)
+        .arg(format!("simvia/code_aster:{}", version))
+        .logged_status()?;
+    if !status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to tag {} as version {}",
+            image, version
+        )));
+    }
+    Ok(())
+}
 
+/// Removes a tag of a local image.
+fn untag(image: &str) -> Result<(), CaveError> {
+    if uses_engine() {
+        return engine::remove(image);
+    }
+    let output = runtime().command().arg("rmi").arg(image).logged_output()?;
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(CaveError::DockerError(format!(
-            "Failed to pull version: {}\n{}",
-            version, stderr
+            "Failed to remove {}: {}",
+            image,
+            String::from_utf8_lossy(&output.stderr).trim()
         )));
     }
     Ok(())
 }
 
+/// Removes the local image of a code_aster version.
+///
+/// The `simvia/code_aster:<version>` tag is removed; the image layers are
+/// deleted once no other tag references them.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if `docker rmi` fails, for instance
+/// when a container still uses the image.
+pub fn remove_image(version: &str) -> Result<(), CaveError> {
+    if uses_apptainer() {
+        apptainer::remove(version)?;
+    } else {
+        untag(&format!("simvia/code_aster:{}", version))?;
+    }
+    sources::forget(version)
+}
 
 pub enum DockerMode<'a> {
     RunAster { export_file: &'a Option<String>, args: &'a Vec<String> },
     Shell,
+    Exec { command: &'a [String] },
+}
+
+/// Options of a `docker run` invocation that do not depend on the mode.
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    /// Host directory mounted as the working directory (defaults to the current directory).
+    pub host_dir: Option<PathBuf>,
+    /// Copy the study to a local scratch directory before running.
+    pub stage_local: bool,
+    /// User-given run name, used for the container name and history lookups.
+    pub name: Option<String>,
+    /// Run identifier, generated by [`docker_aster`] when not set.
+    pub run_id: Option<String>,
+    /// Run even if another run holds the study lock.
+    pub force: bool,
+    /// Extra arguments given to `docker run`, before the image name.
+    pub docker_args: Vec<String>,
+    /// CPUs and NUMA node the container is pinned to.
+    pub placement: Option<Placement>,
+    /// Run with low CPU and block I/O priority.
+    pub nice: bool,
+    /// Result extractors of the project, run after the run.
+    pub extractors: BTreeMap<String, ExtractorConfig>,
+    /// Number of MPI processes, for MPI-parallel runs.
+    pub mpi: Option<u32>,
+    /// Run on the hosts of the `[cluster]` section of `cave.toml` (experimental).
+    pub cluster: bool,
+    /// Restart from databases produced by another code_aster version.
+    pub allow_version_mismatch: bool,
+    /// Only echo the output lines matching this pattern, the whole output going to the run log.
+    pub filter: Option<Regex>,
+    /// Also write the output to `.cave-logs/<run id>.log` in the study directory.
+    pub log_file: bool,
+    /// Write the whole output to the logs, whatever their size limit.
+    pub full_log: bool,
+    /// Keep the workspace of the run in `.cave-tmp/<run id>` after it ends.
+    pub keep_temp: bool,
+    /// Download the pinned version again without asking if its image was removed.
+    pub yes: bool,
+    /// Start the container in the background and return without waiting for it.
+    pub detach: bool,
+    /// Start the services of the `[compose]` section of `cave.toml` alongside the run.
+    pub compose: bool,
+    /// Version to run instead of the pinned one.
+    pub version: Option<String>,
+    /// Extra volumes given on the command line, as `HOST:CONTAINER[:ro]`.
+    pub mounts: Vec<String>,
+    /// Pass the standard input of cave to the container, for piped inputs.
+    pub stdin: bool,
+    /// Run on the host of this `[remote.<name>]` section of `cave.toml`.
+    pub remote: Option<String>,
+    /// Run as a Job of the Kubernetes cluster of the `[k8s]` section of `cave.toml`.
+    pub k8s: bool,
+    /// `cave run` invocation of the user, recorded in the history for `cave rerun`.
+    pub invocation: Option<Invocation>,
+}
+
+/// Checks that a run name can be used in container and file names.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidRunName`] if the name is empty or contains
+/// characters other than ASCII letters, digits, `_`, `.` and `-`.
+pub fn validate_run_name(name: &str) -> Result<(), CaveError> {
+    let valid = !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(CaveError::InvalidRunName(name.to_string()))
+    }
+}
+
+/// Returns the container name of a run: `cave-<run id>`, or
+/// `cave-<name>-<run id>` for named runs, so that runs given the same name
+/// do not collide.
+pub fn container_name(run_id: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("cave-{}-{}", name, run_id),
+        None => format!("cave-{}", run_id),
+    }
 }
 
 /// Runs code_aster with Docker with the given version and mode.
 ///
 /// - [`DockerMode::RunAster`]: sources the activate script and runs `run_aster` with the given args and export file.
 /// - [`DockerMode::Shell`]: drops the user into an interactive bash shell inside the container.
+/// - [`DockerMode::Exec`]: sources the activate script and runs a command.
+///
+/// The container gets a TTY when the standard input is a terminal. Otherwise
+/// the piped input is passed to shells, commands and runs with
+/// `options.stdin`, and runs without it get no input.
 ///
 /// # Example
 /// ```
-/// docker_aster("22.0", DockerMode::RunAster { export_file: &Some("output.msh".to_string()), args: &vec![] })
+/// let options = RunOptions::default();
+/// docker_aster("22.0", DockerMode::RunAster { export_file: &Some("output.msh".to_string()), args: &vec![] }, &options)
 ///     .expect("Failed to run Code_Aster in Docker");
-/// docker_aster("22.0", DockerMode::Shell).expect("Failed to start shell");
+/// docker_aster("22.0", DockerMode::Shell, &options).expect("Failed to start shell");
 /// ```
-pub fn docker_aster(version: &str, mode: DockerMode) -> Result<(), CaveError> {
+pub fn docker_aster(version: &str, mode: DockerMode, options: &RunOptions) -> Result<(), CaveError> {
+    // Concurrent runs of a batch or a sweep each keep their own commands
+    let _log = cmdlog::scope();
+    ensure_docker()?;
     let start = std::time::Instant::now();
-
-    let current_dir = std::env::current_dir().map_err(CaveError::IoError)?;
-    let volume_arg = format!("{}:/home/user/data", current_dir.display());
+    let started_at = std::time::SystemTime::now();
+    let started_utc = chrono::Utc::now();
+    let timestamp = started_utc.to_rfc3339();
+    let run_id = options.run_id.clone().unwrap_or_else(history::new_run_id);
+
+    let host_dir = match &options.host_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().map_err(CaveError::IoError)?,
+    };
+    let volume_arg = format!("{}:/home/user/data", host_dir.display());
     let image = format!("simvia/code_aster:{}", version);
 
-    // Get the current user's UID and GID to avoid permission issues
-    let (uid, gid) = get_uid_gid();
-    let user_arg = format!("{}:{}", uid, gid);
-
-    let mut cmd = Command::new("docker");
-    cmd.arg("run")
-        .arg("--rm")
-        .arg("-it")
-        .arg("--user")
-        .arg(&user_arg)
-        .arg("-v")
-        .arg(&volume_arg)
-        .arg("-w")
-        .arg("/home/user/data")
-        .arg(&image);
-
-    let is_shell = matches!(mode, DockerMode::Shell);
-
-    match mode {
+    // Shells and commands are not recorded nor monitored like runs
+    let is_shell = !matches!(mode, DockerMode::RunAster { .. });
+    let study_log = if options.log_file && !is_shell && !options.detach {
+        Some(output::study_log_path(&host_dir, &run_id)?)
+    } else {
+        None
+    };
+    // The output is captured when filtered or logged
+    let captured = options.filter.is_some() || study_log.is_some();
+    let tty = io::stdin().is_terminal();
+    let forward_stdin = tty || is_shell || options.stdin || captured;
+    let mut record = RunRecord {
+        invocation: options.invocation.clone(),
+        ..Default::default()
+    };
+    let script = match mode {
         DockerMode::RunAster { export_file, args } => {
+            record.export_file = export_file.clone();
+            record.args = args.clone();
             let export = export_file.clone().unwrap_or_default();
-            let docker_command = format!("source /opt/activate.sh &&  run_aster {} {}", args.join(" "), export);
-            cmd.arg("/bin/bash").arg("-i").arg("-c").arg(docker_command);
+            Some(format!("source /opt/activate.sh &&  run_aster {} {}", args.join(" "), export))
+        }
+        DockerMode::Shell => None,
+        DockerMode::Exec { command } => {
+            let command: Vec<String> = command.iter().map(|a| cmdlog::shell_quote(a)).collect();
+            Some(format!("source /opt/activate.sh >/dev/null 2>&1; exec {}", command.join(" ")))
+        }
+    };
+
+    // The container ID is needed to follow its cgroup during the run
+    let cidfile = env::temp_dir().join(format!("cave-{}.cid", Uuid::new_v4()));
+    let mut cmd = if uses_apptainer() {
+        apptainer::exec_command(version, &host_dir, &options.docker_args, script.as_deref())?
+    } else {
+        let user_args = container_user_args()?;
+
+        let mut cmd = runtime().command();
+        cmd.arg("run")
+            .arg("--rm")
+            // The output is piped when captured, which needs the container without a TTY
+            .args(if options.detach {
+                &["-d"][..]
+            } else if captured || (forward_stdin && !tty) {
+                &["-i"][..]
+            } else if forward_stdin {
+                &["-it"][..]
+            } else {
+                &[][..]
+            })
+            .args(&user_args)
+            .arg("-v")
+            .arg(&volume_arg)
+            .arg("-w")
+            .arg("/home/user/data");
+
+        cmd.arg("--label").arg(jobs::LABEL);
+        if !is_shell && !options.detach {
+            cmd.arg("--cidfile").arg(&cidfile);
         }
-        DockerMode::Shell => {
-            cmd.arg("/bin/bash");
+        if !is_shell {
+            cmd.arg("--name")
+                .arg(container_name(&run_id, options.name.as_deref()))
+                .arg("--label")
+                .arg(format!("{}={}", jobs::RUN_ID_LABEL, run_id))
+                .arg("--label")
+                .arg(format!("{}={}", jobs::VERSION_LABEL, version))
+                .arg("--label")
+                .arg(format!("{}={}", jobs::DIR_LABEL, host_dir.display()));
         }
+        cmd.args(&options.docker_args);
+        cmd.arg(&image);
+        // An interactive bash needs a terminal
+        match &script {
+            Some(script) if tty => cmd.arg("/bin/bash").arg("-i").arg("-c").arg(script),
+            Some(script) => cmd.arg("/bin/bash").arg("-c").arg(script),
+            None => cmd.arg("/bin/bash"),
+        };
+        cmd
+    };
+    record.docker_command = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    cmdlog::record(&cmd);
+
+    if options.detach {
+        return start_detached(&mut cmd, version, &run_id, options, RunRecord { timestamp, ..record });
     }
 
+    let piped = || if captured { Stdio::piped() } else { Stdio::inherit() };
+    // The error output of a run is kept to diagnose a container that fails
+    // to start; with a TTY, it only holds the messages of the runtime
     let mut child = cmd
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
+        .stdin(if forward_stdin { Stdio::inherit() } else { Stdio::null() })
+        .stdout(piped())
+        .stderr(if is_shell { Stdio::inherit() } else { Stdio::piped() })
         .spawn()
         .map_err(|e| {
             if e.kind() == ErrorKind::NotFound {
@@ -274,21 +1054,86 @@ pub fn docker_aster(version: &str, mode: DockerMode) -> Result<(), CaveError> {
             }
         })?;
 
+    let sampler = if is_shell {
+        None
+    } else {
+        CgroupSampler::start(cidfile.clone())
+    };
+    let config = read_config()?;
+    let heartbeat = (!is_shell && config.heartbeat_secs > 0).then(|| {
+        Heartbeat::start(
+            cidfile.clone(),
+            run_id.clone(),
+            Duration::from_secs(config.heartbeat_secs),
+            config.capture_stack,
+        )
+    });
+
+    let _process_guard = signals::register(Operation::Process(child.id()));
+    let _container_guard = (!is_shell).then(|| signals::register(Operation::Container(cidfile.clone())));
+    let mut log_cut = false;
+    let (log_file, error_output) = match (&options.filter, study_log) {
+        (filter, Some(path)) => {
+            let filter = filter.clone().unwrap_or_else(output::all_lines);
+            let log = output::CappedLog::create(&path, output::log_limit(options.full_log))?;
+            log_cut = output::tee_filtered(&mut child, log, &filter, true);
+            (Some(path), None)
+        }
+        (Some(filter), None) => {
+            let path = output::log_path(&run_id)?;
+            let log = output::CappedLog::create(&path, output::log_limit(options.full_log))?;
+            log_cut = output::tee_filtered(&mut child, log, filter, false);
+            (Some(path), None)
+        }
+        (None, None) => (None, output::tee_stderr(&mut child)),
+    };
     let status = child.wait().map_err(CaveError::IoError)?;
+    let error_output = match (error_output, &log_file) {
+        (Some(handle), _) => handle.join().unwrap_or_default(),
+        (None, Some(path)) => output::file_tail(path),
+        (None, None) => String::new(),
+    };
+    match &log_file {
+        Some(path) if log_cut => println!(
+            "Beginning and end of the output written to {} (see --full-log)",
+            path.display()
+        ),
+        Some(path) => println!("Full output written to {}", path.display()),
+        None => {}
+    }
+    let cancelled = signals::cancelled() || (!is_shell && jobs::take_aborted(&run_id));
+
+    let usage = sampler.and_then(CgroupSampler::finish);
+    let stalls = heartbeat.map(Heartbeat::finish).unwrap_or_default();
+    let _ = std::fs::remove_file(&cidfile);
+    if let Some(usage) = &usage {
+        debug!("Ressources consommées: {:?}", usage);
+    }
 
     if !is_shell {
         debug!("Début de la telemetry");
         debug!("Début de la collecte des données du run");
 
-        let mut execution_data = ExecutionData::default();
-        execution_data.user_id = read_user_id()?;
+        let share_usage = answers::resource_tracking().unwrap_or(read_config()?.share_resource_usage);
+        let execution_data = ExecutionData {
+            user_id: read_user_id()?,
+            time_execution: start.elapsed().as_millis(),
+            valid_result: status.success(),
+            timezone: Local::now().offset().fix().to_string(),
+            started_at: started_utc,
+            cave_version: env!("CARGO_PKG_VERSION").to_string(),
+            mode: if options.cluster {
+                "cluster"
+            } else if options.mpi.is_some() {
+                "mpi"
+            } else {
+                "sequential"
+            },
+            version: version.to_string(),
+            id_docker: image_id(version)?,
+            resource_usage: usage.filter(|_| share_usage),
+        };
         debug!("user_id récupéré: {}", execution_data.user_id);
-
-        execution_data.time_execution = start.elapsed().as_millis();
-        execution_data.valid_result = status.success();
-        execution_data.timezone = Local::now().offset().fix().to_string();
-        execution_data.version = version.to_string();
-        execution_data.id_docker = image_id(version)?;
         debug!("ID docker récupéré: {}", execution_data.id_docker);
 
         let rt = tokio::runtime::Runtime::new()
@@ -300,17 +1145,98 @@ pub fn docker_aster(version: &str, mode: DockerMode) -> Result<(), CaveError> {
         debug!("Runtime tokio créé, envoi des données...");
 
         rt.block_on(async {
-            debug!("Appel de send_execution_data()");
+            debug!("Appel de telemetry::dispatch()");
             let local_telemetry = env::var("LOCAL_TELEMETRY").map(|v| v == "true").unwrap_or(false);
-            let _ = send_execution_data(execution_data, local_telemetry).await;
-            debug!("Fin de send_execution_data()");
+            telemetry::dispatch(execution_data, &config, local_telemetry).await;
+            debug!("Fin de telemetry::dispatch()");
         });
 
         debug!("Collecte et envoi des données terminés");
+
+        let produced_files = history::files_modified_since(&host_dir, started_at);
+        let mut summary = results::summarize(&host_dir, &produced_files);
+        let context = extractors::RunContext {
+            dir: &host_dir,
+            run_id: &run_id,
+            version,
+            export: record.export_file.as_deref(),
+        };
+        extractors::apply(&options.extractors, &context, &produced_files, &mut summary);
+        // Taken in any case, so that the next run of the invocation starts a new log
+        let mut commands = cmdlog::take();
+        if !config.log_commands {
+            commands.clear();
+        }
+        if !commands.is_empty() {
+            if let Err(e) = append_commands(&run_id, &commands) {
+                debug!("Impossible d'écrire les commandes dans le journal du run: {}", e);
+            }
+        }
+        let record = RunRecord {
+            id: run_id,
+            name: options.name.clone(),
+            timestamp,
+            version: version.to_string(),
+            image_id: image_id(version)?,
+            // The study, not the local scratch directory of --stage-local
+            working_dir: if options.stage_local {
+                std::env::current_dir()?.display().to_string()
+            } else {
+                host_dir.display().to_string()
+            },
+            duration_ms: start.elapsed().as_millis() as u64,
+            exit_code: status.code(),
+            success: status.success() && !cancelled,
+            cancelled,
+            export_snapshot: record
+                .export_file
+                .as_ref()
+                .and_then(|e| std::fs::read_to_string(host_dir.join(e)).ok()),
+            resource_usage: usage,
+            image_digest: image_digest(version),
+            diagnostic: history::diagnostic(&host_dir, &produced_files),
+            log_tail: history::message_tail(&host_dir, &produced_files),
+            results: summary,
+            log_file: log_file.map(|p| p.display().to_string()),
+            placement: options.placement.clone(),
+            low_priority: options.nice,
+            stalls,
+            commands,
+            produced_files,
+            ..record
+        };
+        if !record.success && !cancelled {
+            if let Some(mess) = record.produced_files.iter().find(|f| f.ends_with(".mess")) {
+                if let Ok(content) = std::fs::read(host_dir.join(mess)) {
+                    explain::print_hints(&explain::diagnose(&String::from_utf8_lossy(&content)));
+                }
+            }
+        }
+        if let Err(e) = history::append(&record) {
+            debug!("Impossible d'enregistrer le run dans l'historique: {}", e);
+        }
+        if read_config()?.run_metadata {
+            if let Err(e) = history::write_run_metadata(&host_dir, &record) {
+                debug!("Impossible d'écrire {}: {}", history::RUN_METADATA_FILE, e);
+            }
+        }
     }
 
 
+    if cancelled {
+        return Err(CaveError::Cancelled);
+    }
     if !status.success() {
+        if let Some(reason) = (!is_shell)
+            .then(|| startup_failure(status.code(), start.elapsed(), &error_output))
+            .flatten()
+        {
+            return Err(CaveError::ContainerStartFailed {
+                version: version.to_string(),
+                reason: reason.to_string(),
+                output: error_output,
+            });
+        }
         return Err(CaveError::CodeAsterError(format!(
             "run failed for version: {}",
             version
@@ -320,6 +1246,136 @@ pub fn docker_aster(version: &str, mode: DockerMode) -> Result<(), CaveError> {
     Ok(())
 }
 
+/// Time after which a failed run is considered to have started, so that its
+/// failure comes from the solver.
+const STARTUP_WINDOW: Duration = Duration::from_secs(30);
+
+/// Tells a container that failed to start apart from a failed solve, from
+/// its exit code and error output, and returns the reason of the failure.
+///
+/// The runtime exits with 125 when it cannot create or start the
+/// container, and the shell of the container with 126 or 127 when the
+/// command cannot be run or is not found; the latter are only trusted
+/// within [`STARTUP_WINDOW`], as the solver can run commands itself.
+fn startup_failure(code: Option<i32>, elapsed: Duration, error_output: &str) -> Option<&'static str> {
+    let output = error_output.to_lowercase();
+    let early = elapsed < STARTUP_WINDOW;
+    if output.contains("exec format error") || output.contains("does not match the specified platform") {
+        return Some("the image was built for another platform than this host");
+    }
+    match code {
+        Some(125) => Some("the container runtime could not create or start the container"),
+        Some(126) if early => Some("the entrypoint or run_aster of the image cannot be executed"),
+        Some(127) if early => Some("run_aster, or the shell running it, was not found in the image"),
+        _ => None,
+    }
+}
+
+/// Starts the container of a detached run and records the run in the
+/// history, without an exit status; it is followed and stopped with `cave jobs`.
+fn start_detached(
+    cmd: &mut Command,
+    version: &str,
+    run_id: &str,
+    options: &RunOptions,
+    record: RunRecord,
+) -> Result<(), CaveError> {
+    if uses_apptainer() {
+        return Err(CaveError::CodeAsterError(
+            "`--detach` needs Docker or Podman".to_string(),
+        ));
+    }
+    let output = cmd.stdin(Stdio::null()).output().map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            CaveError::NoDocker
+        } else {
+            CaveError::IoError(e)
+        }
+    })?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to start the run: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let container = container_name(run_id, options.name.as_deref());
+    debug!("Conteneur {} démarré en arrière-plan", container);
+
+    let mut commands = cmdlog::take();
+    if !read_config()?.log_commands {
+        commands.clear();
+    }
+    let record = RunRecord {
+        id: run_id.to_string(),
+        name: options.name.clone(),
+        version: version.to_string(),
+        image_id: image_id(version)?,
+        image_digest: image_digest(version),
+        working_dir: std::env::current_dir()?.display().to_string(),
+        placement: options.placement.clone(),
+        low_priority: options.nice,
+        detached: true,
+        commands,
+        ..record
+    };
+    if let Err(e) = history::append(&record) {
+        debug!("Impossible d'enregistrer le run dans l'historique: {}", e);
+    }
+
+    println!("Run {} started in the background, in container {}.", run_id.bold(), container);
+    println!(
+        "Follow it with `cave jobs attach {}`, stop it with `cave jobs kill {}`.",
+        run_id, run_id
+    );
+    Ok(())
+}
+
+/// Appends the container commands of a run to its run log.
+fn append_commands(run_id: &str, commands: &[cmdlog::LoggedCommand]) -> Result<(), CaveError> {
+    use std::io::Write;
+    let mut log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output::log_path(run_id)?)?;
+    writeln!(log, "\n# Container commands run by cave")?;
+    for command in commands {
+        writeln!(log, "# {}\n{}", command.date, command.command)?;
+    }
+    Ok(())
+}
+
+/// Returns the `run` arguments selecting the user of the container, after
+/// the `container_user` setting.
+///
+/// With the host user or a given UID, the files written in the study belong
+/// to that user rather than to the image's. A UID unknown to the image
+/// would get `/` as home, and cannot write in `/home/user`, the home of
+/// the image's user: see [`home_args`].
+fn container_user_args() -> Result<Vec<String>, CaveError> {
+    let (uid, gid) = match ContainerUser::parse(&read_config()?.container_user)? {
+        ContainerUser::Image => return Ok(Vec::new()),
+        ContainerUser::Host => get_uid_gid(),
+        ContainerUser::Fixed(uid, gid) => (uid, gid),
+    };
+    debug!("Conteneur lancé avec l'utilisateur {}:{}", uid, gid);
+    let mut args = runtime().user_args(uid, gid);
+    args.extend(home_args());
+    Ok(args)
+}
+
+/// Home of the containers not run as the image's user.
+const CONTAINER_HOME: &str = "/tmp/home";
+
+/// Returns the `run` arguments giving a container run as another user than
+/// the image's a writable home: an in-memory directory open to every user.
+pub fn home_args() -> Vec<String> {
+    vec![
+        "--tmpfs".to_string(),
+        format!("{}:mode=1777", CONTAINER_HOME),
+        "-e".to_string(),
+        format!("HOME={}", CONTAINER_HOME),
+    ]
+}
 
 /// Returns the current user's UID and GID.
 /// On Unix systems, gets the actual UID/GID.
@@ -328,7 +1384,7 @@ fn get_uid_gid() -> (u32, u32) {
     #[cfg(unix)]
     {
         // Try to get UID/GID from the current directory's metadata
-        if let Ok(metadata) = std::env::current_dir().and_then(|p| std::fs::metadata(p)) {
+        if let Ok(metadata) = std::env::current_dir().and_then(std::fs::metadata) {
             (metadata.uid(), metadata.gid())
         } else {
             // Fallback to environment or default
@@ -351,13 +1407,78 @@ fn get_uid_gid() -> (u32, u32) {
     }
 }
 
+/// Returns the labels of the image of a local version.
+///
+/// # Errors
+/// - [`CaveError::NoDocker`] if Docker is not installed.
+/// - [`CaveError::DockerError`] if the image cannot be inspected.
+pub fn image_labels(version: &str) -> Result<HashMap<String, String>, CaveError> {
+    if uses_apptainer() {
+        return Ok(HashMap::new());
+    }
+    if uses_engine() {
+        return Ok(engine::inspect(version)?.labels);
+    }
+    let reference = format!("simvia/code_aster:{}", version);
+    let output = runtime().command()
+        .args(["image", "inspect", "--format", "{{json .Config.Labels}}"])
+        .arg(&reference)
+        .logged()
+        .output()
+        .map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                CaveError::NoDocker
+            } else {
+                CaveError::IoError(e)
+            }
+        })?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!("Failed to inspect {}", reference)));
+    }
+    let labels: Option<HashMap<String, String>> =
+        serde_json::from_slice(&output.stdout).map_err(CaveError::SerdeError)?;
+    Ok(labels.unwrap_or_default())
+}
+
+/// Returns the repository digest (`simvia/code_aster@sha256:...`) of a local
+/// version, or `None` for images that were not pulled from a registry.
+pub fn image_digest(version: &str) -> Option<String> {
+    if uses_apptainer() {
+        return None;
+    }
+    if uses_engine() {
+        return engine::inspect(version).ok()?.digests.into_iter().next();
+    }
+    let output = runtime().command()
+        .args(["image", "inspect", "--format", "{{join .RepoDigests \"\\n\"}}"])
+        .arg(format!("simvia/code_aster:{}", version))
+        .logged()
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(str::to_string)
+}
+
 pub fn image_id(version: &str) -> Result<String, CaveError> {
+    if uses_apptainer() {
+        return apptainer::image_id(version);
+    }
+    if uses_engine() {
+        return Ok(engine::inspect(version)?.id);
+    }
     let reference = format!("simvia/code_aster:{}", version);
 
-    let output = Command::new("docker")
+    let output = runtime().command()
         .arg("images")
         .arg("-q")
         .arg(&reference)
+        .logged()
         .output()
         .map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
@@ -383,6 +1504,46 @@ pub fn image_id(version: &str) -> Result<String, CaveError> {
     Ok(id.to_string())
 }
 
+/// Shell script run in the container by [`probe_environment`], printing `key: value` lines.
+const ENV_PROBE_SCRIPT: &str = r#"source /opt/activate.sh >/dev/null 2>&1
+echo "code_aster: $(run_aster --version 2>/dev/null | head -n 1)"
+echo "python: $(python3 --version 2>&1)"
+echo "mpi: $(mpirun --version 2>/dev/null | head -n 1 || echo none)"
+echo "code_aster package: $(python3 -c 'import code_aster, os; print(os.path.dirname(code_aster.__file__))' 2>/dev/null)"
+echo "ASTER_DATADIR: ${ASTER_DATADIR:-}"
+echo "ASTER_LIBDIR: ${ASTER_LIBDIR:-}"
+echo "PATH: $PATH""#;
+
+/// Starts a throwaway container of `version` and returns its key environment
+/// (code_aster and Python versions, MPI, catalog paths) as `key: value` lines.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if the container cannot be run.
+pub fn probe_environment(version: &str) -> Result<String, CaveError> {
+    ensure_docker()?;
+    let image = format!("simvia/code_aster:{}", version);
+    let mut cmd = if uses_apptainer() {
+        apptainer::exec_command(version, &env::temp_dir(), &[], Some(ENV_PROBE_SCRIPT))?
+    } else {
+        let mut cmd = runtime().command();
+        cmd.arg("run")
+            .arg("--rm")
+            .arg(&image)
+            .arg("/bin/bash")
+            .arg("-c")
+            .arg(ENV_PROBE_SCRIPT);
+        cmd
+    };
+    let output = cmd.logged_output()?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(format!(
+            "Failed to inspect the environment of {}: {}",
+            image,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
 /// Returns the version associated with a given tag (`stable` or `testing`).
 ///
@@ -430,36 +1591,29 @@ struct StabTestTagsResponse {
 /// println!("Stable: {}, Testing: {}", stable, testing);
 /// ```
 pub fn get_stable_and_testing() -> Result<(String, String), CaveError> {
+    // Every page is fetched whatever the budget, the channels may be older than the listed tags
+    let page_size = read_config().map(|c| c.hub_budget.page_size).unwrap_or(100);
     let mut all_versions = Vec::new();
-    let mut url = "https://hub.docker.com/v2/repositories/simvia/code_aster/tags?page_size=100".to_string();
+    let mut url = format!(
+        "https://hub.docker.com/v2/repositories/simvia/code_aster/tags?page_size={}&ordering=last_updated",
+        page_size
+    );
+    let _spinner = Spinner::start("Resolving stable and testing on Docker Hub");
     loop {
-        let resp = reqwest::blocking::get(&url)
-            .map_err(|e| CaveError::HttpError(e.to_string()))?;
-
-        if !resp.status().is_success() {
-            return Err(CaveError::HttpError(format!(
-                "Failed to fetch Docker tags: {}",
-                resp.status()
-            )));
-        }
-
-        let tags_response: StabTestTagsResponse =
-            resp.json().map_err(|e| CaveError::HttpError(e.to_string()))?;
+        let tags_response: StabTestTagsResponse = hub_get(&url)?;
 
         for tag in tags_response.results {
             let digest = tag
                 .images
-                .get(0)
+                .first()
                 .and_then(|img| img.digest.clone())
                 .unwrap_or_else(|| "unknown".to_string());
 
             all_versions.push((tag.name, digest));
         }
-
-        if let Some(next_url) = tags_response.next {
-            url = next_url;
-        } else {
-            break;
+        match tags_response.next {
+            Some(next_url) => url = next_url,
+            None => break,
         }
     }
     let mut stable_digest = None;
@@ -487,125 +1641,273 @@ pub fn get_stable_and_testing() -> Result<(String, String), CaveError> {
     Ok((stable_tag, testing_tag))
 }
 
-// TODO : uncomment to have registry option
-//
-// fn docker_login(registry_cfg: &Registry) -> Result<(), CaveError> {
-//     let registry = "registry.gitlab.com";
-//     let user = &registry_cfg.user;
-//     let token = &registry_cfg.token; 
-
-//     let login_status = Command::new("docker")
-//         .arg("login")
-//         .arg(registry)
-//         .arg("-u")
-//         .arg(user)
-//         .arg("--password-stdin")
-//         .stdin(std::process::Stdio::piped())
-//         .spawn()
-//         .and_then(|mut child| {
-//             use std::io::Write;
-//             if let Some(stdin) = &mut child.stdin {
-//                 stdin.write_all(token.as_bytes())?;
-//             }
-//             child.wait()
-//         })
-//         .map_err(|e| CaveError::IoError(e))?;
-
-//     if !login_status.success() {
-//         return Err(CaveError::DockerError("Docker login failed".into()));
-//     }
-//     Ok(())
-// }
-
-
-// TODO : uncomment to have registry option
-//
-// / Returns a list of tags available in the private registry.
-// / 
-// / Each time, it processes a docker login with the registry_cf (call to docker_login),
-// / then pull the available versions on the registry and finally logout.
-// /
-// / # Example
-// / ```
-// / let registry_cfg = Registry {
-// /     repo: "myrepo".to_string(),
-// /     user: "username".to_string(),
-// /     token: "mytoken".to_string(),
-// / };
-// / let tags = registry_versions(&registry_cfg).expect("Failed to fetch registry tags");
-// / println!("Registry tags: {:?}", tags);
-// / ```
-// pub fn registry_versions(registry_cfg: &Registry) -> Result<Vec<String>, CaveError> {
-//     docker_login(registry_cfg)?;
-
-//     let registry = "registry.gitlab.com";
-//     let repo = &registry_cfg.repo;
-//     let token = &registry_cfg.token; 
-
-
-//     let auth_header = reqwest::blocking::Client::new()
-//         .head(&format!("https://{}/v2/{}/tags/list", registry, repo))
-//         .send()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?
-//         .headers()
-//         .get("www-authenticate")
-//         .ok_or_else(|| CaveError::DockerError("No www-authenticate header".into()))?
-//         .to_str()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?
-//         .to_string();
-
-//     let realm = Regex::new(r#"realm="([^"]+)""#).unwrap()
-//         .captures(&auth_header)
-//         .and_then(|c| c.get(1))
-//         .ok_or_else(|| CaveError::DockerError("No realm found".into()))?
-//         .as_str()
-//         .to_string();
-
-//     let service = Regex::new(r#"service="([^"]+)""#).unwrap()
-//         .captures(&auth_header)
-//         .and_then(|c| c.get(1))
-//         .ok_or_else(|| CaveError::DockerError("No service found".into()))?
-//         .as_str()
-//         .to_string();
-
-//     let scope = Regex::new(r#"scope="([^"]+)""#).unwrap()
-//         .captures(&auth_header)
-//         .and_then(|c| c.get(1))
-//         .ok_or_else(|| CaveError::DockerError("No scope found".into()))?
-//         .as_str()
-//         .to_string();
-
-//     let jwt_resp: serde_json::Value = reqwest::blocking::Client::new()
-//         .get(&format!("{}?service={}&scope={}", realm, service, scope))
-//         .basic_auth("oauth2", Some(token))
-//         .send()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?
-//         .json()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?;
-
-//     let jwt = jwt_resp.get("token")
-//         .and_then(|t| t.as_str())
-//         .ok_or_else(|| CaveError::DockerError("No token in JWT response".into()))?;
-
-//     let tags_resp: serde_json::Value = reqwest::blocking::Client::new()
-//         .get(&format!("https://{}/v2/{}/tags/list", registry, repo))
-//         .bearer_auth(jwt)
-//         .send()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?
-//         .json()
-//         .map_err(|e| CaveError::HttpError(e.to_string()))?;
-
-//     let tags = tags_resp.get("tags")
-//         .and_then(|t| t.as_array())
-//         .ok_or_else(|| CaveError::DockerError("No tags found".into()))?
-//         .iter()
-//         .filter_map(|t| t.as_str().map(|s| s.to_string()))
-//         .collect::<Vec<String>>();
-
-//     let _ = Command::new("docker")
-//         .arg("logout")
-//         .arg(registry)
-//         .status();
-
-//     Ok(tags)
-// }
+/// Returns the private registry of the user's config, if any.
+pub fn configured_registry() -> Option<Registry> {
+    read_config().ok().and_then(|c| c.registry)
+}
+
+/// Returns the private registry if it has the version. An unreachable
+/// registry is reported and skipped, so that Docker Hub is used instead.
+fn private_source(version: &str) -> Option<Registry> {
+    let registry = configured_registry()?;
+    let credentials = Some((registry.user.as_str(), registry.token.as_str()));
+    match registry_has_tag(&registry.host, &registry.repo, version, credentials) {
+        Ok(found) => found.then_some(registry),
+        Err(e) => {
+            eprintln!(
+                "{} private registry {} unavailable, using Docker Hub: {}",
+                "Warning:".yellow().bold(),
+                registry.host,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Credentials of the private registry, logged in to a temporary runtime
+/// configuration removed when dropped, so that the credentials of the user
+/// are left untouched.
+struct RegistryAuth {
+    dir: PathBuf,
+}
+
+impl RegistryAuth {
+    /// Logs in to the private registry, the token being given on the standard input.
+    fn login(registry: &Registry) -> Result<Self, CaveError> {
+        let dir = env::temp_dir().join(format!("cave-auth-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        let auth = RegistryAuth { dir };
+        let mut child = runtime()
+            .auth_command(&auth.dir, "login")
+            .arg(&registry.host)
+            .arg("-u")
+            .arg(&registry.user)
+            .arg("--password-stdin")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .logged()
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            use std::io::Write;
+            stdin.write_all(registry.token.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(CaveError::DockerError(format!(
+                "Login to {} failed: {}",
+                registry.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(auth)
+    }
+}
+
+impl Drop for RegistryAuth {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Returns the value of a parameter of a `WWW-Authenticate` challenge.
+fn challenge_param(challenge: &str, name: &str) -> Option<String> {
+    Regex::new(&format!(r#"{}="([^"]*)""#, name))
+        .ok()?
+        .captures(challenge)
+        .map(|c| c[1].to_string())
+}
+
+/// Returns the tags of the private registry.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the registry cannot be reached or
+/// refuses the credentials.
+///
+/// # Example
+/// ```
+/// let registry = configured_registry().expect("No registry configured");
+/// let tags = registry_versions(&registry).expect("Failed to fetch registry tags");
+/// println!("Registry tags: {:?}", tags);
+/// ```
+pub fn registry_versions(registry: &Registry) -> Result<Vec<String>, CaveError> {
+    registry_tags(
+        &registry.host,
+        &registry.repo,
+        Some((&registry.user, &registry.token)),
+    )
+}
+
+/// Media types of the manifests looked up by [`registry_has_tag`].
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// Sends a request built by `build` to a registry, with the Docker Registry
+/// API authentication.
+///
+/// The registry is first queried with the credentials, if any; when it
+/// answers with a bearer challenge (GitLab, Harbor, ...), a token is
+/// requested from its authorization server with the same credentials, or
+/// anonymously, and the request is sent again with it.
+fn registry_send(
+    host: &str,
+    credentials: Option<(&str, &str)>,
+    build: impl Fn(&reqwest::blocking::Client) -> reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response, CaveError> {
+    ensure_online(&format!("Querying the registry {}", host))?;
+    let http_error = |e: reqwest::Error| CaveError::HttpError(e.to_string());
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(20))
+        .build()
+        .map_err(http_error)?;
+    let authenticated = |request: reqwest::blocking::RequestBuilder| match credentials {
+        Some((user, token)) => request.basic_auth(user, Some(token)),
+        None => request,
+    };
+
+    let response = authenticated(build(&client)).send().map_err(http_error)?;
+    let challenge = response
+        .headers()
+        .get(reqwest::header::WWW_AUTHENTICATE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if response.status() != reqwest::StatusCode::UNAUTHORIZED || !challenge.starts_with("Bearer") {
+        return Ok(response);
+    }
+    let realm = challenge_param(&challenge, "realm")
+        .ok_or_else(|| CaveError::HttpError(format!("No realm in the challenge of {}", host)))?;
+    let mut query = Vec::new();
+    for name in ["service", "scope"] {
+        if let Some(value) = challenge_param(&challenge, name) {
+            query.push((name, value));
+        }
+    }
+    let token: serde_json::Value = authenticated(client.get(&realm).query(&query))
+        .send()
+        .and_then(|r| r.error_for_status())
+        .map_err(http_error)?
+        .json()
+        .map_err(http_error)?;
+    let jwt = token
+        .get("token")
+        .or_else(|| token.get("access_token"))
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| CaveError::HttpError(format!("No token returned by {}", realm)))?;
+    build(&client).bearer_auth(jwt).send().map_err(http_error)
+}
+
+/// Returns the tags of a repository of a registry, with the Docker Registry
+/// API (see [`registry_send`] for the authentication).
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the registry cannot be reached or
+/// refuses the credentials.
+pub fn registry_tags(host: &str, repo: &str, credentials: Option<(&str, &str)>) -> Result<Vec<String>, CaveError> {
+    let url = format!("https://{}/v2/{}/tags/list", host, repo);
+    let response = registry_send(host, credentials, |client| client.get(&url))?;
+    if !response.status().is_success() {
+        return Err(CaveError::HttpError(format!(
+            "Failed to list the tags of {}/{}: {}",
+            host,
+            repo,
+            response.status()
+        )));
+    }
+
+    let tags: serde_json::Value = response
+        .json()
+        .map_err(|e| CaveError::HttpError(e.to_string()))?;
+    Ok(tags
+        .get("tags")
+        .and_then(|t| t.as_array())
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|t| t.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Returns the labels of an image of a registry, read from its
+/// configuration with the Docker Registry API, without pulling it. For a
+/// multi-platform image, the labels of the image of the host platform are
+/// returned.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the registry cannot be reached, or
+/// the tag or its manifests are missing.
+pub fn remote_labels(
+    host: &str,
+    repo: &str,
+    tag: &str,
+    credentials: Option<(&str, &str)>,
+) -> Result<HashMap<String, String>, CaveError> {
+    let fetch = |path: &str, accept: &str| -> Result<serde_json::Value, CaveError> {
+        let url = format!("https://{}/v2/{}/{}", host, repo, path);
+        let response = registry_send(host, credentials, |client| {
+            client.get(&url).header(reqwest::header::ACCEPT, accept)
+        })?;
+        if !response.status().is_success() {
+            return Err(CaveError::HttpError(format!(
+                "Failed to fetch {}: {}",
+                url,
+                response.status()
+            )));
+        }
+        response.json().map_err(|e| CaveError::HttpError(e.to_string()))
+    };
+
+    let mut manifest = fetch(&format!("manifests/{}", tag), MANIFEST_TYPES)?;
+    if let Some(manifests) = manifest.get("manifests").and_then(|m| m.as_array()) {
+        let architecture = match env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        let digest = manifests
+            .iter()
+            .find(|m| m["platform"]["os"] == "linux" && m["platform"]["architecture"] == architecture)
+            .or(manifests.first())
+            .and_then(|m| m["digest"].as_str())
+            .ok_or_else(|| CaveError::HttpError(format!("No image for {}:{}", repo, tag)))?
+            .to_string();
+        manifest = fetch(&format!("manifests/{}", digest), MANIFEST_TYPES)?;
+    }
+    let config = manifest["config"]["digest"]
+        .as_str()
+        .ok_or_else(|| CaveError::HttpError(format!("No configuration for {}:{}", repo, tag)))?;
+    let blob = fetch(&format!("blobs/{}", config), "application/json")?;
+    Ok(serde_json::from_value(blob["config"]["Labels"].clone()).unwrap_or_default())
+}
+
+/// Checks if a repository of a registry has a tag, with a `HEAD` request on
+/// its manifest rather than listing every tag.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the registry cannot be reached or
+/// refuses the credentials.
+pub fn registry_has_tag(
+    host: &str,
+    repo: &str,
+    tag: &str,
+    credentials: Option<(&str, &str)>,
+) -> Result<bool, CaveError> {
+    let url = format!("https://{}/v2/{}/manifests/{}", host, repo, tag);
+    let response = registry_send(host, credentials, |client| {
+        client.head(&url).header(reqwest::header::ACCEPT, MANIFEST_TYPES)
+    })?;
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        reqwest::StatusCode::NOT_FOUND => Ok(false),
+        status => Err(CaveError::HttpError(format!(
+            "Failed to look up {}/{}:{}: {}",
+            host, repo, tag, status
+        ))),
+    }
+}
@@ -0,0 +1,246 @@
+//! Environment diagnostics (`cave doctor`).
+//!
+//! Each check prints a pass, warning or failure line, with a hint on how to
+//! fix it. Failed critical checks make the command exit with an error.
+
+use crate::config::{config_path, offline, read_config, write_config};
+use crate::docker::{ensure_docker, exists_locally, uses_apptainer};
+use crate::manage::{internet_available, pin_files, CaveError};
+use crate::progress::Spinner;
+use colored::*;
+use reqwest::blocking::Client;
+use std::env;
+use std::time::Duration;
+
+/// Commands of the NVIDIA Container Toolkit, needed by `docker run --gpus`.
+const NVIDIA_TOOLKIT: [&str; 2] = ["nvidia-ctk", "nvidia-container-cli"];
+
+/// Outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Non-critical failure.
+    Warn,
+    /// Critical failure.
+    Fail,
+}
+
+/// A check of the environment and its outcome.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// How to fix the problem, empty if the check passed.
+    pub hint: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+            hint: hint.into(),
+        }
+    }
+
+    /// Returns the label of the outcome: `ok`, `warn` or `fail`.
+    pub fn label(&self) -> &'static str {
+        match self.status {
+            Status::Pass => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+
+    /// Returns the check as a plain text line, with its hint unless it passed.
+    pub fn line(&self) -> String {
+        let mut line = format!("{:<6}{:<16}{}", self.label(), self.name, self.detail);
+        if self.status != Status::Pass && !self.hint.is_empty() {
+            line.push_str(&format!("\n{:<22}{}", "", self.hint));
+        }
+        line
+    }
+}
+
+/// Prints the outcome of a check, with its hint unless it passed.
+fn report(check: &Check) {
+    let label = match check.status {
+        Status::Pass => check.label().green().bold(),
+        Status::Warn => check.label().yellow().bold(),
+        Status::Fail => check.label().red().bold(),
+    };
+    println!("{:<6}{:<16}{}", label, check.name, check.detail);
+    if check.status != Status::Pass && !check.hint.is_empty() {
+        println!("{:<22}{}", "", check.hint.dimmed());
+    }
+}
+
+fn docker_hub_reachable() -> bool {
+    let _spinner = Spinner::start("Reaching Docker Hub");
+    Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .and_then(|client| {
+            client
+                .get("https://hub.docker.com/v2/repositories/simvia/code_aster/")
+                .send()
+        })
+        .map(|response| response.status().is_success())
+        .unwrap_or(false)
+}
+
+fn on_path(binary: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(binary).is_file()))
+}
+
+/// Checks the GPU support when GPUs are configured or the host has an NVIDIA
+/// GPU; fails if the configured GPUs cannot be used.
+fn check_gpus(configured: Option<&str>) -> Option<Check> {
+    let driver = on_path("nvidia-smi");
+    if configured.is_none() && !driver {
+        return None;
+    }
+    if uses_apptainer() {
+        // Apptainer binds the host driver itself with --nv
+        return match (driver, configured) {
+            (true, _) => Some(Check::new("GPU", Status::Pass, "NVIDIA driver installed", "")),
+            (false, Some(spec)) => Some(Check::new(
+                "GPU",
+                Status::Fail,
+                format!("GPUs {} configured, no NVIDIA driver found", spec),
+                "Install the NVIDIA driver, or run `cave config set-gpus` to remove the setting.",
+            )),
+            (false, None) => None,
+        };
+    }
+    let hint = "Install the NVIDIA Container Toolkit: \
+        https://docs.nvidia.com/datacenter/cloud-native/container-toolkit/latest/install-guide.html";
+    Some(match (NVIDIA_TOOLKIT.iter().any(|binary| on_path(binary)), configured) {
+        (true, _) => Check::new("GPU", Status::Pass, "NVIDIA Container Toolkit installed", ""),
+        (false, Some(spec)) => Check::new(
+            "GPU",
+            Status::Fail,
+            format!("GPUs {} configured, NVIDIA Container Toolkit not found", spec),
+            hint,
+        ),
+        (false, None) => Check::new(
+            "GPU",
+            Status::Warn,
+            "NVIDIA GPU found, NVIDIA Container Toolkit not found",
+            format!("`cave run --gpus` will fail. {}", hint),
+        ),
+    })
+}
+
+/// Checks Docker, the network, the configuration file, the GPU support and
+/// the selected version, and returns the outcomes in that order.
+pub fn checks() -> Result<Vec<Check>, CaveError> {
+    let mut checks = Vec::new();
+
+    // Docker
+    let docker = ensure_docker();
+    checks.push(match &docker {
+        Ok(()) => Check::new("Docker", Status::Pass, "installed, daemon reachable", ""),
+        Err(e) => Check::new(
+            "Docker",
+            Status::Fail,
+            e.to_string(),
+            e.code()
+                .map(|code| format!("Run `cave explain {}` for details.", code))
+                .unwrap_or_default(),
+        ),
+    });
+
+    // Network, only needed to download versions
+    checks.push(if offline() {
+        Check::new(
+            "Internet",
+            Status::Warn,
+            "offline mode",
+            "Versions cannot be downloaded; run `cave config disable-offline` when the network is back.",
+        )
+    } else if !internet_available() {
+        Check::new(
+            "Internet",
+            Status::Warn,
+            "no connection",
+            "Versions cannot be downloaded; installed versions still run.",
+        )
+    } else if !docker_hub_reachable() {
+        Check::new(
+            "Docker Hub",
+            Status::Warn,
+            "unreachable",
+            "Check your proxy settings (HTTPS_PROXY) or firewall.",
+        )
+    } else {
+        Check::new("Docker Hub", Status::Pass, "reachable", "")
+    });
+
+    // Configuration
+    let path = config_path()?;
+    checks.push(match read_config().and_then(|config| write_config(&config)) {
+        Ok(()) => Check::new("Configuration", Status::Pass, path.display().to_string(), ""),
+        Err(e) => Check::new(
+            "Configuration",
+            Status::Fail,
+            e.to_string(),
+            format!("Fix or delete {} to restore the defaults.", path.display()),
+        ),
+    });
+
+    // GPUs, only checked when configured or present
+    let gpus = read_config().ok().and_then(|config| config.gpus);
+    checks.extend(check_gpus(gpus.as_deref()));
+
+    // Selected version
+    let pins = pin_files()?;
+    for (pin, version) in &pins {
+        checks.push(Check::new("Pin", Status::Pass, format!("{} in {}", version, pin.display()), ""));
+    }
+    checks.push(match pins.last() {
+        None => Check::new(
+            "Version",
+            Status::Fail,
+            "no version selected",
+            "Run `cave use <version>` or `cave pin <version>`.",
+        ),
+        Some((_, version)) if docker.is_ok() => match exists_locally(version) {
+            Ok(true) => Check::new("Version", Status::Pass, format!("{} installed", version), ""),
+            Ok(false) => Check::new(
+                "Version",
+                Status::Fail,
+                format!("{} not installed", version),
+                format!("Run `cave pin {}` to download it.", version),
+            ),
+            Err(e) => Check::new("Version", Status::Fail, e.to_string(), ""),
+        },
+        Some((_, version)) => Check::new(
+            "Version",
+            Status::Warn,
+            format!("{} not checked", version),
+            "Docker is needed to check the installed versions.",
+        ),
+    });
+    Ok(checks)
+}
+
+/// Handler of `cave doctor`: prints the outcome of the [`checks`].
+///
+/// # Errors
+/// Returns [`CaveError::ChecksFailed`] with the number of failed critical checks.
+pub fn doctor() -> Result<(), CaveError> {
+    let checks = checks()?;
+    for check in &checks {
+        report(check);
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        return Err(CaveError::ChecksFailed(failed));
+    }
+    println!("All checks passed.");
+    Ok(())
+}
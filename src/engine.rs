@@ -0,0 +1,344 @@
+//! Docker Engine API client, for the image operations of the Docker runtime.
+//!
+//! Listing, inspecting, pulling and removing images go through the Engine
+//! API (with [`bollard`]) instead of parsing the output of the docker CLI:
+//! errors come back with the daemon's status code and message, pulls report
+//! their progress as structured events, and an unreachable daemon is told
+//! apart from a missing installation without running `docker info`.
+//!
+//! Containers are still run with the docker CLI, which handles the terminal
+//! (raw mode, resizing, signals) of interactive runs and shells.
+//!
+//! Each operation is recorded in the command log (see [`crate::cmdlog`]) as
+//! the equivalent docker CLI command.
+//!
+//! The API is asynchronous; this module exposes blocking functions, run on a
+//! runtime created on first use.
+//!
+//! The daemon is the one of the docker CLI: `DOCKER_HOST`, else the endpoint
+//! of the current `docker context` (see [`endpoint`]). The CLI is used for
+//! the images too when that endpoint cannot be reached through the API, such
+//! as an SSH or a TLS endpoint (see [`supported`]).
+
+use crate::cmdlog;
+use crate::config::Registry;
+use crate::manage::CaveError;
+use crate::progress::PullProgress;
+use crate::signals::{self, Operation};
+use bollard::auth::DockerCredentials;
+use bollard::errors::Error;
+use bollard::image::{CreateImageOptions, ListImagesOptions, RemoveImageOptions};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use chrono::{DateTime, FixedOffset};
+use futures_util::StreamExt;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+
+/// Repository of the code_aster images.
+const REPOSITORY: &str = "simvia/code_aster";
+
+static CLIENT: OnceLock<Option<(Docker, Runtime)>> = OnceLock::new();
+
+/// Timeout of the API requests, in seconds.
+const TIMEOUT_SECS: u64 = 120;
+
+/// Daemon endpoint of the docker CLI.
+#[derive(Debug, Clone, PartialEq)]
+enum Endpoint {
+    /// The default local socket.
+    Default,
+    /// A daemon address, such as `unix:///run/user/1000/docker.sock`.
+    Host(String),
+    /// A context whose endpoint cannot be read.
+    Unknown,
+}
+
+/// Returns the daemon endpoint the docker CLI uses: `DOCKER_HOST`, else the
+/// endpoint of the context named by `DOCKER_CONTEXT` or the `currentContext`
+/// of the CLI configuration.
+fn endpoint() -> Endpoint {
+    if let Some(host) = env::var("DOCKER_HOST").ok().filter(|h| !h.is_empty()) {
+        return Endpoint::Host(host);
+    }
+    let Some(config_dir) = env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".docker")))
+    else {
+        return Endpoint::Default;
+    };
+    let read_json = |path: PathBuf| -> Option<serde_json::Value> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    };
+    let context = env::var("DOCKER_CONTEXT").ok().filter(|c| !c.is_empty()).or_else(|| {
+        read_json(config_dir.join("config.json"))?
+            .get("currentContext")?
+            .as_str()
+            .map(str::to_string)
+    });
+    let Some(context) = context.filter(|c| c != "default") else {
+        return Endpoint::Default;
+    };
+    // The CLI stores the metadata of a context under the SHA-256 of its name
+    let meta = config_dir
+        .join("contexts")
+        .join("meta")
+        .join(format!("{:x}", Sha256::digest(context.as_bytes())))
+        .join("meta.json");
+    read_json(meta)
+        .and_then(|meta| meta.pointer("/Endpoints/docker/Host")?.as_str().map(str::to_string))
+        .map_or(Endpoint::Unknown, Endpoint::Host)
+}
+
+/// Returns `true` if the endpoint of the docker CLI is reached through the
+/// API: the default socket, another Unix socket or a plain TCP address.
+pub fn supported() -> bool {
+    match endpoint() {
+        Endpoint::Default => true,
+        Endpoint::Host(host) => {
+            host.starts_with("unix://")
+                || (host.starts_with("tcp://") && env::var("DOCKER_TLS_VERIFY").map_or(true, |v| v.is_empty()))
+        }
+        Endpoint::Unknown => false,
+    }
+}
+
+fn connect() -> Result<Docker, Error> {
+    match endpoint() {
+        Endpoint::Host(host) if host.starts_with("unix://") => {
+            Docker::connect_with_unix(&host, TIMEOUT_SECS, API_DEFAULT_VERSION)
+        }
+        Endpoint::Host(host) => Docker::connect_with_http(&host, TIMEOUT_SECS, API_DEFAULT_VERSION),
+        Endpoint::Default | Endpoint::Unknown => Docker::connect_with_local_defaults(),
+    }
+}
+
+fn client() -> Result<&'static (Docker, Runtime), CaveError> {
+    CLIENT
+        .get_or_init(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .ok()?;
+            match connect() {
+                Ok(docker) => Some((docker, runtime)),
+                Err(e) => {
+                    debug!("Connexion à l'API Docker impossible: {}", e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+        .ok_or(CaveError::DockerDaemonNotRunning)
+}
+
+/// Returns `true` if one of the causes of `error` is an I/O error of the given kind.
+fn caused_by(error: &Error, kind: io::ErrorKind) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(e) = source {
+        if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == kind) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Converts an API error, keeping the message of the daemon when it answered.
+fn api_error(context: &str, error: Error) -> CaveError {
+    match error {
+        Error::DockerResponseServerError { message, .. } => {
+            CaveError::DockerError(format!("{}: {}", context, message))
+        }
+        e if caused_by(&e, io::ErrorKind::PermissionDenied) => CaveError::DockerPermissionDenied,
+        e if caused_by(&e, io::ErrorKind::NotFound)
+            || caused_by(&e, io::ErrorKind::ConnectionRefused) =>
+        {
+            CaveError::DockerDaemonNotRunning
+        }
+        e => CaveError::DockerError(format!("{}: {}", context, e)),
+    }
+}
+
+/// Checks that the daemon answers.
+///
+/// # Errors
+/// - [`CaveError::NoDocker`] if neither the docker binary nor the socket exists.
+/// - [`CaveError::DockerDaemonNotRunning`] if the daemon cannot be reached.
+/// - [`CaveError::DockerPermissionDenied`] if the user cannot access the socket.
+pub fn ping() -> Result<(), CaveError> {
+    let result = client().and_then(|(docker, rt)| {
+        rt.block_on(docker.ping())
+            .map(|_| ())
+            .map_err(|e| api_error("Failed to reach the Docker daemon", e))
+    });
+    match result {
+        Err(CaveError::DockerDaemonNotRunning) if !installed() => Err(CaveError::NoDocker),
+        result => result,
+    }
+}
+
+/// Returns `true` if Docker looks installed: its binary is on the `PATH` or
+/// its socket exists.
+fn installed() -> bool {
+    let socket = std::env::var("DOCKER_HOST").ok();
+    let socket = socket
+        .as_deref()
+        .and_then(|host| host.strip_prefix("unix://"))
+        .unwrap_or("/var/run/docker.sock");
+    Path::new(socket).exists()
+        || std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| dir.join("docker").is_file())
+        })
+}
+
+/// Returns the local code_aster versions with the creation date of their image.
+pub fn local_versions_created() -> Result<Vec<(String, DateTime<FixedOffset>)>, CaveError> {
+    let (docker, rt) = client()?;
+    cmdlog::record_args(["docker", "images", "--filter", "reference=simvia/code_aster"]);
+    let filters = HashMap::from([("reference", vec![REPOSITORY])]);
+    let images = rt
+        .block_on(docker.list_images(Some(ListImagesOptions {
+            filters,
+            ..Default::default()
+        })))
+        .map_err(|e| api_error("Failed to list images", e))?;
+    let prefix = format!("{}:", REPOSITORY);
+    Ok(images
+        .into_iter()
+        .flat_map(|image| {
+            let created = DateTime::from_timestamp(image.created, 0)
+                .unwrap_or_default()
+                .fixed_offset();
+            image
+                .repo_tags
+                .into_iter()
+                .filter_map(|tag| tag.strip_prefix(&prefix).map(str::to_string))
+                .map(move |tag| (tag, created))
+                .collect::<Vec<_>>()
+        })
+        .collect())
+}
+
+/// Returns the disk space of the local code_aster images, in bytes, an
+/// image tagged with several versions being counted once.
+pub fn images_size() -> Result<u64, CaveError> {
+    let (docker, rt) = client()?;
+    cmdlog::record_args(["docker", "images", "--filter", "reference=simvia/code_aster"]);
+    let filters = HashMap::from([("reference", vec![REPOSITORY])]);
+    let images = rt
+        .block_on(docker.list_images(Some(ListImagesOptions {
+            filters,
+            ..Default::default()
+        })))
+        .map_err(|e| api_error("Failed to list images", e))?;
+    Ok(images.iter().map(|image| image.size.max(0) as u64).sum())
+}
+
+/// Details of a local image.
+pub struct ImageDetails {
+    /// Short image ID, as printed by `docker images`.
+    pub id: String,
+    /// Repository digests.
+    pub digests: Vec<String>,
+    /// Labels of the image.
+    pub labels: HashMap<String, String>,
+}
+
+/// Inspects the image of a local version.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if the image does not exist.
+pub fn inspect(version: &str) -> Result<ImageDetails, CaveError> {
+    let (docker, rt) = client()?;
+    let reference = format!("{}:{}", REPOSITORY, version);
+    cmdlog::record_args(["docker", "image", "inspect", &reference]);
+    let image = rt
+        .block_on(docker.inspect_image(&reference))
+        .map_err(|e| api_error(&format!("Failed to inspect {}", reference), e))?;
+    let id = image.id.unwrap_or_default();
+    let id = id.strip_prefix("sha256:").unwrap_or(&id);
+    Ok(ImageDetails {
+        id: id.chars().take(12).collect(),
+        digests: image.repo_digests.unwrap_or_default(),
+        labels: image.config.and_then(|c| c.labels).unwrap_or_default(),
+    })
+}
+
+/// Pulls an image, printing the download progress on one line. The
+/// credentials of `registry` are sent with the request, if given.
+///
+/// # Errors
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::DockerError`] if the pull fails, with the daemon's message.
+pub fn pull(image: &str, registry: Option<&Registry>) -> Result<(), CaveError> {
+    let (docker, rt) = client()?;
+    cmdlog::record_args(["docker", "pull", image]);
+    let options = CreateImageOptions {
+        from_image: image,
+        ..Default::default()
+    };
+    // Interrupting is checked between events: the pull stops when the stream is dropped
+    let _guard = signals::register(Operation::Request);
+    let mut progress = PullProgress::start(image);
+    let credentials = registry.map(|registry| DockerCredentials {
+        username: Some(registry.user.clone()),
+        password: Some(registry.token.clone()),
+        serveraddress: Some(registry.host.clone()),
+        ..Default::default()
+    });
+    let mut stream = docker.create_image(Some(options), None, credentials);
+    let result = rt.block_on(async {
+        while let Some(info) = stream.next().await {
+            if signals::cancelled() {
+                return Err(CaveError::Cancelled);
+            }
+            let info = info.map_err(|e| api_error(&format!("Failed to pull image {}", image), e))?;
+            if let Some(message) = info.error {
+                return Err(CaveError::DockerError(format!(
+                    "Failed to pull image {}: {}",
+                    image, message
+                )));
+            }
+            let Some(status) = info.status else {
+                continue;
+            };
+            let Some(id) = info.id else {
+                debug!("Pull {}: {}", image, status);
+                continue;
+            };
+            let bytes = info
+                .progress_detail
+                .and_then(|detail| Some((u64::try_from(detail.current?).ok()?, u64::try_from(detail.total?).ok()?)));
+            progress.update(&id, &status, bytes);
+        }
+        Ok(())
+    });
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    if result.is_ok() {
+        progress.finish();
+    }
+    result
+}
+
+/// Removes a tag of a local image; the layers are deleted once no other tag
+/// references them.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] with the daemon's message, for
+/// instance when a container still uses the image.
+pub fn remove(image: &str) -> Result<(), CaveError> {
+    let (docker, rt) = client()?;
+    cmdlog::record_args(["docker", "rmi", image]);
+    rt.block_on(docker.remove_image(image, Some(RemoveImageOptions::default()), None))
+        .map_err(|e| api_error(&format!("Failed to remove {}", image), e))?;
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! Knowledge base of common failures (`cave explain`).
+//!
+//! Each entry explains a frequent code_aster fatal error or cave error and
+//! suggests a fix. code_aster entries are recognised in the message file of
+//! failed runs through text patterns, cave entries through
+//! [`CaveError::code`]; in both cases a hint pointing to `cave explain` is
+//! printed after the error.
+
+use crate::manage::CaveError;
+use crate::table::{Cell, Table};
+use colored::*;
+
+/// An entry of the knowledge base.
+pub struct Entry {
+    /// Identifier given to `cave explain`.
+    pub code: &'static str,
+    /// One-line summary.
+    pub title: &'static str,
+    /// What the error means.
+    pub explanation: &'static str,
+    /// How to fix it.
+    pub fix: &'static str,
+    /// Case-sensitive patterns identifying the error in a message file.
+    pub patterns: &'static [&'static str],
+}
+
+/// The knowledge base.
+pub const ENTRIES: &[Entry] = &[
+    Entry {
+        code: "aster-memory",
+        title: "code_aster ran out of memory",
+        explanation: "The memory requested for the job (`memory_limit` in the export file) is too low for the model, or the host itself ran out of memory and the kernel killed the solver.",
+        fix: "Increase `memory_limit` in the export file, use a less memory-hungry linear solver (MUMPS with `GESTION_MEMOIRE='OUT_OF_CORE'`, or PETSC), or run on a host with more memory.",
+        patterns: &[
+            "MEMOIRE INSUFFISANTE",
+            "mémoire insuffisante",
+            "Cannot allocate memory",
+            "std::bad_alloc",
+            "Out of memory",
+        ],
+    },
+    Entry {
+        code: "aster-cpu-time",
+        title: "code_aster stopped by lack of CPU time",
+        explanation: "The job reached the time limit given in the export file (`time_limit`) or with `--tpmax`.",
+        fix: "Increase `time_limit` in the export file, or `--tpmax` in the `[run]` arguments of cave.toml.",
+        patterns: &["MANQUE DE TEMPS CPU", "manque de temps CPU"],
+    },
+    Entry {
+        code: "aster-mesh-group",
+        title: "A mesh group used in the command file does not exist",
+        explanation: "A `GROUP_MA` or `GROUP_NO` referenced in the command file is not defined in the mesh, often because of a typo, a renamed group in the mesher, or a group name longer than 24 characters.",
+        fix: "Check the group names in the mesh (e.g. with `INFO_MAILLAGE`) and in the command file, including their case.",
+        patterns: &["ne fait pas partie du maillage", "n'appartient pas au maillage"],
+    },
+    Entry {
+        code: "aster-catalog",
+        title: "The command file does not match the command catalog of this version",
+        explanation: "A keyword or a value is not accepted by the command catalog of the code_aster version in use. Command files written for another version often use renamed or removed keywords.",
+        fix: "Check the keyword in the documentation of the version pinned in `.cave`, or pin the version the command file was written for.",
+        patterns: &[
+            "n'est pas autorisé",
+            "mot-clé inconnu",
+            "Unexpected keyword",
+            "Unauthorized keyword",
+            "SyntaxError",
+        ],
+    },
+    Entry {
+        code: "aster-convergence",
+        title: "The non-linear solver did not converge",
+        explanation: "Newton iterations did not converge within `ITER_GLOB_MAXI` iterations and the time step could not be subdivided further.",
+        fix: "Enable automatic time step subdivision (`DEFI_LIST_INST` with `ECHEC`), increase `ITER_GLOB_MAXI`, or check the boundary conditions and material parameters for instabilities.",
+        patterns: &["NON CONVERGENCE", "non convergence", "ECHEC DE LA SUBDIVISION"],
+    },
+    Entry {
+        code: "aster-singular-matrix",
+        title: "The system matrix is singular",
+        explanation: "The model has rigid body modes, usually because of missing or insufficient boundary conditions, disconnected parts, or elements with zero stiffness.",
+        fix: "Check that every part of the model is constrained, and that contacts and connections are properly defined.",
+        patterns: &["matrice est singulière", "MATRICE SINGULIERE", "pivot nul", "singular matrix"],
+    },
+    Entry {
+        code: "no-docker",
+        title: "Docker is not installed",
+        explanation: "cave runs code_aster in Docker containers and could not find the `docker` command.",
+        fix: "Install Docker (https://docs.docker.com/get-docker/) and make sure `docker` is in your PATH.",
+        patterns: &[],
+    },
+    Entry {
+        code: "docker-daemon",
+        title: "The Docker daemon is not running",
+        explanation: "The `docker` command is installed but cannot reach the Docker daemon.",
+        fix: "Start Docker Desktop, or run `sudo systemctl start docker`.",
+        patterns: &[],
+    },
+    Entry {
+        code: "docker-permission",
+        title: "Permission denied on the Docker socket",
+        explanation: "Your user is not allowed to talk to the Docker daemon.",
+        fix: "Add your user to the docker group with `sudo usermod -aG docker $USER`, then log out and back in.",
+        patterns: &[],
+    },
+    Entry {
+        code: "version-not-installed",
+        title: "The selected code_aster version is not installed",
+        explanation: "The version pinned in `.cave` or `~/.cave` has no local Docker image.",
+        fix: "Run `cave pin <version>` or `cave use <version>` to download it, or `cave list` to see the installed versions.",
+        patterns: &[],
+    },
+    Entry {
+        code: "version-not-available",
+        title: "The requested code_aster version does not exist",
+        explanation: "No image with this tag is published on DockerHub.",
+        fix: "Run `cave available` to list the published versions.",
+        patterns: &[],
+    },
+    Entry {
+        code: "run-in-progress",
+        title: "Another run is in progress in this directory",
+        explanation: "Two runs in the same study directory would overwrite each other's result files, so cave refuses to start a second one.",
+        fix: "Wait for the other run to finish, or use `cave run --force` if the lock is left over from a crashed run.",
+        patterns: &[],
+    },
+    Entry {
+        code: "restart-version-mismatch",
+        title: "The study restarts from a database of another code_aster version",
+        explanation: "`POURSUITE` reads the database of a previous run, whose format is specific to the code_aster version that wrote it. Reading it with another version can fail, or worse, silently give wrong results.",
+        fix: "Pin the version that produced the database for the restart, or run the whole study, including its initial part, with the new version.",
+        patterns: &[],
+    },
+    Entry {
+        code: "project-config",
+        title: "The cave.toml file is invalid",
+        explanation: "The project configuration could not be parsed, or contains unknown keys.",
+        fix: "Fix the reported line of cave.toml; see `man cave` for the supported sections.",
+        patterns: &[],
+    },
+    Entry {
+        code: "invalid-export",
+        title: "The export file is invalid",
+        explanation: "The export file given to `cave run` is malformed, lacks a memory or time limit or a command file, or references input files that do not exist. code_aster would fail on it after the container started.",
+        fix: "Fix the reported lines; relative paths are taken from the directory of the export file. Run `cave check <file.export>` to check it again.",
+        patterns: &[],
+    },
+];
+
+/// Returns the entry with the given code.
+pub fn find(code: &str) -> Option<&'static Entry> {
+    ENTRIES.iter().find(|e| e.code.eq_ignore_ascii_case(code))
+}
+
+/// Returns the entries whose patterns appear in `text`.
+pub fn diagnose(text: &str) -> Vec<&'static Entry> {
+    ENTRIES
+        .iter()
+        .filter(|e| e.patterns.iter().any(|p| text.contains(p)))
+        .collect()
+}
+
+/// Prints a one-line hint for each entry, pointing to `cave explain`.
+pub fn print_hints(entries: &[&Entry]) {
+    for entry in entries {
+        eprintln!(
+            "{} {}. Run `cave explain {}` for details.",
+            "Hint:".cyan().bold(),
+            entry.title,
+            entry.code
+        );
+    }
+}
+
+/// Handler of `cave explain`: prints the entry matching `query`, given as a
+/// code or as an error message, or lists every entry when `query` is `None`.
+///
+/// # Errors
+/// Returns [`CaveError::FileNotFound`] if nothing in the knowledge base matches.
+pub fn explain(query: Option<String>) -> Result<(), CaveError> {
+    let Some(query) = query else {
+        let mut table = Table::new(&["Code", "Description"]);
+        for entry in ENTRIES {
+            table.push(vec![Cell::from(entry.code).bold(), entry.title.into()]);
+        }
+        table.print();
+        return Ok(());
+    };
+
+    let entries = match find(&query) {
+        Some(entry) => vec![entry],
+        None => diagnose(&query),
+    };
+    if entries.is_empty() {
+        return Err(CaveError::FileNotFound(format!(
+            "No explanation found for '{}'. Run `cave explain` to list the known errors.",
+            query
+        )));
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{} {}", entry.code.bold(), entry.title);
+        println!();
+        println!("{}", entry.explanation);
+        println!();
+        println!("{} {}", "Fix:".green().bold(), entry.fix);
+    }
+    Ok(())
+}
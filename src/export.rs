@@ -0,0 +1,502 @@
+//! Validation of code_aster export files (`cave check`).
+//!
+//! An export file describes a study for `run_aster`, one entry per line:
+//! - `P name value...`: a parameter, such as `P memory_limit 2048`;
+//! - `A name value`: an argument of code_aster, such as `A tpmax 60`;
+//! - `F type path flags unit`: a file, `D` flagged for an input, `R` for a
+//!   result, `C` for a compressed one;
+//! - `R type path flags unit`: a directory, such as a database.
+//!
+//! The file is checked before the container starts, so that a typo or a
+//! missing mesh is reported in a second instead of after the image is
+//! started: `cave check` fails on it, runs print it as a warning.
+//! Relative paths are taken from the directory of the export file; absolute
+//! paths are container paths and are not checked.
+//!
+//! `cave init-export` writes a starter export file for a study of the current
+//! directory, which is the directory mounted in the container.
+
+use crate::answers;
+use crate::manage::CaveError;
+use colored::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+/// Memory limit parameter, in MiB, and its code_aster argument counterpart.
+const MEMORY_LIMIT: [&str; 2] = ["memory_limit", "memjeveux"];
+/// Time limit parameter, in seconds, and its code_aster argument counterpart.
+const TIME_LIMIT: [&str; 2] = ["time_limit", "tpmax"];
+/// Limits of a generated export file, in MiB and seconds.
+const DEFAULT_MEMORY_MB: u32 = 2048;
+const DEFAULT_TIME_SECS: u32 = 3600;
+/// Mesh file extensions, with their export file type.
+const MESH_TYPES: [(&str, &str); 3] = [("med", "mmed"), ("mmed", "mmed"), ("mail", "mail")];
+/// Parameters holding a positive integer.
+const INTEGER_PARAMETERS: [&str; 4] = ["ncpus", "mpi_nbcpu", "mpi_nbnoeud", "mpi_ncpus"];
+
+/// Severity of a problem found in an export file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The run would fail.
+    Error,
+    /// The run may not do what is expected.
+    Warning,
+}
+
+/// A problem found in an export file.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Line number, from 1, when the problem is on a single line.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(line: Option<usize>, message: String) -> Self {
+        Problem { severity: Severity::Error, line, message }
+    }
+
+    fn warning(line: Option<usize>, message: String) -> Self {
+        Problem { severity: Severity::Warning, line, message }
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// A file or directory entry of an export file.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: usize,
+    /// Type of the file, such as `comm`, `mmed` or `base`.
+    pub kind: String,
+    pub path: String,
+    pub input: bool,
+    pub output: bool,
+    pub directory: bool,
+    pub unit: u32,
+}
+
+/// Contents of an export file.
+#[derive(Debug, Default)]
+pub struct Export {
+    /// `P` parameters and `A` arguments, with their line and values.
+    pub parameters: HashMap<String, (usize, Vec<String>)>,
+    pub entries: Vec<Entry>,
+}
+
+impl Export {
+    /// Parses an export file, returning the malformed lines as problems.
+    pub fn parse(content: &str) -> (Self, Vec<Problem>) {
+        let mut export = Export::default();
+        let mut problems = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let number = i + 1;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                [first, ..] if first.starts_with('#') => {}
+                ["P" | "A", name, values @ ..] => {
+                    let values = values.iter().map(|v| v.to_string()).collect();
+                    export.parameters.insert(name.to_string(), (number, values));
+                }
+                [kind @ ("F" | "R"), file_type, path, flags, unit] => {
+                    let Ok(unit) = unit.parse() else {
+                        problems.push(Problem::error(
+                            Some(number),
+                            format!("the logical unit '{}' is not a number", unit),
+                        ));
+                        continue;
+                    };
+                    if flags.is_empty() || !flags.chars().all(|c| "DRC".contains(c)) {
+                        problems.push(Problem::error(
+                            Some(number),
+                            format!("invalid flags '{}', expected a combination of D, R and C", flags),
+                        ));
+                        continue;
+                    }
+                    export.entries.push(Entry {
+                        line: number,
+                        kind: file_type.to_string(),
+                        path: path.to_string(),
+                        input: flags.contains('D'),
+                        output: flags.contains('R'),
+                        directory: *kind == "R",
+                        unit,
+                    });
+                }
+                ["F" | "R", ..] => problems.push(Problem::error(
+                    Some(number),
+                    "expected `F|R type path flags unit`".to_string(),
+                )),
+                [other, ..] => problems.push(Problem::error(
+                    Some(number),
+                    format!("unknown entry '{}', expected P, A, F or R", other),
+                )),
+            }
+        }
+        (export, problems)
+    }
+
+    /// Returns the first of the given parameters that is set, with its line and value.
+    fn parameter(&self, names: &[&'static str]) -> Option<(&'static str, usize, &[String])> {
+        names.iter().find_map(|name| {
+            self.parameters
+                .get(*name)
+                .map(|(line, values)| (*name, *line, values.as_slice()))
+        })
+    }
+
+    /// Returns the value of the first of the given parameters that is a number.
+    fn number(&self, names: &[&'static str]) -> Option<(&'static str, f64)> {
+        match self.parameter(names)? {
+            (name, _, [value]) => value.parse().ok().map(|v| (name, v)),
+            _ => None,
+        }
+    }
+
+    /// Returns the memory limit in MiB, `memjeveux` being in megawords of 8 bytes.
+    pub fn memory_limit(&self) -> Option<f64> {
+        self.number(&MEMORY_LIMIT)
+            .map(|(name, value)| if name == "memjeveux" { value * 8.0 } else { value })
+    }
+
+    /// Returns the time limit in seconds.
+    pub fn time_limit(&self) -> Option<f64> {
+        self.number(&TIME_LIMIT).map(|(_, value)| value)
+    }
+
+    /// Returns a positive integer parameter, such as `ncpus`.
+    pub fn integer(&self, name: &'static str) -> Option<u32> {
+        match self.parameter(&[name])? {
+            (_, _, [value]) => value.parse().ok().filter(|v| *v > 0),
+            _ => None,
+        }
+    }
+
+    /// Returns the code_aster version declared by the `P version` parameter.
+    pub fn version(&self) -> Option<&str> {
+        match self.parameter(&["version"])? {
+            (_, _, [version]) => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Checks the limits, the parameters and the entries, the relative paths
+    /// being taken from `base`.
+    pub fn validate(&self, base: &Path) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        for (names, what) in [(MEMORY_LIMIT, "memory"), (TIME_LIMIT, "time")] {
+            match self.parameter(&names) {
+                None => problems.push(Problem::error(
+                    None,
+                    format!("no {} limit, add `P {} <value>`", what, names[0]),
+                )),
+                Some((name, line, values)) => {
+                    if !matches!(values, [value] if value.parse::<f64>().is_ok_and(|v| v > 0.0)) {
+                        problems.push(Problem::error(
+                            Some(line),
+                            format!("the {} limit '{}' is not a positive number", what, name),
+                        ));
+                    }
+                }
+            }
+        }
+        for name in INTEGER_PARAMETERS {
+            if let Some((_, line, values)) = self.parameter(&[name]) {
+                if !matches!(values, [value] if value.parse::<u32>().is_ok_and(|v| v > 0)) {
+                    problems.push(Problem::error(
+                        Some(line),
+                        format!("'{}' is not a positive integer", name),
+                    ));
+                }
+            }
+        }
+
+        if !self.entries.iter().any(|e| e.kind == "comm" && e.input) {
+            problems.push(Problem::error(None, "no command file, add `F comm <file> D 1`".to_string()));
+        }
+
+        let mut units: HashMap<(u32, bool), usize> = HashMap::new();
+        for entry in &self.entries {
+            if entry.unit != 0 {
+                if let Some(first) = units.insert((entry.unit, entry.input), entry.line) {
+                    problems.push(Problem::warning(
+                        Some(entry.line),
+                        format!("logical unit {} is also used line {}", entry.unit, first),
+                    ));
+                }
+            }
+            if Path::new(&entry.path).is_absolute() {
+                continue;
+            }
+            let path = base.join(&entry.path);
+            if entry.input {
+                let found = if entry.directory { path.is_dir() } else { path.is_file() };
+                if !found {
+                    problems.push(Problem::error(
+                        Some(entry.line),
+                        format!("input {} '{}' not found", entry.kind, entry.path),
+                    ));
+                }
+            } else if entry.output {
+                // code_aster creates the result files, not their directories
+                let parent = path.parent().unwrap_or(base);
+                if !parent.is_dir() {
+                    problems.push(Problem::warning(
+                        Some(entry.line),
+                        format!(
+                            "the directory of the result {} '{}' does not exist",
+                            entry.kind, entry.path
+                        ),
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Reads, parses and validates an export file.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if the file does not exist.
+/// - [`CaveError::IoError`] if it cannot be read.
+pub fn check_file(file: &str) -> Result<Vec<Problem>, CaveError> {
+    let path = Path::new(file);
+    if !path.is_file() {
+        return Err(CaveError::FileNotFound(format!("Export file '{}' not found.", file)));
+    }
+    let content = fs::read_to_string(path)?;
+    let (export, mut problems) = Export::parse(&content);
+    let base = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    problems.extend(export.validate(base));
+    problems.sort_by_key(|p| p.line.unwrap_or(0));
+    Ok(problems)
+}
+
+/// Checks an export file before a run: warnings are printed, errors abort.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidExport`] if the file has errors.
+pub fn ensure_valid(file: &str) -> Result<(), CaveError> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = check_file(file)?
+        .into_iter()
+        .partition(|p| p.severity == Severity::Error);
+    for warning in &warnings {
+        eprintln!("{} {}: {}", "Warning:".yellow().bold(), file, warning);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(CaveError::InvalidExport {
+            file: file.to_string(),
+            problems: errors.iter().map(|p| p.to_string()).collect(),
+        })
+    }
+}
+
+/// Checks an export file before a run, printing its problems as warnings:
+/// the limits may come from the `cave.toml` arguments or a preset, and the
+/// inputs from the staging of the run, so that only `cave check` fails on them.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if the file does not exist.
+/// - [`CaveError::IoError`] if it cannot be read.
+pub fn warn_problems(file: &str) -> Result<(), CaveError> {
+    for problem in check_file(file)? {
+        eprintln!("{} {}: {}", "Warning:".yellow().bold(), file, problem);
+    }
+    Ok(())
+}
+
+/// Handler of `cave check`: prints the warnings of an export file, and
+/// fails with its errors.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidExport`] if the file has errors.
+pub fn check(file: &str) -> Result<(), CaveError> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = check_file(file)?
+        .into_iter()
+        .partition(|p| p.severity == Severity::Error);
+    for warning in &warnings {
+        println!("{:<6}{}", "warn".yellow().bold(), warning);
+    }
+    if !errors.is_empty() {
+        return Err(CaveError::InvalidExport {
+            file: file.to_string(),
+            problems: errors.iter().map(|p| p.to_string()).collect(),
+        });
+    }
+    println!("{} {} is valid.", "ok".green().bold(), file);
+    Ok(())
+}
+
+/// Returns the files of the current directory with one of `extensions`.
+pub fn files_with(extensions: &[&str]) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(".")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| extensions.contains(&e))
+        })
+        .filter_map(|path| path.file_name()?.to_str().map(str::to_string))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Asks a positive number until one is given.
+fn ask_number(question: &str, default: u32) -> Result<u32, CaveError> {
+    loop {
+        match answers::ask(question, Some(&default.to_string()))?.parse() {
+            Ok(value) if value > 0 => return Ok(value),
+            _ => println!("Expected a positive integer."),
+        }
+    }
+}
+
+/// Returns the path of a study file relative to the current directory,
+/// the only one seen by the container.
+///
+/// # Errors
+/// Returns [`CaveError::FileNotFound`] if the file does not exist or is
+/// outside the current directory.
+pub fn study_path(file: &str) -> Result<String, CaveError> {
+    let path = fs::canonicalize(file)
+        .map_err(|_| CaveError::FileNotFound(format!("File '{}' not found.", file)))?;
+    let dir = fs::canonicalize(".")?;
+    let relative = path.strip_prefix(&dir).map_err(|_| {
+        CaveError::FileNotFound(format!(
+            "'{}' is outside the current directory, the only one mounted in the container. Copy it into the study, or use `cave run --mount` and an absolute container path.",
+            file
+        ))
+    })?;
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Handler of `cave init-export`: writes a starter export file for a study of
+/// the current directory. The values not given are asked when the standard
+/// input is a terminal, and take their default otherwise: the only command
+/// file and mesh of the directory, 2048 MiB and one hour.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if the command file or the mesh cannot be
+///   found in the current directory.
+/// - [`CaveError::InvalidExport`] if `output` is not a file name ending with `.export`.
+/// - [`CaveError::FileExists`] if the export file exists and `force` is not set.
+pub fn init(
+    comm: Option<String>,
+    mesh: Option<String>,
+    memory: Option<u32>,
+    time: Option<u32>,
+    output: Option<String>,
+    force: bool,
+) -> Result<(), CaveError> {
+    let interactive = io::stdin().is_terminal();
+
+    let comms = files_with(&["comm"]);
+    let default_comm = (comms.len() == 1).then(|| comms[0].as_str());
+    let comm = match comm {
+        Some(comm) => comm,
+        None if interactive => answers::ask("Command file", default_comm)?,
+        None => default_comm.unwrap_or_default().to_string(),
+    };
+    if comm.is_empty() {
+        return Err(CaveError::FileNotFound(
+            "No command file given, and no single .comm file in the current directory. Use `cave init-export --comm <file>`.".to_string(),
+        ));
+    }
+    let comm = study_path(&comm)?;
+
+    let extensions: Vec<&str> = MESH_TYPES.iter().map(|(extension, _)| *extension).collect();
+    let meshes = files_with(&extensions);
+    let default_mesh = (meshes.len() == 1).then(|| meshes[0].as_str());
+    let mesh = match mesh {
+        Some(mesh) => Some(mesh),
+        None if interactive => Some(answers::ask("Mesh file (empty for none)", default_mesh)?),
+        None => default_mesh.map(str::to_string),
+    };
+    let mesh = match mesh.filter(|m| !m.is_empty()) {
+        Some(mesh) => {
+            let path = study_path(&mesh)?;
+            let extension = Path::new(&path).extension().and_then(|e| e.to_str());
+            let kind = MESH_TYPES
+                .iter()
+                .find(|(ext, _)| Some(*ext) == extension)
+                .map_or("mmed", |(_, kind)| *kind);
+            Some((kind, path))
+        }
+        None => None,
+    };
+
+    let memory = match memory {
+        Some(memory) => memory,
+        None if interactive => ask_number("Memory limit in MiB", DEFAULT_MEMORY_MB)?,
+        None => DEFAULT_MEMORY_MB,
+    };
+    let time = match time {
+        Some(time) => time,
+        None if interactive => ask_number("Time limit in seconds", DEFAULT_TIME_SECS)?,
+        None => DEFAULT_TIME_SECS,
+    };
+
+    let stem = Path::new(&comm)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("study")
+        .to_string();
+    let output = output.unwrap_or_else(|| format!("{}.export", stem));
+    if Path::new(&output).components().count() != 1 || !output.ends_with(".export") {
+        return Err(CaveError::InvalidExport {
+            file: output,
+            problems: vec!["expected a file name ending with .export, written in the current directory".to_string()],
+        });
+    }
+    if Path::new(&output).exists() && !force {
+        return Err(CaveError::FileExists {
+            file: output,
+            command: "cave init-export",
+        });
+    }
+
+    // The paths are relative to the export file, in the study directory
+    let mut lines = vec![
+        format!("P time_limit {}", time),
+        format!("P memory_limit {}", memory),
+        "P ncpus 1".to_string(),
+        "P mpi_nbcpu 1".to_string(),
+        "P mpi_nbnoeud 1".to_string(),
+        format!("F comm {} D 1", comm),
+    ];
+    if let Some((kind, path)) = &mesh {
+        lines.push(format!("F {} {} D 20", kind, path));
+    }
+    lines.push(format!("F mess {}.mess R 6", stem));
+    lines.push(format!("F rmed {}.rmed R 80", stem));
+    fs::write(&output, lines.join("\n") + "\n")?;
+
+    println!("Wrote {}", output);
+    ensure_valid(&output)?;
+    println!("Run it with `cave run -- {}`.", output);
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! Project-specific result extractors.
+//!
+//! The extractors declared in the `[extractors]` section of `cave.toml` are
+//! external programs run after each run, from the project directory (the
+//! one of `cave.toml`) so that their relative paths are the ones of the
+//! file, with the absolute paths of the produced files as arguments. The
+//! environment holds `CAVE_RUN_ID`, `CAVE_RUN_DIR` (the study directory),
+//! `CAVE_VERSION` and `CAVE_EXPORT`. The program prints on its standard
+//! output a JSON object of numbers:
+//!
+//! ```json
+//! {"max_vmis": 312.5, "max_dz": -1.2e-3}
+//! ```
+//!
+//! The values are added to the [`ResultsSummary`] of the run under
+//! `<extractor>:<key>`, so that `cave diff-results` and `cave nrt` compare
+//! them like table values. A failing extractor only prints a warning.
+
+use crate::project::{find_project_dir, ExtractorConfig};
+use crate::results::ResultsSummary;
+use colored::*;
+use log::debug;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Context of the run given to the extractors.
+pub struct RunContext<'a> {
+    /// Study directory.
+    pub dir: &'a Path,
+    /// Run identifier.
+    pub run_id: &'a str,
+    /// code_aster version.
+    pub version: &'a str,
+    /// Export file of the run.
+    pub export: Option<&'a str>,
+}
+
+/// Runs one extractor and returns its values.
+fn extract(
+    name: &str,
+    extractor: &ExtractorConfig,
+    files: &[&String],
+    run: &RunContext,
+) -> Result<BTreeMap<String, f64>, String> {
+    let (program, args) = extractor.command.split_first().ok_or("empty command")?;
+    let project_dir = find_project_dir(run.dir).unwrap_or_else(|| run.dir.to_path_buf());
+    let output = Command::new(program)
+        .args(args)
+        .args(files.iter().map(|file| run.dir.join(file)))
+        .current_dir(&project_dir)
+        .env("CAVE_RUN_ID", run.run_id)
+        .env("CAVE_RUN_DIR", run.dir)
+        .env("CAVE_VERSION", run.version)
+        .env("CAVE_EXPORT", run.export.unwrap_or_default())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("exited with {}", output.status));
+    }
+    let values: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("output is not a JSON object: {}", e))?;
+    Ok(values
+        .into_iter()
+        .filter_map(|(key, value)| match value.as_f64() {
+            Some(number) => Some((format!("{}:{}", name, key), number)),
+            None => {
+                debug!(
+                    "Extracteur {}: valeur non numérique ignorée pour {}",
+                    name, key
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/// Runs the extractors on the files produced by a run and adds their values
+/// to `summary`, creating it if needed.
+pub fn apply(
+    extractors: &BTreeMap<String, ExtractorConfig>,
+    run: &RunContext,
+    produced_files: &[String],
+    summary: &mut Option<ResultsSummary>,
+) {
+    for (name, extractor) in extractors {
+        let files: Vec<&String> = produced_files
+            .iter()
+            .filter(|f| {
+                extractor.extensions.is_empty()
+                    || Path::new(f)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .is_some_and(|e| extractor.extensions.iter().any(|x| x == e))
+            })
+            .collect();
+        if files.is_empty() {
+            debug!("Extracteur {}: aucun fichier à traiter", name);
+            continue;
+        }
+        match extract(name, extractor, &files, run) {
+            Ok(values) => summary
+                .get_or_insert_with(ResultsSummary::default)
+                .values
+                .extend(values),
+            Err(e) => eprintln!(
+                "{} extractor {} failed: {}",
+                "Warning:".yellow().bold(),
+                name,
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Human-readable numbers, sizes and durations, shared by the commands
+//! printing tables and summaries.
+//!
+//! Decimal and thousands separators follow the locale of the environment
+//! (`LC_ALL`, `LC_NUMERIC`, then `LANG`): `1,234.5` in English, `1 234,5` in
+//! French, `1.234,5` in German. Machine-readable outputs (`--json`, JUnit
+//! reports, history records) do not use these functions.
+
+use std::env;
+use std::sync::OnceLock;
+
+/// Decimal and thousands separators of a locale.
+#[derive(Debug, Clone, Copy)]
+struct Separators {
+    decimal: char,
+    thousands: char,
+}
+
+static SEPARATORS: OnceLock<Separators> = OnceLock::new();
+
+/// Returns the separators of a locale name such as `fr_FR.UTF-8`.
+fn locale_separators(locale: &str) -> Separators {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+    match language {
+        "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "uk" => Separators {
+            decimal: ',',
+            thousands: ' ',
+        },
+        "de" | "es" | "it" | "pt" | "nl" | "da" | "tr" | "id" => Separators {
+            decimal: ',',
+            thousands: '.',
+        },
+        _ => Separators {
+            decimal: '.',
+            thousands: ',',
+        },
+    }
+}
+
+fn separators() -> Separators {
+    *SEPARATORS.get_or_init(|| {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        locale_separators(&locale)
+    })
+}
+
+/// Groups the digits of an integer part by thousands.
+fn group(digits: &str, thousands: char) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(thousands);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Formats a number with `precision` decimals and the separators of the locale.
+pub fn decimal(value: f64, precision: usize) -> String {
+    let separators = separators();
+    let formatted = format!("{:.*}", precision, value.abs());
+    let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    let mut result = String::new();
+    if value < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        result.push('-');
+    }
+    result.push_str(&group(integer, separators.thousands));
+    if !fraction.is_empty() {
+        result.push(separators.decimal);
+        result.push_str(fraction);
+    }
+    result
+}
+
+/// Formats a count with the thousands separator of the locale.
+pub fn count(value: u64) -> String {
+    group(&value.to_string(), separators().thousands)
+}
+
+/// Formats a size in bytes with binary units, such as `512 B`, `3,2 MiB`.
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{} {}", decimal(value, 1), UNITS[unit])
+}
+
+/// Formats a duration in milliseconds as `12.3s`, `3m05s` or `1h02m`.
+pub fn duration_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..=59 => format!("{}s", decimal(ms as f64 / 1000.0, 1)),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Formats the time elapsed since an event, in seconds, as `5 min ago`.
+pub fn age(secs: i64) -> String {
+    match secs {
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{} min ago", s / 60),
+        s if s < 86400 => format!("{} h ago", s / 3600),
+        s => format!("{} days ago", count((s / 86400) as u64)),
+    }
+}
@@ -0,0 +1,237 @@
+//! Environment manifests for long-term archival (`cave freeze` / `cave thaw`).
+//!
+//! `cave freeze` writes `cave-freeze.json` in the project directory. It
+//! records everything needed to run the studies again on another machine,
+//! years later: the image digest of the pinned version, the cave version,
+//! the execution-relevant configuration, the project's `cave.toml`, and the
+//! SHA-256 of the study inputs (export files and the data files they read).
+//! `cave thaw` pulls the image by digest, restores the pin and `cave.toml`,
+//! and checks the inputs against their recorded hashes.
+
+use crate::config::read_config;
+use crate::docker::{exists_locally, image_digest, image_id, pull_image, pull_version, tag_image};
+use crate::manage::{read_cave_version, write_pin, CaveError};
+use crate::project::{find_project_dir, PROJECT_FILE};
+use crate::stage::list_files;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Default name of the manifest.
+pub const FREEZE_FILE: &str = "cave-freeze.json";
+
+/// A study input and its hash.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputHash {
+    /// Path relative to the project directory.
+    pub path: String,
+    /// SHA-256 of the content, in hexadecimal.
+    pub sha256: String,
+}
+
+/// Content of `cave-freeze.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of cave that wrote the manifest.
+    pub cave_version: String,
+    /// Date of the freeze, RFC 3339 in UTC.
+    pub created: String,
+    /// code_aster version pinned for the project.
+    pub version: String,
+    /// Docker image ID of the version.
+    pub image_id: String,
+    /// Repository digest of the image, when it was pulled from a registry.
+    pub image_digest: Option<String>,
+    /// License server environment injected into runs.
+    pub license_env: BTreeMap<String, String>,
+    /// Content of the project's `cave.toml`, if any.
+    pub project_config: Option<String>,
+    /// Study inputs.
+    pub inputs: Vec<InputHash>,
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the study inputs of a project: its export files and the data
+/// files (`F` lines with the `D` flag) they read, relative to `dir`.
+fn study_inputs(dir: &Path) -> Result<Vec<PathBuf>, CaveError> {
+    let mut inputs = Vec::new();
+    for export in list_files(dir)?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "export"))
+        .filter(|p| !p.to_string_lossy().starts_with(".cave-"))
+    {
+        let base = export.parent().map(Path::to_path_buf).unwrap_or_default();
+        for line in fs::read_to_string(dir.join(&export))?.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let ["F", _, path, flags, ..] = fields.as_slice() {
+                let path = base.join(path);
+                if flags.contains('D') && dir.join(&path).is_file() {
+                    inputs.push(path);
+                }
+            }
+        }
+        inputs.push(export);
+    }
+    inputs.sort();
+    inputs.dedup();
+    Ok(inputs)
+}
+
+fn project_dir() -> Result<PathBuf, CaveError> {
+    let current_dir = std::env::current_dir()?;
+    Ok(find_project_dir(&current_dir).unwrap_or(current_dir))
+}
+
+/// Handler of `cave freeze`: writes the manifest of the project, to `output`
+/// or to `cave-freeze.json` in the project directory.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the pinned version is not installed.
+/// - Any error reading the study inputs or writing the manifest.
+pub fn freeze(output: Option<String>) -> Result<(), CaveError> {
+    let dir = project_dir()?;
+    let version = read_cave_version()?;
+    if !exists_locally(&version)? {
+        return Err(CaveError::VersionNotInstalled(version));
+    }
+    let digest = image_digest(&version);
+    if digest.is_none() {
+        eprintln!(
+            "{} version {} has no registry digest (locally built image), `cave thaw` will pull it by tag.",
+            "Warning:".yellow().bold(),
+            version
+        );
+    }
+
+    let project_config = match fs::read_to_string(dir.join(PROJECT_FILE)) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    let inputs = study_inputs(&dir)?
+        .into_iter()
+        .map(|path| {
+            Ok(InputHash {
+                sha256: sha256(&dir.join(&path))?,
+                path: path.display().to_string(),
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let manifest = Manifest {
+        cave_version: env!("CARGO_PKG_VERSION").to_string(),
+        created: chrono::Utc::now().to_rfc3339(),
+        image_id: image_id(&version)?,
+        image_digest: digest,
+        version,
+        license_env: read_config()?.license_env,
+        project_config,
+        inputs,
+    };
+    let path = output.map(PathBuf::from).unwrap_or_else(|| dir.join(FREEZE_FILE));
+    let content = serde_json::to_string_pretty(&manifest).map_err(CaveError::SerdeError)?;
+    fs::write(&path, content + "\n")?;
+
+    println!(
+        "Froze version {} ({}) and {} input file(s) in {}",
+        manifest.version,
+        manifest.image_digest.as_deref().unwrap_or(&manifest.image_id),
+        manifest.inputs.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Handler of `cave thaw`: restores the environment described by a manifest
+/// (`cave-freeze.json` in the project directory by default).
+///
+/// The image is pulled by digest and tagged with its version, the version is
+/// pinned, and `cave.toml` is restored. An existing, different `cave.toml` is
+/// only replaced with `force`. Finally, the study inputs are checked against
+/// their recorded hashes.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if the manifest does not exist.
+/// - Any error pulling the image or writing the files.
+pub fn thaw(manifest: Option<String>, force: bool) -> Result<(), CaveError> {
+    let dir = project_dir()?;
+    let path = manifest.map(PathBuf::from).unwrap_or_else(|| dir.join(FREEZE_FILE));
+    let content = fs::read_to_string(&path).map_err(|_| {
+        CaveError::FileNotFound(format!("Manifest '{}' not found.", path.display()))
+    })?;
+    let manifest: Manifest = serde_json::from_str(&content).map_err(CaveError::SerdeError)?;
+
+    // Image
+    if exists_locally(&manifest.version)? {
+        if image_id(&manifest.version)? != manifest.image_id {
+            eprintln!(
+                "{} the local image of {} differs from the frozen one ({}).",
+                "Warning:".yellow().bold(),
+                manifest.version,
+                manifest.image_digest.as_deref().unwrap_or(&manifest.image_id)
+            );
+        }
+    } else if let Some(digest) = &manifest.image_digest {
+        pull_image(digest)?;
+        tag_image(digest, &manifest.version)?;
+    } else {
+        pull_version(&manifest.version)?;
+    }
+
+    // Pin and project configuration
+    write_pin(&dir.join(".cave"), &manifest.version)?;
+    println!("Pinned version {}", manifest.version);
+    if let Some(config) = &manifest.project_config {
+        let target = dir.join(PROJECT_FILE);
+        match fs::read_to_string(&target) {
+            Ok(current) if current == *config => {}
+            Ok(_) if !force => eprintln!(
+                "{} {} differs from the frozen one, use `cave thaw --force` to restore it.",
+                "Warning:".yellow().bold(),
+                PROJECT_FILE
+            ),
+            _ => {
+                fs::write(&target, config)?;
+                println!("Restored {}", PROJECT_FILE);
+            }
+        }
+    }
+
+    // License servers are machine-specific, only report the missing ones
+    let license_env = read_config()?.license_env;
+    for (name, value) in &manifest.license_env {
+        if license_env.get(name) != Some(value) {
+            println!(
+                "License variable {} differs, set it with: cave config set-license-env '{}={}'",
+                name, name, value
+            );
+        }
+    }
+
+    // Inputs
+    let mut mismatches = 0;
+    for input in &manifest.inputs {
+        let status = match sha256(&dir.join(&input.path)) {
+            Ok(hash) if hash == input.sha256 => continue,
+            Ok(_) => "modified".yellow().bold(),
+            Err(_) => "missing".red().bold(),
+        };
+        mismatches += 1;
+        println!("  {:<9}{}", status, input.path);
+    }
+    println!(
+        "{} of {} input file(s) match the manifest.",
+        manifest.inputs.len() - mismatches,
+        manifest.inputs.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Detection of silent, possibly hung, solvers.
+//!
+//! While a run is in progress, the output of its container is followed with
+//! `docker logs --follow`. When nothing is printed for longer than the
+//! configured threshold (`cave config set-heartbeat`), a warning is printed
+//! and, if enabled (`cave config enable-stack-capture`), the stacks of the
+//! solver process are dumped with `py-spy` or `gdb` when the image provides
+//! them. Each silence is recorded in the history, so that a long
+//! factorization can be told apart from a deadlock afterwards.
+
+use crate::cmdlog::{self, Logged};
+use crate::docker::runtime;
+use crate::config::state_dir;
+use colored::*;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Dumps the stacks of the newest python3 process (code_aster's supervisor),
+/// with py-spy if installed in the image, gdb otherwise.
+const STACK_SCRIPT: &str = "pid=$(pgrep -n python3) || exit 1; \
+    py-spy dump --native --pid \"$pid\" 2>/dev/null \
+    || gdb -p \"$pid\" -batch -ex 'thread apply all bt' 2>&1";
+
+/// A period without output, recorded in the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stall {
+    /// When the warning was raised, RFC 3339 in UTC.
+    pub date: String,
+    /// Time since the last output when the warning was raised, in seconds.
+    pub silent_secs: u64,
+    /// Stacks of the solver captured at that time.
+    pub stack_file: Option<String>,
+}
+
+/// Background monitor of the output of a container.
+pub struct Heartbeat {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Vec<Stall>>,
+}
+
+impl Heartbeat {
+    /// Starts monitoring the container whose ID is written in `cidfile`.
+    pub fn start(
+        cidfile: PathBuf,
+        run_id: String,
+        threshold: Duration,
+        capture_stack: bool,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = Arc::clone(&stop);
+            // The commands of the monitor belong to the run
+            let log = cmdlog::current().unwrap_or_default();
+            thread::spawn(move || {
+                let _log = log.enter();
+                monitor(&cidfile, &run_id, threshold, capture_stack, &stop)
+            })
+        };
+        Self { stop, handle }
+    }
+
+    /// Stops monitoring and returns the recorded silences.
+    pub fn finish(self) -> Vec<Stall> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// Follows the logs of a container, updating `last` (milliseconds since
+/// `start`) on each output.
+fn follow(container_id: &str, start: Instant, last: &Arc<AtomicU64>) -> Option<Child> {
+    let mut child = runtime().command()
+        .args(["logs", "--follow", "--tail", "0", container_id])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged()
+        .spawn()
+        .ok()?;
+    let streams: [Option<Box<dyn Read + Send>>; 2] = [
+        child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>),
+    ];
+    for mut stream in streams.into_iter().flatten() {
+        let last = Arc::clone(last);
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while matches!(stream.read(&mut buffer), Ok(n) if n > 0) {
+                last.store(start.elapsed().as_millis() as u64, Ordering::Relaxed);
+            }
+        });
+    }
+    Some(child)
+}
+
+/// Captures the stacks of the solver in the state directory.
+fn capture(container_id: &str, run_id: &str, index: usize) -> Option<PathBuf> {
+    let output = runtime().command()
+        .args(["exec", container_id, "sh", "-c", STACK_SCRIPT])
+        .logged()
+        .output()
+        .ok()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        debug!(
+            "Capture de la pile impossible: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+    let dir = state_dir().ok()?.join("stacks");
+    fs::create_dir_all(&dir).ok()?;
+    let path = dir.join(format!("{}-{}.txt", run_id, index));
+    fs::write(&path, &output.stdout).ok()?;
+    Some(path)
+}
+
+fn monitor(
+    cidfile: &Path,
+    run_id: &str,
+    threshold: Duration,
+    capture_stack: bool,
+    stop: &AtomicBool,
+) -> Vec<Stall> {
+    let start = Instant::now();
+    let last = Arc::new(AtomicU64::new(0));
+    let mut container_id = None;
+    let mut follower = None;
+    let mut stalls = Vec::new();
+    let mut warned = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(POLL_INTERVAL);
+        let Some(id) = &container_id else {
+            container_id = fs::read_to_string(cidfile)
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty());
+            if let Some(id) = &container_id {
+                last.store(start.elapsed().as_millis() as u64, Ordering::Relaxed);
+                follower = follow(id, start, &last);
+            }
+            continue;
+        };
+
+        let silent = start
+            .elapsed()
+            .saturating_sub(Duration::from_millis(last.load(Ordering::Relaxed)));
+        if silent < threshold {
+            warned = false;
+            continue;
+        }
+        if warned {
+            continue;
+        }
+        warned = true;
+        eprintln!(
+            "\n{} no output for {} min, the solver may be hung (or still factorizing).",
+            "Warning:".yellow().bold(),
+            silent.as_secs() / 60
+        );
+        let stack_file = if capture_stack {
+            capture(id, run_id, stalls.len() + 1)
+        } else {
+            None
+        };
+        if let Some(path) = &stack_file {
+            eprintln!("Solver stacks written to {}", path.display());
+        }
+        stalls.push(Stall {
+            date: chrono::Utc::now().to_rfc3339(),
+            silent_secs: silent.as_secs(),
+            stack_file: stack_file.map(|p| p.display().to_string()),
+        });
+    }
+    if let Some(mut child) = follower {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    stalls
+}
@@ -0,0 +1,465 @@
+//! Run history for the `cave` CLI.
+//!
+//! Every `cave run` appends a [`RunRecord`] to `history.jsonl` in the cave
+//! state directory (see [`state_dir`]). A record holds everything needed to
+//! trace a result file back to how it was produced: the exact docker command,
+//! the image, a snapshot of the export file, resource usage, produced files
+//! and the tail of the message file.
+
+use crate::cgroup::ResourceUsage;
+use crate::cmdlog::LoggedCommand;
+use crate::config::state_dir;
+use crate::docker::RunOptions;
+use crate::format;
+use crate::heartbeat::Stall;
+use crate::manage::{CaveError, ErrorContext};
+use crate::output;
+use crate::placement::Placement;
+use crate::results::ResultsSummary;
+use crate::stage::list_files;
+use crate::table::{Cell, Table};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Number of lines of the message file kept in a record.
+const LOG_TAIL_LINES: usize = 30;
+/// Bytes read from the end of a `.mess` file for its tail and diagnostic.
+const MESSAGE_TAIL_BYTES: u64 = 256 * 1024;
+
+/// A single `cave run` invocation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Short unique identifier of the run.
+    pub id: String,
+    /// Name given with `cave run --name`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Start time of the run, RFC 3339 in UTC.
+    pub timestamp: String,
+    /// code_aster version used.
+    pub version: String,
+    /// Docker image ID of the version.
+    pub image_id: String,
+    /// Repository digest of the image, when it was pulled from a registry.
+    #[serde(default)]
+    pub image_digest: Option<String>,
+    /// Export file given to run_aster, if any.
+    pub export_file: Option<String>,
+    /// Arguments given to run_aster.
+    pub args: Vec<String>,
+    /// Directory the run was started from.
+    pub working_dir: String,
+    /// Duration of the run in milliseconds.
+    pub duration_ms: u64,
+    /// Exit code of the container.
+    pub exit_code: Option<i32>,
+    /// Whether the run succeeded.
+    pub success: bool,
+    /// Whether the run was interrupted by SIGINT/SIGTERM.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// code_aster job diagnostic (`OK`, `<A>_ALARM`, `<F>_ERROR`, ...) read from the message file.
+    #[serde(default)]
+    pub diagnostic: Option<String>,
+    /// Full docker command line.
+    pub docker_command: Vec<String>,
+    /// Content of the export file at the time of the run.
+    pub export_snapshot: Option<String>,
+    /// Resource usage measured through cgroup v2.
+    pub resource_usage: Option<ResourceUsage>,
+    /// Files created or modified by the run, relative to the working directory.
+    pub produced_files: Vec<String>,
+    /// Last lines of the message file produced by the run.
+    pub log_tail: Option<String>,
+    /// Log holding the whole output of the run, when it was captured.
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// Key values and message counts, compared by `cave diff-results`.
+    #[serde(default)]
+    pub results: Option<ResultsSummary>,
+    /// CPUs and NUMA node the run was pinned to.
+    #[serde(default)]
+    pub placement: Option<Placement>,
+    /// Whether the run had a low CPU and I/O priority.
+    #[serde(default)]
+    pub low_priority: bool,
+    /// Periods without output longer than the heartbeat threshold.
+    #[serde(default)]
+    pub stalls: Vec<Stall>,
+    /// Whether the run was started in the background with `cave run --detach`;
+    /// its outcome is then unknown.
+    #[serde(default)]
+    pub detached: bool,
+    /// Container commands run by cave for this run, when the command log is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<LoggedCommand>,
+    /// `cave run` invocation of the user, replayed by `cave rerun`.
+    #[serde(default)]
+    pub invocation: Option<Invocation>,
+}
+
+/// A `cave run` invocation as given by the user, before the project
+/// defaults, the flag translation of the version and the rewriting of the
+/// export file for MPI.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Invocation {
+    /// Arguments given to `cave run`: run_aster arguments, then the export file.
+    pub args: Vec<String>,
+    /// Container arguments of the command line and the preset: resource limits, environment and GPUs.
+    pub docker_args: Vec<String>,
+    /// Extra volumes, as `HOST:CONTAINER[:ro]`.
+    pub mounts: Vec<String>,
+    /// Number of MPI processes.
+    pub mpi: Option<u32>,
+    /// Whether the run used the `[cluster]` hosts.
+    pub cluster: bool,
+    /// Whether the run started the `[compose]` services.
+    pub compose: bool,
+    /// Whether the study was copied to a local scratch directory.
+    pub stage_local: bool,
+    /// Whether databases of another version were allowed.
+    pub allow_version_mismatch: bool,
+    /// Whether the output was written to `.cave-logs`.
+    pub log_file: bool,
+    /// Whether the logs kept the whole output.
+    pub full_log: bool,
+    /// `[remote.<name>]` host the run used.
+    pub remote: Option<String>,
+    /// Whether the run was a Kubernetes Job.
+    pub k8s: bool,
+}
+
+impl Invocation {
+    /// Returns the invocation of a `cave run` with the given arguments and options.
+    pub fn new(args: &[String], options: &RunOptions) -> Self {
+        Invocation {
+            args: args.to_vec(),
+            docker_args: options.docker_args.clone(),
+            mounts: options.mounts.clone(),
+            mpi: options.mpi,
+            cluster: options.cluster,
+            compose: options.compose,
+            stage_local: options.stage_local,
+            allow_version_mismatch: options.allow_version_mismatch,
+            log_file: options.log_file,
+            full_log: options.full_log,
+            remote: options.remote.clone(),
+            k8s: options.k8s,
+        }
+    }
+}
+
+/// Returns the history file, in JSON lines.
+pub fn history_path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("history.jsonl"))
+}
+
+/// Generates a new short run identifier.
+pub fn new_run_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// Appends a record to the history file.
+pub fn append(record: &RunRecord) -> Result<(), CaveError> {
+    let path = history_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let line = serde_json::to_string(record).map_err(CaveError::SerdeError)?;
+    let context = || format!("Writing {}", path.display());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(context)?;
+    // A single write, so that the records of concurrent runs are not interleaved
+    file.write_all(format!("{}\n", line).as_bytes()).with_context(context)
+}
+
+/// Reads all records, oldest first. Malformed lines are skipped.
+pub fn read_all() -> Result<Vec<RunRecord>, CaveError> {
+    let path = history_path()?;
+    let file = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+/// Number of runs and last use of a version.
+#[derive(Debug, Default)]
+pub struct VersionUsage {
+    /// Number of recorded runs.
+    pub runs: usize,
+    /// Timestamp of the most recent run.
+    pub last_used: String,
+}
+
+/// Aggregates the history per code_aster version.
+pub fn usage_by_version() -> Result<HashMap<String, VersionUsage>, CaveError> {
+    let mut usage: HashMap<String, VersionUsage> = HashMap::new();
+    for record in read_all()? {
+        let entry = usage.entry(record.version).or_default();
+        entry.runs += 1;
+        if record.timestamp > entry.last_used {
+            entry.last_used = record.timestamp;
+        }
+    }
+    Ok(usage)
+}
+
+/// Finds a record by its run name, identifier, or an unambiguous prefix of its identifier.
+///
+/// A run name takes precedence, the most recent run with that name is returned.
+///
+/// # Errors
+/// Returns [`CaveError::RunNotFound`] if no record, or several records, match.
+pub fn find(id: &str) -> Result<RunRecord, CaveError> {
+    let records = read_all()?;
+    if let Some(named) = records.iter().rev().find(|r| r.name.as_deref() == Some(id)) {
+        return Ok(named.clone());
+    }
+    let mut matches: Vec<RunRecord> = records
+        .into_iter()
+        .filter(|r| r.id.starts_with(id))
+        .collect();
+    if matches.len() != 1 {
+        return Err(CaveError::RunNotFound(id.to_string()));
+    }
+    Ok(matches.remove(0))
+}
+
+/// Lists the files of `dir` modified at or after `since`, relative to `dir`.
+///
+/// cave's own files (`.cave-*`) are left out.
+pub fn files_modified_since(dir: &Path, since: SystemTime) -> Vec<String> {
+    let mut files: Vec<String> = list_files(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|rel| !rel.to_string_lossy().starts_with(".cave-"))
+        .filter(|rel| {
+            fs::metadata(dir.join(rel))
+                .and_then(|m| m.modified())
+                .map(|t| t >= since)
+                .unwrap_or(false)
+        })
+        .map(|rel| rel.display().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Returns the last lines of the first `.mess` file among `files`.
+pub fn message_tail(dir: &Path, files: &[String]) -> Option<String> {
+    let mess = files.iter().find(|f| f.ends_with(".mess"))?;
+    let content = output::sanitize(&output::read_end(&dir.join(mess), MESSAGE_TAIL_BYTES).ok()?);
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.len().saturating_sub(LOG_TAIL_LINES);
+    Some(lines[start..].join("\n"))
+}
+
+/// Returns the code_aster job diagnostic of the first `.mess` file among `files`.
+pub fn diagnostic(dir: &Path, files: &[String]) -> Option<String> {
+    let mess = files.iter().find(|f| f.ends_with(".mess"))?;
+    let content = output::read_end(&dir.join(mess), MESSAGE_TAIL_BYTES).ok()?;
+    String::from_utf8_lossy(&content)
+        .lines()
+        .rev()
+        .find_map(|line| {
+            let (_, value) = line.split_once("DIAGNOSTIC JOB :")?;
+            Some(value.trim().trim_end_matches('-').trim().to_string())
+        })
+        .filter(|d| !d.is_empty())
+}
+
+/// Provenance of a run, written to [`RUN_METADATA_FILE`] in the study directory.
+#[derive(Debug, Serialize)]
+struct RunMetadata<'a> {
+    run_id: &'a str,
+    timestamp: &'a str,
+    version: &'a str,
+    image_id: &'a str,
+    image_digest: Option<&'a str>,
+    duration_ms: u64,
+    success: bool,
+    diagnostic: Option<&'a str>,
+    cave_version: &'a str,
+}
+
+/// Name of the provenance file written next to the results.
+pub const RUN_METADATA_FILE: &str = ".cave-run.json";
+
+/// Writes the provenance of `record` to `dir/.cave-run.json`, replacing the previous one.
+pub fn write_run_metadata(dir: &Path, record: &RunRecord) -> Result<(), CaveError> {
+    let metadata = RunMetadata {
+        run_id: &record.id,
+        timestamp: &record.timestamp,
+        version: &record.version,
+        image_id: &record.image_id,
+        image_digest: record.image_digest.as_deref(),
+        duration_ms: record.duration_ms,
+        success: record.success,
+        diagnostic: record.diagnostic.as_deref(),
+        cave_version: env!("CARGO_PKG_VERSION"),
+    };
+    let content = serde_json::to_string_pretty(&metadata).map_err(CaveError::SerdeError)?;
+    fs::write(dir.join(RUN_METADATA_FILE), content + "\n")?;
+    Ok(())
+}
+
+/// Filters of `cave history`.
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    /// Only the runs that failed (cancelled runs included).
+    pub failed: bool,
+    /// Only the runs of this code_aster version.
+    pub version: Option<String>,
+    /// Only the runs started from this directory.
+    pub dir: Option<String>,
+    /// Maximum number of runs listed, the most recent ones.
+    pub limit: usize,
+}
+
+/// Status of a record, as printed in the history, with its color.
+fn status(record: &RunRecord) -> (&'static str, Color) {
+    if record.cancelled {
+        ("cancelled", Color::Yellow)
+    } else if record.detached {
+        ("detached", Color::Blue)
+    } else if record.success {
+        ("success", Color::Green)
+    } else {
+        ("failed", Color::Red)
+    }
+}
+
+/// Prints the recent runs matching `filter`, most recent first.
+pub fn list(filter: &HistoryFilter) -> Result<(), CaveError> {
+    let records: Vec<RunRecord> = read_all()?
+        .into_iter()
+        .rev()
+        .filter(|r| !filter.failed || (!r.success && !r.detached))
+        .filter(|r| filter.version.as_ref().is_none_or(|v| r.version == *v))
+        .filter(|r| filter.dir.as_ref().is_none_or(|d| r.working_dir == *d))
+        .take(filter.limit)
+        .collect();
+    if records.is_empty() {
+        println!("No run matches in the history.");
+        return Ok(());
+    }
+
+    let mut table = Table::new(&["Run", "Name", "Date", "Version", "Export", "Duration", "Status", "Directory"]);
+    for record in &records {
+        let date = chrono::DateTime::parse_from_rfc3339(&record.timestamp)
+            .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| record.timestamp.clone());
+        let duration = if record.detached {
+            String::new()
+        } else {
+            format::duration_ms(record.duration_ms)
+        };
+        let (status, color) = status(record);
+        table.push(vec![
+            Cell::from(record.id.as_str()).bold(),
+            record.name.clone().unwrap_or_default().into(),
+            date.into(),
+            record.version.as_str().into(),
+            record.export_file.clone().unwrap_or_default().into(),
+            duration.into(),
+            Cell::from(status).color(color).bold(),
+            record.working_dir.as_str().into(),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+/// Prints every detail of a single history entry.
+pub fn show(id: &str) -> Result<(), CaveError> {
+    let record = find(id)?;
+    let (status, color) = status(&record);
+    let status = status.color(color).bold();
+
+    println!("{:<16}{}", "Run", record.id.bold());
+    if let Some(name) = &record.name {
+        println!("{:<16}{}", "Name", name);
+    }
+    println!("{:<16}{}", "Date", record.timestamp);
+    println!("{:<16}{}", "Status", status);
+    if let Some(code) = record.exit_code {
+        println!("{:<16}{}", "Exit code", code);
+    }
+    if let Some(diagnostic) = &record.diagnostic {
+        println!("{:<16}{}", "Diagnostic", diagnostic);
+    }
+    println!("{:<16}{}", "Version", record.version);
+    println!("{:<16}{}", "Image", record.image_id);
+    if let Some(digest) = &record.image_digest {
+        println!("{:<16}{}", "Digest", digest);
+    }
+    println!("{:<16}{}", "Directory", record.working_dir);
+    println!("{:<16}{}", "Duration", format::duration_ms(record.duration_ms));
+    if record.low_priority {
+        println!("{:<16}low", "Priority");
+    }
+    if let Some(placement) = &record.placement {
+        match placement.numa_node {
+            Some(node) => println!("{:<16}{} (NUMA node {})", "CPUs", placement.cpus, node),
+            None => println!("{:<16}{}", "CPUs", placement.cpus),
+        }
+    }
+    if let Some(usage) = &record.resource_usage {
+        if let Some(mem) = usage.peak_memory_bytes {
+            println!("{:<16}{}", "Peak memory", format::size(mem));
+        }
+        if let Some(cpu) = usage.cpu_usage_usec {
+            println!("{:<16}{}", "CPU time", format::duration_ms(cpu / 1000));
+        }
+    }
+    println!("{:<16}{}", "Command", record.docker_command.join(" "));
+    if let Some(log) = &record.log_file {
+        println!("{:<16}{}", "Log", log);
+    }
+
+    for stall in &record.stalls {
+        let stack = stall.stack_file.as_deref().map(|f| format!(", stacks in {}", f)).unwrap_or_default();
+        println!("{:<16}{} after {} s without output{}", "Stall", stall.date, stall.silent_secs, stack);
+    }
+
+    if !record.commands.is_empty() {
+        println!();
+        println!("{}", "Container commands".bold());
+        for command in &record.commands {
+            println!("  {}  {}", command.date.dimmed(), command.command);
+        }
+    }
+
+    if let Some(export) = &record.export_snapshot {
+        println!();
+        println!("{}", format!("Export file ({})", record.export_file.unwrap_or_default()).bold());
+        println!("{}", export.trim_end());
+    }
+    if !record.produced_files.is_empty() {
+        println!();
+        println!("{}", "Produced files".bold());
+        for file in &record.produced_files {
+            println!("  {}", file);
+        }
+    }
+    if let Some(tail) = &record.log_tail {
+        println!();
+        println!("{}", "Log tail".bold());
+        println!("{}", tail);
+    }
+    Ok(())
+}
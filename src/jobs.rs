@@ -0,0 +1,322 @@
+//! Containers started by cave on this host, listed by `cave jobs`.
+//!
+//! Every container started by cave carries the [`LABEL`] label, and run
+//! containers also carry their run identifier, version and study directory
+//! ([`RUN_ID_LABEL`], [`VERSION_LABEL`], [`DIR_LABEL`]), so that they can be
+//! found with `docker ps` independently of the cave process that started them.
+//!
+//! A run stopped from another cave process (`cave kill-all`) leaves an abort
+//! marker in the state directory; the cave process owning the run then
+//! records it as cancelled in the history.
+
+use crate::answers::{self, Prompt};
+use crate::cmdlog::Logged;
+use crate::docker::{container_name, runtime};
+use crate::history;
+use crate::lock;
+use crate::config::state_dir;
+use crate::manage::CaveError;
+use crate::signals::{self, Operation};
+use crate::table::{Cell, Table};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Label set on every container started by cave.
+pub const LABEL: &str = "cave";
+/// Label holding the run identifier.
+pub const RUN_ID_LABEL: &str = "cave.run-id";
+/// Label holding the code_aster version.
+pub const VERSION_LABEL: &str = "cave.version";
+/// Label holding the study directory.
+pub const DIR_LABEL: &str = "cave.dir";
+/// Label holding the name of an auxiliary container of a coupled run.
+pub const SERVICE_LABEL: &str = "cave.service";
+
+/// A running cave container.
+#[derive(Debug)]
+pub struct Job {
+    /// Container ID.
+    pub container_id: String,
+    /// Container name.
+    pub name: String,
+    /// Run identifier, empty for interactive shells.
+    pub run_id: String,
+    /// Service name, for the auxiliary containers of coupled runs.
+    pub service: String,
+    /// code_aster version.
+    pub version: String,
+    /// Study directory.
+    pub dir: String,
+    /// Creation date of the container, as reported by docker.
+    pub created: String,
+    /// Time since the container started, as reported by docker.
+    pub running_for: String,
+}
+
+/// Lists the running containers started by cave.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if `docker ps` fails.
+pub fn running_jobs() -> Result<Vec<Job>, CaveError> {
+    let runtime = runtime();
+    let format = format!(
+        "{{{{.ID}}}}\t{{{{.Names}}}}\t{}\t{}\t{}\t{}\t{{{{.CreatedAt}}}}\t{{{{.RunningFor}}}}",
+        runtime.label_template(RUN_ID_LABEL),
+        runtime.label_template(SERVICE_LABEL),
+        runtime.label_template(VERSION_LABEL),
+        runtime.label_template(DIR_LABEL)
+    );
+    let output = runtime
+        .command()
+        .args(["ps", "--filter", &format!("label={}", LABEL), "--format", &format])
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(CaveError::DockerError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [id, name, run_id, service, version, dir, created, running_for] = fields.as_slice() else {
+                return None;
+            };
+            Some(Job {
+                container_id: id.to_string(),
+                name: name.to_string(),
+                run_id: run_id.to_string(),
+                service: service.to_string(),
+                version: version.to_string(),
+                dir: dir.to_string(),
+                created: created.to_string(),
+                running_for: running_for.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Returns `true` if a cave container of this name is running.
+pub fn is_running(name: &str) -> bool {
+    runtime()
+        .command()
+        .args(["ps", "-q", "--filter", &format!("label={}", LABEL), "--filter", &format!("name=^{}$", name)])
+        .stderr(Stdio::null())
+        .logged_output()
+        .map(|o| o.status.success() && !o.stdout.trim_ascii().is_empty())
+        .unwrap_or(false)
+}
+
+/// Releases the study locks of the detached runs whose container is no
+/// longer among the running `jobs`.
+pub fn release_finished(jobs: &[Job]) {
+    let Ok(records) = history::read_all() else {
+        return;
+    };
+    for record in records.iter().filter(|r| r.detached) {
+        let container = container_name(&record.id, record.name.as_deref());
+        if !jobs.iter().any(|job| job.name == container) {
+            lock::release_stale(Path::new(&record.working_dir), &container);
+            // No cave process is left to take the abort marker of a detached run
+            take_aborted(&record.id);
+        }
+    }
+}
+
+/// Returns `true` if `run_id` is a run started with `cave run --detach`.
+fn is_detached(run_id: &str) -> bool {
+    history::read_all()
+        .map(|records| records.iter().any(|r| r.id == run_id && r.detached))
+        .unwrap_or(false)
+}
+
+fn abort_marker(run_id: &str) -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("aborted").join(run_id))
+}
+
+/// Marks a run as aborted from another cave process.
+pub fn mark_aborted(run_id: &str) -> Result<(), CaveError> {
+    let path = abort_marker(run_id)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, "")?;
+    Ok(())
+}
+
+/// Returns `true` if the run was aborted from another cave process, removing the marker.
+pub fn take_aborted(run_id: &str) -> bool {
+    abort_marker(run_id)
+        .map(|path| fs::remove_file(path).is_ok())
+        .unwrap_or(false)
+}
+
+/// Returns the running job matching `id`: a run identifier, a run name, a
+/// container name (with or without the `cave-` prefix) or the start of a
+/// container ID.
+/// The services of a coupled run are only matched by their container.
+///
+/// # Errors
+/// Returns [`CaveError::JobNotFound`] if no job, or several, match.
+pub fn find(id: &str) -> Result<Job, CaveError> {
+    let mut matching: Vec<Job> = running_jobs()?
+        .into_iter()
+        .filter(|job| {
+            (job.run_id == id && job.service.is_empty())
+                || job.name == id
+                || job.name == format!("cave-{}", id)
+                || (!job.run_id.is_empty() && job.name == container_name(&job.run_id, Some(id)))
+                || (id.len() >= 4 && job.container_id.starts_with(id))
+        })
+        .collect();
+    match matching.len() {
+        1 => Ok(matching.remove(0)),
+        _ => Err(CaveError::JobNotFound(id.to_string())),
+    }
+}
+
+/// Stops a job, marking its run as aborted for the cave process owning it
+/// and releasing the study lock of a detached run, which has no such process
+/// and is not marked. Returns `true` if the container was killed.
+pub fn kill(job: &Job) -> Result<bool, CaveError> {
+    if !job.run_id.is_empty() && !is_detached(&job.run_id) {
+        mark_aborted(&job.run_id)?;
+    }
+    let killed = runtime()
+        .command()
+        .args(["kill", &job.container_id])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if killed {
+        lock::release(Path::new(&job.dir), &job.name);
+    }
+    Ok(killed)
+}
+
+/// Handler of `cave jobs`: lists the running cave containers, releasing
+/// the study locks of the finished detached runs.
+///
+/// # Errors
+/// Returns [`CaveError::DockerError`] if docker cannot be queried.
+pub fn list() -> Result<(), CaveError> {
+    let jobs = running_jobs()?;
+    release_finished(&jobs);
+    if jobs.is_empty() {
+        println!("No cave container is running.");
+        return Ok(());
+    }
+    let mut table = Table::new(&["Run", "Container", "Version", "Started", "Running for", "Directory"]);
+    for job in &jobs {
+        let run = if job.run_id.is_empty() { "shell" } else { job.run_id.as_str() };
+        let version = if job.service.is_empty() {
+            job.version.clone()
+        } else {
+            format!("service {}", job.service)
+        };
+        table.push(vec![
+            Cell::from(run).bold(),
+            job.name.as_str().into(),
+            version.into(),
+            job.created.as_str().into(),
+            job.running_for.as_str().into(),
+            job.dir.as_str().into(),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+/// Handler of `cave jobs attach`: follows the output of a running job.
+/// Interrupting detaches from the container without stopping it.
+///
+/// # Errors
+/// - [`CaveError::JobNotFound`] if no single job matches `id`.
+/// - [`CaveError::DockerError`] if the container cannot be attached.
+pub fn attach(id: &str) -> Result<(), CaveError> {
+    let job = find(id)?;
+    println!("Attached to {}, Ctrl-C to detach.", job.name);
+    let mut child = runtime()
+        .command()
+        .args(["attach", "--no-stdin", "--sig-proxy=false", &job.container_id])
+        .logged()
+        .spawn()?;
+    // Only the attach process is stopped on interrupt, not the container
+    let _guard = signals::register(Operation::Process(child.id()));
+    let status = child.wait()?;
+    if signals::cancelled() {
+        println!("Detached from {}, the run goes on.", job.name);
+        return Ok(());
+    }
+    if !status.success() {
+        return Err(CaveError::DockerError(format!("Failed to attach to {}", job.name)));
+    }
+    Ok(())
+}
+
+/// Handler of `cave jobs kill`: stops a running job, after confirmation
+/// unless `yes` is set.
+///
+/// # Errors
+/// - [`CaveError::JobNotFound`] if no single job matches `id`.
+/// - [`CaveError::UserAborted`] if the user declines.
+/// - [`CaveError::DockerError`] if the container cannot be stopped.
+pub fn kill_one(id: &str, yes: bool) -> Result<(), CaveError> {
+    let job = find(id)?;
+    let question = format!("Stop {} ({}, running for {})?", job.name, job.dir, job.running_for);
+    if !yes && !answers::confirm(Prompt::Stop, &question)? {
+        return Err(CaveError::UserAborted);
+    }
+    if !kill(&job)? {
+        return Err(CaveError::DockerError(format!("Failed to stop {}", job.name)));
+    }
+    println!("Stopped {}", job.name);
+    Ok(())
+}
+
+/// Handler of `cave kill-all`: stops every running cave container, after
+/// confirmation unless `yes` is set.
+///
+/// # Errors
+/// - [`CaveError::UserAborted`] if the user declines.
+/// - [`CaveError::DockerError`] if docker cannot be queried.
+pub fn kill_all(yes: bool) -> Result<(), CaveError> {
+    let jobs = running_jobs()?;
+    if jobs.is_empty() {
+        println!("No cave container is running.");
+        return Ok(());
+    }
+
+    let mut table = Table::new(&["Container", "Run", "Version", "Running for", "Directory"]);
+    for job in &jobs {
+        table.push(vec![
+            Cell::from(job.name.as_str()).bold(),
+            job.run_id.as_str().into(),
+            job.version.as_str().into(),
+            job.running_for.as_str().into(),
+            job.dir.as_str().into(),
+        ]);
+    }
+    table.print();
+
+    let question = format!("Stop these {} container(s)?", jobs.len());
+    if !yes && !answers::confirm(Prompt::Stop, &question)? {
+        return Err(CaveError::UserAborted);
+    }
+
+    let mut stopped = 0;
+    for job in &jobs {
+        if kill(job)? {
+            stopped += 1;
+            println!("Stopped {}", job.name);
+        } else {
+            eprintln!("Failed to stop {}", job.name);
+        }
+    }
+    println!("{} of {} container(s) stopped.", stopped, jobs.len());
+    Ok(())
+}
@@ -0,0 +1,547 @@
+//! Kubernetes Job backend (`cave run --backend k8s`), built with the `k8s` feature.
+//!
+//! The run is a Job of the `simvia/code_aster:<version>` image, created with
+//! `kubectl` in the context and namespace of the `[k8s]` section of
+//! `cave.toml`. The study is mounted from a volume of the pod, or from a
+//! directory of the PersistentVolumeClaim `pvc`, kept between runs. The pod
+//! starts with an `upload` init container, into which the study directory
+//! is copied with `tar` through `kubectl exec`. The `aster` container then
+//! runs the study, its output being streamed, and waits for the files of the
+//! study to be copied back before exiting with the status of run_aster.
+//!
+//! The Job is deleted once the results are copied, or when the run is
+//! interrupted; Jobs left behind by an interrupted cave are removed by the
+//! cluster an hour after they end. The runs are recorded in the local
+//! history like the remote ones, without resource usage.
+
+use crate::cmdlog::{self, Logged};
+use crate::config::{env_args, read_config};
+use crate::docker::{container_name, RunOptions};
+use crate::export::{self, Export};
+use crate::extractors;
+use crate::history::{self, RunRecord};
+use crate::jobs;
+use crate::license;
+use crate::lock;
+use crate::manage::{export_version, find_export_file, read_cave_version, CaveError};
+use crate::profiles;
+use crate::project::{find_project_dir, merge_args, read_project_config, K8sConfig};
+use crate::remote;
+use crate::results;
+use crate::signals::{self, Operation};
+use colored::*;
+use log::debug;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Working directory of the images, where the study is copied.
+const DATA_DIR: &str = "/home/user/data";
+/// Files marking the end of the copies, from and to the pod.
+const UPLOADED: &str = ".cave-uploaded";
+const FETCHED: &str = ".cave-fetched";
+/// Time the pod waits for a copy before giving up, in seconds.
+const COPY_TIMEOUT_SECS: u32 = 3600;
+/// Interval at which the pod is checked while it starts.
+const POLL: Duration = Duration::from_secs(2);
+/// Reasons of a waiting container that will not start by itself.
+const FAILED_REASONS: [&str; 5] = [
+    "ErrImagePull",
+    "ImagePullBackOff",
+    "InvalidImageName",
+    "CreateContainerConfigError",
+    "CrashLoopBackOff",
+];
+
+/// kubectl, in the context and namespace of the project.
+struct Kubectl<'a> {
+    config: &'a K8sConfig,
+}
+
+impl Kubectl<'_> {
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.config.kubectl);
+        if let Some(context) = &self.config.context {
+            cmd.args(["--context", context]);
+        }
+        if let Some(namespace) = &self.config.namespace {
+            cmd.args(["--namespace", namespace]);
+        }
+        cmd
+    }
+
+    /// Runs kubectl with `args`, `input` as standard input, and returns its output.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::K8sError`] if kubectl is missing or fails.
+    fn output(&self, args: &[&str], input: Option<&[u8]>) -> Result<String, CaveError> {
+        let mut cmd = self.command();
+        cmd.args(args);
+        cmdlog::record(&cmd);
+        let mut child = cmd
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                if e.kind() == ErrorKind::NotFound {
+                    CaveError::K8sError(format!("{} not found on this host", self.config.kubectl))
+                } else {
+                    CaveError::IoError(e)
+                }
+            })?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(CaveError::K8sError(format!(
+                "kubectl {}: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Returns the pod of `job`, once created.
+    fn pod(&self, job: &str) -> Result<Option<Value>, CaveError> {
+        let pods: Value =
+            serde_json::from_str(&self.output(&["get", "pods", "-l", &format!("job-name={}", job), "-o", "json"], None)?)?;
+        Ok(pods["items"].as_array().and_then(|items| items.first()).cloned())
+    }
+
+    /// Deletes `job` and its pod, without waiting.
+    fn delete(&self, job: &str) {
+        if let Err(e) = self.output(&["delete", "job", job, "--wait=false", "--ignore-not-found"], None) {
+            debug!("Impossible de supprimer le job {}: {}", job, e);
+        }
+    }
+}
+
+/// Returns the state of the container `name` of `pod`, an init container with `init`.
+fn container_state<'a>(pod: &'a Value, name: &str, init: bool) -> Option<&'a Value> {
+    let key = if init { "initContainerStatuses" } else { "containerStatuses" };
+    pod["status"][key]
+        .as_array()?
+        .iter()
+        .find(|status| status["name"] == name)
+        .map(|status| &status["state"])
+}
+
+/// Returns why `pod` is not running yet, if known.
+fn pending_reason(pod: &Value, state: Option<&Value>) -> Option<String> {
+    if let Some(waiting) = state.map(|s| &s["waiting"]).filter(|w| w.is_object()) {
+        let reason = waiting["reason"].as_str().unwrap_or("waiting");
+        return Some(match waiting["message"].as_str() {
+            Some(message) => format!("{}: {}", reason, message),
+            None => reason.to_string(),
+        });
+    }
+    pod["status"]["conditions"]
+        .as_array()?
+        .iter()
+        .find(|c| c["type"] == "PodScheduled" && c["status"] == "False")
+        .and_then(|c| c["message"].as_str())
+        .map(String::from)
+}
+
+/// Waits until the container `name` of the pod of `job` has started, and
+/// returns the name of the pod.
+///
+/// # Errors
+/// - [`CaveError::K8sError`] if the container cannot start.
+/// - [`CaveError::Cancelled`] if interrupted.
+fn wait_started(kubectl: &Kubectl, job: &str, name: &str, init: bool) -> Result<String, CaveError> {
+    let mut reported = String::new();
+    loop {
+        if signals::cancelled() {
+            return Err(CaveError::Cancelled);
+        }
+        if let Some(pod) = kubectl.pod(job)? {
+            let pod_name = pod["metadata"]["name"].as_str().unwrap_or_default().to_string();
+            let state = container_state(&pod, name, init);
+            if state.is_some_and(|s| s["running"].is_object() || s["terminated"].is_object()) {
+                return Ok(pod_name);
+            }
+            let waiting = state.and_then(|s| s["waiting"]["reason"].as_str());
+            if let Some(reason) = waiting.filter(|r| FAILED_REASONS.contains(r)) {
+                return Err(CaveError::K8sError(format!(
+                    "container {} of pod {} cannot start: {}",
+                    name,
+                    pod_name,
+                    pending_reason(&pod, state).unwrap_or_else(|| reason.to_string())
+                )));
+            }
+            if let Some(reason) = pending_reason(&pod, state).filter(|r| *r != reported) {
+                println!("{} for pod {}: {}", "Waiting".yellow().bold(), pod_name, reason);
+                reported = reason;
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Copies the study directory `dir` into the `upload` container of `pod`.
+fn upload(kubectl: &Kubectl, pod: &str, dir: &Path) -> Result<(), CaveError> {
+    let mut tar = Command::new("tar")
+        .args(["-cf", "-", "--exclude=.cave-*", "-C"])
+        .arg(dir)
+        .arg(".")
+        .stdout(Stdio::piped())
+        .logged()
+        .spawn()?;
+    let mut cmd = kubectl.command();
+    cmd.args(["exec", "-i", pod, "-c", "upload", "--", "sh", "-c"])
+        .arg(format!("tar -xf - -C {dir} && touch {dir}/{}", UPLOADED, dir = DATA_DIR));
+    let status = cmd
+        .stdin(tar.stdout.take().map_or_else(Stdio::null, Stdio::from))
+        .logged_status()?;
+    let tar_status = tar.wait()?;
+    if !status.success() || !tar_status.success() {
+        return Err(CaveError::K8sError(format!("copy of the study to pod {} failed", pod)));
+    }
+    Ok(())
+}
+
+/// Copies the files of the study from the `aster` container of `pod` into
+/// `dir`, without replacing the files changed locally in the meantime.
+fn download(kubectl: &Kubectl, pod: &str, dir: &Path) -> Result<(), CaveError> {
+    let mut cmd = kubectl.command();
+    cmd.args(["exec", pod, "-c", "aster", "--", "tar", "-cf", "-", "--exclude=.cave-*", "-C", DATA_DIR, "."]);
+    let mut exec = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).logged().spawn()?;
+    let output = Command::new("tar")
+        .args(["-xf", "-", "--keep-newer-files", "-C"])
+        .arg(dir)
+        .stdin(exec.stdout.take().map_or_else(Stdio::null, Stdio::from))
+        .stderr(Stdio::piped())
+        .logged_output()?;
+    let status = exec.wait()?;
+    if !status.success() || !output.status.success() {
+        return Err(CaveError::K8sError(format!(
+            "copy of the results from pod {} failed: {}",
+            pod,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the line printed by the pod after run_aster, before its exit code.
+fn exit_marker(run_id: &str) -> String {
+    format!("cave-exit-{}", run_id)
+}
+
+/// Streams the output of the `aster` container of `pod` until run_aster
+/// ends, and returns its exit code, `None` if the container ended first.
+fn stream_output(kubectl: &Kubectl, pod: &str, run_id: &str) -> Result<Option<i32>, CaveError> {
+    let marker = format!("{} ", exit_marker(run_id));
+    let mut cmd = kubectl.command();
+    cmd.args(["logs", "-f", pod, "-c", "aster"]);
+    let mut child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).logged().spawn()?;
+    let _guard = signals::register(Operation::Process(child.id()));
+    let mut code = None;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(rest) = line.strip_prefix(&marker) {
+                code = rest.trim().parse().ok();
+                break;
+            }
+            println!("{}", line);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(code)
+}
+
+/// Copies the study into the pod of `job`, streams the output of the run,
+/// and copies the results back. Returns the exit code of run_aster, or of
+/// the container if it ended first, and the image ID.
+fn follow(kubectl: &Kubectl, job: &str, dir: &Path, run_id: &str) -> Result<(Option<i32>, String), CaveError> {
+    let pod = wait_started(kubectl, job, "upload", true)?;
+    println!("{} the study to pod {}", "Copying".bold(), pod);
+    upload(kubectl, &pod, dir)?;
+    wait_started(kubectl, job, "aster", false)?;
+    let code = stream_output(kubectl, &pod, run_id)?;
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    let pod_info = kubectl.pod(job)?.unwrap_or_default();
+    let state = container_state(&pod_info, "aster", false).cloned().unwrap_or_default();
+    let code = match code {
+        Some(code) => {
+            println!("{} the results from pod {}", "Copying".bold(), pod);
+            download(kubectl, &pod, dir)?;
+            let fetched = format!("{}/{}", DATA_DIR, FETCHED);
+            let _ = kubectl.output(&["exec", &pod, "-c", "aster", "--", "touch", &fetched], None);
+            Some(code)
+        }
+        None => {
+            eprintln!(
+                "{} the container ended before the end of the run ({}), the results cannot be copied.",
+                "Note:".cyan().bold(),
+                state["terminated"]["reason"].as_str().unwrap_or("unknown reason")
+            );
+            state["terminated"]["exitCode"].as_i64().map(|c| c as i32)
+        }
+    };
+    let image_id = pod_info["status"]["containerStatuses"]
+        .as_array()
+        .and_then(|statuses| statuses.iter().find(|s| s["name"] == "aster"))
+        .and_then(|status| status["imageID"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    Ok((code, image_id))
+}
+
+/// Converts a `docker run --memory` value, such as `16g`, into a Kubernetes quantity.
+fn quantity(memory: &str) -> String {
+    let memory = memory.to_ascii_lowercase();
+    let (number, suffix) = memory.split_at(memory.trim_end_matches(char::is_alphabetic).len());
+    match suffix {
+        "k" | "kb" => format!("{}Ki", number),
+        "m" | "mb" => format!("{}Mi", number),
+        "g" | "gb" => format!("{}Gi", number),
+        "t" | "tb" => format!("{}Ti", number),
+        _ => number.to_string(),
+    }
+}
+
+/// Translates the `docker run` arguments of a run: environment variables
+/// become the environment of the container, `--cpus` and `--memory` its
+/// limits; the other options have no equivalent and are ignored. Returns
+/// the environment and the limits with the number of ignored options.
+fn translate_args(docker_args: &[String]) -> (Vec<Value>, Map<String, Value>, usize) {
+    let mut env = Vec::new();
+    let mut limits = Map::new();
+    let mut ignored = 0;
+    let mut args = docker_args.iter();
+    while let Some(arg) = args.next() {
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next().cloned());
+        match option {
+            "-e" | "--env" => {
+                if let Some((name, value)) = value().as_deref().and_then(|v| v.split_once('=')) {
+                    env.push(json!({ "name": name, "value": value }));
+                }
+            }
+            "--cpus" => {
+                limits.extend(value().map(|cpus| ("cpu".to_string(), json!(cpus))));
+            }
+            "--memory" => {
+                limits.extend(value().map(|memory| ("memory".to_string(), json!(quantity(&memory)))));
+            }
+            _ => {
+                debug!("Option docker ignorée avec Kubernetes: {}", arg);
+                ignored += 1;
+            }
+        }
+    }
+    (env, limits, ignored)
+}
+
+/// Returns the resources requested for the study of `export`: the CPUs of
+/// its processes and its memory limit.
+fn requests(export: Option<&str>) -> Map<String, Value> {
+    let mut requests = Map::new();
+    let Some(content) = export.and_then(|e| fs::read_to_string(e).ok()) else {
+        return requests;
+    };
+    let (parsed, _) = Export::parse(&content);
+    let tasks = parsed.integer("mpi_nbcpu").unwrap_or(1);
+    let cpus = tasks * parsed.integer("ncpus").unwrap_or(1);
+    requests.insert("cpu".to_string(), json!(cpus.to_string()));
+    if let Some(mb) = parsed.memory_limit() {
+        requests.insert("memory".to_string(), json!(format!("{}Mi", (mb * tasks as f64).ceil() as u64)));
+    }
+    requests
+}
+
+/// Runs a study as a Kubernetes Job, with the run_aster arguments `args`,
+/// the export file last.
+///
+/// # Errors
+/// - [`CaveError::K8sError`] if the Job cannot be created, its pod cannot
+///   start, or the study cannot be copied.
+/// - [`CaveError::RunInProgress`] if another run holds the study lock.
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::CodeAsterError`] if the run fails.
+pub fn run(args: &[String], options: RunOptions) -> Result<(), CaveError> {
+    let version = match options.version.clone() {
+        Some(version) => version,
+        None => export_version(args)?.map_or_else(read_cave_version, Ok)?,
+    };
+    let (export, rest_args): (Option<String>, Vec<String>) = match args.split_last() {
+        Some((last, rest)) if last.ends_with(".export") => {
+            find_export_file(last)?;
+            export::warn_problems(last)?;
+            (Some(last.clone()), rest.to_vec())
+        }
+        _ => (None, args.to_vec()),
+    };
+
+    let current_dir = env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or_else(|| current_dir.clone());
+    let project = read_project_config(&project_dir)?;
+    let study = export
+        .as_deref()
+        .and_then(|e| Path::new(e).file_stem())
+        .and_then(|s| s.to_str());
+    let defaults = project.defaults_for(study);
+    let rest_args = profiles::translate(&version, &merge_args(&defaults.args, &rest_args))?;
+    let mut docker_args = merge_args(&defaults.docker_args, &options.docker_args);
+    docker_args.extend(license::docker_args(&read_config()?.license_env, &project.license.env));
+    docker_args.extend(env_args(&read_config()?.forward_env)?);
+    let (env, limits, ignored) = translate_args(&remote::resolve_env(&docker_args)?);
+    if ignored > 0 {
+        eprintln!(
+            "{} {} docker option argument(s) ignored with Kubernetes.",
+            "Note:".cyan().bold(),
+            ignored
+        );
+    }
+
+    let run_id = options.run_id.clone().unwrap_or_else(history::new_run_id);
+    let job = container_name(&run_id, options.name.as_deref());
+    let _lock = lock::acquire(&current_dir, &job, options.force)?;
+    let config = &project.k8s;
+    let kubectl = Kubectl { config };
+
+    let image = format!("simvia/code_aster:{}", version);
+    let aster: Vec<String> = rest_args.iter().map(|a| cmdlog::shell_quote(a)).collect();
+    let export_arg = export.as_deref().map(cmdlog::shell_quote).unwrap_or_default();
+    let wait_for = |file: &str| {
+        format!(
+            "n=0; until [ -e {file} ] || [ $n -ge {timeout} ]; do sleep 1; n=$((n+1)); done",
+            file = file,
+            timeout = COPY_TIMEOUT_SECS
+        )
+    };
+    let script = format!(
+        "source /opt/activate.sh && run_aster {} {}\ncode=$?\necho \"{} $code\"\n{}\nexit $code",
+        aster.join(" "),
+        export_arg,
+        exit_marker(&run_id),
+        wait_for(FETCHED)
+    );
+    let volume = match &config.pvc {
+        Some(claim) => json!({ "name": "data", "persistentVolumeClaim": { "claimName": claim } }),
+        None => json!({ "name": "data", "emptyDir": {} }),
+    };
+    let mut mount = json!({ "name": "data", "mountPath": DATA_DIR });
+    if config.pvc.is_some() {
+        mount["subPath"] = json!(remote::remote_dir(&config.dir, &current_dir));
+    }
+    let labels: Map<String, Value> = [(jobs::LABEL, ""), (jobs::RUN_ID_LABEL, &run_id), (jobs::VERSION_LABEL, &version)]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), json!(value)))
+        .collect();
+    let mut pod_spec = json!({
+        "restartPolicy": "Never",
+        "volumes": [volume],
+        "initContainers": [{
+            "name": "upload",
+            "image": image,
+            "command": ["/bin/sh", "-c", wait_for(UPLOADED)],
+            "workingDir": DATA_DIR,
+            "volumeMounts": [mount],
+        }],
+        "containers": [{
+            "name": "aster",
+            "image": image,
+            "command": ["/bin/bash", "-c", script],
+            "workingDir": DATA_DIR,
+            "env": env,
+            "resources": { "requests": requests(export.as_deref()), "limits": limits },
+            "volumeMounts": [mount],
+        }],
+    });
+    if let Some(secret) = &config.image_pull_secret {
+        pod_spec["imagePullSecrets"] = json!([{ "name": secret }]);
+    }
+    let manifest = json!({
+        "apiVersion": "batch/v1",
+        "kind": "Job",
+        "metadata": { "name": job, "labels": labels },
+        "spec": {
+            "backoffLimit": 0,
+            "ttlSecondsAfterFinished": 3600,
+            "template": { "metadata": { "labels": labels }, "spec": pod_spec },
+        },
+    });
+
+    println!("{} {} as job {} (run {})", "Running".bold(), version, job, run_id);
+    let started_at = SystemTime::now();
+    let started_utc = chrono::Utc::now();
+    let start = Instant::now();
+    // Interrupting stops the polling loops instead of exiting, so that the Job is deleted
+    let guard = signals::register(Operation::Request);
+    kubectl.output(&["create", "-f", "-"], Some(manifest.to_string().as_bytes()))?;
+    let outcome = follow(&kubectl, &job, &current_dir, &run_id);
+    kubectl.delete(&job);
+    drop(guard);
+    let (exit_code, image_id) = outcome?;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let success = exit_code == Some(0);
+
+    let produced_files = history::files_modified_since(&current_dir, started_at);
+    let mut summary = results::summarize(&current_dir, &produced_files);
+    let context = extractors::RunContext {
+        dir: &current_dir,
+        run_id: &run_id,
+        version: &version,
+        export: export.as_deref(),
+    };
+    extractors::apply(&project.extractors, &context, &produced_files, &mut summary);
+    let mut commands = cmdlog::take();
+    if !read_config()?.log_commands {
+        commands.clear();
+    }
+    let record = RunRecord {
+        id: run_id.clone(),
+        name: options.name.clone(),
+        timestamp: started_utc.to_rfc3339(),
+        version: version.clone(),
+        image_id,
+        export_file: export.clone(),
+        args: rest_args.clone(),
+        working_dir: current_dir.display().to_string(),
+        duration_ms,
+        exit_code,
+        success,
+        diagnostic: history::diagnostic(&current_dir, &produced_files),
+        docker_command: vec![config.kubectl.clone(), "create".to_string(), "-f".to_string(), "-".to_string()],
+        export_snapshot: export.as_ref().and_then(|e| fs::read_to_string(current_dir.join(e)).ok()),
+        log_tail: history::message_tail(&current_dir, &produced_files),
+        results: summary,
+        commands,
+        produced_files,
+        invocation: options.invocation.clone(),
+        ..Default::default()
+    };
+    if let Err(e) = history::append(&record) {
+        debug!("Impossible d'enregistrer le run dans l'historique: {}", e);
+    }
+    if read_config()?.run_metadata {
+        if let Err(e) = history::write_run_metadata(&current_dir, &record) {
+            debug!("Impossible d'écrire {}: {}", history::RUN_METADATA_FILE, e);
+        }
+    }
+
+    if !success {
+        return Err(CaveError::CodeAsterError(format!(
+            "run failed for version: {} on Kubernetes",
+            version
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! License server passthrough for tools shipped in the images.
+//!
+//! Some coupled tools inside the images check out licenses from a license
+//! server. The environment variables pointing to the servers are declared
+//! once in the global configuration (`cave config set-license-env`), can be
+//! overridden per project in the `[license]` section of `cave.toml`, and are
+//! injected into every run.
+//!
+//! A server on the local machine (`<port>@localhost`) is not reachable from
+//! the container under that name: such values are rewritten to the Docker
+//! host gateway.
+
+use std::collections::BTreeMap;
+
+/// Name of the Docker host, as seen from the container.
+const DOCKER_HOST_NAME: &str = "host.docker.internal";
+
+/// Rewrites `port@localhost` server addresses to the Docker host. Several
+/// servers may be listed, separated by `:` or `;`. Returns `None` when the
+/// value has no local server.
+fn rewrite_localhost(value: &str) -> Option<String> {
+    let separator = if value.contains(';') { ';' } else { ':' };
+    let mut changed = false;
+    let servers: Vec<String> = value
+        .split(separator)
+        .map(|server| match server.split_once('@') {
+            Some((port, "localhost" | "127.0.0.1")) => {
+                changed = true;
+                format!("{}@{}", port, DOCKER_HOST_NAME)
+            }
+            _ => server.to_string(),
+        })
+        .collect();
+    changed.then(|| servers.join(&separator.to_string()))
+}
+
+/// Returns the `docker run` arguments passing the license environment:
+/// the global variables, overridden by the project ones.
+pub fn docker_args(global: &BTreeMap<String, String>, project: &BTreeMap<String, String>) -> Vec<String> {
+    let mut env = global.clone();
+    env.extend(project.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    let mut args = Vec::new();
+    let mut needs_host = false;
+    for (name, value) in env {
+        let value = match rewrite_localhost(&value) {
+            Some(rewritten) => {
+                needs_host = true;
+                rewritten
+            }
+            None => value,
+        };
+        args.push("-e".to_string());
+        args.push(format!("{}={}", name, value));
+    }
+    if needs_host {
+        args.push("--add-host".to_string());
+        args.push(format!("{}:host-gateway", DOCKER_HOST_NAME));
+    }
+    args
+}
@@ -0,0 +1,124 @@
+//! Per-study advisory locking for `cave run`.
+//!
+//! Two runs in the same study directory write the same aster databases and
+//! result files, which corrupts them. A run holds a `.cave-run.lock` file in
+//! the study directory, containing the PID of the cave process and the
+//! container name, for as long as it runs.
+//!
+//! The runs of a batch (`cave run a.export b.export`) share the lock taken
+//! by the batch: a lock held by the current process is not taken again.
+//!
+//! A detached run (`cave run --detach`) outlives its cave process: its lock
+//! is kept, and held for as long as its container runs. It is released when
+//! the run is stopped with `cave jobs kill`, or found finished by `cave jobs`,
+//! `cave status` or the next run.
+
+use crate::jobs;
+use crate::manage::CaveError;
+use log::debug;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Name of the lock file created in the study directory.
+pub const LOCK_FILE: &str = ".cave-run.lock";
+
+/// A held study lock, released when dropped.
+pub struct StudyLock {
+    dir: PathBuf,
+    container: String,
+    /// Whether the lock was taken by this guard, rather than shared with an
+    /// outer lock of the same process.
+    owned: bool,
+}
+
+impl StudyLock {
+    /// Leaves the lock in place when the guard is dropped, for a run that
+    /// goes on in a detached container.
+    pub fn keep(mut self) {
+        self.owned = false;
+    }
+}
+
+impl Drop for StudyLock {
+    /// Removes the lock file, unless another run took it over in the meantime
+    /// (`--force`, or a takeover of a lock it found stale).
+    fn drop(&mut self) {
+        if self.owned {
+            remove_if(&self.dir, |pid, holder| pid == Some(process::id()) && holder == self.container);
+        }
+    }
+}
+
+/// Returns `true` if a process with this PID is still running.
+fn pid_alive(pid: u32) -> bool {
+    if Path::new("/proc").is_dir() {
+        return Path::new(&format!("/proc/{}", pid)).exists();
+    }
+    process::Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Acquires the run lock of `dir` for the given container.
+///
+/// A lock left by a process that no longer exists, and whose container is
+/// not running either, is taken over; a lock of the current process is
+/// shared. With `force`, an active lock is ignored and replaced.
+///
+/// # Errors
+/// Returns [`CaveError::RunInProgress`] if another run holds the lock.
+pub fn acquire(dir: &Path, container: &str, force: bool) -> Result<StudyLock, CaveError> {
+    let path = dir.join(LOCK_FILE);
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                writeln!(file, "{} {}", process::id(), container)?;
+                return Ok(StudyLock { dir: dir.to_path_buf(), container: container.to_string(), owned: true });
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let content = fs::read_to_string(&path).unwrap_or_default();
+                let mut parts = content.split_whitespace();
+                let pid = parts.next().and_then(|p| p.parse::<u32>().ok());
+                let holder = parts.next().unwrap_or("unknown").to_string();
+                if pid == Some(process::id()) {
+                    return Ok(StudyLock { dir: dir.to_path_buf(), container: holder, owned: false });
+                }
+                let held = |pid: &u32| pid_alive(*pid) || jobs::is_running(&holder);
+                if let Some(pid) = pid.filter(|p| !force && held(p)) {
+                    return Err(CaveError::RunInProgress { pid, container: holder });
+                }
+                fs::remove_file(&path)?;
+            }
+            Err(e) => return Err(CaveError::IoError(e)),
+        }
+    }
+}
+
+/// Removes the lock of `dir` if it is held for `container`.
+pub fn release(dir: &Path, container: &str) {
+    remove_if(dir, |_, holder| holder == container);
+}
+
+/// Removes the lock of `dir` if it is held for `container` and its cave
+/// process has exited, for a detached run whose container is known to be
+/// finished.
+pub fn release_stale(dir: &Path, container: &str) {
+    remove_if(dir, |pid, holder| holder == container && !pid.is_some_and(pid_alive));
+}
+
+fn remove_if(dir: &Path, matches: impl Fn(Option<u32>, &str) -> bool) {
+    let path = dir.join(LOCK_FILE);
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    let mut parts = content.split_whitespace();
+    let pid = parts.next().and_then(|p| p.parse::<u32>().ok());
+    if let Some(holder) = parts.next().filter(|holder| matches(pid, holder)) {
+        debug!("Verrou de {} libéré ({})", dir.display(), holder);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -8,15 +8,71 @@
 //! The structure of the cli is described in the cli.rs file. It's in this file you can
 //! modify the cli's commands.
 
+mod about;
+mod answers;
+mod apptainer;
+mod batch;
+mod catalog;
+mod cgroup;
 mod cli;
+mod cluster;
+mod cmdlog;
+mod comm;
+mod compose;
 mod config;
+mod deprecations;
 mod docker;
+mod doctor;
+mod engine;
+mod explain;
+mod export;
+mod extractors;
+mod format;
+mod freeze;
+mod heartbeat;
+mod history;
+mod jobs;
+#[cfg(feature = "k8s")]
+mod k8s;
+mod license;
+mod lock;
 mod manage;
+mod manpage;
+mod mounts;
+mod mpi;
+mod nrt;
+mod orchestrator;
+mod output;
+mod pins;
+mod placement;
+mod plugins;
+mod profiles;
+mod progress;
+mod project;
+mod remote;
+mod restart;
+mod results;
+mod shim;
+mod signals;
+mod slurm;
+mod sources;
+mod stage;
+mod stats;
+mod status;
+mod support;
+mod sweep;
+mod table;
 mod telemetry;
+mod telemetry_schema;
+mod vcs;
+mod watch;
+mod workspace;
 
 use clap::Parser;
-use cli::{Cli, Command, ConfigAction};
+use colored::Colorize;
+use cli::{Cli, Command, ComposeAction, ConfigAction, HistoryAction, ImagesAction, JobsAction, ManAction, NrtAction, PluginAction, ShimAction, SubmitAction};
 use config::*;
+use docker::RunOptions;
 use env_logger::Builder;
 use log::debug;
 use log::LevelFilter;
@@ -30,6 +86,8 @@ fn init_logging() {
 
     let mut builder = Builder::new();
     if debug_enabled {
+        // The log lines would be drawn over the spinners
+        progress::suppress();
         builder.filter_level(LevelFilter::Debug);
     } else {
         builder.filter_level(LevelFilter::Info);
@@ -51,8 +109,29 @@ fn init_logging() {
 /// Errors from subcommands are printed and cause an exit with code `1`.
 fn main() -> io::Result<()> {
     init_logging();
+    signals::install();
     debug!("Mode debug activé");
     let args = Cli::parse();
+    let json = matches!(
+        args.command,
+        Command::Current { json: true }
+            | Command::List { json: true, .. }
+            | Command::Available { json: true, .. }
+    );
+    if args.quiet || json {
+        progress::suppress();
+    }
+    if args.offline {
+        go_offline();
+    }
+    if matches!(
+        args.command,
+        Command::Use { refresh: true, .. }
+            | Command::Pin { refresh: true, .. }
+            | Command::Available { refresh: true, .. }
+    ) {
+        catalog::force_refresh();
+    }
     let _ = match read_config() {
         Ok(cfg) => cfg,
         Err(e) => {
@@ -60,10 +139,17 @@ fn main() -> io::Result<()> {
             process::exit(1);
         }
     };
+    if let Some(path) = &args.answers {
+        if let Err(e) = answers::load(path) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 
     // If auto_release_check is enabled, check for new cave release
     if let Ok(cfg) = read_config() {
-        if cfg.auto_release_check {
+        // `cave status` reports it itself
+        if cfg.auto_release_check && !offline() && !matches!(args.command, Command::Status) {
             let current = env!("CARGO_PKG_VERSION");
             if let Err(e) = check_latest_version(current) {
                 eprintln!("Failed to check for updates: {}", e);
@@ -72,12 +158,214 @@ fn main() -> io::Result<()> {
     }
 
     let result = match args.command {
-        Command::Use { version } => set_version(version, true),
-        Command::Pin { version } => set_version(version, false),
-        Command::Run { args } => run_aster(&args),
-        Command::Shell {} => shell_aster(),
-        Command::List { prefix } => print_local_versions(prefix.unwrap_or_default()),
-        Command::Available { prefix } => print_remote_versions(prefix.unwrap_or_default()),
+        Command::Use { version, no_download, .. } => set_version(version, true, !no_download),
+        Command::Pin { version: Some(version), no_download, .. } => {
+            set_version(version, false, !no_download)
+        }
+        Command::Pin { version: None, .. } => pin_from_global(),
+        Command::Unpin { yes } => unpin(yes),
+        Command::Current { json } => print_current(json),
+        Command::About { version } => about::about(&version),
+        Command::Doctor => doctor::doctor(),
+        Command::Status => status::status(),
+        Command::Pull { from_list: Some(path), jobs, yes, .. } => pull_version_list(&path, jobs, yes),
+        Command::Pull { versions, jobs, .. } => pull_versions(&versions, jobs),
+        Command::Images { action } => match action {
+            ImagesAction::ExportList { output } => export_version_list(output),
+            ImagesAction::ImportList { file, jobs, yes } => pull_version_list(&file, jobs, yes),
+        },
+        Command::Remove { version, force } => remove_version(&version, force),
+        Command::Prune { keep_last, older_than, yes } => prune_versions(keep_last, older_than, yes),
+        Command::Run {
+            args,
+            stage_local,
+            name,
+            force,
+            mpi,
+            cluster,
+            cpuset,
+            numa,
+            nice,
+            allow_version_mismatch,
+            filter,
+            log_file,
+            full_log,
+            keep_temp,
+            yes,
+            cpus,
+            memory,
+            memory_swap,
+            envs,
+            env_files,
+            mounts,
+            gpus,
+            preset,
+            detach,
+            stdin,
+            jobs,
+            watch,
+            remote,
+            backend,
+        } => {
+            let placement = match (cpuset, numa) {
+                (Some(cpus), _) => placement::cpuset(&cpus).map(Some),
+                (None, true) => placement::numa_auto(),
+                (None, false) => Ok(None),
+            };
+            let filter = filter
+                .as_deref()
+                .map(regex::Regex::new)
+                .transpose()
+                .map_err(|e| CaveError::InvalidPattern(e.to_string()));
+            let preset = preset.as_deref().map(config::preset).transpose();
+            let env = env_args(&envs).and_then(|mut args| {
+                for file in env_files {
+                    if !std::path::Path::new(&file).is_file() {
+                        return Err(CaveError::FileNotFound(format!("Environment file '{}' not found.", file)));
+                    }
+                    args.extend(["--env-file".to_string(), file]);
+                }
+                Ok(args)
+            });
+            match (placement, filter, preset, env, read_config()) {
+                (Ok(placement), Ok(filter), Ok(preset), Ok(env), Ok(config)) => {
+                    let preset = preset.unwrap_or_default();
+                    // Command line, then preset, then configured defaults
+                    let limits = ResourceLimits { cpus, memory, memory_swap }
+                        .or(preset.limits)
+                        .or(config.limits);
+                    let mut docker_args = limits.docker_args();
+                    docker_args.extend(env);
+                    if let Some(spec) = gpus.or(config.gpus).filter(|spec| spec != "none") {
+                        docker_args.extend(["--gpus".to_string(), spec]);
+                    }
+                    let options = RunOptions {
+                        stage_local: stage_local || preset.stage_local,
+                        name,
+                        force,
+                        docker_args,
+                        mpi,
+                        cluster,
+                        placement,
+                        nice: nice || preset.nice,
+                        allow_version_mismatch,
+                        filter,
+                        log_file,
+                        full_log,
+                        keep_temp,
+                        yes,
+                        detach,
+                        mounts,
+                        stdin,
+                        remote,
+                        k8s: backend.as_deref() == Some("k8s"),
+                        ..Default::default()
+                    };
+                    match batch::split_args(&args, jobs) {
+                        Ok(batch) if batch.exports.len() > 1 && watch => Err(CaveError::CodeAsterError(
+                            "`--watch` cannot be used with several export files".to_string(),
+                        )),
+                        Ok(batch) if watch => watch::run(&batch.run_args(), options),
+                        Ok(batch) if batch.exports.len() > 1 => {
+                            batch::run(&batch.exports, &batch.args, batch.jobs.unwrap_or(1), options)
+                        }
+                        Ok(batch) => run_aster(&batch.run_args(), options),
+                        Err(e) => Err(e),
+                    }
+                }
+                (Err(e), _, _, _, _)
+                | (_, Err(e), _, _, _)
+                | (_, _, Err(e), _, _)
+                | (_, _, _, Err(e), _)
+                | (_, _, _, _, Err(e)) => Err(e),
+            }
+        }
+        Command::Rerun { run, with } => rerun(run, with),
+        Command::Compose { action } => match action {
+            ComposeAction::Run { name, force, yes, args } => run_aster(
+                &args,
+                RunOptions {
+                    name,
+                    force,
+                    yes,
+                    compose: true,
+                    ..Default::default()
+                },
+            ),
+        },
+        Command::Shell { yes } => shell_aster(yes),
+        Command::Exec { yes, command } => exec_aster(&command, yes),
+        Command::Env { refresh } => print_env(refresh),
+        Command::SupportBundle { run, output, force } => support::support_bundle(run, output, force),
+        Command::List { prefix, no_header, json } => {
+            print_local_versions(prefix.unwrap_or_default(), no_header, json)
+        }
+        Command::Available { prefix, no_header, json, .. } => {
+            print_remote_versions(prefix.unwrap_or_default(), no_header, json)
+        }
+        Command::Ignore { check, max_size } => vcs::ignore(check, max_size),
+        Command::History { action, failed, version, here, limit } => match action {
+            Some(HistoryAction::Show { id }) => history::show(&id),
+            Some(HistoryAction::Stats { by, days, csv }) => {
+                stats::print(stats::Period::parse(&by).unwrap_or(stats::Period::Day), days, csv)
+            }
+            None => std::env::current_dir().map_err(CaveError::IoError).and_then(|dir| {
+                history::list(&history::HistoryFilter {
+                    failed,
+                    version,
+                    dir: here.then(|| dir.display().to_string()),
+                    limit,
+                })
+            }),
+        },
+        Command::DiffResults { run_a, run_b, rtol, atol, time_rtol, all } => results::diff_results(
+            &run_a,
+            &run_b,
+            results::Tolerances { rtol, atol, time_rtol },
+            all,
+        ),
+        Command::Jobs { action } => match action {
+            None => jobs::list(),
+            Some(JobsAction::Attach { id }) => jobs::attach(&id),
+            Some(JobsAction::Kill { id, yes }) => jobs::kill_one(&id, yes),
+        },
+        Command::KillAll { yes } => jobs::kill_all(yes),
+        Command::Freeze { output } => freeze::freeze(output),
+        Command::Thaw { manifest, force } => freeze::thaw(manifest, force),
+        Command::Explain { query } => explain::explain(query),
+        Command::Submit { action, partition, account, job_name, sbatch_args, dry_run, yes, args } => match action {
+            Some(SubmitAction::Status { job }) => slurm::status(job.as_deref()),
+            None => slurm::submit(
+                &args,
+                slurm::SubmitOptions { partition, account, job_name, sbatch_args, dry_run, yes },
+            ),
+        },
+        Command::Check { export } => export::check(&export),
+        Command::CheckComm { files } => comm::check_comm(&files),
+        Command::InitExport { output, comm, mesh, memory, time, force } => {
+            export::init(comm, mesh, memory, time, output, force)
+        }
+        Command::Nrt { action, cases, junit } => match action {
+            Some(NrtAction::Bless { cases, run }) => nrt::bless(&cases, run),
+            None => nrt::run(&cases, junit),
+        },
+        Command::Sweep { export, params, file, jobs, retries, fail_fast, output, force, yes } => {
+            sweep::run(sweep::SweepOptions { export, params, file, jobs, retries, fail_fast, output, force, yes })
+        }
+        Command::Shim { action } => match action {
+            ShimAction::Install { dir } => shim::install(dir),
+            ShimAction::Uninstall { dir } => shim::uninstall(dir),
+        },
+        Command::Man { action } => match action {
+            ManAction::Install { dir, user } => manpage::install(dir, user),
+        },
+        Command::Plugin { action } => match action {
+            PluginAction::List => plugins::print_plugins(),
+        },
+        Command::External(args) => plugins::run(&args),
+        Command::Config { .. } if read_only_home() => {
+            Err(CaveError::ReadOnlyHome("changing the configuration".to_string()))
+        }
         Command::Config { action } => {
             match action {
                 ConfigAction::EnableAutoUpdate => set_auto_update(true),
@@ -86,18 +374,69 @@ fn main() -> io::Result<()> {
                 ConfigAction::DisableUpdateCheck => set_auto_release_check(false),
                 ConfigAction::EnableUsageTracking => set_version_tracking(true),
                 ConfigAction::DisableUsageTracking => set_version_tracking(false),
-                // TODO : uncomment to have registry option
-                //
-                // ConfigAction::SetRegistry { repo, user, token } => {
-                //     set_registry(Some(Registry { repo, user, token }))
-                // }
-                // ConfigAction::EraseRegistry => set_registry(None),
+                ConfigAction::EnableResourceTracking => set_share_resource_usage(true),
+                ConfigAction::DisableResourceTracking => set_share_resource_usage(false),
+                ConfigAction::EnablePinNotice => set_pin_notice(true),
+                ConfigAction::DisablePinNotice => set_pin_notice(false),
+                ConfigAction::EnableExportVersion => set_export_version(true),
+                ConfigAction::DisableExportVersion => set_export_version(false),
+                ConfigAction::EnableRunMetadata => set_run_metadata(true),
+                ConfigAction::DisableRunMetadata => set_run_metadata(false),
+                ConfigAction::EnableNiceBatch => set_nice_batch(true),
+                ConfigAction::DisableNiceBatch => set_nice_batch(false),
+                ConfigAction::SetHeartbeat { seconds } => set_heartbeat(seconds),
+                ConfigAction::EnableStackCapture => set_capture_stack(true),
+                ConfigAction::DisableStackCapture => set_capture_stack(false),
+                ConfigAction::EnableCommandLog => set_log_commands(true),
+                ConfigAction::DisableCommandLog => set_log_commands(false),
+                ConfigAction::EnableLogFile => set_log_file(true),
+                ConfigAction::DisableLogFile => set_log_file(false),
+                ConfigAction::EnableOffline => set_offline(true),
+                ConfigAction::DisableOffline => set_offline(false),
+                ConfigAction::SetRuntime { runtime } => set_runtime(&runtime),
+                ConfigAction::SetContainerUser { user } => set_container_user(&user),
+                ConfigAction::SetLicenseEnv { assignment } => set_license_env(&assignment),
+                ConfigAction::UnsetLicenseEnv { name } => unset_license_env(&name),
+                ConfigAction::SetRunArg { major, flag, option, scale } => set_run_arg(major, &flag, &option, scale),
+                ConfigAction::UnsetRunArg { major, flag } => unset_run_arg(major, &flag),
+                ConfigAction::SetRegistry { repo, user, token, host } => {
+                    set_registry(Some(Registry { host, repo, user, token }))
+                }
+                ConfigAction::EraseRegistry => set_registry(None),
+                ConfigAction::SetImageSources { sources } => set_image_sources(sources),
+                ConfigAction::SetForwardEnv { variables } => set_forward_env(variables),
+                ConfigAction::SetTelemetrySinks { sinks } => set_telemetry_sinks(sinks),
+                ConfigAction::SetLimits { cpus, memory, memory_swap } => {
+                    set_limits(ResourceLimits { cpus, memory, memory_swap })
+                }
+                ConfigAction::SetPreset { name, cpus, memory, memory_swap, stage_local, nice } => {
+                    let limits = ResourceLimits { cpus, memory, memory_swap };
+                    set_preset(&name, Preset { limits, stage_local, nice })
+                }
+                ConfigAction::SetHubBudget { page_size, max_pages, max_tags } => {
+                    set_hub_budget(HubBudget { page_size, max_pages, max_tags })
+                }
+                ConfigAction::SetCacheTtl { minutes } => set_cache_ttl(minutes),
+                ConfigAction::SetLogLimit { mib } => set_log_limit(mib),
+                ConfigAction::SetGpus { spec } => set_gpus(spec),
+                ConfigAction::RemovePreset { name } => remove_preset(&name),
             }
         }
     };
 
+    remove_invocation_state();
     if let Err(e) = result {
-        eprintln!("{}", e);
+        if json {
+            eprintln!("{}", e.to_json());
+        } else {
+            eprintln!("{}", e);
+            if let Some(hint) = e.hint() {
+                eprintln!("{} {}", "Hint:".cyan().bold(), hint);
+            }
+        }
+        if matches!(e, CaveError::Cancelled) {
+            process::exit(signals::EXIT_CANCELLED);
+        }
         process::exit(1);
     }
 
@@ -1,7 +1,7 @@
 //! Version management and configuration handling for the `cave` CLI.
 //!
 //! It provides utilities for:
-//! - Validating version formats (numeric, `stable`, `testing`).
+//! - Validating version formats (numeric, `stable`, `testing`, `latest`).
 //! - Checking if a version exists locally or on a remote registry.
 //! - Pulling missing versions from Docker Hub or a private registry.
 //! - Storing the selected version either globally or locally.
@@ -11,18 +11,44 @@
 //! Errors are centralized in the [`CaveError`] enum, which provides
 //! descriptive messages for all failure cases.
 
-use crate::config::read_config;
+use crate::config::{ensure_online, env_args, offline, read_config, read_only_home, state_dir, POLICY_FILE};
 use crate::docker::*;
+use crate::explain;
+use crate::export;
+use crate::format;
+use crate::cmdlog;
+use crate::history::{self, Invocation};
+#[cfg(feature = "k8s")]
+use crate::k8s;
+use crate::cluster::{self, Workers};
+use crate::compose::Services;
+use crate::deprecations;
+use crate::license;
+use crate::lock;
+use crate::mounts::Mount;
+use crate::mpi;
+use crate::orchestrator::{Orchestrator, Outcome};
+use crate::pins::{self, Pin};
+use crate::placement;
+use crate::progress;
+use crate::profiles;
+use crate::remote;
+use crate::signals;
+use crate::restart;
+use crate::answers::{self, MissingVersion, Prompt};
+use crate::catalog;
+use crate::sources;
+use crate::project::{find_local_pin, find_project_dir, merge_args, read_project_config};
+use crate::stage::{network_filesystem, StagedDir};
+use crate::table::{Cell, Table};
+use crate::workspace::Workspace;
 use colored::*;
-use regex::Regex;
 use std::{
     cmp::Ordering,
     fmt, fs,
-    io::{self, Write},
+    io::{self, IsTerminal, Write},
     path::{Path, PathBuf},
 };
-// TODO : uncomment to have registry option
-//use crate::config::Config;
 use reqwest::blocking::Client;
 use semver::Version;
 
@@ -51,6 +77,10 @@ pub enum CaveError {
     CheckReleaseError(String),
     /// Docker is not installed.
     NoDocker,
+    /// Docker is installed but its daemon is not running.
+    DockerDaemonNotRunning,
+    /// The user is not allowed to access the Docker socket.
+    DockerPermissionDenied,
     /// No internet connection for the client
     NoInternetConnection,
     /// JSON serialization/deserialization error.
@@ -61,13 +91,85 @@ pub enum CaveError {
     TelemetryError(String),
     /// Error parsing version from GitHub
     VersionParseError(String),
+    /// No run, or several runs, match the given identifier in the history.
+    RunNotFound(String),
+    /// The operation was interrupted by SIGINT/SIGTERM.
+    Cancelled,
+    /// Run name not usable as a container or file name.
+    InvalidRunName(String),
+    /// Another run is in progress in the same study directory.
+    RunInProgress { pid: u32, container: String },
+    /// Invalid project configuration file (`cave.toml`).
+    ProjectConfigError(String),
+    /// Results of two runs differ beyond the tolerances, with the number of differences.
+    ResultsDiffer(usize),
+    /// Non-regression cases failed, with the number of failed cases.
+    NrtFailed(usize),
+    /// Environment assignment not of the form `NAME=VALUE`.
+    InvalidAssignment(String),
+    /// Invalid regular expression.
+    InvalidPattern(String),
+    /// A study restarts from a database produced by another code_aster version.
+    RestartVersionMismatch { base: String, produced_by: String, current: String },
+    /// The version is referenced by a `.cave` pin file.
+    VersionPinned { version: String, pin: String },
+    /// CPU list not in the `cpuset` format.
+    InvalidCpuset(String),
+    /// Container user setting neither `host`, `image` nor `UID:GID`.
+    InvalidContainerUser(String),
+    /// Telemetry sink neither `http`, `file:PATH` nor `command:COMMAND`.
+    InvalidTelemetrySink(String),
+    /// Extra volume (`cave run --mount`) not valid.
+    InvalidMount { spec: String, reason: String },
+    /// Export file with errors, one message per error.
+    InvalidExport { file: String, problems: Vec<String> },
+    /// No resource preset has the given name.
+    UnknownPreset(String),
+    /// Answers file (`cave --answers`) not valid.
+    InvalidAnswers(String),
+    /// Errors found in command files by `cave check-comm`, with their number.
+    CommCheckFailed(usize),
+    /// Invalid parameters or sweep file for `cave sweep`.
+    InvalidSweep(String),
+    /// Cases of a sweep failed, with the number of failed cases.
+    SweepFailed(usize),
+    /// Studies of a batch run failed, with the number of failed studies.
+    BatchFailed(usize),
+    /// Versions given to `cave pull` could not be pulled, with their number.
+    PullFailed(usize),
+    /// The operation needs the network, and cave works offline.
+    Offline(String),
+    /// A remote run (`cave run --remote`) cannot reach its host or copy the study.
+    RemoteError(String),
+    /// The files of a study cannot be watched by `cave run --watch`.
+    WatchFailed(String),
+    /// `sbatch` or `squeue` failed, or is missing, for `cave submit`.
+    SlurmError(String),
+    /// A Kubernetes Job (`cave run --backend k8s`) cannot be created, run or followed.
+    K8sError(String),
+    /// Critical `cave doctor` checks failed, with the number of failed checks.
+    ChecksFailed(usize),
+    /// Neither a built-in command nor a `cave-<name>` plugin on the `PATH`.
+    UnknownCommand(String),
+    /// No running cave container, or several, match the given identifier.
+    JobNotFound(String),
+    /// The container of a run failed before code_aster started, with the
+    /// reason and the error output of the container.
+    ContainerStartFailed { version: String, reason: String, output: String },
+    /// The operation would write to the home directory in read-only mode.
+    ReadOnlyHome(String),
+    /// The file a command writes exists, and `--force` was not given.
+    FileExists { file: String, command: &'static str },
+    /// A lower-level error, with what cave was doing when it occurred: the
+    /// URL requested, the file read or the container command run.
+    Context { context: String, source: Box<CaveError> },
 }
 
 impl fmt::Display for CaveError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CaveError::InvalidFormat(ver) =>
-                write!(f, "Invalid version input: '{}'. Expected stable, testing or under this format: xx.x.xx", ver),
+                write!(f, "Invalid version input: '{}'. Expected stable, testing, latest or a version tag such as 17.1.4", ver),
             CaveError::VersionNotAvailable(ver) =>
                 write!(f, "Version '{}' is not available. Run `cave available` or see on https://hub.docker.com/r/simvia/code_aster.", ver),
             CaveError::UserAborted =>
@@ -88,6 +190,10 @@ impl fmt::Display for CaveError {
                 write!(f, "Error checking for new cave release : {}", e),
             CaveError::NoDocker =>
                 write!(f, "Docker not found. Please install Docker and try again."),
+            CaveError::DockerDaemonNotRunning =>
+                write!(f, "Docker daemon is not running. Start Docker Desktop or run `sudo systemctl start docker`, then try again."),
+            CaveError::DockerPermissionDenied =>
+                write!(f, "Permission denied on the Docker socket. Add your user to the docker group with `sudo usermod -aG docker $USER`, then log out and back in."),
             CaveError::NoInternetConnection =>
                 write!(f, "Error: No internet connection detected. Please check your network and try again."),
             CaveError::SerdeError(e) =>
@@ -98,7 +204,175 @@ impl fmt::Display for CaveError {
             write!(f, "telemetry error: {}", msg),
             CaveError::VersionParseError(msg) =>
                 write!(f, "Version parse error: {}", msg),
+            CaveError::RunNotFound(id) =>
+                write!(f, "No single run matches '{}' in the history.", id),
+            CaveError::Cancelled =>
+                write!(f, "Operation cancelled."),
+            CaveError::InvalidRunName(name) =>
+                write!(f, "Invalid run name: '{}'. Use letters, digits, '_', '.' and '-' only.", name),
+            CaveError::RunInProgress { pid, container } =>
+                write!(f, "A run is already in progress in this directory (pid {}, container {}). Use `cave run --force` to run anyway.", pid, container),
+            CaveError::ProjectConfigError(msg) =>
+                write!(f, "Invalid project configuration: {}", msg),
+            CaveError::ResultsDiffer(count) =>
+                write!(f, "{} result(s) differ beyond the tolerances.", count),
+            CaveError::NrtFailed(count) =>
+                write!(f, "{} non-regression case(s) failed.", count),
+            CaveError::InvalidAssignment(assignment) =>
+                write!(f, "Invalid assignment: '{}'. Expected NAME=VALUE.", assignment),
+            CaveError::InvalidPattern(msg) =>
+                write!(f, "Invalid pattern: {}", msg),
+            CaveError::RestartVersionMismatch { base, produced_by, current } =>
+                write!(f, "'{}' was produced by code_aster {}, restarting from it with {} would corrupt it. Run `cave pin {}` to restart with the original version, or run the initial study again with {}. Use `cave run --allow-version-mismatch` to run anyway.", base, produced_by, current, produced_by, current),
+            CaveError::VersionPinned { version, pin } =>
+                write!(f, "Version {} is pinned in {}. Use --force to remove it anyway.", version, pin),
+            CaveError::InvalidAnswers(msg) =>
+                write!(f, "Invalid answers file: {}", msg),
+            CaveError::UnknownPreset(name) =>
+                write!(f, "Unknown preset '{}'. Define it with `cave config set-preset {} --cpus <N> --memory <SIZE>`.", name, name),
+            CaveError::InvalidCpuset(cpus) =>
+                write!(f, "Invalid CPU list: '{}'. Expected a list such as 0-7,16-23.", cpus),
+            CaveError::InvalidContainerUser(user) =>
+                write!(f, "Invalid container user: '{}'. Expected host, image or UID:GID such as 1000:1000.", user),
+            CaveError::InvalidTelemetrySink(sink) =>
+                write!(f, "Invalid telemetry sink: '{}'. Expected http, file:PATH or command:COMMAND.", sink),
+            CaveError::InvalidMount { spec, reason } =>
+                write!(f, "Invalid mount '{}': {}. Expected HOST:CONTAINER[:ro].", spec, reason),
+            CaveError::InvalidExport { file, problems } => {
+                write!(f, "Export file '{}' is invalid:", file)?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            CaveError::CommCheckFailed(count) =>
+                write!(f, "{} error(s) found in the command files.", count),
+            CaveError::InvalidSweep(msg) =>
+                write!(f, "Invalid sweep: {}", msg),
+            CaveError::SweepFailed(count) =>
+                write!(f, "{} sweep case(s) failed.", count),
+            CaveError::BatchFailed(count) =>
+                write!(f, "{} batch run(s) failed, see `cave history`.", count),
+            CaveError::PullFailed(count) =>
+                write!(f, "{} version(s) could not be pulled.", count),
+            CaveError::Offline(operation) =>
+                write!(f, "{} needs the network, and cave works offline.", operation),
+            CaveError::RemoteError(msg) =>
+                write!(f, "Remote run error: {}", msg),
+            CaveError::WatchFailed(msg) =>
+                write!(f, "Cannot watch the study files: {}", msg),
+            CaveError::SlurmError(msg) =>
+                write!(f, "Slurm error: {}", msg),
+            CaveError::K8sError(msg) =>
+                write!(f, "Kubernetes error: {}", msg),
+            CaveError::ChecksFailed(count) =>
+                write!(f, "{} critical check(s) failed.", count),
+            CaveError::UnknownCommand(name) =>
+                write!(f, "Unknown command '{}': no built-in command nor `cave-{}` plugin on the PATH. Run `cave --help` or `cave plugin list`.", name, name),
+            CaveError::JobNotFound(id) =>
+                write!(f, "No single running cave container matches '{}'. Run `cave jobs` to list them.", id),
+            CaveError::ContainerStartFailed { version, reason, output } => {
+                write!(f, "The container of version {} failed to start: {}.", version, reason)?;
+                if !output.is_empty() {
+                    write!(f, "\nContainer output:\n{}", output)?;
+                }
+                write!(f, "\nRun `cave doctor` to check the runtime and the image.")
+            }
+            CaveError::ReadOnlyHome(operation) =>
+                write!(f, "cave runs in read-only mode (CAVE_READ_ONLY or {}): {} would write to the home directory.", POLICY_FILE, operation),
+            CaveError::FileExists { file, command } =>
+                write!(f, "{} already exists. Use `{} --force` to replace it.", file, command),
+            CaveError::Context { context, source } =>
+                write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl CaveError {
+    /// Returns the code of the `cave explain` entry describing this error, if any.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            CaveError::NoDocker => Some("no-docker"),
+            CaveError::DockerDaemonNotRunning => Some("docker-daemon"),
+            CaveError::DockerPermissionDenied => Some("docker-permission"),
+            CaveError::VersionNotInstalled(_) => Some("version-not-installed"),
+            CaveError::VersionNotAvailable(_) => Some("version-not-available"),
+            CaveError::RunInProgress { .. } => Some("run-in-progress"),
+            CaveError::ProjectConfigError(_) => Some("project-config"),
+            CaveError::RestartVersionMismatch { .. } => Some("restart-version-mismatch"),
+            CaveError::ContainerStartFailed { .. } => Some("container-start"),
+            CaveError::InvalidExport { .. } => Some("invalid-export"),
+            CaveError::Context { source, .. } => source.code(),
+            _ => None,
+        }
+    }
+
+    /// Returns what to do about this error, if cave can tell.
+    pub fn hint(&self) -> Option<String> {
+        if let Some(entry) = self.code().and_then(explain::find) {
+            return Some(format!("Run `cave explain {}` for details.", entry.code));
+        }
+        match self {
+            CaveError::Context { source, .. } => source.hint(),
+            CaveError::HttpError(_) | CaveError::CheckReleaseError(_) | CaveError::NoInternetConnection => Some(
+                "Check the network connection and the proxy settings (HTTPS_PROXY, NO_PROXY), then run `cave doctor`.".to_string(),
+            ),
+            CaveError::DockerError(_) => Some("Run `cave doctor` to check the container runtime.".to_string()),
+            CaveError::Offline(_) => Some(
+                "Run it without `--offline`, or `cave config disable-offline` if it is configured.".to_string(),
+            ),
+            CaveError::IoError(e) if e.kind() == io::ErrorKind::PermissionDenied => Some(
+                "Check the permissions of the file, and of the cave state directory ~/.cave.d.".to_string(),
+            ),
+            CaveError::InvalidFormat(_) => Some("Run `cave available` to list the published versions.".to_string()),
+            CaveError::RunNotFound(_) => Some("Run `cave history` to list the runs.".to_string()),
+            CaveError::RemoteError(_) => Some(
+                "Check the [remote] section of cave.toml, and that `ssh <host>` connects without a password prompt.".to_string(),
+            ),
+            CaveError::SlurmError(_) => Some(
+                "Run cave from a login node of the cluster, where `sbatch` and `squeue` are available; `cave submit status` lists the submitted jobs.".to_string(),
+            ),
+            CaveError::K8sError(_) => Some(
+                "Check the [k8s] section of cave.toml, and that `kubectl get pods` works with its context and namespace.".to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Returns the error as JSON, for the commands run with `--json`: the
+    /// message of the original error, what cave was doing from the outermost
+    /// operation, and the `cave explain` code and hint if any.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut context = Vec::new();
+        let mut error = self;
+        while let CaveError::Context { context: c, source } = error {
+            context.push(c.clone());
+            error = source;
         }
+        serde_json::json!({
+            "error": {
+                "message": error.to_string(),
+                "context": context,
+                "code": self.code(),
+                "hint": self.hint(),
+            }
+        })
+    }
+}
+
+/// Adds what cave was doing to the error of a lower-level operation.
+pub trait ErrorContext<T> {
+    /// Wraps the error in [`CaveError::Context`], `context` being evaluated
+    /// on error only.
+    fn with_context(self, context: impl FnOnce() -> String) -> Result<T, CaveError>;
+}
+
+impl<T, E: Into<CaveError>> ErrorContext<T> for Result<T, E> {
+    fn with_context(self, context: impl FnOnce() -> String) -> Result<T, CaveError> {
+        self.map_err(|e| CaveError::Context {
+            context: context(),
+            source: Box::new(e.into()),
+        })
     }
 }
 
@@ -108,10 +382,20 @@ impl From<io::Error> for CaveError {
     }
 }
 
+impl From<serde_json::Error> for CaveError {
+    fn from(e: serde_json::Error) -> Self {
+        CaveError::SerdeError(e)
+    }
+}
+
 /// Sets the `code_aster` version to use, with an option to set it as the default.
 ///
-/// - If `version` is `"stable"` or `"testing"`, resolves to the real version via [`version_under_tag`].
-/// - Otherwise, validates the format `xx.x.xx` and pulls the version if it is missing.
+/// Pinning goes in three steps, so that a failure or a declined prompt
+/// leaves the previous pin untouched:
+/// 1. the version is resolved ([`resolve_version`]);
+/// 2. its image is made present ([`ensure_present`]), downloaded unless
+///    `download` is `false`;
+/// 3. the pin file is written atomically ([`write_pin`]).
 ///
 /// # Errors
 /// - [`CaveError::InvalidFormat`] if the version string is in an invalid format.
@@ -122,69 +406,483 @@ impl From<io::Error> for CaveError {
 ///
 /// # Example
 /// ```
-/// set_version("22.0.1".to_string(), true).expect("Unable to set version");
+/// set_version("22.0.1".to_string(), true, true).expect("Unable to set version");
 /// ```
-pub fn set_version(version: String, default_version: bool) -> Result<(), CaveError> {
-    let true_version: String;
-
-    if version == "stable" || version == "testing" {
-        if !internet_available() {
-            return Err(CaveError::NoInternetConnection);
+pub fn set_version(version: String, default_version: bool, download: bool) -> Result<(), CaveError> {
+    let path: PathBuf = if default_version {
+        if read_only_home() {
+            return Err(CaveError::ReadOnlyHome("setting the global version".to_string()));
         }
-        true_version = version_under_tag(version.clone())?;
+        let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+        home.join(".cave")
     } else {
-        let version_regex = Regex::new(r"^\d{1,2}\.\d{1,2}\.\d{1,2}$").unwrap();
-        if !version_regex.is_match(&version) {
-            return Err(CaveError::InvalidFormat(version));
+        PathBuf::from(".cave")
+    };
+
+    let (true_version, content) = resolve_version(&version)?;
+    ensure_present(&true_version, download)?;
+    deprecations::warn(&true_version);
+    write_pin(&path, &content)
+}
+
+/// Resolves the version given to `cave use`/`cave pin`, and returns it with
+/// the content of its pin file.
+///
+/// - The channels `"stable"` and `"testing"` resolve to the version under
+///   the tag, and `"latest"` to the highest numeric version published (see
+///   [`pins::resolve`]), pinned as `<channel>:<version>@<digest>`. A recent
+///   resolution is reused (see [`pins::cached`]).
+/// - Otherwise, the version must be able to be an image tag (see [`catalog::is_tag`]).
+///
+/// # Errors
+/// - [`CaveError::InvalidFormat`] if the version cannot be an image tag.
+/// - [`CaveError::NoInternetConnection`] if a tag cannot be resolved offline.
+pub fn resolve_version(version: &str) -> Result<(String, String), CaveError> {
+    if pins::CHANNELS.contains(&version) {
+        let pin = match pins::cached(version) {
+            Some(pin) => pin,
+            None => {
+                ensure_online(&format!("Resolving {}", version))?;
+                if !internet_available() {
+                    return Err(CaveError::NoInternetConnection);
+                }
+                pins::resolve(version, None)?
+            }
+        };
+        Ok((pin.version.clone(), pin.to_string()))
+    } else if catalog::is_tag(version) {
+        Ok((version.to_string(), version.to_string()))
+    } else {
+        Err(CaveError::InvalidFormat(version.to_string()))
+    }
+}
+
+/// Makes sure that the image of `version` is installed before it is pinned,
+/// pulling it after confirmation. With `download` set to `false`, a missing
+/// version is only checked to be published: `cave run` offers to download it.
+///
+/// # Errors
+/// - [`CaveError::VersionNotAvailable`] if the version is neither installed nor published.
+/// - [`CaveError::UserAborted`] if the user declines the download.
+/// - The errors of [`pull_version`] if the download fails.
+pub fn ensure_present(version: &str, download: bool) -> Result<(), CaveError> {
+    if exists_locally(version)? {
+        return Ok(());
+    }
+    if !exists_remotely(version)? {
+        return Err(CaveError::VersionNotAvailable(version.to_string()));
+    }
+    if !download {
+        println!("Version '{}' is not installed, `cave run` will download it.", version);
+        return Ok(());
+    }
+    if !answers::confirm(Prompt::Download, &format!("Version '{}' not installed. Download it?", version))? {
+        return Err(CaveError::UserAborted);
+    }
+    pull_version(version)
+}
+
+/// Copies the global version (`~/.cave`) into the local `.cave` as is,
+/// without resolving the channel or downloading the image.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if no global version is set.
+/// - [`CaveError::IoError`] on file reading or writing issues.
+pub fn pin_from_global() -> Result<(), CaveError> {
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    let global = fs::read_to_string(home.join(".cave")).map_err(|_| {
+        CaveError::FileNotFound("No global version found. Use `cave use <version>` first.".to_string())
+    })?;
+    write_pin(Path::new(".cave"), global.trim())?;
+    println!("Pinned {} in the current directory.", Pin::parse(&global).spec());
+    Ok(())
+}
+
+/// Removes the local `.cave` file effective in the current directory (see
+/// [`find_local_pin`]), after confirmation unless `yes` is set.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if no local `.cave` file applies.
+/// - [`CaveError::UserAborted`] if the user declines.
+pub fn unpin(yes: bool) -> Result<(), CaveError> {
+    let local = find_local_pin(&std::env::current_dir()?).ok_or_else(|| {
+        CaveError::FileNotFound("No version pinned in the current directory.".to_string())
+    })?;
+    let local = local.as_path();
+    let pinned = fs::read_to_string(local)?;
+    let question = format!("Remove the local pin '{}' ({})?", Pin::parse(&pinned).spec(), local.display());
+    if !yes && !answers::confirm(Prompt::Remove, &question)? {
+        return Err(CaveError::UserAborted);
+    }
+    fs::remove_file(local)?;
+
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    match fs::read_to_string(home.join(".cave")) {
+        Ok(global) => println!("Local pin removed, now using the global version {}.", Pin::parse(&global).spec()),
+        Err(_) => println!("Local pin removed. No global version is set, use `cave use <version>`."),
+    }
+    Ok(())
+}
+
+/// Returns the `.cave` files applying to the current directory, global then
+/// local, with the version each of them pins (`stable:`/`testing:` prefixes
+/// removed).
+pub fn pin_files() -> Result<Vec<(PathBuf, String)>, CaveError> {
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    let mut files = vec![home.join(".cave")];
+    files.extend(find_local_pin(&std::env::current_dir()?));
+    Ok(files
+        .into_iter()
+        .filter_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            Some((path, Pin::parse(&content).version))
+        })
+        .collect())
+}
+
+/// Returns the version declared by the export file ending `args` (`P version`),
+/// when `cave config enable-export-version` is set, with a notice if it
+/// differs from the pinned version. `stable` and `testing` are resolved as
+/// for `cave use`.
+///
+/// # Errors
+/// - [`CaveError::InvalidFormat`] if the declared version cannot be an image tag.
+/// - [`CaveError::NoInternetConnection`] if a declared channel cannot be resolved offline.
+pub fn export_version(args: &[String]) -> Result<Option<String>, CaveError> {
+    if !read_config()?.export_version {
+        return Ok(None);
+    }
+    let Some(file) = args.last().filter(|a| a.ends_with(".export")) else {
+        return Ok(None);
+    };
+    // A missing export file is reported by the checks of the run
+    let Ok(content) = fs::read_to_string(file) else {
+        return Ok(None);
+    };
+    let (parsed, _) = export::Export::parse(&content);
+    let Some(declared) = parsed.version() else {
+        return Ok(None);
+    };
+    let (version, _) = resolve_version(declared)?;
+    if let Some((path, pinned)) = pin_files()?.pop() {
+        if pinned != version {
+            eprintln!(
+                "{} using {} declared by {} instead of {} pinned by {}. Disable with `cave config disable-export-version`.",
+                "Note:".cyan().bold(),
+                version,
+                file,
+                pinned,
+                path.display()
+            );
         }
-        true_version = version.clone();
     }
+    Ok(Some(version))
+}
 
-    let exists_locally = exists_locally(&true_version)?;
-    let version_ok = if exists_locally {
-        true_version
+/// Prints the version used in the current directory, the `.cave` file it
+/// comes from, its `stable`/`testing` channel if any, and whether its image
+/// is installed.
+///
+/// Unlike [`read_cave_version`], the channel is not checked for updates.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if no version is set.
+pub fn print_current(json: bool) -> Result<(), CaveError> {
+    let (path, version) = pin_files()?.pop().ok_or_else(|| {
+        CaveError::FileNotFound(
+            "No version found. Use `cave use <version>` or `cave pin <version>`.".to_string(),
+        )
+    })?;
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    let source = if path == home.join(".cave") { "global" } else { "local" };
+    let content = fs::read_to_string(&path)?;
+    let channel = Pin::parse(&content).channel;
+    let installed = exists_locally(&version)?;
+
+    if json {
+        let current = serde_json::json!({
+            "version": version,
+            "scope": source,
+            "pin": path,
+            "channel": channel,
+            "installed": installed,
+        });
+        return print_json(&current);
+    }
+    println!("{:<12}{}", "Version", version.bold());
+    println!("{:<12}{} ({})", "Source", source, path.display());
+    if let Some(channel) = channel {
+        println!("{:<12}tracking {}", "Channel", channel);
+    }
+    if installed {
+        println!("{:<12}{}", "Installed", "yes".green());
     } else {
-        let exists_remotely = exists_remotely(&true_version)?;
-        if exists_remotely {
-            println!(
-                "Version '{}' not installed. Download it? (y/n):",
-                true_version
-            );
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            if input.trim().to_lowercase() == "y" {
-                pull_version(&true_version)?;
-                true_version
-            } else {
-                return Err(CaveError::UserAborted);
-            }
+        println!("{:<12}{} (run `cave pin {}`)", "Installed", "no".red().bold(), version);
+    }
+    Ok(())
+}
+
+/// Removes the local image of a code_aster version.
+///
+/// A version pinned by the global `~/.cave` or by the local `.cave` of the
+/// current directory is only removed with `force`.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the version is not installed.
+/// - [`CaveError::VersionPinned`] if the version is pinned and `force` is not set.
+/// - [`CaveError::DockerError`] if the image cannot be removed.
+pub fn remove_version(version: &str, force: bool) -> Result<(), CaveError> {
+    if !exists_locally(version)? {
+        return Err(CaveError::VersionNotInstalled(version.to_string()));
+    }
+    for (path, pinned) in pin_files()? {
+        if pinned != version {
+            continue;
+        }
+        if !force {
+            return Err(CaveError::VersionPinned {
+                version: version.to_string(),
+                pin: path.display().to_string(),
+            });
+        }
+        eprintln!(
+            "{} version {} is pinned in {}, runs will fail until another version is pinned.",
+            "Warning:".yellow().bold(),
+            version,
+            path.display()
+        );
+    }
+    remove_image(version)?;
+    println!("Removed version {}", version);
+    Ok(())
+}
+
+/// Handler of `cave pull`: pulls `versions`, `jobs` at a time, and prints
+/// the outcome of each of them.
+///
+/// `stable` and `testing` are resolved first, and a version given twice is
+/// pulled once. Installed versions are not pulled again. With several jobs,
+/// the layer progress is not shown, only the start and end of each pull.
+///
+/// # Errors
+/// - [`CaveError::InvalidFormat`] if a version cannot be an image tag.
+/// - [`CaveError::NoInternetConnection`] if a channel cannot be resolved offline.
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::PullFailed`] if any version fails to pull.
+pub fn pull_versions(versions: &[String], jobs: usize) -> Result<(), CaveError> {
+    // (as given, resolved version)
+    let mut targets: Vec<(String, String)> = Vec::new();
+    for requested in versions {
+        let (version, _) = resolve_version(requested)?;
+        if !targets.iter().any(|(_, v)| *v == version) {
+            targets.push((requested.clone(), version));
+        }
+    }
+    let jobs = jobs.max(1).min(targets.len());
+    if jobs > 1 {
+        progress::suppress();
+        println!("{} {} versions, {} at a time", "Pulling".bold(), targets.len(), jobs);
+    }
+
+    let label = |(requested, version): &(String, String)| {
+        if requested == version {
+            version.clone()
         } else {
-            return Err(CaveError::VersionNotAvailable(true_version));
+            format!("{} ({})", version, requested)
         }
     };
+    let outcomes = Orchestrator::new(jobs).run(
+        &targets,
+        label,
+        |(_, version), _| {
+            if exists_locally(version)? {
+                return Ok(false);
+            }
+            if !exists_remotely(version)? {
+                return Err(CaveError::VersionNotAvailable(version.clone()));
+            }
+            pull_version(version)?;
+            Ok(true)
+        },
+    );
 
-    let path: PathBuf = if default_version {
-        let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
-        home.join(".cave")
-    } else {
-        PathBuf::from(".cave")
-    };
+    let mut table = Table::new(&["Version", "Status"]);
+    for (target, outcome) in targets.iter().zip(&outcomes) {
+        let status = match outcome.outcome {
+            Outcome::Done(true) => Cell::from("pulled").color(Color::Green),
+            Outcome::Done(false) => Cell::from("already installed").color(Color::Blue),
+            Outcome::Failed(_) => Cell::from("failed").color(Color::Red),
+            Outcome::Skipped => Cell::from("not pulled").color(Color::Yellow),
+        };
+        table.push(vec![label(target).into(), status]);
+    }
+    println!();
+    table.print();
 
-    let version_to_write: String = if version == "stable" || version == "testing" {
-        format!("{}:{}", version, version_ok)
-    } else {
-        version_ok
-    };
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    let failed = outcomes.iter().filter(|o| !o.succeeded()).count();
+    if failed > 0 {
+        return Err(CaveError::PullFailed(failed));
+    }
+    Ok(())
+}
+
+/// Handler of `cave images export-list`: writes the installed versions, one
+/// per line, to `output` or the standard output, for `cave pull --from-list`.
+///
+/// # Errors
+/// - [`CaveError::DockerError`] if the images cannot be listed.
+/// - [`CaveError::IoError`] if the file cannot be written.
+pub fn export_version_list(output: Option<String>) -> Result<(), CaveError> {
+    let mut versions: Vec<String> = local_versions()?
+        .into_iter()
+        .filter(|v| v.chars().next().is_some_and(|c| c.is_ascii_digit()))
+        .collect();
+    versions.sort_by(|a, b| version_cmp(a, b));
+    let content: String = versions.iter().map(|v| format!("{}\n", v)).collect();
+    match output {
+        Some(path) => {
+            let header = format!(
+                "# code_aster versions, written by cave {}; install them with `cave pull --from-list {}`\n",
+                env!("CARGO_PKG_VERSION"),
+                path
+            );
+            fs::write(&path, header + &content)?;
+            println!("Wrote {} versions to {}", versions.len(), path);
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Reads a list of versions written by [`export_version_list`] or by hand:
+/// one version or channel per line, blank lines and `#` comments ignored.
+///
+/// # Errors
+/// Returns [`CaveError::FileNotFound`] if the file cannot be read.
+pub fn read_version_list(path: &str) -> Result<Vec<String>, CaveError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| CaveError::FileNotFound(format!("Cannot read the version list {}: {}", path, e)))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Handler of `cave pull --from-list` and `cave images import-list`: pulls
+/// the versions of the list at `path` that are not installed, after
+/// confirmation unless `yes` is set.
+///
+/// # Errors
+/// - [`CaveError::FileNotFound`] if the list cannot be read.
+/// - [`CaveError::UserAborted`] if the user declines the download.
+/// - The errors of [`pull_versions`].
+pub fn pull_version_list(path: &str, jobs: usize, yes: bool) -> Result<(), CaveError> {
+    let versions = read_version_list(path)?;
+    let mut missing = Vec::new();
+    for requested in &versions {
+        let (version, _) = resolve_version(requested)?;
+        if !exists_locally(&version)? && !missing.contains(&version) {
+            missing.push(version);
+        }
+    }
+    if missing.is_empty() {
+        println!("The {} versions of {} are installed.", versions.len(), path);
+        return Ok(());
+    }
+    let question = format!(
+        "Download {} of the {} versions of {} ({})?",
+        missing.len(),
+        versions.len(),
+        path,
+        missing.join(", ")
+    );
+    if !yes && !answers::confirm(Prompt::Download, &question)? {
+        return Err(CaveError::UserAborted);
+    }
+    pull_versions(&versions, jobs)
+}
+
+/// Removes the installed versions that are not pinned by the global
+/// `~/.cave` or the local `.cave` of the current directory.
+///
+/// - `keep_last` keeps the N most recently created images besides the pinned ones.
+/// - `older_than` only removes images created more than this number of days ago.
+///
+/// The versions to remove are listed and confirmed unless `yes` is set.
+///
+/// # Errors
+/// - [`CaveError::UserAborted`] if the user declines.
+/// - [`CaveError::DockerError`] if the images cannot be listed.
+pub fn prune_versions(keep_last: usize, older_than: Option<u32>, yes: bool) -> Result<(), CaveError> {
+    let pinned: Vec<String> = pin_files()?.into_iter().map(|(_, version)| version).collect();
+    let mut images = local_versions_created()?;
+    images.retain(|(version, _)| !pinned.contains(version));
+    images.sort_by_key(|(_, created)| std::cmp::Reverse(*created));
+    let limit = older_than.map(|days| chrono::Utc::now() - chrono::Duration::days(days.into()));
+    let candidates: Vec<_> = images
+        .into_iter()
+        .skip(keep_last)
+        .filter(|(_, created)| limit.is_none_or(|limit| *created < limit))
+        .collect();
+
+    if candidates.is_empty() {
+        println!("Nothing to prune.");
+        return Ok(());
+    }
+    let mut table = Table::new(&["Version", "Created"]);
+    for (version, created) in &candidates {
+        table.push(vec![
+            Cell::from(version.as_str()).bold(),
+            created.format("%Y-%m-%d").to_string().into(),
+        ]);
+    }
+    table.print();
+    let question = format!("Remove these {} version(s)?", candidates.len());
+    if !yes && !answers::confirm(Prompt::Remove, &question)? {
+        return Err(CaveError::UserAborted);
+    }
+
+    let mut removed = 0;
+    for (version, _) in &candidates {
+        match remove_image(version) {
+            Ok(()) => {
+                removed += 1;
+                println!("Removed version {}", version);
+            }
+            Err(e) => eprintln!("{} {}", "Warning:".yellow().bold(), e),
+        }
+    }
+    println!("{} of {} version(s) removed.", removed, candidates.len());
+    Ok(())
+}
 
-    let mut file = fs::File::create(&path)?;
-    writeln!(file, "{}", version_to_write)?;
+/// Writes a `.cave` pin file atomically.
+///
+/// The content goes to a temporary file renamed over `path`, so an
+/// interruption never leaves a half-written pin.
+pub fn write_pin(path: &Path, version: &str) -> Result<(), CaveError> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp)?;
+    writeln!(file, "{}", version)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
     Ok(())
 }
 
 /// Runs `code_aster` with the currently set version from `.cave`.
 ///
 /// - Optionally accepts a `.export` file as the last argument.
-/// - Remaining arguments are passed directly to `run_aster`.
+/// - Remaining arguments are passed directly to `run_aster`, after the defaults
+///   declared in the project `cave.toml` (see [`crate::project`]).
+/// - Warns when the current directory is on a network filesystem. With `options.stage_local`,
+///   the study is copied to a local scratch directory and the results are copied back.
 ///
 /// # Errors
 /// - [`CaveError::VersionNotInstalled`] if the configured version is not installed locally.
@@ -193,24 +891,321 @@ pub fn set_version(version: String, default_version: bool) -> Result<(), CaveErr
 ///
 /// # Example
 /// ```
-/// run_aster(&vec!["--help".to_string()]).expect("Failed to run code_aster");
+/// run_aster(&vec!["--help".to_string()], RunOptions::default()).expect("Failed to run code_aster");
 /// ```
-pub fn run_aster(args: &Vec<String>) -> Result<(), CaveError> {
-    let version = read_cave_version()?;
-    if !exists_locally(&version)? {
-        return Err(CaveError::VersionNotInstalled(version));
+pub fn run_aster(args: &[String], mut options: RunOptions) -> Result<(), CaveError> {
+    if let Some(name) = &options.name {
+        validate_run_name(name)?;
+    }
+    options.invocation = Some(Invocation::new(args, &options));
+    let _log = cmdlog::scope();
+    if let Some(remote) = options.remote.clone() {
+        return remote::run(&remote, args, options);
+    }
+    if options.k8s {
+        #[cfg(feature = "k8s")]
+        return k8s::run(args, options);
+        #[cfg(not(feature = "k8s"))]
+        return Err(CaveError::K8sError(
+            "this cave is built without the Kubernetes backend, install it with `cargo install cave --features k8s`".to_string(),
+        ));
+    }
+    // The record and the abort marker of a detached run would be lost with
+    // the state directory of the invocation
+    if options.detach && read_only_home() {
+        return Err(CaveError::ReadOnlyHome("a detached run".to_string()));
+    }
+    // --stage-local can come from a preset, after the checks of the command line
+    if options.detach && options.stage_local {
+        return Err(CaveError::CodeAsterError(
+            "`--detach` cannot be used with a local scratch directory (--stage-local or preset)".to_string(),
+        ));
+    }
+    let requested = match options.version.clone() {
+        Some(version) => Some(version),
+        None => export_version(args)?,
+    };
+    let mut version = match requested {
+        Some(version) => {
+            ensure_present(&version, true)?;
+            version
+        }
+        None => ensure_installed(read_cave_version()?, options.yes)?,
+    };
+    if options.mpi.is_some() {
+        version = mpi::flavor(&version)?;
     }
+    deprecations::warn(&version);
 
     let (export, rest_args): (Option<String>, Vec<String>) = match args.split_last() {
         Some((last, rest)) if last.ends_with(".export") => {
             find_export_file(last)?;
+            export::warn_problems(last)?;
             (Some(last.clone()), rest.to_vec())
         }
         _ => (None, args.to_vec()),
     };
 
-    docker_aster(&version, DockerMode::RunAster { export_file: &export, args: &rest_args })?;
-    Ok(())
+    let current_dir = std::env::current_dir()?;
+    let study = export
+        .as_deref()
+        .and_then(|e| Path::new(e).file_stem())
+        .and_then(|s| s.to_str());
+    let project_dir = find_project_dir(&current_dir).unwrap_or_else(|| current_dir.clone());
+    let project = read_project_config(&project_dir)?;
+    let defaults = project.defaults_for(study);
+    let cluster = match (options.cluster, project.cluster) {
+        (false, _) => None,
+        (true, Some(cluster)) if !cluster.hosts.is_empty() && !options.stage_local => Some(cluster),
+        (true, _) => {
+            return Err(CaveError::ProjectConfigError(
+                "`--cluster` needs the hosts of a [cluster] section in cave.toml, and cannot be used with --stage-local".to_string(),
+            ))
+        }
+    };
+    let compose = match (options.compose, project.compose) {
+        (false, _) => None,
+        (true, Some(compose)) if !compose.services.is_empty() => Some(compose),
+        (true, _) => {
+            return Err(CaveError::ProjectConfigError(
+                "`cave compose run` needs the services of a [compose] section in cave.toml".to_string(),
+            ))
+        }
+    };
+    options.extractors = project.extractors;
+    let rest_args = profiles::translate(&version, &merge_args(&defaults.args, &rest_args))?;
+    options.docker_args = merge_args(&defaults.docker_args, &options.docker_args);
+    // A volume of the command line replaces the project one at the same container path
+    let mut mounts = options
+        .mounts
+        .iter()
+        .map(|spec| Mount::parse(spec, &current_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+    for spec in &defaults.mounts {
+        let mount = Mount::parse(spec, &project_dir)?;
+        if !mounts.iter().any(|m| m.container == mount.container) {
+            mounts.push(mount);
+        }
+    }
+    for mount in &mounts {
+        options.docker_args.extend(mount.docker_args());
+    }
+    options
+        .docker_args
+        .extend(license::docker_args(&read_config()?.license_env, &project.license.env));
+    options.docker_args.extend(env_args(&read_config()?.forward_env)?);
+
+    if let Some(placement) = &options.placement {
+        options.docker_args.extend(placement.docker_args());
+    }
+    options.nice |= read_config()?.nice_batch && !io::stdin().is_terminal();
+    options.log_file |= read_config()?.log_file;
+    if options.nice {
+        options.docker_args.extend(placement::low_priority_args());
+    }
+
+    if let (Some(export), false) = (&export, options.allow_version_mismatch) {
+        restart::check(&current_dir, export, &version)?;
+    }
+
+    let run_id = options.run_id.get_or_insert_with(history::new_run_id).clone();
+    let lock = lock::acquire(
+        &current_dir,
+        &container_name(&run_id, options.name.as_deref()),
+        options.force,
+    )?;
+
+    // Dropped after the run, removing the files it was given
+    let mut workspace = Workspace::new(&current_dir, &run_id, options.keep_temp || options.detach);
+    let mut _workers = None;
+    let mpi_export = match (options.mpi, &cluster) {
+        (Some(np), _) => {
+            let export = export.as_deref().ok_or_else(|| {
+                CaveError::CodeAsterError("`--mpi` needs an export file".to_string())
+            })?;
+            options.docker_args.extend(mpi::docker_args(np)?);
+            Some(mpi::export(&mut workspace, export, np, 1)?)
+        }
+        (None, Some(cluster)) => {
+            let export = export.as_deref().ok_or_else(|| {
+                CaveError::CodeAsterError("`--cluster` needs an export file".to_string())
+            })?;
+            mpi::check_support(&version)?;
+            let image = format!("simvia/code_aster:{}", version);
+            cluster::check_images(cluster, &image)?;
+            options.docker_args.extend(cluster::launcher_args(cluster)?);
+            _workers = Some(Workers::start(cluster, &image, &current_dir, &run_id)?);
+            let np = cluster.hosts.iter().map(|h| h.slots).sum();
+            Some(mpi::export(&mut workspace, export, np, cluster.hosts.len())?)
+        }
+        (None, None) => None,
+    };
+    // The services see the tree where code_aster runs: the local copy with `--stage-local`
+    let staged = if options.stage_local { Some(StagedDir::create(&current_dir)?) } else { None };
+    let host_dir = staged.as_ref().map_or_else(|| current_dir.clone(), |staged| staged.path().to_path_buf());
+    let services = match &compose {
+        Some(compose) => {
+            let services = Services::start(compose, &host_dir, &run_id)?;
+            options.docker_args.extend(services.network_args());
+            Some(services)
+        }
+        None => None,
+    };
+    let export = mpi_export.or(export);
+
+    let Some(staged) = staged else {
+        if let (Some(fstype), None) = (network_filesystem(&current_dir), &cluster) {
+            eprintln!(
+                "{} the current directory is on a network filesystem ({}), which can cause locking and performance issues. Consider `cave run --stage-local`.",
+                "Warning:".yellow().bold(),
+                fstype
+            );
+        }
+        docker_aster(&version, DockerMode::RunAster { export_file: &export, args: &rest_args }, &options)?;
+        // The detached container goes on writing the study, it holds the lock
+        if options.detach {
+            lock.keep();
+        }
+        return Ok(());
+    };
+
+    options.host_dir = Some(host_dir);
+    let result = docker_aster(&version, DockerMode::RunAster { export_file: &export, args: &rest_args }, &options);
+    // Stop the services first so that their output is copied back with the results
+    drop(services);
+    let copied = staged.sync_back()?;
+    println!("{} result file(s) copied back from the local scratch directory.", copied);
+    result
+}
+
+/// Repeats a past run with the same `cave run` arguments and options: the
+/// given run (name, identifier or prefix), or else the most recent run
+/// started from the current directory.
+///
+/// The run uses the version of the repeated run, or `with` to compare
+/// versions; the project defaults and the flags of that version are applied
+/// again as for any run. A run of another directory is repeated from its
+/// directory.
+///
+/// # Errors
+/// - [`CaveError::RunNotFound`] if no run matches, or none was started from the current directory.
+/// - The errors of [`run_aster`].
+pub fn rerun(run: Option<String>, with: Option<String>) -> Result<(), CaveError> {
+    let current_dir = std::env::current_dir()?;
+    let record = match &run {
+        Some(id) => history::find(id)?,
+        None => history::read_all()?
+            .into_iter()
+            .rev()
+            .find(|r| Path::new(&r.working_dir) == current_dir)
+            .ok_or_else(|| CaveError::RunNotFound(current_dir.display().to_string()))?,
+    };
+    if Path::new(&record.working_dir) != current_dir {
+        println!("Moving to {}", record.working_dir);
+        std::env::set_current_dir(&record.working_dir)?;
+    }
+    let version = with.unwrap_or_else(|| record.version.clone());
+    let options = RunOptions {
+        version: Some(version.clone()),
+        placement: record.placement.clone(),
+        nice: record.low_priority,
+        ..Default::default()
+    };
+    // Runs recorded before the invocation only kept the arguments given to run_aster
+    let (args, options) = match record.invocation {
+        Some(invocation) => (
+            invocation.args,
+            RunOptions {
+                docker_args: invocation.docker_args,
+                mounts: invocation.mounts,
+                mpi: invocation.mpi,
+                cluster: invocation.cluster,
+                compose: invocation.compose,
+                stage_local: invocation.stage_local,
+                allow_version_mismatch: invocation.allow_version_mismatch,
+                log_file: invocation.log_file,
+                full_log: invocation.full_log,
+                remote: invocation.remote,
+                k8s: invocation.k8s,
+                ..options
+            },
+        ),
+        None => (record.args.iter().chain(&record.export_file).cloned().collect(), options),
+    };
+    println!("Running {} again with version {}: {}", record.id.bold(), version, args.join(" "));
+    run_aster(&args, options)
+}
+
+/// Checks that the image of the pinned `version` is installed, and returns
+/// the version to run.
+///
+/// When the image was removed behind cave's back (`docker rmi`, `docker
+/// system prune`), or pinned with `--no-download`, the pin names a missing
+/// version: the user is offered to download it, or to pin the most recent
+/// installed version instead, so that the run and its usage tracking use
+/// that version. With `yes`, the version is downloaded again without asking.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the user declines, or cannot be
+///   asked (no terminal) and `yes` is not set.
+/// - The errors of [`pull_version`] if the download fails.
+pub fn ensure_installed(version: String, yes: bool) -> Result<String, CaveError> {
+    if exists_locally(&version)? {
+        return Ok(version);
+    }
+    let Some((pin, _)) = pin_files()?.pop() else {
+        return Err(CaveError::VersionNotInstalled(version));
+    };
+    eprintln!(
+        "{} version {} pinned in {} is not installed.",
+        "Warning:".yellow().bold(),
+        version,
+        pin.display()
+    );
+    let answer = match (yes, answers::missing_version()) {
+        (true, _) | (false, Some(MissingVersion::Download)) => "d".to_string(),
+        (false, Some(MissingVersion::PinInstalled)) => "p".to_string(),
+        (false, Some(MissingVersion::Abort)) => "n".to_string(),
+        (false, None) if !io::stdin().is_terminal() => {
+            return Err(CaveError::VersionNotInstalled(version));
+        }
+        (false, None) => String::new(),
+    };
+
+    let replacement = local_versions()?
+        .into_iter()
+        .filter(|v| v.chars().next().is_some_and(|c| c.is_ascii_digit()))
+        .max_by(|a, b| version_cmp(a, b));
+    let input = if answer.is_empty() {
+        let question = match &replacement {
+            Some(installed) => format!(
+                "Download {} again, or pin the installed version {} instead? (d/p/n)",
+                version, installed
+            ),
+            None => format!("Download {} again? (y/n)", version),
+        };
+        answers::ask(&question, None)?
+    } else {
+        answer
+    };
+    match (input.trim().to_lowercase().as_str(), replacement) {
+        ("d" | "y", _) => {
+            pull_version(&version)?;
+            Ok(version)
+        }
+        ("p", Some(installed)) => {
+            let global = dirs::home_dir().map(|home| home.join(".cave"));
+            // In read-only mode, the global version is only replaced for the invocation
+            if read_only_home() && global.as_ref() == Some(&pin) {
+                println!("Using {} for this invocation.", installed);
+            } else {
+                write_pin(&pin, &installed)?;
+                println!("{} now pins {}.", pin.display(), installed);
+            }
+            Ok(installed)
+        }
+        _ => Err(CaveError::VersionNotInstalled(version)),
+    }
 }
 
 /// Start interactive shell in the container 
@@ -219,72 +1214,171 @@ pub fn run_aster(args: &Vec<String>) -> Result<(), CaveError> {
 /// - [`CaveError::VersionNotInstalled`] if the configured version is not installed locally.
 /// - [`CaveError::FileNotFound`] if the `.export` file does not exist.
 /// - Any error returned by [`docker_aster`].
+pub fn shell_aster(yes: bool) -> Result<(), CaveError> {
+    let version = ensure_installed(read_cave_version()?, yes)?;
 
-pub fn shell_aster() -> Result<(), CaveError> {
+    docker_aster(&version, DockerMode::Shell, &RunOptions::default())?;
+    Ok(())
+}
+
+/// Runs a command in the container of the selected version, in the current
+/// directory, with the code_aster environment activated. The piped standard
+/// input is passed to the command.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the configured version is not installed locally.
+/// - Any error returned by [`docker_aster`].
+pub fn exec_aster(command: &[String], yes: bool) -> Result<(), CaveError> {
+    let version = ensure_installed(read_cave_version()?, yes)?;
+    docker_aster(&version, DockerMode::Exec { command }, &RunOptions::default())
+}
+
+
+/// Prints the key environment of the image of the current version.
+///
+/// The environment is obtained by [`probe_environment`] and cached per image ID
+/// in `~/.cave.d/env/`, `refresh` forces a new probe.
+///
+/// # Errors
+/// - [`CaveError::VersionNotInstalled`] if the configured version is not installed locally.
+/// - Any error returned by [`probe_environment`].
+pub fn print_env(refresh: bool) -> Result<(), CaveError> {
     let version = read_cave_version()?;
     if !exists_locally(&version)? {
         return Err(CaveError::VersionNotInstalled(version));
     }
+    let id = image_id(&version)?;
+    let cache = state_dir()?.join("env").join(format!("{}.txt", id));
+
+    let env = match fs::read_to_string(&cache) {
+        Ok(content) if !refresh => content,
+        _ => {
+            let content = probe_environment(&version)?;
+            if let Some(parent) = cache.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&cache, &content)?;
+            content
+        }
+    };
 
-    docker_aster(&version, DockerMode::Shell)?;
+    println!("cave: {}", env!("CARGO_PKG_VERSION"));
+    println!("image: simvia/code_aster:{} ({})", version, id);
+    print!("{}", env);
     Ok(())
 }
 
-
-/// Prints a list of locally available versions filtered by an optionnal prefix.
+/// Prints a list of locally available versions filtered by an optionnal prefix,
+/// with the number of runs and the last use of each version from the run history.
 ///
 /// # Example
 /// ```
-/// print_local_versions("22".to_string()).unwrap();
+/// print_local_versions("22".to_string(), false).unwrap();
 /// ```
-pub fn print_local_versions(prefix: String) -> Result<(), CaveError> {
+pub fn print_local_versions(prefix: String, no_header: bool, json: bool) -> Result<(), CaveError> {
     let versions = local_versions()?;
     let mut numeric_versions: Vec<_> = versions
         .into_iter()
-        .filter(|v| v.chars().next().map_or(false, |c| c.is_ascii_digit()))
+        .filter(|v| v.chars().next().is_some_and(|c| c.is_ascii_digit()))
         .filter(|v| v.starts_with(&prefix))
         .collect();
 
     numeric_versions.sort_by(|a, b| version_cmp(a, b));
 
+    let deprecated = deprecations::list();
+    if json {
+        let usage = history::usage_by_version()?;
+        let installed_from = sources::installed()?;
+        let versions: Vec<serde_json::Value> = numeric_versions
+            .iter()
+            .map(|version| {
+                let usage = usage.get(version);
+                serde_json::json!({
+                    "tag": version,
+                    "runs": usage.map_or(0, |u| u.runs),
+                    "last_used": usage.map(|u| &u.last_used),
+                    "source": installed_from.get(version),
+                    "deprecation": deprecations::find(&deprecated, version),
+                })
+            })
+            .collect();
+        return print_json(&versions);
+    }
     if !numeric_versions.is_empty() {
-        let per_line = 6;
-        let column_width = 12;
-        for chunk in numeric_versions.chunks(per_line) {
-            let line = chunk
-                .iter()
-                .map(|v| format!("{:<width$}", v, width = column_width))
-                .collect::<String>();
-            println!("  {}", line.trim_end());
+        let usage = history::usage_by_version()?;
+        // The source only matters when versions can come from elsewhere than Docker Hub
+        let config = read_config()?;
+        let show_source = config.registry.is_some() || sources::configured() != [sources::DEFAULT_SOURCE];
+        let installed_from = sources::installed()?;
+        // Like the source, the support column only shows when it has something to say
+        let show_support = numeric_versions.iter().any(|v| deprecations::find(&deprecated, v).is_some());
+        let mut headers = vec!["Tag", "Runs", "Last used"];
+        if show_source {
+            headers.push("Source");
+        }
+        if show_support {
+            headers.push("Support");
         }
+        let mut table = Table::new(&headers).header(!no_header);
+        for version in numeric_versions {
+            let (runs, last_used) = match usage.get(&version) {
+                Some(u) => (u.runs, u.last_used.get(0..10).unwrap_or(&u.last_used).to_string()),
+                None => (0, "never".to_string()),
+            };
+            let mut row: Vec<Cell> = vec![version.as_str().into(), format::count(runs as u64).into(), last_used.into()];
+            if show_source {
+                row.push(installed_from.get(&version).map_or("-", String::as_str).into());
+            }
+            if show_support {
+                row.push(support_cell(&deprecated, &version));
+            }
+            table.push(row);
+        }
+        table.print();
     }
     Ok(())
 }
 
 /// Prints a list of remotely available versions filtered by a prefix.
 ///
-/// - If a private registry is configured, also prints its versions.
-/// - Labels which versions are `stable` or `testing`.
+/// - The published tags come from the catalog cache while it is recent (see
+///   [`catalog::versions`]), `--refresh` fetching them again.
+/// - If a private registry is configured, also prints its versions, with the
+///   source of each version in an additional column.
+/// - Labels which versions are `stable`, `testing` or `latest` (see
+///   [`channel_versions`]).
 /// - Highlights installed versions in blue.
 ///
 /// # Example
 /// ```
 /// let cfg = read_config().unwrap();
-/// print_remote_versions("22".to_string(), cfg).unwrap();
+/// print_remote_versions("22".to_string(), false).unwrap();
 /// ```
-pub fn print_remote_versions(prefix: String) -> Result<(), CaveError> {
-    // TODO : uncomment to have registry option, add , cfg: Config in the arguments
-    //
-    // if let Some(reg) = &cfg.registry {
-    //     let registry_versions = registry_versions(&reg)?;
-    //     println!("Versions on the registry : ");
-    //     println!("{:#?}", registry_versions);
-    // }
+pub fn print_remote_versions(prefix: String, no_header: bool, json: bool) -> Result<(), CaveError> {
+    let mut versions = catalog::listing().map_err(|e| match e {
+        CaveError::Offline(_) => e,
+        e if internet_available() => e,
+        _ => CaveError::NoInternetConnection,
+    })?;
 
-    if !internet_available() {
-        return Err(CaveError::NoInternetConnection);
+    let registry = read_config()?.registry;
+    let mut private_versions = Vec::new();
+    if let Some(registry) = registry.as_ref().filter(|_| !offline()) {
+        match registry_versions(registry) {
+            Ok(tags) => private_versions = tags,
+            Err(e) => eprintln!(
+                "{} private registry {} unavailable: {}",
+                "Warning:".yellow().bold(),
+                registry.host,
+                e
+            ),
+        }
+    }
+    for tag in &private_versions {
+        if !versions.iter().any(|(t, _)| t == tag) {
+            versions.push((tag.clone(), "unknown".to_string()));
+        }
     }
-    let versions = remote_versions()?;
 
     let mut numeric_versions: Vec<_> = versions
         .iter()
@@ -295,40 +1389,115 @@ pub fn print_remote_versions(prefix: String) -> Result<(), CaveError> {
 
     numeric_versions.sort_by(|(a, _), (b, _)| version_cmp(a, b));
 
+    let deprecated = deprecations::list();
+    if json {
+        let channels = channel_versions();
+        let installed_versions = local_versions()?;
+        let versions: Vec<serde_json::Value> = numeric_versions
+            .iter()
+            .map(|(tag, date)| {
+                let tag_channels: Vec<&str> = channels.iter().filter(|(_, v)| v == tag).map(|(c, _)| *c).collect();
+                let source = match &registry {
+                    Some(registry) if private_versions.contains(tag) => Some(registry.host.as_str()),
+                    Some(_) => Some("docker hub"),
+                    None => None,
+                };
+                serde_json::json!({
+                    "tag": tag,
+                    "pushed": (date != "unknown").then_some(date),
+                    "installed": installed_versions.contains(tag),
+                    "channel": tag_channels.first(),
+                    "channels": tag_channels,
+                    "source": source,
+                    "deprecation": deprecations::find(&deprecated, tag),
+                })
+            })
+            .collect();
+        return print_json(&versions);
+    }
     if numeric_versions.is_empty() {
-        println!("No code_aster versions found on simvia dockerhub");
+        println!("No code_aster versions found on simvia dockerhub or the private registry");
     } else {
-        println!("{:<15}{}", "Tag", "Date");
-        let (stable_version, testing_version) = get_stable_and_testing()?;
+        let channels = channel_versions();
+        let installed_versions = local_versions()?;
+        let show_support = numeric_versions.iter().any(|(tag, _)| deprecations::find(&deprecated, tag).is_some());
+        let mut headers = vec!["Tag", "Date", "Channel"];
+        if registry.is_some() {
+            headers.push("Source");
+        }
+        if show_support {
+            headers.push("Support");
+        }
+        let mut table = Table::new(&headers).header(!no_header);
         for (tag, date) in numeric_versions {
             let short_date = date
                 .get(0..13)
                 .map(|s| s.replace('T', " ") + "h")
                 .unwrap_or_else(|| "unknown".to_string());
-            let mut image = String::new();
-            if tag == stable_version {
-                image = "stable".to_string()
+            let image: Vec<&str> = channels.iter().filter(|(_, v)| *v == tag).map(|(c, _)| *c).collect();
+            let mut row: Vec<Cell> = vec![tag.as_str().into(), short_date.into()];
+            if installed_versions.contains(&tag) {
+                row = row.into_iter().map(|c| c.color(Color::Blue).bold()).collect();
             }
-            if tag == testing_version {
-                image = "testing".to_string()
+            row.push(image.join(", ").into());
+            if let Some(registry) = &registry {
+                // The private registry is preferred when it has the version
+                if private_versions.contains(&tag) {
+                    row.push(registry.host.as_str().into());
+                } else {
+                    row.push("docker hub".into());
+                }
             }
-            let installed = exists_locally(&tag)?;
-            if installed {
-                println!(
-                    "{:<15}{:<15}{:<15}",
-                    tag.blue().bold(),
-                    short_date.blue().bold(),
-                    image
-                );
-            } else {
-                println!("{:<15}{:<15}{:<15}", tag, short_date, image);
+            if show_support {
+                row.push(support_cell(&deprecated, &tag));
             }
+            table.push(row);
         }
+        table.print();
     }
     Ok(())
 }
 
-fn version_cmp(a: &str, b: &str) -> Ordering {
+/// Returns the versions under the `testing`, `stable` and `latest` channels,
+/// in this order of precedence for a version under several. `latest` comes
+/// from the published tags (see [`catalog::latest`]). For the others, a
+/// recent resolution is reused (see [`pins::cached`]), else the channel is
+/// resolved on Docker Hub and cached; offline, only the cached resolutions
+/// are used. A channel that cannot be resolved, as from air-gapped sites, is
+/// left out.
+fn channel_versions() -> Vec<(&'static str, String)> {
+    let latest = catalog::latest().ok().map(|version| ("latest", version));
+    ["testing", "stable"]
+        .into_iter()
+        .filter_map(|channel| {
+            let pin = match pins::cached(channel) {
+                Some(pin) => pin,
+                None if offline() => return None,
+                None => pins::resolve(channel, None).ok()?,
+            };
+            Some((channel, pin.version))
+        })
+        .chain(latest)
+        .collect()
+}
+
+/// Returns the support column of a version in `cave list` and `cave available`.
+fn support_cell(deprecated: &[deprecations::Deprecation], version: &str) -> Cell {
+    match deprecations::find(deprecated, version) {
+        Some(d) if d.end_of_life() => Cell::from(d.status()).color(Color::Red),
+        Some(d) => Cell::from(d.status()).color(Color::Yellow),
+        None => "".into(),
+    }
+}
+
+/// Prints a value as indented JSON, for the `--json` options.
+fn print_json(value: &impl serde::Serialize) -> Result<(), CaveError> {
+    println!("{}", serde_json::to_string_pretty(value).map_err(CaveError::SerdeError)?);
+    Ok(())
+}
+
+/// Orders versions by their numeric components (`17.10.0` after `17.9.2`).
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
     let parse = |s: &str| {
         s.split('.')
             .filter_map(|part| part.parse::<u32>().ok())
@@ -341,7 +1510,7 @@ use std::net::TcpStream;
 use std::time::Duration;
 
 //check the internet connection
-fn internet_available() -> bool {
+pub fn internet_available() -> bool {
     TcpStream::connect_timeout(
         &"8.8.8.8:53".parse().unwrap(), // Google DNS
         Duration::from_secs(2),
@@ -349,16 +1518,40 @@ fn internet_available() -> bool {
     .is_ok()
 }
 
+/// Prints a one-line notice on stderr when the local pin differs from the global one.
+fn notify_shadowed_global(local: &Path, global: &Path) {
+    let (Ok(local_version), Ok(global_version)) =
+        (fs::read_to_string(local), fs::read_to_string(global))
+    else {
+        return;
+    };
+    let (local_version, global_version) = (Pin::parse(&local_version).spec(), Pin::parse(&global_version).spec());
+    if local_version != global_version {
+        eprintln!(
+            "{} using {} from {} (global {} is {}). Silence with `cave config disable-pin-notice`.",
+            "Note:".cyan().bold(),
+            local_version,
+            local.display(),
+            global.display(),
+            global_version
+        );
+    }
+}
+
 /// Reads the currently configured `code_aster` version from the `.cave` file.
 ///
-/// This function checks in first the **local** `.cave` file in the current directory,
-/// if not found search for the **global** version file in `~/.cave`
+/// This function checks in first the **local** `.cave` file in the current directory
+/// or its parents (see [`find_local_pin`]), if not found search for the **global**
+/// version file in `~/.cave`
 ///
-/// If the stored version is in the form `stable:<version>` or `testing:<version>`  
-/// and `auto_update` is enabled in the configuration, it will:
-/// - Check if the "stable" or "testing" tag now points to a newer version.
+/// If the stored version is in the form `stable:<version>`, `testing:<version>`
+/// or `latest:<version>` and `auto_update` is enabled in the configuration, it will:
+/// - Check if the channel now points to a newer version, or
+///   to another digest of the same version, reusing the resolution cached for
+///   [`catalog::max_age`] without querying Docker Hub.
 /// - Automatically update the `.cave` file if the newer version is already installed.
-/// - Optionally prompt the user to install the updated version if missing.
+/// - Optionally prompt the user to install the updated version if missing, or
+///   to download again a version whose image was pushed again.
 ///
 /// # Returns
 /// - The actual version string to be used (e.g., `"22.0.1"`).
@@ -375,7 +1568,7 @@ fn internet_available() -> bool {
 /// let current_version = read_cave_version().unwrap();
 /// println!("Currently configured version: {}", current_version);
 /// ```
-fn read_cave_version() -> Result<String, CaveError> {
+pub fn read_cave_version() -> Result<String, CaveError> {
     let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
     let config = read_config()?;
     let auto_update = config.auto_update;
@@ -383,11 +1576,13 @@ fn read_cave_version() -> Result<String, CaveError> {
     let mut cave_file: Option<PathBuf> = None;
     let global = home.join(".cave");
     if global.exists() {
-        cave_file = Some(global);
+        cave_file = Some(global.clone());
     }
-    let local = Path::new(".cave");
-    if local.exists() {
-        cave_file = Some(local.to_path_buf());
+    if let Some(local) = find_local_pin(&std::env::current_dir()?) {
+        if config.pin_notice {
+            notify_shadowed_global(&local, &global);
+        }
+        cave_file = Some(local);
     }
     let cave_file = cave_file.ok_or_else(|| {
         CaveError::FileNotFound(
@@ -396,38 +1591,41 @@ fn read_cave_version() -> Result<String, CaveError> {
     })?;
 
     let content = fs::read_to_string(&cave_file).map_err(CaveError::IoError)?;
-    let content = content.trim();
-
-    if content.starts_with("stable:") || content.starts_with("testing:") {
-        let parts: Vec<&str> = content.splitn(2, ':').collect();
-        let tag = parts[0];
-        let old_version = parts[1];
-        if auto_update {
-            if internet_available() {
-                let new_version = version_under_tag(tag.to_string())?;
-                if new_version != old_version {
-                    if !exists_locally(&new_version)? {
-                        println!("{} version updated. Install new version? (y/n):", tag);
-                        let mut input = String::new();
-                        io::stdin().read_line(&mut input)?;
-                        if input.trim().to_lowercase() == "y" {
-                            pull_version(&new_version)?;
-                            let version_to_write = format!("{}:{}", tag, new_version);
-                            fs::write(&cave_file, version_to_write).map_err(CaveError::IoError)?;
-                            return Ok(new_version);
-                        }
-                        return Ok(old_version.to_string());
-                    }
-                    let version_to_write = format!("{}:{}", tag, new_version);
-                    fs::write(&cave_file, version_to_write).map_err(CaveError::IoError)?;
-                    return Ok(new_version);
-                }
-            }
+    let pin = Pin::parse(&content);
+    // Offline, a channel stays on the version it was resolved to
+    let Some(tag) = pin.channel.clone().filter(|_| auto_update && !offline()) else {
+        return Ok(pin.version);
+    };
+    let latest = match pins::cached(&tag) {
+        Some(latest) => latest,
+        None if internet_available() => pins::resolve(&tag, Some(&pin))?,
+        None => return Ok(pin.version),
+    };
+    if latest.version != pin.version {
+        if !exists_locally(&latest.version)? {
+            println!("{} version updated to {}.", tag, latest.version);
         }
-        Ok(old_version.to_string())
-    } else {
-        Ok(content.to_string())
+        // The pin keeps the old version if the new one is declined or fails to download
+        match ensure_present(&latest.version, true) {
+            Ok(()) => {}
+            Err(CaveError::UserAborted) => return Ok(pin.version),
+            Err(e) => return Err(e),
+        }
+    } else if latest.digest == pin.digest || latest.digest.is_none() {
+        return Ok(pin.version);
+    } else if pin.digest.is_some() && exists_locally(&pin.version)? {
+        // Same version under another digest: the image was pushed again
+        let question = format!("{} {} was pushed again. Download it again?", tag, pin.version);
+        if !answers::confirm(Prompt::Download, &question)? {
+            return Ok(pin.version);
+        }
+        pull_version(&pin.version)?;
+    }
+    // In read-only mode, the global version is only updated for the invocation
+    if !(read_only_home() && cave_file == global) {
+        write_pin(&cave_file, &latest.to_string())?;
     }
+    Ok(latest.version)
 }
 
 pub fn find_export_file(requested: &str) -> Result<(), CaveError> {
@@ -442,7 +1640,12 @@ pub fn find_export_file(requested: &str) -> Result<(), CaveError> {
     }
 }
 
-pub fn check_latest_version(current: &str) -> Result<(), CaveError> {
+/// Returns the latest cave release published on GitHub.
+///
+/// # Errors
+/// - [`CaveError::CheckReleaseError`] if GitHub cannot be reached.
+/// - [`CaveError::VersionParseError`] if the release tag is not a version.
+pub fn latest_release() -> Result<Version, CaveError> {
     let client = Client::builder()
         .timeout(Duration::from_millis(500))
         .user_agent("cave-updater")
@@ -463,14 +1666,17 @@ pub fn check_latest_version(current: &str) -> Result<(), CaveError> {
         .as_str()
         .ok_or_else(|| CaveError::VersionParseError("Invalid GitHub tag".to_string()))?;
 
-    // Parse semantic versions
-    let latest = Version::parse(latest_tag.trim_start_matches('v'))
-        .map_err(|_| CaveError::VersionParseError(latest_tag.to_string()))?;
+    Version::parse(latest_tag.trim_start_matches('v'))
+        .map_err(|_| CaveError::VersionParseError(latest_tag.to_string()))
+}
+
+pub fn check_latest_version(current: &str) -> Result<(), CaveError> {
+    let latest = latest_release()?;
     let local = Version::parse(current.trim_start_matches('v'))
         .map_err(|_| CaveError::VersionParseError(current.to_string()))?;
 
     if latest > local {
-        println!(
+        eprintln!(
             "🔔 New cave version available: {} (current: {}) 🔔\nDownload: https://github.com/simvia-tech/cave/releases/latest",
             latest, local
         );
@@ -478,3 +1684,25 @@ pub fn check_latest_version(current: &str) -> Result<(), CaveError> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_versions_numerically() {
+        assert_eq!(version_cmp("17.10.0", "17.9.2"), Ordering::Greater);
+        assert_eq!(version_cmp("17.2", "17.2.1"), Ordering::Less);
+        assert_eq!(version_cmp("16.4.0", "16.4.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn orders_channels_before_versions() {
+        // `latest` and the other tags have no numeric component
+        assert_eq!(version_cmp("latest", "15.0.0"), Ordering::Less);
+        let highest = ["17.1.0", "latest", "stable", "17.4.0"]
+            .into_iter()
+            .max_by(|a, b| version_cmp(a, b));
+        assert_eq!(highest, Some("17.4.0"));
+    }
+}
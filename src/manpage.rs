@@ -0,0 +1,75 @@
+//! Installation of the cave man pages.
+//!
+//! The pages are rendered at runtime with `clap_mangen` from the same CLI
+//! definition as the binary, so an installed page never lags behind the
+//! commands it documents.
+
+use crate::cli::Cli;
+use crate::config::read_only_home;
+use crate::manage::CaveError;
+use clap::CommandFactory;
+use clap_mangen::Man;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SYSTEM_MAN_DIR: &str = "/usr/local/share/man/man1";
+
+fn user_man_dir() -> Result<PathBuf, CaveError> {
+    if read_only_home() {
+        return Err(CaveError::ReadOnlyHome("installing the man pages in ~/.local/share/man".to_string()));
+    }
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    Ok(home.join(".local/share/man/man1"))
+}
+
+fn is_writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(".cave-write-test");
+    let writable = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(probe);
+    writable
+}
+
+/// Writes `cave.1` and one `cave-<subcommand>.1` page per subcommand into `dir`.
+///
+/// Returns the paths of the written pages.
+pub fn write_pages(dir: &Path) -> Result<Vec<PathBuf>, CaveError> {
+    fs::create_dir_all(dir)?;
+    let mut cmd = Cli::command();
+    cmd.build();
+
+    let mut pages = vec![(dir.join("cave.1"), Man::new(cmd.clone()))];
+    for sub in cmd.get_subcommands().filter(|s| s.get_name() != "help") {
+        let name = format!("cave-{}", sub.get_name());
+        let page = Man::new(sub.clone().display_name(name.clone())).title(name.clone());
+        pages.push((dir.join(format!("{}.1", name)), page));
+    }
+
+    let mut written = Vec::new();
+    for (path, page) in pages {
+        let mut buffer = Vec::new();
+        page.render(&mut buffer)?;
+        fs::write(&path, buffer)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Handler of `cave man install`.
+pub fn install(dir: Option<String>, user: bool) -> Result<(), CaveError> {
+    let target = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None if user => user_man_dir()?,
+        None if is_writable(Path::new(SYSTEM_MAN_DIR)) => PathBuf::from(SYSTEM_MAN_DIR),
+        None => user_man_dir()?,
+    };
+
+    let pages = write_pages(&target)?;
+    println!("Installed {} man pages in {}", pages.len(), target.display());
+    if user_man_dir().is_ok_and(|dir| dir == target) {
+        println!("If `man cave` does not find them, add ~/.local/share/man to your MANPATH.");
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Extra volumes of a run (`cave run --mount`).
+//!
+//! The study directory is always mounted at `/home/user/data`. Data living
+//! elsewhere (meshes on a network share, a material database, ...) is
+//! mounted with `--mount HOST:CONTAINER[:ro]`, or for every run of a project
+//! with `mounts` in the `[run]` or `[studies.<name>]` sections of
+//! `cave.toml`. Relative host paths are resolved from the current directory
+//! on the command line, and from the project directory in `cave.toml`.
+
+use crate::manage::CaveError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Mount point of the study directory, which extra volumes cannot replace.
+const STUDY_MOUNT: &str = "/home/user/data";
+
+/// An extra volume of a run.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    /// Absolute host path.
+    pub host: PathBuf,
+    /// Absolute path in the container.
+    pub container: String,
+    /// Whether the volume is mounted read-only.
+    pub read_only: bool,
+}
+
+impl Mount {
+    /// Parses `HOST:CONTAINER[:ro|:rw]`, a relative host path being taken
+    /// from `base`.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::InvalidMount`] if the specification is malformed,
+    /// the host path does not exist or the container path is not absolute.
+    pub fn parse(spec: &str, base: &Path) -> Result<Self, CaveError> {
+        let invalid = |reason: &str| CaveError::InvalidMount {
+            spec: spec.to_string(),
+            reason: reason.to_string(),
+        };
+        // Split from the right, the host path may hold a colon (`C:\data`)
+        let (rest, read_only) = match spec.rsplit_once(':') {
+            Some((rest, "ro")) => (rest, true),
+            Some((rest, "rw")) => (rest, false),
+            _ => (spec, false),
+        };
+        let (host, container) = rest
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("expected HOST:CONTAINER[:ro]"))?;
+        if host.is_empty() {
+            return Err(invalid("the host path is empty"));
+        }
+        if !container.starts_with('/') {
+            return Err(match host.rsplit_once(':') {
+                Some((_, path)) if path.starts_with('/') => invalid("the mode must be ro or rw"),
+                _ => invalid("the container path must be absolute"),
+            });
+        }
+        if Path::new(container) == Path::new(STUDY_MOUNT) {
+            return Err(invalid("the study directory is already mounted there"));
+        }
+        // Docker would create a missing host path, owned by root
+        let host = fs::canonicalize(base.join(host))
+            .map_err(|_| invalid("the host path does not exist"))?;
+        Ok(Mount {
+            host,
+            container: container.to_string(),
+            read_only,
+        })
+    }
+
+    /// Returns the `docker run` arguments of the volume.
+    pub fn docker_args(&self) -> Vec<String> {
+        let mode = if self.read_only { ":ro" } else { "" };
+        vec![
+            "-v".to_string(),
+            format!("{}:{}{}", self.host.display(), self.container, mode),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(spec: &str, base: &Path) -> String {
+        match Mount::parse(spec, base) {
+            Err(CaveError::InvalidMount { reason, .. }) => reason,
+            other => panic!("expected an invalid mount, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_the_mode() {
+        let base = tempfile::tempdir().unwrap();
+        fs::create_dir(base.path().join("meshes")).unwrap();
+        let mount = Mount::parse("meshes:/meshes", base.path()).unwrap();
+        assert_eq!(mount.host, fs::canonicalize(base.path().join("meshes")).unwrap());
+        assert_eq!(mount.container, "/meshes");
+        assert!(!mount.read_only);
+        assert!(Mount::parse("meshes:/meshes:ro", base.path()).unwrap().read_only);
+        assert!(!Mount::parse("meshes:/meshes:rw", base.path()).unwrap().read_only);
+        assert_eq!(reason("meshes:/meshes:xx", base.path()), "the mode must be ro or rw");
+    }
+
+    #[test]
+    fn splits_from_the_right() {
+        let base = tempfile::tempdir().unwrap();
+        fs::create_dir(base.path().join("C:\\data")).unwrap();
+        let mount = Mount::parse("C:\\data:/data:ro", base.path()).unwrap();
+        assert!(mount.host.ends_with("C:\\data"));
+        assert_eq!(mount.container, "/data");
+        assert!(mount.read_only);
+    }
+
+    #[test]
+    fn rejects_malformed_specifications() {
+        let base = tempfile::tempdir().unwrap();
+        assert_eq!(reason("/data", base.path()), "expected HOST:CONTAINER[:ro]");
+        assert_eq!(reason(":/data", base.path()), "the host path is empty");
+        assert_eq!(reason(".:data", base.path()), "the container path must be absolute");
+        assert_eq!(reason(".:/home/user/data", base.path()), "the study directory is already mounted there");
+        assert_eq!(reason("missing:/data", base.path()), "the host path does not exist");
+    }
+}
@@ -0,0 +1,134 @@
+//! MPI-parallel runs (`cave run --mpi N`).
+//!
+//! MPI processes of a run all live in the same container. The container is
+//! pinned to `N` host CPUs, shares the host network namespace (as Open MPI's
+//! TCP transport expects), and gets a shared memory segment large enough for
+//! the intra-node transport. The number of processes is given to run_aster
+//! through the `mpi_nbcpu` parameter of a copy of the export file.
+//!
+//! When the selected version is a sequential build and an MPI flavor of it
+//! is published as `<version>-mpi`, the flavor is run instead.
+
+use crate::catalog;
+use crate::docker::{exists_locally, image_labels};
+use crate::manage::{ensure_present, CaveError};
+use crate::workspace::Workspace;
+use colored::*;
+use log::debug;
+
+/// Image label telling whether code_aster was built with MPI.
+pub const MPI_LABEL: &str = "org.code-aster.mpi";
+
+/// Shared memory per MPI process, in MiB.
+const SHM_PER_PROCESS_MB: u32 = 512;
+
+/// Checks that the image of `version` declares MPI support through [`MPI_LABEL`].
+///
+/// # Errors
+/// Returns [`CaveError::CodeAsterError`] if the label is missing or not `true`.
+pub fn check_support(version: &str) -> Result<(), CaveError> {
+    let labels = image_labels(version)?;
+    debug!("Labels de l'image {}: {:?}", version, labels);
+    match labels.get(MPI_LABEL).map(|v| v.to_lowercase()) {
+        Some(v) if v == "true" || v == "1" || v == "yes" => Ok(()),
+        _ => Err(CaveError::CodeAsterError(format!(
+            "version {} is a sequential build (image label {} not set), `--mpi` needs an MPI build",
+            version, MPI_LABEL
+        ))),
+    }
+}
+
+/// Returns the version to run with several MPI processes on another host,
+/// where its image may not be installed: the `<version>-mpi` flavor when
+/// `version` is not known to be an MPI build and the flavor is installed or
+/// published, else `version` itself.
+pub fn host_flavor(version: &str) -> String {
+    if version.ends_with("-mpi") || check_support(version).is_ok() {
+        return version.to_string();
+    }
+    let candidate = format!("{}-mpi", version);
+    if !exists_locally(&candidate).unwrap_or(false) && !catalog::contains(&candidate).unwrap_or(false) {
+        return version.to_string();
+    }
+    debug!("Version {} séquentielle, variante MPI {} utilisée", version, candidate);
+    eprintln!("{} running the MPI flavor {} of {}.", "Note:".cyan().bold(), candidate, version);
+    candidate
+}
+
+/// Returns the version to run with MPI: `version` itself if it is an MPI
+/// build, else its `<version>-mpi` flavor when one is installed or published,
+/// downloaded if needed.
+///
+/// # Errors
+/// - [`CaveError::CodeAsterError`] if neither `version` nor a flavor of it is an MPI build.
+/// - The errors of [`ensure_present`] if the flavor cannot be downloaded.
+pub fn flavor(version: &str) -> Result<String, CaveError> {
+    let sequential = match check_support(version) {
+        Ok(()) => return Ok(version.to_string()),
+        Err(e) => e,
+    };
+    let candidate = format!("{}-mpi", version);
+    if !exists_locally(&candidate)? && !catalog::contains(&candidate).unwrap_or(false) {
+        return Err(sequential);
+    }
+    debug!("Version {} séquentielle, variante MPI {} utilisée", version, candidate);
+    ensure_present(&candidate, true)?;
+    check_support(&candidate)?;
+    eprintln!(
+        "{} {} is a sequential build, running its MPI flavor {}.",
+        "Note:".cyan().bold(),
+        version,
+        candidate
+    );
+    Ok(candidate)
+}
+
+/// Returns the `docker run` arguments for `np` MPI processes. The container
+/// is not pinned to CPUs, so that concurrent runs are spread by the kernel;
+/// `--cpuset` and `--numa` pin it. More processes than host CPUs are allowed,
+/// with a warning: they are oversubscribed on all the CPUs.
+///
+/// # Errors
+/// Returns [`CaveError::CodeAsterError`] if `np` is zero.
+pub fn docker_args(np: u32) -> Result<Vec<String>, CaveError> {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1);
+    if np == 0 {
+        return Err(CaveError::CodeAsterError(
+            "the number of MPI processes must be at least 1".to_string(),
+        ));
+    }
+    let mut args = vec!["--network".to_string(), "host".to_string()];
+    if np > cpus {
+        eprintln!(
+            "{} {} MPI processes on a host with {} CPUs: the run is oversubscribed and will be slower.",
+            "Warning:".yellow().bold(),
+            np,
+            cpus
+        );
+        args.extend([
+            "-e".to_string(),
+            "OMPI_MCA_rmaps_base_oversubscribe=1".to_string(),
+        ]);
+    }
+    args.extend([
+        "--shm-size".to_string(),
+        format!("{}m", np * SHM_PER_PROCESS_MB),
+        // Docker's default seccomp profile forbids the CMA single-copy mechanism
+        "-e".to_string(),
+        "OMPI_MCA_btl_vader_single_copy_mechanism=none".to_string(),
+        "-e".to_string(),
+        "OMP_NUM_THREADS=1".to_string(),
+    ]);
+    Ok(args)
+}
+
+/// Writes a copy of `export` in the run's workspace, with `mpi_nbcpu` set
+/// to `np` and `mpi_nbnoeud` to `nodes`, and returns its path relative to
+/// the study directory.
+pub fn export(workspace: &mut Workspace, export: &str, np: u32, nodes: usize) -> Result<String, CaveError> {
+    workspace.write_export(export, |copy| {
+        copy.set_parameter("mpi_nbcpu", np).set_parameter("mpi_nbnoeud", nodes);
+    })
+}
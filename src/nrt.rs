@@ -0,0 +1,386 @@
+//! Non-regression test runner (`cave nrt`).
+//!
+//! The cases declared in the `[nrt]` section of `cave.toml` are run one after
+//! the other from the project directory. The monitored quantities of each run
+//! are taken from its results summary in the history and compared with the
+//! reference values of the case. A JUnit XML report can be written for CI.
+//!
+//! `cave nrt bless` records the reference values from the latest run of each
+//! case, along with the run's version and image digest, directly in
+//! `cave.toml` so that baseline updates are reviewed like any other change.
+
+use crate::docker::RunOptions;
+use crate::format;
+use crate::history::{self, RunRecord};
+use crate::manage::{run_aster, CaveError};
+use crate::orchestrator::{self, Orchestrator};
+use crate::output;
+use crate::project::{find_project_dir, read_project_config, NrtCase, NrtConfig, PROJECT_FILE};
+use crate::results::{relative_deviation, within};
+use colored::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Outcome of a non-regression case.
+#[derive(Debug)]
+enum Outcome {
+    /// Every reference value is within tolerance.
+    Passed,
+    /// Some reference values are out of tolerance or missing.
+    Failed(Vec<String>),
+    /// The case could not be run or compared.
+    Error(String),
+}
+
+/// Result of a non-regression case.
+#[derive(Debug)]
+struct CaseReport {
+    name: String,
+    duration_ms: u64,
+    outcome: Outcome,
+}
+
+/// Compares the values of a run with the references of a case.
+fn compare(case: &NrtCase, config: &NrtConfig, values: &BTreeMap<String, f64>) -> Vec<String> {
+    let rtol = case.rtol.unwrap_or(config.rtol);
+    let atol = case.atol.unwrap_or(config.atol);
+    case.reference
+        .iter()
+        .filter_map(|(key, reference)| match values.get(key) {
+            None => Some(format!("{}: missing from the results", key)),
+            Some(value) if !within(*reference, *value, rtol, atol) => Some(format!(
+                "{}: expected {:e}, got {:e} (relative deviation {:.3e}, rtol {:e}, atol {:e})",
+                key,
+                reference,
+                value,
+                relative_deviation(*reference, *value),
+                rtol,
+                atol
+            )),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+fn run_case(name: &str, case: &NrtCase, config: &NrtConfig) -> Result<CaseReport, CaveError> {
+    let run_id = history::new_run_id();
+    let options = RunOptions {
+        run_id: Some(run_id.clone()),
+        ..Default::default()
+    };
+    let run_error = match run_aster(std::slice::from_ref(&case.export), options) {
+        Ok(()) => None,
+        Err(CaveError::Cancelled) => return Err(CaveError::Cancelled),
+        Err(e) => Some(e.to_string()),
+    };
+
+    let record = history::find(&run_id).ok();
+    let duration_ms = record.as_ref().map(|r| r.duration_ms).unwrap_or_default();
+    let outcome = match (run_error, record.and_then(|r| r.results)) {
+        (Some(e), _) => Outcome::Error(e),
+        (None, None) => Outcome::Error("the run produced no results to compare".to_string()),
+        (None, Some(_)) if case.reference.is_empty() => {
+            Outcome::Error("no reference values, record them with `cave nrt bless`".to_string())
+        }
+        (None, Some(results)) => {
+            let mut failures = compare(case, config, &results.values);
+            if let (false, Some(blessed)) = (failures.is_empty(), &case.blessed) {
+                failures.push(format!(
+                    "references blessed on {} from run {} (version {}{})",
+                    blessed.date,
+                    blessed.run,
+                    blessed.version,
+                    blessed
+                        .image_digest
+                        .as_ref()
+                        .map(|d| format!(", {}", d))
+                        .unwrap_or_default()
+                ));
+            }
+            if failures.is_empty() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(failures)
+            }
+        }
+    };
+    Ok(CaseReport {
+        name: name.to_string(),
+        duration_ms,
+        outcome,
+    })
+}
+
+fn xml_escape(text: &str) -> String {
+    // Control characters are not allowed in XML 1.0
+    output::sanitize(text.as_bytes())
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the reports as a JUnit XML file.
+fn write_junit(path: &Path, reports: &[CaseReport]) -> Result<(), CaveError> {
+    let failures = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+        .count();
+    let errors = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, Outcome::Error(_)))
+        .count();
+    let total_ms: u64 = reports.iter().map(|r| r.duration_ms).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"cave nrt\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        reports.len(),
+        failures,
+        errors,
+        total_ms as f64 / 1000.0
+    ));
+    for report in reports {
+        xml.push_str(&format!(
+            "  <testcase classname=\"nrt\" name=\"{}\" time=\"{:.3}\"",
+            xml_escape(&report.name),
+            report.duration_ms as f64 / 1000.0
+        ));
+        match &report.outcome {
+            Outcome::Passed => xml.push_str("/>\n"),
+            Outcome::Failed(details) => xml.push_str(&format!(
+                ">\n    <failure message=\"values out of tolerance\">{}</failure>\n  </testcase>\n",
+                xml_escape(&details.join("\n"))
+            )),
+            Outcome::Error(message) => xml.push_str(&format!(
+                ">\n    <error message=\"{}\"/>\n  </testcase>\n",
+                xml_escape(message)
+            )),
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    fs::write(path, xml)?;
+    Ok(())
+}
+
+/// Handler of `cave nrt`: runs the non-regression cases of the project and
+/// compares their results with the references.
+///
+/// `cases` restricts the run to the given case names, `junit` writes a JUnit
+/// XML report to the given path.
+///
+/// # Errors
+/// - [`CaveError::ProjectConfigError`] if `cave.toml` is invalid or a case is unknown.
+/// - [`CaveError::NrtFailed`] if any case fails.
+pub fn run(cases: &[String], junit: Option<String>) -> Result<(), CaveError> {
+    let current_dir = std::env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or(current_dir);
+    let config = read_project_config(&project_dir)?.nrt;
+    if config.cases.is_empty() {
+        return Err(CaveError::ProjectConfigError(
+            "no non-regression case declared in the [nrt.cases] section of cave.toml".to_string(),
+        ));
+    }
+    if let Some(unknown) = cases.iter().find(|c| !config.cases.contains_key(*c)) {
+        return Err(CaveError::ProjectConfigError(format!(
+            "unknown non-regression case '{}'",
+            unknown
+        )));
+    }
+    // The JUnit path is given relative to where cave was started
+    let junit = junit.map(std::path::absolute).transpose()?;
+    std::env::set_current_dir(&project_dir)?;
+
+    // The cases share the study lock of the project directory, one at a time
+    let selected: Vec<(&String, &NrtCase)> = config
+        .cases
+        .iter()
+        .filter(|(n, _)| cases.is_empty() || cases.contains(n))
+        .collect();
+    let mut reports = Vec::new();
+    for outcome in Orchestrator::new(1).run(
+        &selected,
+        |(name, _)| name.to_string(),
+        |(name, case), _| run_case(name, case, &config),
+    ) {
+        match outcome.outcome {
+            orchestrator::Outcome::Done(report) => reports.push(report),
+            orchestrator::Outcome::Failed(e) => return Err(e),
+            orchestrator::Outcome::Skipped => return Err(CaveError::Cancelled),
+        }
+    }
+
+    println!();
+    for report in &reports {
+        let duration = format!("({})", format::duration_ms(report.duration_ms));
+        match &report.outcome {
+            Outcome::Passed => println!("{} {} {}", "PASS ".green().bold(), report.name, duration),
+            Outcome::Failed(details) => {
+                println!("{} {} {}", "FAIL ".red().bold(), report.name, duration);
+                for detail in details {
+                    println!("      {}", detail);
+                }
+            }
+            Outcome::Error(message) => {
+                println!("{} {} {}", "ERROR".red().bold(), report.name, duration);
+                println!("      {}", message);
+            }
+        }
+    }
+
+    if let Some(path) = &junit {
+        write_junit(path, &reports)?;
+        println!();
+        println!("JUnit report written to {}", path.display());
+    }
+
+    let failed = reports
+        .iter()
+        .filter(|r| !matches!(r.outcome, Outcome::Passed))
+        .count();
+    println!();
+    println!(
+        "{} case(s) run, {} passed, {} failed.",
+        reports.len(),
+        reports.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        return Err(CaveError::NrtFailed(failed));
+    }
+    Ok(())
+}
+
+/// Selects the values of a run recorded as the references of a case.
+fn monitored_values(case: &NrtCase, values: &BTreeMap<String, f64>) -> BTreeMap<String, f64> {
+    values
+        .iter()
+        .filter(|(key, _)| {
+            if !case.monitor.is_empty() {
+                case.monitor
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix.as_str()))
+            } else if !case.reference.is_empty() {
+                case.reference.contains_key(*key)
+            } else {
+                true
+            }
+        })
+        .map(|(key, value)| (key.clone(), *value))
+        .collect()
+}
+
+/// Finds the latest successful run of a case with recorded results.
+fn latest_run(case: &NrtCase, project_dir: &Path) -> Result<RunRecord, CaveError> {
+    let dir = project_dir.display().to_string();
+    history::read_all()?
+        .into_iter()
+        .rev()
+        .find(|r| {
+            r.success
+                && r.results.is_some()
+                && r.working_dir == dir
+                && r.export_file.as_deref() == Some(case.export.as_str())
+        })
+        .ok_or_else(|| CaveError::RunNotFound(case.export.clone()))
+}
+
+/// Handler of `cave nrt bless`: records the reference values of cases from
+/// their latest run, or from `run` for a single case, in `cave.toml`.
+///
+/// # Errors
+/// - [`CaveError::ProjectConfigError`] if `cave.toml` is invalid or a case is unknown.
+/// - [`CaveError::RunNotFound`] if a case has no successful run with results.
+pub fn bless(cases: &[String], run: Option<String>) -> Result<(), CaveError> {
+    let current_dir = std::env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or(current_dir);
+    let config = read_project_config(&project_dir)?.nrt;
+    if let Some(unknown) = cases.iter().find(|c| !config.cases.contains_key(*c)) {
+        return Err(CaveError::ProjectConfigError(format!(
+            "unknown non-regression case '{}'",
+            unknown
+        )));
+    }
+    let selected: Vec<(&String, &NrtCase)> = config
+        .cases
+        .iter()
+        .filter(|(n, _)| cases.is_empty() || cases.contains(n))
+        .collect();
+    if selected.is_empty() {
+        return Err(CaveError::ProjectConfigError(
+            "no non-regression case declared in the [nrt.cases] section of cave.toml".to_string(),
+        ));
+    }
+    if run.is_some() && selected.len() != 1 {
+        return Err(CaveError::ProjectConfigError(
+            "--run can only be used to bless a single case".to_string(),
+        ));
+    }
+
+    let path = project_dir.join(PROJECT_FILE);
+    let mut document: toml_edit::DocumentMut = fs::read_to_string(&path)?
+        .parse()
+        .map_err(|e| CaveError::ProjectConfigError(format!("{}: {}", path.display(), e)))?;
+    let date = chrono::Utc::now().to_rfc3339();
+
+    for (name, case) in selected {
+        let record = match &run {
+            Some(id) => history::find(id)?,
+            None => latest_run(case, &project_dir)?,
+        };
+        let values = record
+            .results
+            .as_ref()
+            .map(|r| monitored_values(case, &r.values))
+            .unwrap_or_default();
+        if values.is_empty() {
+            eprintln!(
+                "{} run {} has no monitored value for case '{}', references left unchanged.",
+                "Warning:".yellow().bold(),
+                record.id,
+                name
+            );
+            continue;
+        }
+        let changed = values
+            .iter()
+            .filter(|(key, value)| case.reference.get(*key) != Some(value))
+            .count();
+        let removed = case
+            .reference
+            .keys()
+            .filter(|k| !values.contains_key(*k))
+            .count();
+
+        let mut reference = toml_edit::Table::new();
+        for (key, value) in &values {
+            reference.insert(key, toml_edit::value(*value));
+        }
+        let mut blessed = toml_edit::Table::new();
+        blessed.insert("run", toml_edit::value(record.id.as_str()));
+        blessed.insert("version", toml_edit::value(record.version.as_str()));
+        if let Some(digest) = &record.image_digest {
+            blessed.insert("image_digest", toml_edit::value(digest.as_str()));
+        }
+        blessed.insert("date", toml_edit::value(date.as_str()));
+
+        let entry = &mut document["nrt"]["cases"][name.as_str()];
+        entry["reference"] = toml_edit::Item::Table(reference);
+        entry["blessed"] = toml_edit::Item::Table(blessed);
+
+        println!(
+            "{} {}: {} reference value(s) from run {} (version {}), {} changed, {} removed.",
+            "Blessed".green().bold(),
+            name,
+            values.len(),
+            record.id,
+            record.version,
+            changed,
+            removed
+        );
+    }
+
+    fs::write(&path, document.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+//! Orchestration of the commands running several studies (`cave sweep`,
+//! `cave nrt`).
+//!
+//! The tasks are run by a bounded pool of worker threads, in order. Each
+//! task is announced and its outcome printed with the progress of the whole
+//! batch, so that the commands report the same way. A failed task is tried
+//! again up to the configured number of retries.
+//!
+//! On SIGINT/SIGTERM, the running operations are aborted by [`signals`] and
+//! no task is started anymore: the remaining tasks are reported as skipped,
+//! and the caller returns [`CaveError::Cancelled`] once it has reported the
+//! tasks that ran. With `fail_fast`, the first failure stops the batch the
+//! same way.
+
+use crate::format;
+use crate::manage::CaveError;
+use crate::signals;
+use colored::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// Outcome of a task.
+#[derive(Debug)]
+pub enum Outcome<T> {
+    /// The task succeeded, possibly after retries.
+    Done(T),
+    /// The task failed, with the error of its last attempt.
+    Failed(CaveError),
+    /// The task was not started: the batch was cancelled or stopped at the first failure.
+    Skipped,
+}
+
+/// Report of a task.
+#[derive(Debug)]
+pub struct TaskReport<T> {
+    /// Number of attempts, 0 for skipped tasks.
+    pub attempts: u32,
+    pub outcome: Outcome<T>,
+}
+
+impl<T> TaskReport<T> {
+    /// Returns whether the task succeeded.
+    pub fn succeeded(&self) -> bool {
+        matches!(self.outcome, Outcome::Done(_))
+    }
+}
+
+/// Counts of the batch, for the progress lines.
+#[derive(Debug, Default)]
+struct Progress {
+    done: usize,
+    failed: usize,
+    running: usize,
+}
+
+/// Runs tasks with bounded concurrency, retries and cancellation.
+#[derive(Debug, Clone)]
+pub struct Orchestrator {
+    jobs: usize,
+    retries: u32,
+    fail_fast: bool,
+}
+
+impl Orchestrator {
+    /// Creates an orchestrator running `jobs` tasks at a time (at least one).
+    pub fn new(jobs: usize) -> Self {
+        Orchestrator {
+            jobs: jobs.max(1),
+            retries: 0,
+            fail_fast: false,
+        }
+    }
+
+    /// Sets the number of times a failed task is tried again.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Stops starting tasks after the first failure.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Runs `task` on every item, `name` giving the name of the task of an
+    /// item for the progress lines, and returns the reports in the order of
+    /// the items. `task` receives the item and the attempt number, from 1.
+    ///
+    /// [`CaveError::Cancelled`] is never retried and stops the batch.
+    pub fn run<I, T, N, F>(&self, items: &[I], name: N, task: F) -> Vec<TaskReport<T>>
+    where
+        I: Sync,
+        T: Send,
+        N: Fn(&I) -> String + Sync,
+        F: Fn(&I, u32) -> Result<T, CaveError> + Sync,
+    {
+        let total = items.len();
+        let next = AtomicUsize::new(0);
+        let stopped = AtomicBool::new(false);
+        let progress = Mutex::new(Progress::default());
+        let reports: Mutex<Vec<Option<TaskReport<T>>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.min(total) {
+                scope.spawn(|| loop {
+                    if signals::cancelled() || stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= total {
+                        break;
+                    }
+                    let name = name(&items[i]);
+                    lock(&progress).running += 1;
+                    println!("{} {} {}", "Running".bold(), name, format!("[{}/{}]", i + 1, total).dimmed());
+
+                    let start = Instant::now();
+                    let mut attempts = 0;
+                    let result = loop {
+                        attempts += 1;
+                        match task(&items[i], attempts) {
+                            Err(e) if attempts <= self.retries && !matches!(e, CaveError::Cancelled) && !signals::cancelled() => {
+                                println!(
+                                    "{} {} (attempt {}/{}): {}",
+                                    "Retrying".yellow().bold(),
+                                    name,
+                                    attempts + 1,
+                                    self.retries + 1,
+                                    e
+                                );
+                            }
+                            result => break result,
+                        }
+                    };
+
+                    let summary = {
+                        let mut progress = lock(&progress);
+                        progress.running -= 1;
+                        match &result {
+                            Ok(_) => progress.done += 1,
+                            Err(_) => progress.failed += 1,
+                        }
+                        let left = total - progress.done - progress.failed - progress.running;
+                        format!(
+                            "{} done, {} failed, {} running, {} left",
+                            progress.done, progress.failed, progress.running, left
+                        )
+                    };
+                    let elapsed = format::duration_ms(start.elapsed().as_millis() as u64);
+                    match &result {
+                        Ok(_) => println!("{} {} ({}) {}", "Finished".green().bold(), name, elapsed, summary.dimmed()),
+                        Err(e) => {
+                            println!("{} {} ({}): {} {}", "Failed".red().bold(), name, elapsed, e, summary.dimmed());
+                            if self.fail_fast {
+                                stopped.store(true, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    lock(&reports)[i] = Some(TaskReport {
+                        attempts,
+                        outcome: match result {
+                            Ok(value) => Outcome::Done(value),
+                            Err(e) => Outcome::Failed(e),
+                        },
+                    });
+                });
+            }
+        });
+
+        reports
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .map(|report| {
+                report.unwrap_or(TaskReport {
+                    attempts: 0,
+                    outcome: Outcome::Skipped,
+                })
+            })
+            .collect()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,383 @@
+//! Capture and filtering of the solver output.
+//!
+//! code_aster is extremely verbose during long solves. With `cave run
+//! --filter <regex>`, the container runs without a TTY, its whole output is
+//! written to the run log in the cave state directory, and only the lines
+//! matching the filter are echoed to the terminal.
+//!
+//! With `cave run --log-file`, the output is also written, with the time of
+//! each line, to `.cave-logs/<run id>.log` in the study directory, so that
+//! a run started over SSH keeps its output when the connection drops.
+//!
+//! Captured output is sanitized (see [`sanitize`]) before it reaches the
+//! log, the terminal or a report, so that stray escape sequences and
+//! mixed encodings in the solver output cannot corrupt them.
+//!
+//! Some studies print hundreds of MB. The logs are written through a
+//! [`CappedLog`], which keeps the beginning and the end of the output within
+//! the configured size (see `cave config set-log-limit`), unless the run is
+//! started with `--full-log`.
+
+use crate::config::{read_config, state_dir};
+use crate::format;
+use crate::manage::CaveError;
+use log::debug;
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Bytes of error output kept to diagnose a container that failed to start.
+const ERROR_TAIL_BYTES: usize = 8192;
+/// Lines of error output reported for a container that failed to start.
+const ERROR_TAIL_LINES: usize = 20;
+
+/// Directory of the logs of `cave run --log-file`, in the study directory.
+pub const STUDY_LOG_DIR: &str = ".cave-logs";
+
+/// Size limit of a run log when none is configured, in MiB.
+pub const DEFAULT_LOG_LIMIT_MB: u64 = 64;
+
+/// Returns the size limit of the run logs in bytes, `None` with `full` (for
+/// `--full-log`) or a limit configured to 0.
+pub fn log_limit(full: bool) -> Option<u64> {
+    if full {
+        return None;
+    }
+    let mb = read_config()
+        .ok()
+        .and_then(|cfg| cfg.log_limit_mb)
+        .unwrap_or(DEFAULT_LOG_LIMIT_MB);
+    (mb > 0).then(|| mb * 1024 * 1024)
+}
+
+/// A log file whose size is capped.
+///
+/// The first quarter of the limit is written to the log as is. The output
+/// past it goes to two segments rotated next to the log (`<log>.tail` and
+/// `<log>.tail.1`), each holding at most three eighths of the limit, so that
+/// the disk use stays bounded however long the run. [`CappedLog::finish`]
+/// appends the segments to the log, after a line telling how much output was
+/// left out in between.
+pub struct CappedLog {
+    path: PathBuf,
+    file: File,
+    limit: Option<u64>,
+    written: u64,
+    /// Current segment, with its size and number of lines.
+    tail: Option<(File, u64, u64)>,
+    /// Size and number of lines of the previous segment, if rotated.
+    previous: Option<(u64, u64)>,
+    /// Size and number of lines left out.
+    omitted: (u64, u64),
+}
+
+impl CappedLog {
+    /// Creates the log at `path`, capped to `limit` bytes if given.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::IoError`] if the file cannot be created.
+    pub fn create(path: &Path, limit: Option<u64>) -> Result<Self, CaveError> {
+        Ok(CappedLog {
+            path: path.to_path_buf(),
+            file: File::create(path)?,
+            limit,
+            written: 0,
+            tail: None,
+            previous: None,
+            omitted: (0, 0),
+        })
+    }
+
+    fn segment(&self, rotated: bool) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(if rotated { ".tail.1" } else { ".tail" });
+        PathBuf::from(name)
+    }
+
+    /// Writes a line, ending with its line feed.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let size = line.len() as u64;
+        let Some(limit) = self.limit else {
+            return self.file.write_all(line.as_bytes());
+        };
+        if self.tail.is_none() && self.written + size <= limit / 4 {
+            self.written += size;
+            return self.file.write_all(line.as_bytes());
+        }
+        let segment_limit = limit * 3 / 8;
+        if self.tail.as_ref().is_none_or(|(_, written, _)| written + size > segment_limit) {
+            if let Some((_, written, lines)) = self.tail.take() {
+                if let Some((dropped, dropped_lines)) = self.previous.replace((written, lines)) {
+                    self.omitted.0 += dropped;
+                    self.omitted.1 += dropped_lines;
+                }
+                fs::rename(self.segment(false), self.segment(true))?;
+            }
+            self.tail = Some((File::create(self.segment(false))?, 0, 0));
+        }
+        if let Some((file, written, lines)) = &mut self.tail {
+            // A line longer than a segment is cut, the log having to stay bounded
+            let kept = &line.as_bytes()[..line.len().min(segment_limit as usize)];
+            *written += kept.len() as u64;
+            *lines += 1;
+            file.write_all(kept)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the rotated segments to the log, after a line telling what
+    /// was left out, and removes them. Returns `true` if output was left out.
+    pub fn finish(mut self) -> io::Result<bool> {
+        if self.tail.take().is_none() {
+            self.file.flush()?;
+            return Ok(false);
+        }
+        if self.omitted.1 > 0 {
+            writeln!(
+                self.file,
+                "\n[cave: {} lines ({}) of output left out to keep the log under {}; run with --full-log to keep them]\n",
+                format::count(self.omitted.1),
+                format::size(self.omitted.0),
+                format::size(self.limit.unwrap_or_default())
+            )?;
+        }
+        for rotated in [true, false] {
+            let segment = self.segment(rotated);
+            if let Ok(mut file) = File::open(&segment) {
+                io::copy(&mut file, &mut self.file)?;
+                let _ = fs::remove_file(&segment);
+            }
+        }
+        self.file.flush()?;
+        Ok(self.omitted.1 > 0)
+    }
+}
+
+/// Returns the path of the log of a run.
+pub fn log_path(run_id: &str) -> Result<PathBuf, CaveError> {
+    let dir = state_dir()?.join("logs");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.log", run_id)))
+}
+
+/// Returns the path of the log of a run kept in the study directory `dir`.
+pub fn study_log_path(dir: &Path, run_id: &str) -> Result<PathBuf, CaveError> {
+    let dir = dir.join(STUDY_LOG_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.log", run_id)))
+}
+
+/// Converts captured output to clean UTF-8 text.
+///
+/// Invalid UTF-8 is replaced lossily, ANSI escape sequences (colors, cursor
+/// moves, terminal titles) are removed, carriage returns not followed by a
+/// line feed become line feeds, and the other control characters except
+/// tabulations are dropped.
+pub fn sanitize(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut clean = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' => clean.push('\n'),
+            '\n' | '\t' => clean.push(c),
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+/// Copies `reader` line by line to `log`, prefixed with the time when
+/// `timestamps` is set, and to `echo` for the lines matching `filter`,
+/// sanitized.
+fn pump<R: Read, W: Write>(reader: R, log: &Mutex<CappedLog>, mut echo: W, filter: &Regex, timestamps: bool) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        let clean = sanitize(&line);
+        if let Ok(mut log) = log.lock() {
+            let _ = if timestamps {
+                log.write_line(&format!("[{}] {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), clean))
+            } else {
+                log.write_line(&clean)
+            };
+        }
+        if filter.is_match(&clean) {
+            let _ = echo.write_all(clean.as_bytes());
+            let _ = echo.flush();
+        }
+        line.clear();
+    }
+}
+
+/// Returns a filter matching no line, so that the whole output of a run only
+/// goes to its log, for runs in parallel whose outputs would be interleaved.
+pub fn silent_filter() -> Regex {
+    Regex::new(r"[^\s\S]").unwrap()
+}
+
+/// Returns a filter matching every line, to echo the whole output of a run
+/// that is also written to a log.
+pub fn all_lines() -> Regex {
+    Regex::new("").unwrap()
+}
+
+/// Writes the stdout and stderr of `child`, spawned with piped outputs, to
+/// `log`, echoing the lines matching `filter`. Returns once both streams
+/// are closed and the log is finished, telling whether output was left out
+/// of it.
+pub fn tee_filtered(child: &mut Child, log: CappedLog, filter: &Regex, timestamps: bool) -> bool {
+    let log = Arc::new(Mutex::new(log));
+    let stderr = child.stderr.take().map(|stderr| {
+        let log = Arc::clone(&log);
+        let filter = filter.clone();
+        thread::spawn(move || pump(stderr, &log, io::stderr(), &filter, timestamps))
+    });
+    if let Some(stdout) = child.stdout.take() {
+        pump(stdout, &log, io::stdout(), filter, timestamps);
+    }
+    if let Some(handle) = stderr {
+        let _ = handle.join();
+    }
+    let Ok(log) = Arc::try_unwrap(log) else {
+        return false;
+    };
+    match log.into_inner().unwrap_or_else(|e| e.into_inner()).finish() {
+        Ok(cut) => cut,
+        Err(e) => {
+            debug!("Journal non terminé: {}", e);
+            false
+        }
+    }
+}
+
+/// Echoes the stderr of `child`, spawned with a piped stderr, as is. The
+/// returned handle gives its last lines, sanitized, once the stream is
+/// closed.
+pub fn tee_stderr(child: &mut Child) -> Option<JoinHandle<String>> {
+    let mut stderr = child.stderr.take()?;
+    Some(thread::spawn(move || {
+        let mut tail = Vec::new();
+        let mut buf = [0u8; 4096];
+        let mut echo = io::stderr();
+        while let Ok(n) = stderr.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = echo.write_all(&buf[..n]);
+            let _ = echo.flush();
+            tail.extend_from_slice(&buf[..n]);
+            if tail.len() > ERROR_TAIL_BYTES {
+                tail.drain(..tail.len() - ERROR_TAIL_BYTES);
+            }
+        }
+        last_lines(&sanitize(&tail))
+    }))
+}
+
+/// Returns the last lines of the log of a run, sanitized when it was written.
+pub fn log_tail(run_id: &str) -> String {
+    match log_path(run_id) {
+        Ok(path) => file_tail(&path),
+        Err(_) => String::new(),
+    }
+}
+
+/// Reads the last `bytes` bytes of a file, so that the tail of a huge log
+/// costs no more than a small one.
+pub fn read_end(path: &Path, bytes: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(bytes)))?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Returns the last lines of a log file.
+pub fn file_tail(path: &Path) -> String {
+    match read_end(path, ERROR_TAIL_BYTES as u64) {
+        Ok(content) => last_lines(&String::from_utf8_lossy(&content)),
+        Err(_) => String::new(),
+    }
+}
+
+fn last_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(ERROR_TAIL_LINES)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_lines(log: &mut CappedLog, count: usize) {
+        for i in 1..=count {
+            log.write_line(&format!("line {:04}\n", i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn keeps_a_short_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.log");
+        let mut log = CappedLog::create(&path, Some(80)).unwrap();
+        write_lines(&mut log, 2);
+        assert!(!log.finish().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 0001\nline 0002\n");
+    }
+
+    #[test]
+    fn keeps_the_head_and_the_tail_of_a_long_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.log");
+        // Lines of 10 bytes: 2 in the head, segments of 3
+        let mut log = CappedLog::create(&path, Some(80)).unwrap();
+        write_lines(&mut log, 20);
+        assert!(log.finish().unwrap());
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("line 0001\nline 0002\n"));
+        assert!(content.contains("[cave: 12 lines"));
+        assert!(!content.contains("line 0014"));
+        assert!(content.ends_with("line 0015\nline 0016\nline 0017\nline 0018\nline 0019\nline 0020\n"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn writes_everything_without_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.log");
+        let mut log = CappedLog::create(&path, None).unwrap();
+        write_lines(&mut log, 100);
+        assert!(!log.finish().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 100);
+    }
+}
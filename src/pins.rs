@@ -0,0 +1,196 @@
+//! Content of the `.cave` pin files.
+//!
+//! A pin holds a version (`17.2.0`), or a channel with the version it was
+//! resolved to and the digest of the channel's image
+//! (`stable:17.2.0@sha256:...`). `stable` and `testing` are tags of Docker
+//! Hub; `latest` is the highest numeric version published (see
+//! [`catalog::latest`]), with the digest of its image. The digest lets
+//! [`crate::manage::read_cave_version`] notice a channel whose image was
+//! pushed again under the same version. The last resolution of each channel
+//! is cached in `~/.cave.d/channels.json` for the same time as the published
+//! tags (see [`catalog::max_age`]), so that the runs in between do not query
+//! Docker Hub.
+
+use crate::catalog;
+use crate::config::state_dir;
+use crate::docker::{hub_tag, version_under_tag};
+use crate::manage::CaveError;
+use crate::sources::DEFAULT_SOURCE;
+use chrono::Utc;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Channels a pin can follow.
+pub const CHANNELS: [&str; 3] = ["stable", "testing", "latest"];
+
+/// Parsed content of a pin file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pin {
+    /// Channel followed by the pin (see [`CHANNELS`]).
+    pub channel: Option<String>,
+    /// Pinned version.
+    pub version: String,
+    /// Digest of the channel's image when the version was resolved, for the
+    /// pins written since it is recorded.
+    pub digest: Option<String>,
+}
+
+impl Pin {
+    /// Parses `<version>`, `<channel>:<version>` or `<channel>:<version>@<digest>`.
+    pub fn parse(content: &str) -> Pin {
+        let content = content.trim();
+        let Some((channel, rest)) = content.split_once(':') else {
+            return Pin {
+                channel: None,
+                version: content.to_string(),
+                digest: None,
+            };
+        };
+        let (version, digest) = match rest.split_once('@') {
+            Some((version, digest)) => (version, Some(digest.to_string())),
+            None => (rest, None),
+        };
+        Pin {
+            channel: Some(channel.to_string()),
+            version: version.to_string(),
+            digest,
+        }
+    }
+
+    /// Returns the pin as shown to the user, without the digest (`stable:17.2.0`).
+    pub fn spec(&self) -> String {
+        match &self.channel {
+            Some(channel) => format!("{}:{}", channel, self.version),
+            None => self.version.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.channel, &self.digest) {
+            (Some(_), Some(digest)) => write!(f, "{}@{}", self.spec(), digest),
+            _ => write!(f, "{}", self.spec()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Resolution {
+    /// When the channel was resolved, as a Unix timestamp.
+    checked: i64,
+    version: String,
+    digest: Option<String>,
+}
+
+fn path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("channels.json"))
+}
+
+fn read() -> BTreeMap<String, Resolution> {
+    path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the last resolution of a channel if it is less than
+/// [`catalog::max_age`] old and no refresh is forced.
+pub fn cached(channel: &str) -> Option<Pin> {
+    if catalog::refresh_forced() {
+        return None;
+    }
+    let resolution = read().remove(channel)?;
+    (Utc::now().timestamp() - resolution.checked < catalog::max_age()).then(|| Pin {
+        channel: Some(channel.to_string()),
+        version: resolution.version,
+        digest: resolution.digest,
+    })
+}
+
+/// Resolves a channel on Docker Hub and caches the result. The digest of the
+/// channel is queried first: when it is the digest of `known`, the version of
+/// `known` is kept without listing the tags. `latest` is resolved from the
+/// published tags instead, then the digest of its version is queried.
+///
+/// # Errors
+/// - [`CaveError::HttpError`] if Docker Hub cannot be reached.
+/// - [`CaveError::VersionNotAvailable`] if no version is published for `latest`.
+pub fn resolve(channel: &str, known: Option<&Pin>) -> Result<Pin, CaveError> {
+    let (version, digest) = if channel == "latest" {
+        let version = catalog::latest()?;
+        let digest = hub_tag(DEFAULT_SOURCE, &version)?.and_then(|tag| tag.digest);
+        (version, digest)
+    } else {
+        let digest = hub_tag(DEFAULT_SOURCE, channel)?.and_then(|tag| tag.digest);
+        let version = match known {
+            Some(known) if digest.is_some() && known.digest == digest => known.version.clone(),
+            _ => version_under_tag(channel.to_string())?,
+        };
+        (version, digest)
+    };
+    let mut resolutions = read();
+    resolutions.insert(
+        channel.to_string(),
+        Resolution {
+            checked: Utc::now().timestamp(),
+            version: version.clone(),
+            digest: digest.clone(),
+        },
+    );
+    if let Err(e) = write(&resolutions) {
+        debug!("Résolution de {} non enregistrée: {}", channel, e);
+    }
+    Ok(Pin {
+        channel: Some(channel.to_string()),
+        version,
+        digest,
+    })
+}
+
+fn write(resolutions: &BTreeMap<String, Resolution>) -> Result<(), CaveError> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(resolutions)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_plain_version() {
+        let pin = Pin::parse("17.2.0\n");
+        assert_eq!(pin.channel, None);
+        assert_eq!(pin.version, "17.2.0");
+        assert_eq!(pin.digest, None);
+        assert_eq!(pin.to_string(), "17.2.0");
+    }
+
+    #[test]
+    fn parses_a_channel_with_its_digest() {
+        let pin = Pin::parse("stable:17.2.0@sha256:abc");
+        assert_eq!(pin.channel.as_deref(), Some("stable"));
+        assert_eq!(pin.version, "17.2.0");
+        assert_eq!(pin.digest.as_deref(), Some("sha256:abc"));
+        assert_eq!(pin.spec(), "stable:17.2.0");
+        assert_eq!(pin.to_string(), "stable:17.2.0@sha256:abc");
+    }
+
+    #[test]
+    fn parses_a_channel_without_digest() {
+        let pin = Pin::parse("testing:17.4.0");
+        assert_eq!(pin.channel.as_deref(), Some("testing"));
+        assert_eq!(pin.version, "17.4.0");
+        assert_eq!(pin.digest, None);
+        assert_eq!(Pin::parse(&pin.to_string()), pin);
+    }
+}
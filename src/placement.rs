@@ -0,0 +1,119 @@
+//! CPU placement and priority of runs (`cave run --cpuset` / `--numa` / `--nice`).
+//!
+//! Large direct-solver runs are slowed down by memory traffic between
+//! sockets. `--cpuset` pins the container to the given cores, and `--numa`
+//! pins it to the cores and memory of a single NUMA node, the one with the
+//! most free memory, read from `/sys/devices/system/node`.
+//!
+//! `--nice` lowers the CPU and block I/O weights of the container, so that
+//! long background campaigns leave the desktop responsive.
+
+use crate::manage::CaveError;
+use colored::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+
+/// CPU weight of low-priority runs (Docker's default is 1024).
+const LOW_CPU_SHARES: u32 = 128;
+/// Block I/O weight of low-priority runs (Docker's default is 500, the minimum 10).
+const LOW_BLKIO_WEIGHT: u32 = 10;
+
+/// Returns the `docker run` arguments of a low-priority run.
+pub fn low_priority_args() -> Vec<String> {
+    vec![
+        "--cpu-shares".to_string(),
+        LOW_CPU_SHARES.to_string(),
+        "--blkio-weight".to_string(),
+        LOW_BLKIO_WEIGHT.to_string(),
+    ]
+}
+
+/// CPUs and memory nodes a run is pinned to, recorded in the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Placement {
+    /// CPU list, in the `cpuset` format (`0-7,16-23`).
+    pub cpus: String,
+    /// NUMA node the run is pinned to, with `--numa`.
+    pub numa_node: Option<u32>,
+}
+
+impl Placement {
+    /// Returns the `docker run` arguments applying the placement.
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = vec!["--cpuset-cpus".to_string(), self.cpus.clone()];
+        if let Some(node) = self.numa_node {
+            args.push("--cpuset-mems".to_string());
+            args.push(node.to_string());
+        }
+        args
+    }
+}
+
+/// Checks a CPU list given with `--cpuset`.
+///
+/// # Errors
+/// Returns [`CaveError::InvalidCpuset`] if it is not of the form `0-7,16,18`.
+pub fn cpuset(cpus: &str) -> Result<Placement, CaveError> {
+    let format = Regex::new(r"^\d+(-\d+)?(,\d+(-\d+)?)*$").unwrap();
+    if !format.is_match(cpus) {
+        return Err(CaveError::InvalidCpuset(cpus.to_string()));
+    }
+    Ok(Placement { cpus: cpus.to_string(), numa_node: None })
+}
+
+/// Returns the free memory of a NUMA node in kB.
+fn free_memory(node: &Path) -> Option<u64> {
+    fs::read_to_string(node.join("meminfo"))
+        .ok()?
+        .lines()
+        .find(|l| l.contains("MemFree:"))?
+        .split_whitespace()
+        .rev()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Chooses the NUMA node with the most free memory.
+///
+/// Returns `None`, with a note, on hosts with a single NUMA node, where
+/// placement brings nothing.
+///
+/// # Errors
+/// Returns [`CaveError::FileNotFound`] if the host does not expose its NUMA
+/// topology (non-Linux hosts).
+pub fn numa_auto() -> Result<Option<Placement>, CaveError> {
+    let entries = fs::read_dir(NODE_DIR).map_err(|_| {
+        CaveError::FileNotFound(format!(
+            "NUMA topology not found in {}, `--numa` is only supported on Linux hosts.",
+            NODE_DIR
+        ))
+    })?;
+    let mut nodes: Vec<(u32, String, u64)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let id = entry.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+            let cpus = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            let cpus = cpus.trim().to_string();
+            if cpus.is_empty() {
+                return None;
+            }
+            Some((id, cpus, free_memory(&entry.path()).unwrap_or(0)))
+        })
+        .collect();
+    if nodes.len() < 2 {
+        eprintln!(
+            "{} this host has a single NUMA node, `--numa` has no effect.",
+            "Note:".cyan().bold()
+        );
+        return Ok(None);
+    }
+    nodes.sort_by_key(|(id, _, free)| (std::cmp::Reverse(*free), *id));
+    let (id, cpus, _) = nodes.swap_remove(0);
+    println!("Pinned to NUMA node {} (CPUs {})", id, cpus);
+    Ok(Some(Placement { cpus, numa_node: Some(id) }))
+}
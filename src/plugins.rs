@@ -0,0 +1,121 @@
+//! External subcommands.
+//!
+//! As with cargo and git, `cave <name> [ARGS]...` runs the `cave-<name>`
+//! executable found on the `PATH` when `<name>` is not a built-in command.
+//! The plugin inherits the terminal and receives the context of cave in its
+//! environment:
+//!
+//! - `CAVE_BIN`: path of the running cave binary;
+//! - `CAVE_VERSION`: code_aster version selected in the current directory, if any;
+//! - `CAVE_PROJECT_DIR`: project directory, if any;
+//! - `CAVE_PROJECT_CONFIG`: its `cave.toml`, if it exists;
+//! - `CAVE_STATE_DIR`: cave's state directory (`~/.cave.d`);
+//! - `CAVE_HISTORY`: the run history file, in JSON lines.
+
+use crate::config::state_dir;
+use crate::history;
+use crate::manage::{pin_files, CaveError};
+use crate::project::{find_project_dir, PROJECT_FILE};
+use crate::signals::{self, Operation};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const PREFIX: &str = "cave-";
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Returns the plugins found on the `PATH`, by name. The first directory of
+/// the `PATH` providing a name wins, as for the shell.
+pub fn plugins() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let Some(path) = env::var_os("PATH") else {
+        return plugins;
+    };
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let Some(name) = file_name.strip_prefix(PREFIX) else {
+                continue;
+            };
+            let name = name.strip_suffix(env::consts::EXE_SUFFIX).unwrap_or(name);
+            if !name.is_empty() && !plugins.contains_key(name) && is_executable(&entry.path()) {
+                plugins.insert(name.to_string(), entry.path());
+            }
+        }
+    }
+    plugins
+}
+
+/// Handler of `cave plugin list`.
+pub fn print_plugins() -> Result<(), CaveError> {
+    let plugins = plugins();
+    if plugins.is_empty() {
+        println!("No plugin found. Plugins are `cave-<name>` executables on the PATH.");
+    }
+    for (name, path) in plugins {
+        println!("{:<20}{}", name, path.display());
+    }
+    Ok(())
+}
+
+/// Runs the plugin of an external subcommand, `args` starting with its name,
+/// and exits with its status.
+///
+/// # Errors
+/// - [`CaveError::UnknownCommand`] if no `cave-<name>` executable is on the `PATH`.
+/// - [`CaveError::Cancelled`] if interrupted.
+pub fn run(args: &[String]) -> Result<(), CaveError> {
+    let (name, args) = args
+        .split_first()
+        .ok_or_else(|| CaveError::UnknownCommand(String::new()))?;
+    let program = plugins()
+        .remove(name)
+        .ok_or_else(|| CaveError::UnknownCommand(name.clone()))?;
+
+    let mut cmd = Command::new(&program);
+    cmd.args(args)
+        .env("CAVE_STATE_DIR", state_dir()?)
+        .env("CAVE_HISTORY", history::history_path()?);
+    if let Ok(bin) = env::current_exe() {
+        cmd.env("CAVE_BIN", bin);
+    }
+    if let Some((_, version)) = pin_files()?.pop() {
+        cmd.env("CAVE_VERSION", version);
+    }
+    if let Some(dir) = find_project_dir(&env::current_dir()?) {
+        let config = dir.join(PROJECT_FILE);
+        if config.is_file() {
+            cmd.env("CAVE_PROJECT_CONFIG", config);
+        }
+        cmd.env("CAVE_PROJECT_DIR", dir);
+    }
+
+    let mut child = cmd.spawn()?;
+    let guard = signals::register(Operation::Process(child.id()));
+    let status = child.wait()?;
+    drop(guard);
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
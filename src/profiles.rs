@@ -0,0 +1,107 @@
+//! run_aster argument profiles of the code_aster major versions.
+//!
+//! The names and units of the run_aster options changed between the images
+//! of code_aster 15, 16 and 17. `cave run` accepts user-friendly flags, on
+//! the command line after `--` or in the `args` of `cave.toml`, and
+//! translates them into the options of the selected image:
+//!
+//! ```text
+//! cave run -- --memory-limit 4096 study.export
+//! # code_aster 15: run_aster --memjeveux 512 study.export
+//! # code_aster 17: run_aster --memory_limit 4096 study.export
+//! ```
+//!
+//! The translations are keyed by the first major version they apply to: the
+//! profile of a version stacks every table up to its major version, the
+//! entries of the configuration (`cave config set-run-arg`) overriding the
+//! built-in ones of the same major version. Other arguments are passed as is.
+
+use crate::config::{read_config, RunArg};
+use crate::manage::CaveError;
+use log::debug;
+use std::collections::BTreeMap;
+
+/// Built-in translations: first major version, flag, run_aster option and
+/// factor applied to the value.
+const BUILTIN: [(u32, &str, &str, f64); 4] = [
+    // Memory in megawords of 8 bytes, time in seconds
+    (15, "--memory-limit", "--memjeveux", 0.125),
+    (15, "--time-limit", "--tpmax", 1.0),
+    // Memory in MiB
+    (16, "--memory-limit", "--memory_limit", 1.0),
+    (16, "--time-limit", "--time_limit", 1.0),
+];
+
+/// Returns the major version of a version tag, `None` for channels and
+/// custom tags, which get the newest profile.
+fn major(version: &str) -> Option<u32> {
+    let digits: String = version.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// Returns the translations of the flags for `version`, by flag.
+///
+/// # Errors
+/// Returns [`CaveError::IoError`] or [`CaveError::SerdeError`] if the configuration cannot be read.
+pub fn profile(version: &str) -> Result<BTreeMap<String, RunArg>, CaveError> {
+    let mut tables: BTreeMap<u32, BTreeMap<String, RunArg>> = BTreeMap::new();
+    for (since, flag, option, scale) in BUILTIN {
+        tables.entry(since).or_default().insert(
+            flag.to_string(),
+            RunArg {
+                option: option.to_string(),
+                scale,
+            },
+        );
+    }
+    for (since, args) in read_config()?.run_args {
+        tables.entry(since).or_default().extend(args);
+    }
+    let major = major(version).unwrap_or(u32::MAX);
+    Ok(tables.into_iter().filter(|(since, _)| *since <= major).flat_map(|(_, args)| args).collect())
+}
+
+/// Formats a scaled value, without decimals when it is a whole number.
+fn scaled(flag: &str, value: &str, scale: f64) -> Result<String, CaveError> {
+    if scale == 1.0 {
+        return Ok(value.to_string());
+    }
+    let number: f64 = value
+        .parse()
+        .map_err(|_| CaveError::CodeAsterError(format!("`{}` needs a number, got '{}'", flag, value)))?;
+    let number = number * scale;
+    Ok(if number.fract() == 0.0 {
+        format!("{}", number as i64)
+    } else {
+        format!("{}", number)
+    })
+}
+
+/// Translates the user-friendly flags of `args`, as `--flag value` or
+/// `--flag=value`, into the run_aster options of `version`.
+///
+/// # Errors
+/// Returns [`CaveError::CodeAsterError`] if a flag has no value, or a scaled
+/// flag a value that is not a number.
+pub fn translate(version: &str, args: &[String]) -> Result<Vec<String>, CaveError> {
+    let profile = profile(version)?;
+    let mut translated = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let Some(run_arg) = profile.get(flag) else {
+            translated.push(arg.clone());
+            continue;
+        };
+        let value = inline
+            .or_else(|| args.next().cloned())
+            .ok_or_else(|| CaveError::CodeAsterError(format!("`{}` needs a value", flag)))?;
+        translated.push(run_arg.option.clone());
+        translated.push(scaled(flag, &value, run_arg.scale)?);
+    }
+    debug!("Arguments de run_aster pour la version {}: {:?}", version, translated);
+    Ok(translated)
+}
@@ -0,0 +1,387 @@
+//! Activity indicator for long operations.
+//!
+//! Network requests and container runtime calls can take seconds without
+//! printing anything. A [`Spinner`] shows what cave is waiting on, on one line
+//! of the error output, and erases it when the operation ends; nested
+//! spinners show the innermost message. Operations shorter than [`DELAY`]
+//! draw nothing.
+//!
+//! Image pulls show a [`PullProgress`] instead: a bar per layer being
+//! downloaded or extracted, and a total line with the size, the rate and the
+//! time left.
+//!
+//! Nothing is drawn when the error output is not a terminal, or after
+//! [`suppress`]: with `--quiet`, `--json`, and `CAVE_DEBUG` whose log lines
+//! would be interleaved with the spinner.
+
+use crate::format;
+use colored::*;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+/// Time after which an operation shows its spinner.
+const DELAY: Duration = Duration::from_millis(300);
+const TICK: Duration = Duration::from_millis(100);
+/// Layers shown at most by a pull, the others being counted in its total line.
+const MAX_LAYER_LINES: usize = 6;
+const BAR_WIDTH: usize = 20;
+/// Statuses of the layer events of a pull; the other events are about the image.
+const LAYER_STATUSES: [&str; 8] = [
+    "Pulling fs layer",
+    "Waiting",
+    "Downloading",
+    "Verifying Checksum",
+    "Download complete",
+    "Extracting",
+    "Pull complete",
+    "Already exists",
+];
+
+struct State {
+    /// Messages of the active spinners, the innermost last.
+    waiting: Vec<(u64, String)>,
+    /// Start of the outermost active spinner.
+    since: Option<Instant>,
+    next_id: u64,
+    /// Whether the spinner line is on screen.
+    drawn: bool,
+    ticker: bool,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    waiting: Vec::new(),
+    since: None,
+    next_id: 0,
+    drawn: false,
+    ticker: false,
+});
+static SUPPRESSED: AtomicBool = AtomicBool::new(false);
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Disables the spinners for the rest of the invocation.
+pub fn suppress() {
+    SUPPRESSED.store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` after [`suppress`], for the progress printed by other
+/// programs (`docker pull`, `apptainer pull`), to be run in quiet mode.
+pub fn suppressed() -> bool {
+    SUPPRESSED.load(Ordering::Relaxed)
+}
+
+fn erase(state: &mut State) {
+    if state.drawn {
+        eprint!("\r\x1b[2K");
+        let _ = io::stderr().flush();
+        state.drawn = false;
+    }
+}
+
+/// Erases the spinner line, before printing a message during an operation.
+/// The spinner is drawn again at the next tick.
+pub fn clear() {
+    erase(&mut state());
+}
+
+fn tick() {
+    for frame in FRAMES.iter().cycle() {
+        thread::sleep(TICK);
+        let mut state = state();
+        let Some((_, message)) = state.waiting.last() else {
+            continue;
+        };
+        if state.since.is_some_and(|since| since.elapsed() < DELAY) {
+            continue;
+        }
+        let width = term_size::dimensions_stderr().map_or(80, |(w, _)| w);
+        let message: String = message.chars().take(width.saturating_sub(3)).collect();
+        eprint!("\r\x1b[2K{} {}", frame.cyan(), message);
+        let _ = io::stderr().flush();
+        state.drawn = true;
+    }
+}
+
+/// Shows `message` with a spinner until dropped.
+pub struct Spinner {
+    id: Option<u64>,
+}
+
+impl Spinner {
+    /// Starts a spinner, drawn once the operation lasts more than [`DELAY`].
+    pub fn start(message: impl Into<String>) -> Self {
+        if SUPPRESSED.load(Ordering::Relaxed) || !io::stderr().is_terminal() {
+            return Spinner { id: None };
+        }
+        let mut state = state();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.waiting.push((id, message.into()));
+        state.since.get_or_insert_with(Instant::now);
+        if !state.ticker {
+            state.ticker = true;
+            thread::spawn(tick);
+        }
+        Spinner { id: Some(id) }
+    }
+
+    /// Replaces the message, for an operation going through several steps.
+    pub fn set_message(&self, message: impl Into<String>) {
+        let Some(id) = self.id else {
+            return;
+        };
+        if let Some(entry) = state().waiting.iter_mut().find(|(i, _)| *i == id) {
+            entry.1 = message.into();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = state();
+        state.waiting.retain(|(i, _)| *i != id);
+        if state.waiting.is_empty() {
+            state.since = None;
+            erase(&mut state);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Layer {
+    id: String,
+    status: String,
+    /// Bytes downloaded, or extracted while extracting.
+    current: u64,
+    /// Compressed size, once known.
+    total: u64,
+    downloaded: bool,
+    /// Already present before the pull.
+    cached: bool,
+}
+
+impl Layer {
+    fn downloaded_bytes(&self) -> u64 {
+        if self.downloaded {
+            self.total
+        } else {
+            self.current
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.status.as_str(), "Pull complete" | "Already exists")
+    }
+}
+
+/// Progress of an image pull, drawn on the error output from the layer
+/// events of the pull until [`PullProgress::finish`].
+///
+/// With `--quiet`, nothing is printed; when the error output is not a
+/// terminal (CI logs), only the summary line is.
+pub struct PullProgress {
+    image: String,
+    layers: Vec<Layer>,
+    started: Instant,
+    last_draw: Option<Instant>,
+    /// Lines on screen.
+    drawn: usize,
+    live: bool,
+}
+
+impl PullProgress {
+    /// Starts following the pull of `image`.
+    pub fn start(image: &str) -> Self {
+        if !suppressed() {
+            eprintln!("{} {}", "Pulling".bold(), image);
+        }
+        PullProgress {
+            image: image.to_string(),
+            layers: Vec::new(),
+            started: Instant::now(),
+            last_draw: None,
+            drawn: 0,
+            live: !suppressed() && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Records an event of the pull: the status of layer `id`, with its
+    /// progress in bytes (current, total) when known. Events which are not
+    /// about a layer are ignored.
+    pub fn update(&mut self, id: &str, status: &str, progress: Option<(u64, u64)>) {
+        if !LAYER_STATUSES.contains(&status) {
+            return;
+        }
+        let index = match self.layers.iter().position(|l| l.id == id) {
+            Some(index) => index,
+            None => {
+                self.layers.push(Layer {
+                    id: id.to_string(),
+                    ..Default::default()
+                });
+                self.layers.len() - 1
+            }
+        };
+        let layer = &mut self.layers[index];
+        layer.status = status.to_string();
+        match status {
+            "Downloading" => {
+                if let Some((current, total)) = progress {
+                    layer.current = current;
+                    layer.total = total;
+                }
+            }
+            "Extracting" => {
+                layer.downloaded = true;
+                if let Some((current, _)) = progress {
+                    layer.current = current;
+                }
+            }
+            "Verifying Checksum" | "Download complete" | "Pull complete" => layer.downloaded = true,
+            "Already exists" => layer.cached = true,
+            _ => {}
+        }
+        // Redrawn at most every tick, and when a layer changes phase
+        let due = self.last_draw.is_none_or(|last| last.elapsed() >= TICK) || progress.is_none();
+        if self.live && due {
+            self.draw();
+        }
+    }
+
+    /// Returns the bytes downloaded and the size known so far.
+    fn totals(&self) -> (u64, u64) {
+        self.layers
+            .iter()
+            .filter(|l| !l.cached)
+            .fold((0, 0), |(current, total), l| (current + l.downloaded_bytes(), total + l.total))
+    }
+
+    fn draw(&mut self) {
+        let width = term_size::dimensions_stderr().map_or(80, |(w, _)| w);
+        let mut lines: Vec<String> = self
+            .layers
+            .iter()
+            .filter(|l| !l.finished() && l.total > 0)
+            .take(MAX_LAYER_LINES)
+            .map(|l| {
+                format!(
+                    "  {:<12} {} {}/{}  {}",
+                    &l.id[..l.id.len().min(12)],
+                    bar(l.current, l.total),
+                    format::size(l.current),
+                    format::size(l.total),
+                    if l.status == "Extracting" { "extracting" } else { "downloading" }
+                )
+            })
+            .collect();
+        let (current, total) = self.totals();
+        let secs = self.started.elapsed().as_secs_f64();
+        let rate = if secs > 0.0 { current as f64 / secs } else { 0.0 };
+        let eta = match total.checked_sub(current) {
+            Some(left) if rate > 0.0 && left > 0 => {
+                format!("  ETA {}", format::duration_ms((left as f64 / rate * 1000.0) as u64))
+            }
+            _ => String::new(),
+        };
+        let done = self.layers.iter().filter(|l| l.finished()).count();
+        lines.push(format!(
+            "  {:<12} {} {}/{}  {}/s{}",
+            format!("{}/{} layers", done, self.layers.len()).bold(),
+            bar(current, total),
+            format::size(current),
+            format::size(total),
+            format::size(rate as u64),
+            eta
+        ));
+
+        let mut out = String::new();
+        if self.drawn > 0 {
+            out.push_str(&format!("\x1b[{}A", self.drawn));
+        }
+        out.push_str("\r\x1b[J");
+        for line in &lines {
+            out.push_str(&truncate(line, width));
+            out.push('\n');
+        }
+        eprint!("{}", out);
+        let _ = io::stderr().flush();
+        self.drawn = lines.len();
+        self.last_draw = Some(Instant::now());
+    }
+
+    fn erase(&mut self) {
+        if self.drawn > 0 {
+            eprint!("\x1b[{}A\r\x1b[J", self.drawn);
+            let _ = io::stderr().flush();
+            self.drawn = 0;
+        }
+    }
+
+    /// Erases the bars and prints the downloaded size and the duration of the pull.
+    pub fn finish(mut self) {
+        self.erase();
+        if suppressed() {
+            return;
+        }
+        let (current, _) = self.totals();
+        let cached = self.layers.iter().filter(|l| l.cached).count();
+        eprintln!(
+            "Pulled {}: {} in {}{}",
+            self.image,
+            format::size(current),
+            format::duration_ms(self.started.elapsed().as_millis() as u64),
+            match cached {
+                0 => String::new(),
+                1 => ", 1 layer already present".to_string(),
+                n => format!(", {} layers already present", n),
+            }
+        );
+    }
+}
+
+impl Drop for PullProgress {
+    fn drop(&mut self) {
+        self.erase();
+    }
+}
+
+/// Returns a bar of [`BAR_WIDTH`] characters filled by `current / total`.
+fn bar(current: u64, total: u64) -> String {
+    let filled = if total == 0 {
+        0
+    } else {
+        (current.min(total) as f64 / total as f64 * BAR_WIDTH as f64) as usize
+    };
+    format!("[{}{}]", "#".repeat(filled).cyan(), "-".repeat(BAR_WIDTH - filled).dimmed())
+}
+
+/// Cuts `line` to `width` visible characters, escape sequences excluded.
+fn truncate(line: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut visible = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else if visible < width {
+            out.push(c);
+            visible += 1;
+        }
+    }
+    out
+}
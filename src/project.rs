@@ -0,0 +1,479 @@
+//! Project configuration for the `cave` CLI.
+//!
+//! A study directory can hold a `cave.toml` declaring defaults applied to
+//! every `cave run` started from it:
+//!
+//! ```toml
+//! [run]
+//! args = ["--memjeveux", "2000"]
+//! docker_args = ["--shm-size", "2g"]
+//! mounts = ["/mnt/meshes:/meshes:ro"]
+//!
+//! # Overrides for the study run with `cave run -- beam.export`
+//! [studies.beam]
+//! args = ["--tpmax", "3600"]
+//!
+//! # License servers, overriding `cave config set-license-env`
+//! [license]
+//! env = { LM_LICENSE_FILE = "27000@licsrv" }
+//! ```
+//!
+//! Arguments given on the command line always take precedence. The file also
+//! declares the non-regression cases run by `cave nrt` (see [`NrtConfig`]),
+//! the result extractors run after each run (see [`ExtractorConfig`]), the
+//! auxiliary containers of coupled runs (see [`ComposeConfig`]) and the
+//! hosts of remote runs (see [`RemoteConfig`]).
+//! Like the
+//! `.cave` pin, the file is searched in the parent directories (see
+//! [`find_project_dir`]).
+
+use crate::manage::CaveError;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Name of the project configuration file.
+pub const PROJECT_FILE: &str = "cave.toml";
+
+/// Default arguments of a run.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunDefaults {
+    /// Arguments passed to run_aster.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Arguments added to `docker run`.
+    #[serde(default)]
+    pub docker_args: Vec<String>,
+    /// Extra volumes, as `HOST:CONTAINER[:ro]` (see [`crate::mounts`]).
+    #[serde(default)]
+    pub mounts: Vec<String>,
+}
+
+/// Content of a `cave.toml` file.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectConfig {
+    /// Defaults for every run of the project.
+    #[serde(default)]
+    pub run: RunDefaults,
+    /// Defaults per study, keyed by export file name without extension.
+    #[serde(default)]
+    pub studies: HashMap<String, RunDefaults>,
+    /// Non-regression cases.
+    #[serde(default)]
+    pub nrt: NrtConfig,
+    /// Hosts of experimental multi-node MPI runs.
+    pub cluster: Option<ClusterConfig>,
+    /// License server settings, overriding the global ones.
+    #[serde(default)]
+    pub license: LicenseOverrides,
+    /// Result extractors, keyed by name.
+    #[serde(default)]
+    pub extractors: BTreeMap<String, ExtractorConfig>,
+    /// Auxiliary containers of `cave compose run`.
+    pub compose: Option<ComposeConfig>,
+    /// Hosts of `cave run --remote`, keyed by name.
+    #[serde(default)]
+    pub remote: BTreeMap<String, RemoteConfig>,
+    /// Kubernetes cluster of `cave run --backend k8s`.
+    #[serde(default)]
+    #[cfg_attr(not(feature = "k8s"), allow(dead_code))]
+    pub k8s: K8sConfig,
+}
+
+/// An external program extracting quantities from the result files of a run:
+///
+/// ```toml
+/// [extractors.stress]
+/// command = ["python3", "tools/max_stress.py"]
+/// extensions = ["rmed"]
+/// ```
+///
+/// See [`crate::extractors`] for the protocol.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractorConfig {
+    /// Program and its first arguments, run from the study directory.
+    pub command: Vec<String>,
+    /// Extensions of the produced files given to the program (default: every produced file).
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+/// License server environment of a project:
+///
+/// ```toml
+/// [license]
+/// env = { LM_LICENSE_FILE = "27000@licsrv" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LicenseOverrides {
+    /// Environment variables, overriding the global ones of the same name.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_ssh_port() -> u16 {
+    2222
+}
+
+/// Hosts used by `cave run --cluster`:
+///
+/// ```toml
+/// [cluster]
+/// ssh_port = 2222
+/// hosts = [
+///     { host = "node1", slots = 16 },  # the local host, running mpirun
+///     { host = "node2", slots = 16 },
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Hosts, the first one being the local host.
+    pub hosts: Vec<ClusterHost>,
+    /// Port of the SSH server of the worker containers.
+    #[serde(default = "default_ssh_port")]
+    pub ssh_port: u16,
+}
+
+/// A host of a cluster.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterHost {
+    /// SSH destination of the host, as given to `docker -H ssh://`.
+    pub host: String,
+    /// Number of MPI processes run on the host.
+    pub slots: u32,
+}
+
+fn default_remote_docker() -> String {
+    "docker".to_string()
+}
+
+fn default_remote_dir() -> String {
+    "cave-remote".to_string()
+}
+
+/// A host running studies with `cave run --remote <name>`, reached over SSH:
+///
+/// ```toml
+/// [remote.lab]
+/// host = "lab-server"
+/// user = "jdoe"
+/// docker = "podman"
+/// docker_host = "unix:///run/user/1000/podman/podman.sock"
+/// ```
+///
+/// See [`crate::remote`] for how the study is copied and run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Host name or SSH alias of the host.
+    pub host: String,
+    /// SSH user (default: the one of the SSH configuration).
+    pub user: Option<String>,
+    /// SSH port (default: the one of the SSH configuration).
+    pub port: Option<u16>,
+    /// Container runtime binary on the host.
+    #[serde(default = "default_remote_docker")]
+    pub docker: String,
+    /// `DOCKER_HOST` of the runtime on the host, when not the default one.
+    pub docker_host: Option<String>,
+    /// Directory of the copies of the studies on the host, relative to its home directory.
+    #[serde(default = "default_remote_dir")]
+    pub dir: String,
+}
+
+/// Kubernetes cluster running the studies of `cave run --backend k8s`, every
+/// setting being optional:
+///
+/// ```toml
+/// [k8s]
+/// context = "lab-cluster"
+/// namespace = "simulations"
+/// pvc = "studies"
+/// ```
+///
+/// See [`crate::k8s`] for how the study is copied and run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "k8s"), allow(dead_code))]
+pub struct K8sConfig {
+    /// kubectl binary.
+    #[serde(default = "default_kubectl")]
+    pub kubectl: String,
+    /// Context of the kubeconfig (default: the current context).
+    pub context: Option<String>,
+    /// Namespace of the Jobs (default: the one of the context).
+    pub namespace: Option<String>,
+    /// PersistentVolumeClaim keeping the copies of the studies between runs
+    /// (default: a volume of the pod, removed with it).
+    pub pvc: Option<String>,
+    /// Directory of the copies of the studies in the claim.
+    #[serde(default = "default_remote_dir")]
+    pub dir: String,
+    /// Secret used to pull the image from a private registry.
+    pub image_pull_secret: Option<String>,
+}
+
+impl Default for K8sConfig {
+    fn default() -> Self {
+        K8sConfig {
+            kubectl: default_kubectl(),
+            context: None,
+            namespace: None,
+            pvc: None,
+            dir: default_remote_dir(),
+            image_pull_secret: None,
+        }
+    }
+}
+
+fn default_kubectl() -> String {
+    "kubectl".to_string()
+}
+
+/// Auxiliary containers started alongside the code_aster container by
+/// `cave compose run`, for coupled simulations:
+///
+/// ```toml
+/// [compose.services.thermal]
+/// image = "lab/thermal-solver:2.1"
+/// command = ["thermal-server", "--port", "5000"]
+/// env = { OMP_NUM_THREADS = "4" }
+/// ```
+///
+/// See [`crate::compose`] for the network and volume they share.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeConfig {
+    /// Services, keyed by name; the name is their host name on the run network.
+    pub services: BTreeMap<String, ComposeService>,
+}
+
+/// An auxiliary container of a coupled run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeService {
+    /// Image of the container.
+    pub image: String,
+    /// Command of the container (default: the command of the image).
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Environment variables of the container.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Arguments added to `docker run`.
+    #[serde(default)]
+    pub docker_args: Vec<String>,
+    /// Command run in the container until it succeeds before code_aster
+    /// starts, such as a check of the port of the service (default: none).
+    #[serde(default)]
+    pub ready: Vec<String>,
+    /// Seconds given to `ready` to succeed.
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout: u64,
+}
+
+fn default_ready_timeout() -> u64 {
+    60
+}
+
+fn default_rtol() -> f64 {
+    1e-6
+}
+
+/// Non-regression cases of a project, run by `cave nrt`:
+///
+/// ```toml
+/// [nrt]
+/// rtol = 1e-6
+///
+/// [nrt.cases.beam]
+/// export = "beam.export"
+/// atol = 1e-12
+///
+/// [nrt.cases.beam.reference]
+/// "beam.resu#0/0/DX" = 1.2345e-3
+/// ```
+///
+/// The references and their provenance are usually written by `cave nrt bless`.
+///
+/// Reference keys are those recorded in the run summary (see
+/// [`ResultsSummary`](crate::results::ResultsSummary)).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NrtConfig {
+    /// Default relative tolerance of the cases.
+    #[serde(default = "default_rtol")]
+    pub rtol: f64,
+    /// Default absolute tolerance of the cases.
+    #[serde(default)]
+    pub atol: f64,
+    /// Cases, keyed by name.
+    #[serde(default)]
+    pub cases: BTreeMap<String, NrtCase>,
+}
+
+impl Default for NrtConfig {
+    fn default() -> Self {
+        NrtConfig {
+            rtol: default_rtol(),
+            atol: 0.0,
+            cases: BTreeMap::new(),
+        }
+    }
+}
+
+/// A non-regression case: a study and its reference values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NrtCase {
+    /// Export file of the study, relative to the project directory.
+    pub export: String,
+    /// Relative tolerance, overriding the project one.
+    pub rtol: Option<f64>,
+    /// Absolute tolerance, overriding the project one.
+    pub atol: Option<f64>,
+    /// Prefixes of the result keys recorded as references by `cave nrt bless`
+    /// (default: the current reference keys, or every key when there is none).
+    #[serde(default)]
+    pub monitor: Vec<String>,
+    /// Reference values of the monitored quantities.
+    #[serde(default)]
+    pub reference: BTreeMap<String, f64>,
+    /// Provenance of the reference values, written by `cave nrt bless`.
+    pub blessed: Option<Blessing>,
+}
+
+/// Provenance of the reference values of a non-regression case.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Blessing {
+    /// Run the values were taken from.
+    pub run: String,
+    /// code_aster version of the run.
+    pub version: String,
+    /// Image digest of the run, when known.
+    pub image_digest: Option<String>,
+    /// Date of the blessing, RFC 3339 in UTC.
+    pub date: String,
+}
+
+impl ProjectConfig {
+    /// Returns the defaults for a study: project-wide ones followed by the study's own.
+    pub fn defaults_for(&self, study: Option<&str>) -> RunDefaults {
+        let mut defaults = self.run.clone();
+        if let Some(specific) = study.and_then(|s| self.studies.get(s)) {
+            defaults.args = merge_args(&defaults.args, &specific.args);
+            defaults.docker_args = merge_args(&defaults.docker_args, &specific.docker_args);
+            defaults.mounts.extend(specific.mounts.iter().cloned());
+        }
+        defaults
+    }
+}
+
+/// Finds the project directory of `start`: the nearest directory, `start`
+/// included, holding a `cave.toml`, or else a `.cave` pin.
+///
+/// The two files are searched independently (see [`find_upward`]), so that a
+/// `cave.toml` of a subdirectory does not hide the pin of a parent, and the
+/// other way around.
+pub fn find_project_dir(start: &Path) -> Option<PathBuf> {
+    find_upward(start, PROJECT_FILE).or_else(|| find_upward(start, ".cave"))
+}
+
+/// Finds the nearest directory, `start` included, holding the file `name`.
+///
+/// Like git, the search walks up the tree and stops at a repository root
+/// (a directory containing `.git`), at a filesystem boundary, or below the
+/// home directory, whose `.cave` is the global version.
+fn find_upward(start: &Path, name: &str) -> Option<PathBuf> {
+    let home = dirs::home_dir();
+    let start_device = device(start);
+    let mut dir = Some(start);
+
+    while let Some(current) = dir {
+        if Some(current) == home.as_deref() || device(current) != start_device {
+            return None;
+        }
+        if current.join(name).is_file() {
+            return Some(current.to_path_buf());
+        }
+        if current.join(".git").exists() {
+            return None;
+        }
+        dir = current.parent();
+    }
+    None
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Returns the local `.cave` pin effective in `start`: the nearest one, with
+/// the search bounds of [`find_project_dir`].
+pub fn find_local_pin(start: &Path) -> Option<PathBuf> {
+    find_upward(start, ".cave").map(|dir| dir.join(".cave"))
+}
+
+/// Reads `cave.toml` from `dir`, returning an empty configuration when absent.
+///
+/// # Errors
+/// Returns [`CaveError::ProjectConfigError`] if the file is not valid.
+pub fn read_project_config(dir: &Path) -> Result<ProjectConfig, CaveError> {
+    let path = dir.join(PROJECT_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ProjectConfig::default()),
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    toml::from_str(&content)
+        .map_err(|e| CaveError::ProjectConfigError(format!("{}: {}", path.display(), e)))
+}
+
+/// Splits arguments into options, each with the values following it.
+fn option_groups(args: &[String]) -> Vec<&[String]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for i in 1..=args.len() {
+        if i == args.len() || args[i].starts_with('-') {
+            groups.push(&args[start..i]);
+            start = i;
+        }
+    }
+    groups.into_iter().filter(|g| !g.is_empty()).collect()
+}
+
+/// Merges default arguments with explicit ones.
+///
+/// An option present in `explicit` replaces the default option of the same
+/// name along with its values, other defaults are kept in front.
+pub fn merge_args(defaults: &[String], explicit: &[String]) -> Vec<String> {
+    let explicit_groups = option_groups(explicit);
+    let overridden = |flag: &str| {
+        explicit_groups
+            .iter()
+            .any(|g| g[0].starts_with('-') && g[0].split('=').next() == flag.split('=').next())
+    };
+
+    let mut merged: Vec<String> = option_groups(defaults)
+        .into_iter()
+        .filter(|g| !(g[0].starts_with('-') && overridden(&g[0])))
+        .flatten()
+        .cloned()
+        .collect();
+    merged.extend(explicit.iter().cloned());
+    merged
+}
@@ -0,0 +1,392 @@
+//! Remote runs over SSH (`cave run --remote <name>`).
+//!
+//! The hosts are declared in the `[remote.<name>]` sections of `cave.toml`.
+//! The study directory is copied with `rsync` to a directory of the remote
+//! home, named after the study directory and a hash of its local path so
+//! that the next runs only send the changed files. The container is run by
+//! the runtime of the remote host through `ssh`, its output streamed back,
+//! and the files it produced are copied back into the study directory.
+//!
+//! The runs are recorded in the local history like the local ones, the
+//! image ID being the one of the remote host. Unlike local runs, their
+//! resource usage is not measured. On SIGINT/SIGTERM, the remote container
+//! is killed before the results are copied back.
+
+use crate::cmdlog::{self, Logged};
+use crate::config::{env_args, read_config, ContainerUser};
+use crate::docker::{container_name, home_args, source_image, RunOptions};
+use crate::export::{self, Export};
+use crate::extractors;
+use crate::history::{self, RunRecord};
+use crate::jobs;
+use crate::license;
+use crate::lock;
+use crate::manage::{export_version, find_export_file, read_cave_version, CaveError};
+use crate::mpi;
+use crate::profiles;
+use crate::project::{find_project_dir, merge_args, read_project_config, RemoteConfig};
+use crate::results;
+use crate::signals::{self, Operation};
+use colored::*;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Instant, SystemTime};
+
+/// Files of the study directory that are not copied: cave's own files (locks, hostfiles, ...).
+const EXCLUDED: &str = ".cave-*";
+
+/// A remote host and the copy of the study on it.
+struct Remote<'a> {
+    name: &'a str,
+    config: &'a RemoteConfig,
+    /// Directory of the copy, relative to the remote home.
+    dir: String,
+}
+
+impl Remote<'_> {
+    /// Returns the SSH destination, `user@host` or `host`.
+    fn destination(&self) -> String {
+        match &self.config.user {
+            Some(user) => format!("{}@{}", user, self.config.host),
+            None => self.config.host.clone(),
+        }
+    }
+
+    /// Returns the SSH command, without destination, as given to `rsync -e`.
+    fn ssh(&self) -> Vec<String> {
+        let mut ssh = vec!["ssh".to_string()];
+        if let Some(port) = self.config.port {
+            ssh.extend(["-p".to_string(), port.to_string()]);
+        }
+        ssh
+    }
+
+    /// Returns a command running the shell `script` on the host, from its home directory.
+    fn command(&self, script: &str) -> Command {
+        let ssh = self.ssh();
+        let mut cmd = Command::new(&ssh[0]);
+        cmd.args(&ssh[1..]).arg(self.destination()).arg(script);
+        cmd
+    }
+
+    /// Returns the runtime command line of the host, with its `DOCKER_HOST`.
+    fn docker(&self) -> String {
+        match &self.config.docker_host {
+            Some(host) => format!(
+                "env DOCKER_HOST={} {}",
+                cmdlog::shell_quote(host),
+                cmdlog::shell_quote(&self.config.docker)
+            ),
+            None => cmdlog::shell_quote(&self.config.docker),
+        }
+    }
+
+    /// Runs `script` on the host and returns its output.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::RemoteError`] if `ssh` cannot be run or the script fails.
+    fn output(&self, script: &str) -> Result<Output, CaveError> {
+        let output = self.command(script).stdin(Stdio::null()).logged_output().map_err(|e| {
+            if not_found(&e) {
+                CaveError::RemoteError("ssh not found on this host".to_string())
+            } else {
+                e
+            }
+        })?;
+        if !output.status.success() {
+            return Err(CaveError::RemoteError(format!(
+                "{}: {}",
+                self.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output)
+    }
+
+    /// Copies `from` to `to` with rsync, one of them being on the host.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::RemoteError`] if rsync is missing or fails.
+    fn rsync(&self, args: &[&str], from: &str, to: &str) -> Result<(), CaveError> {
+        let status = Command::new("rsync")
+            .args(["-az", "--exclude", EXCLUDED, "-e", &self.ssh().join(" ")])
+            .args(args)
+            .arg(from)
+            .arg(to)
+            .stdin(Stdio::null())
+            .logged_status()
+            .map_err(|e| {
+                if not_found(&e) {
+                    CaveError::RemoteError("rsync not found on this host, install it to run remotely".to_string())
+                } else {
+                    e
+                }
+            })?;
+        if !status.success() {
+            return Err(CaveError::RemoteError(format!("copy of {} to {} failed", from, to)));
+        }
+        Ok(())
+    }
+
+    /// Returns the `host:dir/` argument of rsync for the copy.
+    fn remote_path(&self) -> String {
+        format!("{}:{}/", self.destination(), self.dir)
+    }
+}
+
+/// Returns the directory of the copy of `study_dir` under `base`, on a host
+/// or a volume.
+pub fn remote_dir(base: &str, study_dir: &Path) -> String {
+    let hash = format!("{:x}", Sha256::digest(study_dir.display().to_string().as_bytes()));
+    let name = study_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "study".to_string());
+    format!("{}/{}-{}", base.trim_end_matches('/'), name, &hash[..12])
+}
+
+/// Returns `true` if `e` comes from a program that was not found.
+pub fn not_found(e: &CaveError) -> bool {
+    match e {
+        CaveError::Context { source, .. } => not_found(source),
+        CaveError::IoError(e) => e.kind() == ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Replaces the environment variables passed by name (`-e NAME`) and the
+/// environment files of `docker_args` by their values on this host, which
+/// the remote runtime cannot read.
+pub fn resolve_env(docker_args: &[String]) -> Result<Vec<String>, CaveError> {
+    let mut resolved = Vec::new();
+    let mut args = docker_args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--env" => {
+                let Some(variable) = args.next() else {
+                    resolved.push(arg.clone());
+                    continue;
+                };
+                if variable.contains('=') {
+                    resolved.extend([arg.clone(), variable.clone()]);
+                } else if let Ok(value) = env::var(variable) {
+                    resolved.extend([arg.clone(), format!("{}={}", variable, value)]);
+                }
+            }
+            "--env-file" => {
+                let Some(file) = args.next() else {
+                    continue;
+                };
+                let content = fs::read_to_string(file)?;
+                let variables: Vec<String> = content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(String::from)
+                    .collect();
+                resolved.extend(resolve_env(&env_args(&variables)?)?);
+            }
+            _ => resolved.push(arg.clone()),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Returns the `docker run` arguments of the user the container runs as,
+/// for a shell script run on another host: the host user is the one running
+/// the script.
+pub fn user_args() -> Result<Vec<String>, CaveError> {
+    let mut args = match ContainerUser::parse(&read_config()?.container_user)? {
+        ContainerUser::Host => vec!["--user".to_string(), "\"$(id -u):$(id -g)\"".to_string()],
+        ContainerUser::Image => return Ok(Vec::new()),
+        ContainerUser::Fixed(uid, gid) => vec!["--user".to_string(), format!("{}:{}", uid, gid)],
+    };
+    args.extend(home_args());
+    Ok(args)
+}
+
+/// Runs a study on the host of the `[remote.<name>]` section of `cave.toml`,
+/// with the run_aster arguments `args`, the export file last.
+///
+/// # Errors
+/// - [`CaveError::ProjectConfigError`] if `cave.toml` has no such section.
+/// - [`CaveError::RemoteError`] if the host cannot be reached, or the study copied.
+/// - [`CaveError::RunInProgress`] if another run holds the study lock.
+/// - [`CaveError::Cancelled`] if interrupted.
+/// - [`CaveError::CodeAsterError`] if the run fails.
+pub fn run(name: &str, args: &[String], options: RunOptions) -> Result<(), CaveError> {
+    let mut version = match options.version.clone() {
+        Some(version) => version,
+        None => export_version(args)?.map_or_else(read_cave_version, Ok)?,
+    };
+    let (export, rest_args): (Option<String>, Vec<String>) = match args.split_last() {
+        Some((last, rest)) if last.ends_with(".export") => {
+            find_export_file(last)?;
+            export::warn_problems(last)?;
+            (Some(last.clone()), rest.to_vec())
+        }
+        _ => (None, args.to_vec()),
+    };
+    if let Some(export) = &export {
+        let (parsed, _) = Export::parse(&fs::read_to_string(export)?);
+        if parsed.integer("mpi_nbcpu").is_some_and(|np| np > 1) {
+            version = mpi::host_flavor(&version);
+        }
+    }
+
+    let current_dir = env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or_else(|| current_dir.clone());
+    let project = read_project_config(&project_dir)?;
+    let config = project.remote.get(name).ok_or_else(|| {
+        CaveError::ProjectConfigError(format!("`--remote {}` needs a [remote.{}] section in cave.toml", name, name))
+    })?;
+    let study = export
+        .as_deref()
+        .and_then(|e| Path::new(e).file_stem())
+        .and_then(|s| s.to_str());
+    let defaults = project.defaults_for(study);
+    let rest_args = profiles::translate(&version, &merge_args(&defaults.args, &rest_args))?;
+    let mut docker_args = merge_args(&defaults.docker_args, &options.docker_args);
+    docker_args.extend(license::docker_args(&read_config()?.license_env, &project.license.env));
+    docker_args.extend(env_args(&read_config()?.forward_env)?);
+    let docker_args = resolve_env(&docker_args)?;
+
+    let run_id = options.run_id.clone().unwrap_or_else(history::new_run_id);
+    let container = container_name(&run_id, options.name.as_deref());
+    let _lock = lock::acquire(&current_dir, &container, options.force)?;
+    let remote = Remote {
+        name,
+        config,
+        dir: remote_dir(&config.dir, &current_dir),
+    };
+
+    println!("{} the study to {}:{}", "Copying".bold(), config.host, remote.dir);
+    remote.output(&format!("mkdir -p {}", cmdlog::shell_quote(&remote.dir)))?;
+    remote.rsync(&["--delete"], &format!("{}/", current_dir.display()), &remote.remote_path())?;
+
+    let image = cmdlog::shell_quote(&source_image(&version));
+    let docker = remote.docker();
+    let export_arg = export.as_deref().map(cmdlog::shell_quote).unwrap_or_default();
+    let aster: Vec<String> = rest_args.iter().map(|a| cmdlog::shell_quote(a)).collect();
+    let script = format!("source /opt/activate.sh && run_aster {} {}", aster.join(" "), export_arg);
+    let mut run_args = vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        "--name".to_string(),
+        container.clone(),
+        "--label".to_string(),
+        jobs::LABEL.to_string(),
+        "--label".to_string(),
+        format!("{}={}", jobs::RUN_ID_LABEL, run_id),
+        "--label".to_string(),
+        format!("{}={}", jobs::VERSION_LABEL, version),
+    ];
+    let quoted: Vec<String> = docker_args.iter().map(|a| cmdlog::shell_quote(a)).collect();
+    run_args.extend(quoted);
+    let remote_script = format!(
+        "{{ {docker} image inspect {image} >/dev/null 2>&1 || {docker} pull {image}; }} && \
+         cd {dir} && exec {docker} {run} {user} -v \"$PWD\":/home/user/data -w /home/user/data {image} /bin/bash -c {script}",
+        docker = docker,
+        image = image,
+        dir = cmdlog::shell_quote(&remote.dir),
+        run = run_args.join(" "),
+        user = user_args()?.join(" "),
+        script = cmdlog::shell_quote(&script),
+    );
+
+    println!("{} {} on {} (run {})", "Running".bold(), version, name, run_id);
+    let started_at = SystemTime::now();
+    let started_utc = chrono::Utc::now();
+    let start = Instant::now();
+    let mut cmd = remote.command(&remote_script);
+    let docker_command: Vec<String> = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    cmdlog::record(&cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| CaveError::RemoteError(format!("cannot run ssh: {}", e)))?;
+    let guard = signals::register(Operation::Process(child.id()));
+    let status = child.wait()?;
+    drop(guard);
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let cancelled = signals::cancelled();
+    if cancelled {
+        debug!("Arrêt du conteneur distant {}", container);
+        let _ = remote.output(&format!("{} kill {}", docker, container));
+    }
+    // ssh exits with 255 when the host cannot be reached
+    if status.code() == Some(255) && start.elapsed().as_secs() < 30 && !cancelled {
+        return Err(CaveError::RemoteError(format!("cannot reach {} with ssh", config.host)));
+    }
+
+    println!("{} the results from {}", "Copying".bold(), config.host);
+    remote.rsync(&["--update"], &remote.remote_path(), &format!("{}/", current_dir.display()))?;
+
+    let produced_files = history::files_modified_since(&current_dir, started_at);
+    let mut summary = results::summarize(&current_dir, &produced_files);
+    let context = extractors::RunContext {
+        dir: &current_dir,
+        run_id: &run_id,
+        version: &version,
+        export: export.as_deref(),
+    };
+    extractors::apply(&project.extractors, &context, &produced_files, &mut summary);
+    let image_id = remote
+        .output(&format!("{} image inspect --format '{{{{.Id}}}}' {}", docker, image))
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default();
+    let mut commands = cmdlog::take();
+    if !read_config()?.log_commands {
+        commands.clear();
+    }
+    let record = RunRecord {
+        id: run_id.clone(),
+        name: options.name.clone(),
+        timestamp: started_utc.to_rfc3339(),
+        version: version.clone(),
+        image_id,
+        export_file: export.clone(),
+        args: rest_args.clone(),
+        working_dir: current_dir.display().to_string(),
+        duration_ms,
+        exit_code: status.code(),
+        success: status.success() && !cancelled,
+        cancelled,
+        diagnostic: history::diagnostic(&current_dir, &produced_files),
+        docker_command,
+        export_snapshot: export.as_ref().and_then(|e| fs::read_to_string(current_dir.join(e)).ok()),
+        log_tail: history::message_tail(&current_dir, &produced_files),
+        results: summary,
+        commands,
+        produced_files,
+        invocation: options.invocation.clone(),
+        ..Default::default()
+    };
+    if let Err(e) = history::append(&record) {
+        debug!("Impossible d'enregistrer le run dans l'historique: {}", e);
+    }
+    if read_config()?.run_metadata {
+        if let Err(e) = history::write_run_metadata(&current_dir, &record) {
+            debug!("Impossible d'écrire {}: {}", history::RUN_METADATA_FILE, e);
+        }
+    }
+
+    if cancelled {
+        return Err(CaveError::Cancelled);
+    }
+    if !status.success() {
+        return Err(CaveError::CodeAsterError(format!(
+            "run failed for version: {} on {}",
+            version, name
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Version checks for restarts (`POURSUITE`) from existing databases.
+//!
+//! A study restarting from base files (`R base ... D` or `R bhdf ... D` in the
+//! export file) must use the code_aster version that produced them: a
+//! database read by another version is corrupted in subtle ways. The
+//! producing version is found in the run history, or else in the
+//! `.cave-run.json` of the study directory, or of the directory of the
+//! database, and a mismatch blocks the run.
+
+use crate::history::{self, RUN_METADATA_FILE};
+use crate::manage::CaveError;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Export file types holding code_aster databases.
+const BASE_TYPES: &[&str] = &["base", "bhdf"];
+
+/// Removes `.` components, so that paths compare with those of the history.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Returns the input databases of an export file, relative to `dir`.
+pub fn input_bases(dir: &Path, export: &str) -> Vec<PathBuf> {
+    let Ok(content) = fs::read_to_string(dir.join(export)) else {
+        return Vec::new();
+    };
+    let base_dir = Path::new(export).parent().unwrap_or(Path::new(""));
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["F" | "R", kind, path, flags, ..]
+                    if BASE_TYPES.contains(kind) && flags.contains('D') =>
+                {
+                    Some(normalize(&base_dir.join(path)))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the code_aster version that produced `base` (relative to `dir`).
+fn producing_version(dir: &Path, base: &Path) -> Option<String> {
+    let working_dir = dir.display().to_string();
+    let from_history = history::read_all().ok().and_then(|records| {
+        records
+            .into_iter()
+            .rev()
+            .filter(|r| r.working_dir == working_dir)
+            .find(|r| {
+                r.produced_files
+                    .iter()
+                    .any(|f| Path::new(f).starts_with(base))
+            })
+            .map(|r| r.version)
+    });
+    // Results copied from elsewhere only carry their `.cave-run.json`, written
+    // in the study directory, or next to bases taken from another study
+    let folder = dir.join(base).parent().map(Path::to_path_buf);
+    from_history.or_else(|| {
+        std::iter::once(dir.to_path_buf())
+            .chain(folder)
+            .find_map(|folder| metadata_version(&folder))
+    })
+}
+
+/// Returns the version of the `.cave-run.json` of `folder`, if any.
+fn metadata_version(folder: &Path) -> Option<String> {
+    let metadata: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(folder.join(RUN_METADATA_FILE)).ok()?).ok()?;
+    metadata.get("version")?.as_str().map(str::to_string)
+}
+
+/// Checks that the databases a study restarts from were produced by `version`.
+///
+/// Databases of unknown origin are accepted.
+///
+/// # Errors
+/// Returns [`CaveError::RestartVersionMismatch`] on the first database
+/// produced by another version.
+pub fn check(dir: &Path, export: &str, version: &str) -> Result<(), CaveError> {
+    for base in input_bases(dir, export) {
+        if let Some(produced_by) = producing_version(dir, &base) {
+            if produced_by != version {
+                return Err(CaveError::RestartVersionMismatch {
+                    base: base.display().to_string(),
+                    produced_by,
+                    current: version.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! Results comparison between runs, for non-regression checks.
+//!
+//! At the end of each run, a [`ResultsSummary`] is extracted from the produced
+//! files and stored in the run history: the numeric values of the tables
+//! written in text result files (`IMPR_TABLE`, `.resu`) and the number of
+//! alarms and errors of the message file. Result files are overwritten by the
+//! next run of the study, so `cave diff-results` compares these summaries
+//! rather than the files themselves.
+
+use crate::format;
+use crate::history::{self, RunRecord};
+use crate::manage::CaveError;
+use crate::table::{Cell, Table};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Extensions of the text files searched for tables.
+const TABLE_EXTENSIONS: &[&str] = &["resu", "tab", "table", "txt", "dat"];
+/// Files larger than this are not parsed.
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+/// Maximum number of values kept per run, to bound the history size.
+const MAX_VALUES: usize = 2000;
+
+/// Key values and message counts of a run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResultsSummary {
+    /// Numeric table values, keyed by `<file>#<table>/<row>/<column>`.
+    pub values: BTreeMap<String, f64>,
+    /// Number of alarms (`<A>`) in the message file.
+    pub alarms: usize,
+    /// Number of errors (`<E>`, `<F>`, `<S>`) in the message file.
+    pub errors: usize,
+}
+
+/// Extracts the summary of the files produced by a run in `dir`.
+///
+/// Returns `None` when the run produced neither a message file nor a table.
+pub fn summarize(dir: &Path, files: &[String]) -> Option<ResultsSummary> {
+    let mut summary = ResultsSummary::default();
+    let mut found = false;
+
+    for file in files {
+        let path = dir.join(file);
+        if fs::metadata(&path)
+            .map(|m| m.len() > MAX_FILE_SIZE)
+            .unwrap_or(true)
+        {
+            continue;
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if extension == "mess" {
+            let Ok(content) = fs::read(&path) else {
+                continue;
+            };
+            let (alarms, errors) = count_messages(&String::from_utf8_lossy(&content));
+            summary.alarms += alarms;
+            summary.errors += errors;
+            found = true;
+        } else if TABLE_EXTENSIONS.contains(&extension) {
+            let Ok(content) = fs::read(&path) else {
+                continue;
+            };
+            for (key, value) in parse_tables(&String::from_utf8_lossy(&content)) {
+                if summary.values.len() >= MAX_VALUES {
+                    break;
+                }
+                summary.values.insert(format!("{}#{}", file, key), value);
+            }
+            found = true;
+        }
+    }
+    found.then_some(summary)
+}
+
+fn count_messages(content: &str) -> (usize, usize) {
+    let mut alarms = 0;
+    let mut errors = 0;
+    for line in content.lines() {
+        if line.contains("<A> <") {
+            alarms += 1;
+        } else if ["<E> <", "<F> <", "<S> <"].iter().any(|m| line.contains(m)) {
+            errors += 1;
+        }
+    }
+    (alarms, errors)
+}
+
+/// Parses the tables of a text result file, as written by `IMPR_TABLE`:
+/// comment lines starting with `#`, a header line of column names, then rows.
+///
+/// Returns the numeric cells keyed by `<table>/<row>/<column>`.
+fn parse_tables(content: &str) -> Vec<(String, f64)> {
+    let mut values = Vec::new();
+    let mut table = 0;
+    let mut header: Option<Vec<&str>> = None;
+    let mut row = 0;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            if header.take().is_some() {
+                table += 1;
+            }
+            continue;
+        }
+        let cells: Vec<&str> = trimmed.split_whitespace().collect();
+        match &header {
+            None => {
+                if cells.iter().all(|c| c.parse::<f64>().is_err()) {
+                    header = Some(cells);
+                    row = 0;
+                }
+            }
+            Some(columns) => {
+                if cells.len() != columns.len() {
+                    continue;
+                }
+                for (column, cell) in columns.iter().zip(&cells) {
+                    if let Ok(value) = cell.parse::<f64>() {
+                        values.push((format!("{}/{}/{}", table, row, column), value));
+                    }
+                }
+                row += 1;
+            }
+        }
+    }
+    values
+}
+
+/// Tolerances used to compare two runs.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerances {
+    /// Relative tolerance on table values.
+    pub rtol: f64,
+    /// Absolute tolerance on table values, for values close to zero.
+    pub atol: f64,
+    /// Relative tolerance on the run duration.
+    pub time_rtol: f64,
+}
+
+/// Relative deviation between `a` and `b`, scaled by the larger magnitude.
+pub fn relative_deviation(a: f64, b: f64) -> f64 {
+    let scale = a.abs().max(b.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (b - a).abs() / scale
+    }
+}
+
+/// Returns `true` if `b` matches `a` within the absolute or the relative tolerance.
+pub fn within(a: f64, b: f64, rtol: f64, atol: f64) -> bool {
+    (b - a).abs() <= atol || relative_deviation(a, b) <= rtol
+}
+
+fn status_cell(ok: bool) -> Cell {
+    if ok {
+        Cell::from("ok").color(Color::Green)
+    } else {
+        Cell::from("DIFF").color(Color::Red).bold()
+    }
+}
+
+fn summary_of(record: &RunRecord) -> Result<&ResultsSummary, CaveError> {
+    record.results.as_ref().ok_or_else(|| {
+        CaveError::CodeAsterError(format!(
+            "run '{}' has no recorded results to compare",
+            record.id
+        ))
+    })
+}
+
+/// Handler of `cave diff-results`: compares the results of two runs of a study.
+///
+/// Only the values out of tolerance are listed, unless `all` is set.
+///
+/// # Errors
+/// - [`CaveError::RunNotFound`] if a run is not in the history.
+/// - [`CaveError::ResultsDiffer`] if values, message counts or the duration
+///   are out of tolerance, so that the command can gate non-regression scripts.
+pub fn diff_results(
+    run_a: &str,
+    run_b: &str,
+    tolerances: Tolerances,
+    all: bool,
+) -> Result<(), CaveError> {
+    let a = history::find(run_a)?;
+    let b = history::find(run_b)?;
+    if a.working_dir != b.working_dir || a.export_file != b.export_file {
+        eprintln!(
+            "{} runs {} and {} are not from the same study.",
+            "Warning:".yellow().bold(),
+            a.id,
+            b.id
+        );
+    }
+    let (sa, sb) = (summary_of(&a)?, summary_of(&b)?);
+
+    println!(
+        "{} {} ({}) -> {} ({})",
+        "Comparing".bold(),
+        a.id,
+        a.version,
+        b.id,
+        b.version
+    );
+    println!();
+
+    let mut differences = 0;
+    let mut table = Table::new(&["Key", "Run A", "Run B", "Deviation", "Status"]);
+
+    let (da, db) = (a.duration_ms as f64 / 1000.0, b.duration_ms as f64 / 1000.0);
+    let time_ok = relative_deviation(da, db) <= tolerances.time_rtol;
+    table.push(vec![
+        Cell::from("duration (s)").bold(),
+        format::decimal(da, 1).into(),
+        format::decimal(db, 1).into(),
+        format!("{:+.1}%", (db - da) / da.max(f64::EPSILON) * 100.0).into(),
+        status_cell(time_ok),
+    ]);
+    for (label, ca, cb) in [
+        ("alarms", sa.alarms, sb.alarms),
+        ("errors", sa.errors, sb.errors),
+    ] {
+        table.push(vec![
+            Cell::from(label).bold(),
+            ca.to_string().into(),
+            cb.to_string().into(),
+            format!("{:+}", cb as i64 - ca as i64).into(),
+            status_cell(ca == cb),
+        ]);
+        differences += usize::from(ca != cb);
+    }
+    differences += usize::from(!time_ok);
+
+    let keys: std::collections::BTreeSet<&String> =
+        sa.values.keys().chain(sb.values.keys()).collect();
+    let mut compared = 0;
+    for key in &keys {
+        let (va, vb) = (sa.values.get(*key), sb.values.get(*key));
+        let (ok, deviation) = match (va, vb) {
+            (Some(va), Some(vb)) => (
+                within(*va, *vb, tolerances.rtol, tolerances.atol),
+                format!("{:.3e}", relative_deviation(*va, *vb)),
+            ),
+            _ => (false, "missing".to_string()),
+        };
+        compared += 1;
+        if !ok {
+            differences += 1;
+        }
+        if ok && !all {
+            continue;
+        }
+        let fmt = |v: Option<&f64>| {
+            v.map(|v| format!("{:e}", v))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        table.push(vec![
+            (*key).clone().into(),
+            fmt(va).into(),
+            fmt(vb).into(),
+            deviation.into(),
+            status_cell(ok),
+        ]);
+    }
+    table.print();
+
+    println!();
+    println!(
+        "{} values compared (rtol {:e}, atol {:e}, duration rtol {:e}), {} out of tolerance.",
+        compared, tolerances.rtol, tolerances.atol, tolerances.time_rtol, differences
+    );
+    if differences > 0 {
+        return Err(CaveError::ResultsDiffer(differences));
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Wrapper shims for legacy code_aster commands.
+//!
+//! Scripts and Makefiles written for native code_aster installs call
+//! `run_aster` or `as_run` directly. The shims installed here are small shell
+//! scripts with those names that forward to `cave run` with the pinned version.
+
+use crate::config::read_only_home;
+use crate::manage::CaveError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Marker line identifying files generated by cave.
+const SHIM_MARKER: &str = "# Generated by `cave shim install`";
+
+/// Names of the generated shims.
+const SHIMS: &[&str] = &["run_aster", "as_run"];
+
+fn default_dir() -> Result<PathBuf, CaveError> {
+    if read_only_home() {
+        return Err(CaveError::ReadOnlyHome("managing the shims of ~/.local/bin".to_string()));
+    }
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    Ok(home.join(".local/bin"))
+}
+
+fn shim_script(name: &str, cave: &Path) -> String {
+    // as_run takes the action as first argument, only `--run` maps to run_aster
+    let forward = if name == "as_run" {
+        "[ \"$1\" = \"--run\" ] && shift\n"
+    } else {
+        ""
+    };
+    format!(
+        "#!/bin/sh\n{}\n{}exec \"{}\" run -- \"$@\"\n",
+        SHIM_MARKER,
+        forward,
+        cave.display()
+    )
+}
+
+/// Writes the `run_aster` and `as_run` shims into `dir` (default `~/.local/bin`).
+///
+/// Existing files not generated by cave are left untouched.
+pub fn install(dir: Option<String>) -> Result<(), CaveError> {
+    let dir = dir.map(PathBuf::from).map_or_else(default_dir, Ok)?;
+    fs::create_dir_all(&dir)?;
+    let cave = std::env::current_exe()?;
+
+    for name in SHIMS {
+        let path = dir.join(name);
+        if path.exists() && !is_shim(&path) {
+            eprintln!("Skipping {}: file exists and was not generated by cave", path.display());
+            continue;
+        }
+        fs::write(&path, shim_script(name, &cave))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        println!("Installed {}", path.display());
+    }
+
+    let on_path = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).any(|p| p == dir))
+        .unwrap_or(false);
+    if !on_path {
+        println!("Add {} to your PATH to use the shims.", dir.display());
+    }
+    Ok(())
+}
+
+/// Removes the shims generated by cave from `dir` (default `~/.local/bin`).
+pub fn uninstall(dir: Option<String>) -> Result<(), CaveError> {
+    let dir = dir.map(PathBuf::from).map_or_else(default_dir, Ok)?;
+    for name in SHIMS {
+        let path = dir.join(name);
+        if is_shim(&path) {
+            fs::remove_file(&path)?;
+            println!("Removed {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn is_shim(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|c| c.contains(SHIM_MARKER))
+        .unwrap_or(false)
+}
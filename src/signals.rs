@@ -0,0 +1,137 @@
+//! SIGINT/SIGTERM handling for the `cave` CLI.
+//!
+//! Long-running docker operations (pulls, runs) register themselves while
+//! they are active. On the first signal, every registered operation is
+//! aborted (docker process killed, container stopped) and the operation's
+//! caller is left to record the cancellation and return
+//! [`CaveError::Cancelled`](crate::manage::CaveError::Cancelled). When no
+//! operation is active, or on a second signal, cave exits immediately.
+
+use crate::cmdlog::Logged;
+use crate::docker::runtime;
+use log::debug;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Exit code used when cave is interrupted, as for shells.
+pub const EXIT_CANCELLED: i32 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static ACTIVE: Mutex<Vec<(u64, Operation)>> = Mutex::new(Vec::new());
+
+/// An abortable operation.
+#[derive(Debug, Clone)]
+pub enum Operation {
+    /// A docker CLI process, killed by PID.
+    Process(u32),
+    /// A container whose ID is written in a `--cidfile`.
+    Container(PathBuf),
+    /// A Docker Engine API request, which checks [`cancelled`] itself.
+    Request,
+}
+
+/// Keeps an operation registered until dropped.
+pub struct OperationGuard(u64);
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE.lock() {
+            active.retain(|(id, _)| *id != self.0);
+        }
+    }
+}
+
+/// Registers an operation to abort on SIGINT/SIGTERM.
+pub fn register(operation: Operation) -> OperationGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut active) = ACTIVE.lock() {
+        active.push((id, operation));
+    }
+    OperationGuard(id)
+}
+
+/// Returns `true` once cave received SIGINT or SIGTERM.
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Installs the signal handler in a background thread.
+pub fn install() {
+    thread::spawn(|| {
+        let Ok(rt) = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        else {
+            return;
+        };
+        rt.block_on(async {
+            loop {
+                wait_for_signal().await;
+                on_signal();
+            }
+        });
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn on_signal() {
+    let active: Vec<Operation> = ACTIVE
+        .lock()
+        .map(|a| a.iter().map(|(_, op)| op.clone()).collect())
+        .unwrap_or_default();
+
+    if CANCELLED.swap(true, Ordering::Relaxed) || active.is_empty() {
+        eprintln!("\nInterrupted.");
+        process::exit(EXIT_CANCELLED);
+    }
+
+    eprintln!("\nInterrupted, stopping docker operations...");
+    for operation in active {
+        debug!("Annulation de {:?}", operation);
+        abort(&operation);
+    }
+}
+
+fn abort(operation: &Operation) {
+    let mut cmd = match operation {
+        Operation::Process(pid) => {
+            let mut cmd = Command::new("kill");
+            cmd.arg("-TERM").arg(pid.to_string());
+            cmd
+        }
+        Operation::Container(cidfile) => {
+            let Ok(id) = std::fs::read_to_string(cidfile) else {
+                return;
+            };
+            let mut cmd = runtime().command();
+            cmd.arg("kill").arg(id.trim());
+            cmd
+        }
+        Operation::Request => return,
+    };
+    let _ = cmd.stdout(Stdio::null()).stderr(Stdio::null()).logged().status();
+}
@@ -0,0 +1,465 @@
+//! Slurm submission of runs (`cave submit`).
+//!
+//! `cave submit study.export` writes an sbatch script running the study in
+//! the container of the selected version, with Apptainer when it is the
+//! runtime of cave, else with Docker or Podman on the compute node, and
+//! submits it with `sbatch` from the study directory. The resources are
+//! requested from the export file:
+//!
+//! - one task per MPI process (`mpi_nbcpu`), with `ncpus` CPUs each;
+//! - the memory limit of each process (`memory_limit`), times the processes;
+//! - the time limit (`time_limit`).
+//!
+//! The limits given to run_aster on the command line override the ones of
+//! the export file.
+//!
+//! The options given with `--sbatch` are passed to `sbatch`, and override
+//! the ones of the script. The submitted jobs are recorded in
+//! `~/.cave.d/slurm.jsonl`, and `cave submit status` polls their state with
+//! `squeue`, or with `sacct` once they have left the queue.
+
+use crate::apptainer;
+use crate::cmdlog::{self, shell_quote, Logged};
+use crate::config::{env_args, read_config, state_dir};
+use crate::docker::{container_name, runtime, source_image, uses_apptainer};
+use crate::export::{self, Export};
+use crate::history;
+use crate::jobs;
+use crate::license;
+use crate::manage::{ensure_installed, export_version, find_export_file, read_cave_version, CaveError};
+use crate::mpi;
+use crate::profiles;
+use crate::project::{find_project_dir, merge_args, read_project_config};
+use crate::remote;
+use crate::table::{Cell, Table};
+use colored::*;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Number of jobs listed by `cave submit status`, the most recent ones.
+const STATUS_LIMIT: usize = 20;
+
+/// Options of `cave submit`.
+#[derive(Debug, Default)]
+pub struct SubmitOptions {
+    /// Slurm partition of the job.
+    pub partition: Option<String>,
+    /// Account charged for the job.
+    pub account: Option<String>,
+    /// Name of the job, the name of the export file by default.
+    pub job_name: Option<String>,
+    /// Extra options given to `sbatch`.
+    pub sbatch_args: Vec<String>,
+    /// Print the script instead of submitting it.
+    pub dry_run: bool,
+    /// Download the pinned version again without asking if its image was removed.
+    pub yes: bool,
+}
+
+/// A job submitted with `cave submit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Submission {
+    job_id: String,
+    job_name: String,
+    /// Run identifier, labelling the container of the job.
+    run_id: String,
+    version: String,
+    export_file: String,
+    working_dir: String,
+    /// Submission date, RFC 3339 in UTC.
+    timestamp: String,
+}
+
+/// Resources requested from Slurm for a study.
+#[derive(Debug)]
+struct Resources {
+    tasks: u32,
+    cpus_per_task: u32,
+    /// Memory of the node, in MiB.
+    memory_mb: Option<u64>,
+    time_secs: Option<u64>,
+}
+
+impl Resources {
+    /// Returns the resources of the study of `export`, the limits given to
+    /// run_aster in `args` overriding the ones of the export file.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::CodeAsterError`] if the study runs on several nodes.
+    fn of(export: &Export, args: &[String]) -> Result<Self, CaveError> {
+        if export.integer("mpi_nbnoeud").is_some_and(|nodes| nodes > 1) {
+            return Err(CaveError::CodeAsterError(
+                "`cave submit` runs a study on a single node, set `P mpi_nbnoeud 1` in the export file".to_string(),
+            ));
+        }
+        let mut memory = export.memory_limit();
+        let mut time = export.time_limit();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let limit = match option {
+                "--memory_limit" | "--memjeveux" | "--time_limit" | "--tpmax" => {
+                    value.or_else(|| args.next().map(String::as_str)).and_then(|v| v.parse::<f64>().ok())
+                }
+                _ => continue,
+            };
+            match option {
+                "--memory_limit" => memory = limit.or(memory),
+                "--memjeveux" => memory = limit.map(|mw| mw * 8.0).or(memory),
+                _ => time = limit.or(time),
+            }
+        }
+        let tasks = export.integer("mpi_nbcpu").unwrap_or(1);
+        Ok(Resources {
+            tasks,
+            cpus_per_task: export.integer("ncpus").unwrap_or(1),
+            memory_mb: memory.map(|mb| (mb * tasks as f64).ceil() as u64),
+            time_secs: time.map(|secs| secs.ceil() as u64),
+        })
+    }
+
+    /// Returns the sbatch options requesting the resources.
+    fn directives(&self) -> Vec<String> {
+        let mut directives = vec![
+            "--nodes=1".to_string(),
+            format!("--ntasks={}", self.tasks),
+            format!("--cpus-per-task={}", self.cpus_per_task),
+        ];
+        if let Some(mb) = self.memory_mb {
+            directives.push(format!("--mem={}M", mb));
+        }
+        if let Some(secs) = self.time_secs {
+            directives.push(format!("--time={}", slurm_time(secs)));
+        }
+        directives
+    }
+}
+
+/// Formats a duration for `sbatch --time`, as `days-hours:minutes:seconds`.
+fn slurm_time(secs: u64) -> String {
+    format!(
+        "{}-{:02}:{:02}:{:02}",
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Returns the file recording the submitted jobs, in JSON lines.
+fn submissions_path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("slurm.jsonl"))
+}
+
+/// Reads the submitted jobs, oldest first. Malformed lines are skipped.
+fn read_submissions() -> Result<Vec<Submission>, CaveError> {
+    let file = match fs::File::open(submissions_path()?) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+fn record(submission: &Submission) -> Result<(), CaveError> {
+    let path = submissions_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let line = serde_json::to_string(submission)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    Ok(())
+}
+
+/// Runs a Slurm query command and returns its standard output, even if it
+/// fails: `squeue` and `sacct` fail when a job is unknown to them.
+///
+/// # Errors
+/// Returns [`CaveError::SlurmError`] if the command is missing.
+fn slurm_output(cmd: &mut Command) -> Result<String, CaveError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let output = cmd.stdin(Stdio::null()).logged_output().map_err(|e| {
+        if remote::not_found(&e) {
+            CaveError::SlurmError(format!("{} not found on this host", program))
+        } else {
+            e
+        }
+    })?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the shell command running the container of the study.
+fn container_command(
+    version: &str,
+    dir: &Path,
+    run_id: &str,
+    docker_args: &[String],
+    script: &str,
+) -> Result<String, CaveError> {
+    if uses_apptainer() {
+        let cmd = apptainer::exec_command(version, dir, docker_args, Some(script))?;
+        return Ok(format!("exec {}", cmdlog::command_line(&cmd)));
+    }
+    let docker = shell_quote(runtime().binary());
+    let image = shell_quote(&source_image(version));
+    let mut run_args = vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        "--name".to_string(),
+        container_name(run_id, None),
+        "--label".to_string(),
+        jobs::LABEL.to_string(),
+        "--label".to_string(),
+        format!("{}={}", jobs::RUN_ID_LABEL, run_id),
+        "--label".to_string(),
+        format!("{}={}", jobs::VERSION_LABEL, version),
+        "--label".to_string(),
+        shell_quote(&format!("{}={}", jobs::DIR_LABEL, dir.display())),
+    ];
+    run_args.extend(remote::user_args()?);
+    run_args.extend(docker_args.iter().map(|a| shell_quote(a)));
+    Ok(format!(
+        "{docker} image inspect {image} >/dev/null 2>&1 || {docker} pull {image}\n\
+         exec {docker} {run} -v {dir}:/home/user/data -w /home/user/data {image} /bin/bash -c {script}",
+        docker = docker,
+        image = image,
+        run = run_args.join(" "),
+        dir = shell_quote(&dir.display().to_string()),
+        script = shell_quote(script),
+    ))
+}
+
+/// Handler of `cave submit`: submits a run of the study to Slurm, with the
+/// run_aster arguments `args`, the export file last.
+///
+/// # Errors
+/// - [`CaveError::CodeAsterError`] if the arguments do not end with a valid
+///   export file, or if the study runs on several nodes.
+/// - [`CaveError::VersionNotInstalled`] if the SIF image of the version is
+///   missing and cannot be downloaded, with Apptainer.
+/// - [`CaveError::SlurmError`] if `sbatch` is missing or fails.
+pub fn submit(args: &[String], options: SubmitOptions) -> Result<(), CaveError> {
+    let Some((export, rest_args)) = args.split_last().filter(|(last, _)| last.ends_with(".export")) else {
+        return Err(CaveError::CodeAsterError(
+            "`cave submit` needs the export file of the study as last argument".to_string(),
+        ));
+    };
+    find_export_file(export)?;
+    export::warn_problems(export)?;
+    let (parsed, _) = Export::parse(&fs::read_to_string(export)?);
+    let mut version = export_version(args)?.map_or_else(read_cave_version, Ok)?;
+    if parsed.integer("mpi_nbcpu").is_some_and(|np| np > 1) {
+        version = mpi::host_flavor(&version);
+    }
+    // Docker images are pulled by the compute node, SIF images are shared through the home directory
+    if uses_apptainer() && !options.dry_run {
+        version = ensure_installed(version, options.yes)?;
+    }
+
+    let current_dir = env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or_else(|| current_dir.clone());
+    let project = read_project_config(&project_dir)?;
+    let study = Path::new(export)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "study".to_string());
+    let defaults = project.defaults_for(Some(&study));
+    let rest_args = profiles::translate(&version, &merge_args(&defaults.args, rest_args))?;
+    let mut docker_args = defaults.docker_args.clone();
+    docker_args.extend(license::docker_args(&read_config()?.license_env, &project.license.env));
+    docker_args.extend(env_args(&read_config()?.forward_env)?);
+
+    let resources = Resources::of(&parsed, &rest_args)?;
+    let run_id = history::new_run_id();
+    let job_name = options.job_name.clone().unwrap_or(study);
+    let aster: Vec<String> = rest_args.iter().map(|a| shell_quote(a)).collect();
+    let script = format!("source /opt/activate.sh && run_aster {} {}", aster.join(" "), shell_quote(export));
+
+    let mut directives = vec![
+        format!("--job-name={}", job_name),
+        format!("--output={}-%j.out", job_name),
+    ];
+    directives.extend(resources.directives());
+    directives.extend(options.partition.iter().map(|p| format!("--partition={}", p)));
+    directives.extend(options.account.iter().map(|a| format!("--account={}", a)));
+    let mut batch = String::from("#!/bin/bash\n");
+    for directive in &directives {
+        batch.push_str(&format!("#SBATCH {}\n", directive));
+    }
+    batch.push_str(&format!(
+        "\n# Written by cave {}: run {} of code_aster {}\n",
+        env!("CARGO_PKG_VERSION"),
+        run_id,
+        version
+    ));
+    batch.push_str(&container_command(&version, &current_dir, &run_id, &docker_args, &script)?);
+    batch.push('\n');
+
+    if options.dry_run {
+        print!("{}", batch);
+        return Ok(());
+    }
+
+    let mut cmd = Command::new("sbatch");
+    cmd.arg("--parsable").args(&options.sbatch_args);
+    cmdlog::record(&cmd);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                CaveError::SlurmError("sbatch not found on this host".to_string())
+            } else {
+                CaveError::IoError(e)
+            }
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(batch.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(CaveError::SlurmError(format!(
+            "sbatch: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // `--parsable` prints `<job id>` or `<job id>;<cluster>`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let job_id = stdout.trim().split(';').next().unwrap_or_default().to_string();
+    debug!("Job Slurm {} soumis pour le run {}", job_id, run_id);
+
+    let submission = Submission {
+        job_id: job_id.clone(),
+        job_name: job_name.clone(),
+        run_id,
+        version: version.clone(),
+        export_file: export.clone(),
+        working_dir: current_dir.display().to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+    if let Err(e) = record(&submission) {
+        debug!("Impossible d'enregistrer le job {}: {}", job_id, e);
+    }
+    println!("{} job {} ({}, code_aster {})", "Submitted".green().bold(), job_id, job_name, version);
+    println!("Follow it with `cave submit status {}`, its output goes to {}-{}.out", job_id, job_name, job_id);
+    Ok(())
+}
+
+/// Returns the state and elapsed time of the jobs `ids`, from the queue,
+/// then from the accounting for the jobs that left it.
+///
+/// # Errors
+/// Returns [`CaveError::SlurmError`] if `squeue` is missing.
+fn job_states(ids: &[&str]) -> Result<HashMap<String, (String, String)>, CaveError> {
+    let parse = |output: String| -> Vec<(String, (String, String))> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.trim().split('|');
+                let id = fields.next()?.to_string();
+                Some((id, (fields.next()?.to_string(), fields.next().unwrap_or_default().to_string())))
+            })
+            .collect()
+    };
+    let jobs = ids.join(",");
+    let mut states: HashMap<String, (String, String)> = parse(slurm_output(
+        Command::new("squeue").args(["--noheader", "--format=%i|%T|%M", "--jobs", &jobs]),
+    )?)
+    .into_iter()
+    .collect();
+    let left: Vec<&str> = ids.iter().copied().filter(|id| !states.contains_key(*id)).collect();
+    if !left.is_empty() {
+        let accounting = slurm_output(
+            Command::new("sacct").args([
+                "--noheader",
+                "--parsable2",
+                "--allocations",
+                "--format=JobID,State,Elapsed",
+                "--jobs",
+                &left.join(","),
+            ]),
+        );
+        match accounting {
+            // sacct prints `CANCELLED by <uid>` for cancelled jobs
+            Ok(output) => states.extend(parse(output).into_iter().map(|(id, (state, elapsed))| {
+                (id, (state.split_whitespace().next().unwrap_or_default().to_string(), elapsed))
+            })),
+            Err(e) => debug!("Comptabilité Slurm indisponible: {}", e),
+        }
+    }
+    Ok(states)
+}
+
+/// Returns the color of a Slurm job state.
+fn state_color(state: &str) -> Color {
+    match state {
+        "PENDING" | "CONFIGURING" | "SUSPENDED" => Color::Yellow,
+        "RUNNING" | "COMPLETING" => Color::Blue,
+        "COMPLETED" => Color::Green,
+        _ => Color::Red,
+    }
+}
+
+/// Handler of `cave submit status`: prints the state of the job `job`,
+/// given by its ID or name, or of the recently submitted jobs.
+///
+/// # Errors
+/// - [`CaveError::SlurmError`] if `squeue` is missing, or no submitted job matches `job`.
+pub fn status(job: Option<&str>) -> Result<(), CaveError> {
+    let submissions: Vec<Submission> = read_submissions()?
+        .into_iter()
+        .rev()
+        .filter(|s| job.is_none_or(|job| s.job_id == job || s.job_name == job))
+        .take(STATUS_LIMIT)
+        .collect();
+    if submissions.is_empty() {
+        return match job {
+            Some(job) => Err(CaveError::SlurmError(format!("no job {} submitted with `cave submit`", job))),
+            None => {
+                println!("No job submitted with `cave submit`.");
+                Ok(())
+            }
+        };
+    }
+    let ids: Vec<&str> = submissions.iter().map(|s| s.job_id.as_str()).collect();
+    let states = job_states(&ids)?;
+
+    let mut table = Table::new(&["Job", "Name", "State", "Elapsed", "Submitted", "Version", "Directory"]);
+    for submission in &submissions {
+        let date = chrono::DateTime::parse_from_rfc3339(&submission.timestamp)
+            .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| submission.timestamp.clone());
+        let (state, elapsed) = states
+            .get(&submission.job_id)
+            .cloned()
+            .unwrap_or_else(|| ("UNKNOWN".to_string(), String::new()));
+        let color = state_color(&state);
+        table.push(vec![
+            Cell::from(submission.job_id.as_str()).bold(),
+            submission.job_name.as_str().into(),
+            Cell::from(state).color(color).bold(),
+            elapsed.into(),
+            date.into(),
+            submission.version.as_str().into(),
+            submission.working_dir.as_str().into(),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Image sources of the code_aster versions.
+//!
+//! Versions are looked up and pulled from the repositories of the
+//! `image_sources` setting (`cave config set-image-sources`), in order, so
+//! that air-gapped sites can use a mirror of `simvia/code_aster` (Harbor,
+//! Artifactory, ...) before, or instead of, Docker Hub. A source is either a
+//! Docker Hub repository (`simvia/code_aster`) or a repository on a registry
+//! host (`harbor.internal/code_aster`), queried with the Docker Registry API.
+//!
+//! Whatever the source, installed versions are tagged
+//! `simvia/code_aster:<version>`; the source each one came from is recorded
+//! in `~/.cave.d/sources.json`.
+
+use crate::config::{read_config, state_dir, HubBudget};
+use crate::docker::{hub_has_tag, hub_versions, registry_has_tag, registry_tags};
+use crate::manage::CaveError;
+use crate::progress::Spinner;
+use log::debug;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Serializes the updates of the record, for the versions pulled in parallel
+/// by `cave pull`.
+static RECORD: Mutex<()> = Mutex::new(());
+
+/// The repository of the published images, on Docker Hub.
+pub const DEFAULT_SOURCE: &str = "simvia/code_aster";
+
+/// Returns the configured sources, in order.
+pub fn configured() -> Vec<String> {
+    let sources = read_config().map(|c| c.image_sources).unwrap_or_default();
+    if sources.is_empty() {
+        vec![DEFAULT_SOURCE.to_string()]
+    } else {
+        sources
+    }
+}
+
+/// Splits a repository into its registry host, if any, and its path. As for
+/// Docker, the first component is a host when it contains a `.` or a `:`,
+/// or is `localhost`.
+fn split_host(source: &str) -> (Option<&str>, &str) {
+    match source.split_once('/') {
+        Some((host, path))
+            if host != "docker.io" && (host.contains(['.', ':']) || host == "localhost") =>
+        {
+            (Some(host), path)
+        }
+        Some(("docker.io", path)) => (None, path),
+        _ => (None, source),
+    }
+}
+
+/// Returns an image reference qualified with its registry, `docker.io` for
+/// Docker Hub, for the tools that do not resolve short names (Podman,
+/// Apptainer).
+pub fn qualified(image: &str) -> String {
+    match split_host(image) {
+        (Some(_), _) => image.to_string(),
+        (None, path) if path.contains('/') => format!("docker.io/{}", path),
+        (None, path) => format!("docker.io/library/{}", path),
+    }
+}
+
+/// Host of the Docker Registry API of Docker Hub.
+const HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// Returns the Docker Hub repository of a source, `None` for a source on
+/// another registry.
+pub fn hub_repository(source: &str) -> Option<String> {
+    match split_host(source) {
+        (Some(_), _) => None,
+        (None, path) if path.contains('/') => Some(path.to_string()),
+        (None, path) => Some(format!("library/{}", path)),
+    }
+}
+
+/// Returns the registry host and repository of a source, for the Docker Registry API.
+pub fn registry(source: &str) -> (String, String) {
+    match (split_host(source), hub_repository(source)) {
+        (_, Some(repo)) => (HUB_REGISTRY.to_string(), repo),
+        ((host, path), None) => (host.unwrap_or(HUB_REGISTRY).to_string(), path.to_string()),
+    }
+}
+
+/// Returns the tags of a source with their push date, `unknown` when the
+/// registry does not tell it, the Docker Hub listings within `budget`.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the source cannot be reached.
+pub fn versions(source: &str, budget: &HubBudget) -> Result<Vec<(String, String)>, CaveError> {
+    let _spinner = Spinner::start(format!("Listing the versions of {}", source));
+    match split_host(source) {
+        (Some(host), path) => Ok(registry_tags(host, path, None)?
+            .into_iter()
+            .map(|tag| (tag, "unknown".to_string()))
+            .collect()),
+        (None, path) if path.contains('/') => hub_versions(path, budget),
+        (None, path) => hub_versions(&format!("library/{}", path), budget),
+    }
+}
+
+/// Checks if a source has a version, looking up this tag only.
+///
+/// # Errors
+/// Returns [`CaveError::HttpError`] if the source cannot be reached.
+pub fn has(source: &str, version: &str) -> Result<bool, CaveError> {
+    let _spinner = Spinner::start(format!("Looking up {} in {}", version, source));
+    match split_host(source) {
+        (Some(host), path) => registry_has_tag(host, path, version, None),
+        (None, path) if path.contains('/') => hub_has_tag(path, version),
+        (None, path) => hub_has_tag(&format!("library/{}", path), version),
+    }
+}
+
+/// Returns the first source having a version. Unreachable sources are skipped.
+pub fn find(version: &str) -> Option<String> {
+    configured()
+        .into_iter()
+        .find(|source| match has(source, version) {
+            Ok(found) => found,
+            Err(e) => {
+                debug!("Source {} injoignable: {}", source, e);
+                false
+            }
+        })
+}
+
+fn record_path() -> Result<PathBuf, CaveError> {
+    Ok(state_dir()?.join("sources.json"))
+}
+
+/// Returns the source of each installed version, for the versions pulled
+/// since sources are recorded.
+pub fn installed() -> Result<BTreeMap<String, String>, CaveError> {
+    match fs::read_to_string(record_path()?) {
+        Ok(content) => serde_json::from_str(&content).map_err(CaveError::SerdeError),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(CaveError::IoError(e)),
+    }
+}
+
+fn write(sources: &BTreeMap<String, String>) -> Result<(), CaveError> {
+    let path = record_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let content = serde_json::to_string_pretty(sources).map_err(CaveError::SerdeError)?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Records the source a version was pulled from.
+pub fn record(version: &str, source: &str) -> Result<(), CaveError> {
+    let _guard = RECORD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut sources = installed()?;
+    sources.insert(version.to_string(), source.to_string());
+    write(&sources)
+}
+
+/// Forgets the source of a removed version.
+pub fn forget(version: &str) -> Result<(), CaveError> {
+    let _guard = RECORD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut sources = installed()?;
+    if sources.remove(version).is_some() {
+        write(&sources)?;
+    }
+    Ok(())
+}
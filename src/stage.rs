@@ -0,0 +1,146 @@
+//! Network filesystem detection and local staging of study directories.
+//!
+//! Running code_aster on an NFS/SMB mounted study directory frequently leads
+//! to locking errors and very poor I/O performance inside the container.
+//! This module detects such mounts and provides [`StagedDir`], which copies
+//! the study into a local scratch directory and copies the results back.
+
+use crate::manage::CaveError;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Filesystem types considered as network filesystems.
+const NETWORK_FS_TYPES: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "fuse.sshfs", "afs", "9p", "ceph", "glusterfs",
+];
+
+/// Returns the filesystem type of `path` if it lives on a network filesystem.
+///
+/// The mount table is read from `/proc/self/mounts`, the longest mount point
+/// containing `path` wins. Always returns `None` on non-Linux systems.
+pub fn network_filesystem(path: &Path) -> Option<String> {
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    let path = path.canonicalize().ok()?;
+
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _source = fields.next()?;
+            let target = fields.next()?.replace("\\040", " ");
+            let fstype = fields.next()?;
+            Some((PathBuf::from(target), fstype.to_string()))
+        })
+        .filter(|(target, _)| path.starts_with(target))
+        .max_by_key(|(target, _)| target.components().count())
+        .map(|(_, fstype)| fstype)
+        .filter(|fstype| NETWORK_FS_TYPES.contains(&fstype.as_str()))
+}
+
+/// A copy of a study directory in a local scratch directory.
+///
+/// The scratch directory is removed when the value is dropped.
+pub struct StagedDir {
+    origin: PathBuf,
+    scratch: PathBuf,
+    snapshot: Vec<(PathBuf, u64, Option<SystemTime>)>,
+}
+
+impl StagedDir {
+    /// Copies `origin` into a new scratch directory under the system temp dir.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::IoError`] if the copy fails.
+    pub fn create(origin: &Path) -> Result<Self, CaveError> {
+        let scratch = std::env::temp_dir().join(format!("cave-stage-{}", Uuid::new_v4()));
+        debug!("Copie de {} vers {}", origin.display(), scratch.display());
+        copy_tree(origin, &scratch)?;
+        let snapshot = list_files(&scratch)?
+            .into_iter()
+            .map(|rel| {
+                let meta = fs::metadata(scratch.join(&rel)).ok();
+                let size = meta.as_ref().map_or(0, |m| m.len());
+                let modified = meta.and_then(|m| m.modified().ok());
+                (rel, size, modified)
+            })
+            .collect();
+        Ok(Self {
+            origin: origin.to_path_buf(),
+            scratch,
+            snapshot,
+        })
+    }
+
+    /// Path of the local scratch copy, to be mounted in the container.
+    pub fn path(&self) -> &Path {
+        &self.scratch
+    }
+
+    /// Copies back every file created or modified in the scratch directory.
+    ///
+    /// Returns the number of files copied.
+    pub fn sync_back(&self) -> Result<usize, CaveError> {
+        let mut copied = 0;
+        for rel in list_files(&self.scratch)? {
+            let src = self.scratch.join(&rel);
+            let meta = fs::metadata(&src)?;
+            let unchanged = self.snapshot.iter().any(|(p, size, modified)| {
+                p == &rel && *size == meta.len() && *modified == meta.modified().ok()
+            });
+            if unchanged {
+                continue;
+            }
+            let dst = self.origin.join(&rel);
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&src, &dst)?;
+            copied += 1;
+        }
+        Ok(copied)
+    }
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.scratch);
+    }
+}
+
+/// Copies the directory `src` to `dst`, recursively.
+pub fn copy_tree(src: &Path, dst: &Path) -> Result<(), CaveError> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the regular files under `root`, as paths relative to `root`.
+pub fn list_files(root: &Path) -> Result<Vec<PathBuf>, CaveError> {
+    let mut files = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(rel) = stack.pop() {
+        for entry in fs::read_dir(root.join(&rel))? {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                stack.push(child);
+            } else if file_type.is_file() {
+                files.push(child);
+            }
+        }
+    }
+    Ok(files)
+}
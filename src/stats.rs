@@ -0,0 +1,292 @@
+//! Utilization of the machine by the runs (`cave history stats`).
+//!
+//! The runs of the history are laid on a timeline, from their start to their
+//! end, to tell per day or week how many solver-hours were run, how long
+//! at least one run was going on, and how many runs were going on at the same
+//! time at most. A solver-hour is an hour of a run, whatever its number of
+//! processes: two runs of an hour side by side make two solver-hours in one
+//! busy hour.
+//!
+//! Only the runs recorded in the history of this machine and user are
+//! counted; detached runs, whose end is not recorded, are left out.
+
+use crate::format;
+use crate::history::{read_all, RunRecord};
+use crate::manage::CaveError;
+use crate::table::{Cell, Table};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+use colored::*;
+use std::collections::BTreeMap;
+
+/// Width of the solver-hours bars, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Length of the periods the runs are summed over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+impl Period {
+    /// Parses `day` or `week`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            _ => None,
+        }
+    }
+
+    /// Returns the first day of the period holding `date`, weeks starting on Monday.
+    fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => date,
+            Period::Week => date - Duration::days(i64::from(date.weekday().num_days_from_monday())),
+        }
+    }
+
+    fn days(self) -> i64 {
+        match self {
+            Period::Day => 1,
+            Period::Week => 7,
+        }
+    }
+
+    fn label(self, start: NaiveDate) -> String {
+        match self {
+            Period::Day => start.format("%Y-%m-%d").to_string(),
+            Period::Week => format!("{} (W{:02})", start.format("%Y-%m-%d"), start.iso_week().week()),
+        }
+    }
+}
+
+/// Use of the machine over a period.
+#[derive(Debug, Default, Clone)]
+struct Usage {
+    /// Runs started in the period.
+    runs: usize,
+    /// Sum of the durations of the runs, in seconds.
+    solver_secs: f64,
+    /// Time at least one run was going on, in seconds.
+    busy_secs: f64,
+    /// Highest number of runs going on at the same time.
+    peak: usize,
+}
+
+/// Returns the local midnight starting `date`.
+fn midnight(date: NaiveDate) -> DateTime<Local> {
+    let naive = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&naive))
+}
+
+/// Returns the start and end of a run, `None` for the detached runs.
+fn interval(record: &RunRecord) -> Option<(DateTime<Local>, DateTime<Local>)> {
+    if record.detached {
+        return None;
+    }
+    let start = DateTime::parse_from_rfc3339(&record.timestamp).ok()?.with_timezone(&Local);
+    Some((start, start + Duration::milliseconds(record.duration_ms as i64)))
+}
+
+/// Sums the use of the machine per period from `since`, and the time spent
+/// at each number of concurrent runs.
+fn usage(
+    records: &[RunRecord],
+    period: Period,
+    since: DateTime<Local>,
+) -> (BTreeMap<NaiveDate, Usage>, BTreeMap<usize, f64>) {
+    let mut periods: BTreeMap<NaiveDate, Usage> = BTreeMap::new();
+    let mut levels: BTreeMap<usize, f64> = BTreeMap::new();
+
+    // Runs ending at an instant are counted out before the ones starting then
+    let mut events: Vec<(DateTime<Local>, i32)> = Vec::new();
+    let now = Local::now();
+    for (start, end) in records.iter().filter_map(interval) {
+        let end = end.min(now);
+        if end <= since {
+            continue;
+        }
+        if start >= since {
+            periods.entry(period.start(start.date_naive())).or_default().runs += 1;
+        }
+        events.push((start.max(since), 1));
+        events.push((end, -1));
+    }
+    events.sort();
+
+    let mut running = 0usize;
+    for pair in events.windows(2) {
+        let ((time, change), (next, _)) = (pair[0], pair[1]);
+        running = (running as i64 + i64::from(change)).max(0) as usize;
+        if running == 0 || next <= time {
+            continue;
+        }
+        // The segment may cross the end of periods
+        let mut from = time;
+        while from < next {
+            let start = period.start(from.date_naive());
+            let boundary = midnight(start + Duration::days(period.days()));
+            let to = next.min(boundary);
+            let secs = (to - from).num_milliseconds() as f64 / 1000.0;
+            let usage = periods.entry(start).or_default();
+            usage.solver_secs += secs * running as f64;
+            usage.busy_secs += secs;
+            usage.peak = usage.peak.max(running);
+            *levels.entry(running).or_default() += secs;
+            from = to;
+        }
+    }
+    (periods, levels)
+}
+
+fn hours(secs: f64) -> String {
+    format::decimal(secs / 3600.0, 1)
+}
+
+/// Handler of `cave history stats`: prints the use of the machine by the
+/// runs of the last `days` days, per `period`, as a table with bars or as
+/// CSV.
+///
+/// # Errors
+/// Returns [`CaveError::IoError`] if the history cannot be read.
+pub fn print(period: Period, days: u32, csv: bool) -> Result<(), CaveError> {
+    let today = Local::now().date_naive();
+    let first = period.start(today - Duration::days(i64::from(days.max(1)) - 1));
+    let (mut periods, levels) = usage(&read_all()?, period, midnight(first));
+
+    // Idle periods are listed too, for the view to follow the calendar
+    let mut start = first;
+    while start <= today {
+        periods.entry(start).or_default();
+        start += Duration::days(period.days());
+    }
+
+    if csv {
+        println!("period_start,runs,solver_hours,busy_hours,peak_concurrent_runs");
+        for (start, usage) in &periods {
+            println!(
+                "{},{},{:.2},{:.2},{}",
+                start,
+                usage.runs,
+                usage.solver_secs / 3600.0,
+                usage.busy_secs / 3600.0,
+                usage.peak
+            );
+        }
+        return Ok(());
+    }
+
+    let busiest = periods.values().map(|u| u.solver_secs).fold(0.0, f64::max);
+    let mut table = Table::new(&["Period", "Runs", "Solver-hours", "Busy hours", "Peak", ""]);
+    for (start, usage) in &periods {
+        let filled = if busiest > 0.0 {
+            (usage.solver_secs / busiest * BAR_WIDTH as f64).round() as usize
+        } else {
+            0
+        };
+        let peak = match usage.peak {
+            0 => Cell::from(""),
+            1 => Cell::from("1"),
+            peak => Cell::from(peak.to_string()).color(Color::Yellow).bold(),
+        };
+        table.push(vec![
+            period.label(*start).into(),
+            format::count(usage.runs as u64).into(),
+            hours(usage.solver_secs).into(),
+            hours(usage.busy_secs).into(),
+            peak,
+            "#".repeat(filled).into(),
+        ]);
+    }
+    table.print();
+
+    let busy: f64 = levels.values().sum();
+    if busy == 0.0 {
+        println!();
+        println!("No run in the last {} days.", days);
+        return Ok(());
+    }
+    let solver: f64 = periods.values().map(|u| u.solver_secs).sum();
+    println!();
+    println!(
+        "{} solver-hours in {} busy hours, {} runs at a time on average when busy.",
+        hours(solver),
+        hours(busy),
+        format::decimal(solver / busy, 2)
+    );
+    let shares: Vec<String> = levels
+        .iter()
+        .map(|(level, secs)| {
+            format!(
+                "{} {}: {} h ({}%)",
+                level,
+                if *level == 1 { "run" } else { "runs" },
+                hours(*secs),
+                format::decimal(secs / busy * 100.0, 0)
+            )
+        })
+        .collect();
+    println!("Time at each number of concurrent runs: {}.", shares.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(start: (u32, u32), hours: i64) -> RunRecord {
+        let (day, hour) = start;
+        let start = Local.with_ymd_and_hms(2024, 6, day, hour, 0, 0).unwrap();
+        RunRecord {
+            timestamp: start.to_rfc3339(),
+            duration_ms: (hours * 3_600_000) as u64,
+            ..Default::default()
+        }
+    }
+
+    fn since() -> DateTime<Local> {
+        midnight(NaiveDate::from_ymd_opt(2024, 6, 1).unwrap())
+    }
+
+    #[test]
+    fn sums_overlapping_runs() {
+        let records = vec![run((4, 10), 2), run((4, 11), 2)];
+        let (periods, levels) = usage(&records, Period::Day, since());
+        let day = &periods[&NaiveDate::from_ymd_opt(2024, 6, 4).unwrap()];
+        assert_eq!(day.runs, 2);
+        assert_eq!(day.solver_secs, 4.0 * 3600.0);
+        assert_eq!(day.busy_secs, 3.0 * 3600.0);
+        assert_eq!(day.peak, 2);
+        assert_eq!(levels[&1], 2.0 * 3600.0);
+        assert_eq!(levels[&2], 3600.0);
+    }
+
+    #[test]
+    fn splits_runs_across_periods() {
+        let mut detached = run((4, 10), 1);
+        detached.detached = true;
+        let records = vec![run((4, 23), 2), detached];
+        let (periods, _) = usage(&records, Period::Day, since());
+        assert_eq!(periods.len(), 2);
+        assert_eq!(periods[&NaiveDate::from_ymd_opt(2024, 6, 4).unwrap()].busy_secs, 3600.0);
+        assert_eq!(periods[&NaiveDate::from_ymd_opt(2024, 6, 5).unwrap()].busy_secs, 3600.0);
+        assert_eq!(periods[&NaiveDate::from_ymd_opt(2024, 6, 5).unwrap()].runs, 0);
+
+        // Weeks start on Monday
+        let (weeks, _) = usage(&records, Period::Week, since());
+        let week = &weeks[&NaiveDate::from_ymd_opt(2024, 6, 3).unwrap()];
+        assert_eq!(week.runs, 1);
+        assert_eq!(week.solver_secs, 2.0 * 3600.0);
+    }
+
+    #[test]
+    fn leaves_out_the_runs_before_the_start() {
+        let records = vec![run((1, 0), 1), run((1, 0), 1)];
+        let (periods, _) = usage(&records, Period::Day, midnight(NaiveDate::from_ymd_opt(2024, 6, 2).unwrap()));
+        assert!(periods.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! One-screen summary of the environment (`cave status`).
+//!
+//! Unlike `cave doctor`, nothing is judged: each line shows where things
+//! stand (selected version, runtime, jobs, images, caches, telemetry, cave
+//! updates), and a line that cannot be determined says so instead of
+//! failing the command.
+
+use crate::answers;
+use crate::apptainer;
+use crate::catalog;
+use crate::config::{offline, read_config, read_only_home, state_dir};
+use crate::docker::{
+    ensure_docker, exists_locally, images_size, local_versions, runtime, uses_apptainer,
+};
+use crate::format;
+use crate::history;
+use crate::jobs::{release_finished, running_jobs};
+use crate::manage::{latest_release, pin_files, CaveError};
+use colored::*;
+use semver::Version;
+use std::collections::HashSet;
+
+fn line(name: &str, value: impl std::fmt::Display) {
+    println!("{:<12}{}", name.bold(), value);
+}
+
+fn unavailable(e: &CaveError) -> String {
+    format!("unknown ({})", e).dimmed().to_string()
+}
+
+fn on_off(value: bool) -> ColoredString {
+    if value {
+        "on".green()
+    } else {
+        "off".normal()
+    }
+}
+
+/// Handler of `cave status`: prints the pinned versions, the container
+/// runtime, the running jobs, the disk used by the images, the freshness of
+/// the version catalog, the telemetry settings and whether a cave update is
+/// available.
+///
+/// # Errors
+/// Returns [`CaveError::HomeNotFound`] if the home directory cannot be found.
+pub fn status() -> Result<(), CaveError> {
+    let docker = ensure_docker();
+
+    // Pinned versions, the last one being selected
+    let home = dirs::home_dir().ok_or(CaveError::HomeNotFound)?;
+    let pins = match pin_files() {
+        Ok(pins) if pins.is_empty() => {
+            line("Version", "none, run `cave use <version>`".yellow());
+            pins
+        }
+        Ok(pins) => pins,
+        Err(e) => {
+            line("Version", unavailable(&e));
+            Vec::new()
+        }
+    };
+    for (i, (path, version)) in pins.iter().enumerate() {
+        let scope = if *path == home.join(".cave") {
+            "Global"
+        } else {
+            "Local"
+        };
+        let mut value = format!("{} ({})", version.bold(), path.display());
+        if i + 1 < pins.len() {
+            value = format!("{} {}", value, "shadowed".dimmed());
+        } else if docker.is_ok() && !exists_locally(version).unwrap_or(true) {
+            value = format!("{} {}", value, "not installed".yellow());
+        }
+        line(scope, value);
+    }
+
+    // Runtime
+    let binary = if uses_apptainer() {
+        apptainer::binary().unwrap_or("apptainer")
+    } else {
+        runtime().binary()
+    };
+    match &docker {
+        Ok(()) => line("Runtime", binary),
+        Err(e) => line(
+            "Runtime",
+            format!("{} {}", binary, format!("({})", e).red()),
+        ),
+    }
+
+    if docker.is_ok() {
+        // Jobs, the detached ones being found in the history
+        if uses_apptainer() {
+            line("Jobs", "not tracked with Apptainer".dimmed());
+        } else {
+            match running_jobs() {
+                Ok(jobs) => {
+                    release_finished(&jobs);
+                    let detached: HashSet<String> = history::read_all()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|record| record.detached)
+                        .map(|record| record.id)
+                        .collect();
+                    let runs: Vec<_> = jobs.iter().filter(|job| job.service.is_empty()).collect();
+                    let background = runs
+                        .iter()
+                        .filter(|job| detached.contains(&job.run_id))
+                        .count();
+                    line(
+                        "Jobs",
+                        format!("{} running, {} in the background", runs.len(), background),
+                    );
+                }
+                Err(e) => line("Jobs", unavailable(&e)),
+            }
+        }
+
+        // Images
+        match local_versions().and_then(|versions| Ok((versions.len(), images_size()?))) {
+            Ok((count, size)) => line(
+                "Images",
+                format!("{} versions, {}", format::count(count as u64), format::size(size)),
+            ),
+            Err(e) => line("Images", unavailable(&e)),
+        }
+    }
+
+    // Catalog of the published versions
+    match catalog::age() {
+        Some(age) if age < catalog::max_age() => {
+            line("Catalog", format!("updated {}", format::age(age)))
+        }
+        Some(age) => line(
+            "Catalog",
+            format!(
+                "updated {} {}",
+                format::age(age),
+                "(refreshed on next use)".dimmed()
+            ),
+        ),
+        None => line("Catalog", "not fetched yet".dimmed()),
+    }
+
+    if read_only_home() {
+        match state_dir() {
+            Ok(dir) => line(
+                "Home",
+                format!("read-only, state kept in {} until cave exits", dir.display()).yellow(),
+            ),
+            Err(e) => line("Home", unavailable(&e)),
+        }
+    }
+
+    // Telemetry
+    match read_config() {
+        Ok(config) => line(
+            "Telemetry",
+            format!(
+                "usage {}, resource usage {}, sinks {}",
+                on_off(answers::usage_tracking().unwrap_or(config.version_tracking)),
+                on_off(answers::resource_tracking().unwrap_or(config.share_resource_usage)),
+                if config.telemetry_sinks.is_empty() {
+                    "none".to_string()
+                } else {
+                    config.telemetry_sinks.join(" ")
+                }
+            ),
+        ),
+        Err(e) => line("Telemetry", unavailable(&e)),
+    }
+
+    // cave itself
+    let current = env!("CARGO_PKG_VERSION");
+    if offline() {
+        line("cave", format!("{} {}", current, "(offline, update check skipped)".dimmed()));
+        return Ok(());
+    }
+    match latest_release() {
+        Ok(latest) if Version::parse(current).is_ok_and(|current| latest > current) => line(
+            "cave",
+            format!("{} {}", current, format!("({} available)", latest).yellow()),
+        ),
+        Ok(_) => line("cave", format!("{} (up to date)", current)),
+        Err(_) => line(
+            "cave",
+            format!("{} {}", current, "(update check failed)".dimmed()),
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Support bundles for bug reports (`cave support-bundle`).
+//!
+//! The bundle is a zip of what Simvia needs to reproduce a problem: the
+//! outcome of the `cave doctor` checks, how the version was resolved from
+//! the `.cave` pin files, the container runtime versions, the configuration,
+//! and the history record, log tail and export file of a failed run.
+//!
+//! Secrets are redacted from every file of the bundle: the registry token,
+//! the license server variables, the forwarded environment variables and the
+//! telemetry user ID. The home directory is written `~`.
+
+use crate::apptainer;
+use crate::cmdlog::Logged;
+use crate::config::{read_config, read_only_home, Config};
+use crate::docker::{exists_locally, image_digest, image_id, runtime, uses_apptainer};
+use crate::doctor;
+use crate::format;
+use crate::history::{self, RunRecord};
+use crate::manage::{pin_files, CaveError};
+use crate::output;
+use crate::progress::Spinner;
+use crate::sources;
+use chrono::Local;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Text replacing the redacted values.
+const REDACTED: &str = "<redacted>";
+/// Shortest secret redacted from free text, shorter values would redact unrelated text.
+const MIN_SECRET_LEN: usize = 4;
+
+/// Returns the values to redact from the bundle.
+fn secrets(config: &Config) -> Vec<String> {
+    let mut secrets = vec![config.user_id.clone()];
+    if let Some(registry) = &config.registry {
+        secrets.push(registry.token.clone());
+    }
+    secrets.extend(config.license_env.values().cloned());
+    for variable in &config.forward_env {
+        match variable.split_once('=') {
+            Some((_, value)) => secrets.push(value.to_string()),
+            None => secrets.extend(env::var(variable).ok()),
+        }
+    }
+    secrets.retain(|s| s.len() >= MIN_SECRET_LEN);
+    // Longest first, so that a secret containing another is redacted whole
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    secrets
+}
+
+/// Replaces the secrets of `text`, and the home directory by `~`.
+fn redact(text: &str, secrets: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    match dirs::home_dir().map(|home| home.display().to_string()) {
+        Some(home) if home.len() > 1 => text.replace(&home, "~"),
+        _ => text,
+    }
+}
+
+/// Returns the configuration with its secret fields redacted.
+fn redacted_config(config: &Config) -> Result<String, CaveError> {
+    let mut config = config.clone();
+    config.user_id = REDACTED.to_string();
+    if let Some(registry) = &mut config.registry {
+        registry.token = REDACTED.to_string();
+    }
+    for value in config.license_env.values_mut() {
+        *value = REDACTED.to_string();
+    }
+    for variable in &mut config.forward_env {
+        if let Some((name, _)) = variable.split_once('=') {
+            *variable = format!("{}={}", name, REDACTED);
+        }
+    }
+    Ok(serde_json::to_string_pretty(&config)?)
+}
+
+/// Describes how the version of the current directory is resolved.
+fn version_trace() -> Result<String, CaveError> {
+    let pins = pin_files()?;
+    let mut lines = Vec::new();
+    if pins.is_empty() {
+        lines.push("No version selected: no ~/.cave nor .cave file.".to_string());
+    }
+    for (i, (path, version)) in pins.iter().enumerate() {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let state = if i + 1 < pins.len() { "shadowed" } else { "selected" };
+        lines.push(format!("{}: {} -> {} ({})", path.display(), content.trim(), version, state));
+    }
+    if let Some((_, version)) = pins.last() {
+        match exists_locally(version) {
+            Ok(true) => {
+                lines.push(format!("Installed: yes, image {}", image_id(version)?));
+                if let Some(digest) = image_digest(version) {
+                    lines.push(format!("Digest: {}", digest));
+                }
+                if let Some(source) = sources::installed()?.get(version) {
+                    lines.push(format!("Source: {}", source));
+                }
+            }
+            Ok(false) => lines.push("Installed: no".to_string()),
+            Err(e) => lines.push(format!("Installed: unknown ({})", e)),
+        }
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Returns the version output of the container runtime.
+fn runtime_versions() -> String {
+    let mut cmd = if uses_apptainer() {
+        let mut cmd = Command::new(apptainer::binary().unwrap_or("apptainer"));
+        cmd.arg("--version");
+        cmd
+    } else {
+        let mut cmd = runtime().command();
+        cmd.arg("version");
+        cmd
+    };
+    match cmd.logged_output() {
+        Ok(output) => format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(e) => format!("{}\n", e),
+    }
+}
+
+/// Returns the run to include: `run` if given, else the last failed run of
+/// the current directory, else its last run.
+fn find_run(run: Option<&str>) -> Result<Option<RunRecord>, CaveError> {
+    if let Some(id) = run {
+        return history::find(id).map(Some);
+    }
+    let current_dir = env::current_dir()?;
+    let runs: Vec<RunRecord> = history::read_all()?
+        .into_iter()
+        .filter(|r| Path::new(&r.working_dir) == current_dir)
+        .collect();
+    let failed = runs.iter().rev().find(|r| !r.success && !r.detached);
+    Ok(failed.or(runs.last()).cloned())
+}
+
+fn summary(record: Option<&RunRecord>) -> String {
+    let mut lines = vec![
+        format!("cave: {}", env!("CARGO_PKG_VERSION")),
+        format!("Date: {}", Local::now().format("%Y-%m-%d %H:%M:%S %z")),
+        format!("System: {} {}", env::consts::OS, env::consts::ARCH),
+        format!(
+            "Runtime: {}",
+            if uses_apptainer() {
+                apptainer::binary().unwrap_or("apptainer")
+            } else {
+                runtime().binary()
+            }
+        ),
+        format!("Read-only home: {}", if read_only_home() { "yes" } else { "no" }),
+    ];
+    match record {
+        Some(record) => lines.push(format!("Run: {} ({})", record.id, record.timestamp)),
+        None => lines.push("Run: none".to_string()),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Handler of `cave support-bundle`: writes a zip of the environment and of
+/// a failed run, to attach to bug reports.
+///
+/// # Errors
+/// - [`CaveError::RunNotFound`] if `run` matches no run.
+/// - [`CaveError::FileExists`] if `output` exists without `force`.
+/// - [`CaveError::IoError`] if `output` cannot be written.
+pub fn support_bundle(run: Option<String>, output: Option<String>, force: bool) -> Result<(), CaveError> {
+    let output = output.unwrap_or_else(|| format!("cave-support-{}.zip", Local::now().format("%Y%m%d-%H%M%S")));
+    if Path::new(&output).exists() && !force {
+        return Err(CaveError::FileExists {
+            file: output,
+            command: "cave support-bundle",
+        });
+    }
+    let record = find_run(run.as_deref())?;
+    let config = read_config()?;
+
+    let checks = {
+        let _spinner = Spinner::start("Checking the environment");
+        doctor::checks()?
+    };
+    let mut files: Vec<(String, String)> = vec![
+        ("summary.txt".to_string(), summary(record.as_ref())),
+        (
+            "doctor.txt".to_string(),
+            checks.iter().map(|c| c.line() + "\n").collect(),
+        ),
+        ("version.txt".to_string(), version_trace()?),
+        ("runtime.txt".to_string(), runtime_versions()),
+        ("config.json".to_string(), redacted_config(&config)?),
+    ];
+    if let Some(record) = &record {
+        files.push(("run.json".to_string(), serde_json::to_string_pretty(record)?));
+        let tail = match &record.log_tail {
+            Some(tail) if !tail.is_empty() => tail.clone(),
+            _ => output::log_tail(&record.id),
+        };
+        if !tail.is_empty() {
+            files.push(("run.log".to_string(), tail + "\n"));
+        }
+        let export = record.export_snapshot.clone().or_else(|| {
+            let file = record.export_file.as_ref()?;
+            fs::read_to_string(Path::new(&record.working_dir).join(file)).ok()
+        });
+        if let (Some(file), Some(export)) = (&record.export_file, export) {
+            let name = Path::new(file).file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+            files.push((format!("export/{}", name), export));
+        }
+    }
+
+    let secrets = secrets(&config);
+    let dir = Path::new(&output)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "cave-support".to_string());
+    let mut zip = ZipWriter::new(fs::File::create(&output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in &files {
+        zip.start_file(format!("{}/{}", dir, name), options).map_err(io::Error::from)?;
+        zip.write_all(redact(content, &secrets).as_bytes())?;
+    }
+    zip.finish().map_err(io::Error::from)?;
+
+    for (name, _) in &files {
+        println!("  {}", name);
+    }
+    let size = fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+    println!("Wrote {} ({})", output, format::size(size));
+    println!("Secrets of the configuration are redacted; check the log and export file before sharing it.");
+    Ok(())
+}
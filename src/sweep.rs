@@ -0,0 +1,548 @@
+//! Parametric sweeps (`cave sweep`).
+//!
+//! A study is run once per combination of parameter values. The values come
+//! from `--param NAME=V1,V2` options or from a sweep file, in YAML:
+//!
+//! ```yaml
+//! export: beam.export
+//! jobs: 2              # cases run at the same time
+//! retries: 1           # runs of a failed case again
+//! output: sweep        # directory of the cases
+//! parameters:
+//!   thickness: [1.0, 2.0, 5.0]
+//!   load: [100, 200]
+//! ```
+//!
+//! Each case gets a directory `<output>/case-NNN` holding a copy of the
+//! export file and of its inputs, where `{{ name }}` placeholders are
+//! replaced with the values of the case in the export and `.comm` files.
+//! The cases are run in their directory by the [`Orchestrator`], each holding
+//! the study lock of its directory, recorded in the history like other runs,
+//! and summed up in a table also written to `<output>/summary.csv`. A case
+//! run again after a failure is prepared again first.
+
+use crate::docker::{container_name, docker_aster, DockerMode, RunOptions};
+use crate::export::{Export, Severity};
+use crate::format;
+use crate::history;
+use crate::lock;
+use crate::manage::{ensure_installed, read_cave_version, CaveError};
+use crate::orchestrator::{Orchestrator, Outcome};
+use crate::output;
+use crate::project::{find_project_dir, read_project_config};
+use crate::signals;
+use crate::stage::copy_tree;
+use crate::table::{Cell, Table};
+use colored::*;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Default directory of the cases.
+const DEFAULT_OUTPUT: &str = "sweep";
+/// File listing the cases and their outcome, in the output directory.
+const SUMMARY_FILE: &str = "summary.csv";
+/// File holding the parameter values, in each case directory.
+const PARAMETERS_FILE: &str = "parameters.json";
+
+/// Options of `cave sweep`, those of the command line taking precedence
+/// over the sweep file.
+#[derive(Debug, Default)]
+pub struct SweepOptions {
+    /// Export file of the study.
+    pub export: Option<String>,
+    /// Parameters given as `NAME=V1,V2,...`.
+    pub params: Vec<String>,
+    /// Sweep file, in YAML.
+    pub file: Option<String>,
+    /// Number of cases run at the same time.
+    pub jobs: Option<usize>,
+    /// Number of times a failed case is run again.
+    pub retries: Option<u32>,
+    /// Stop starting cases after the first failure.
+    pub fail_fast: bool,
+    /// Directory of the cases.
+    pub output: Option<String>,
+    /// Replace the existing case directories.
+    pub force: bool,
+    /// Download the pinned version again without asking if its image was removed.
+    pub yes: bool,
+}
+
+/// Content of a sweep file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SweepFile {
+    export: Option<String>,
+    jobs: Option<usize>,
+    retries: Option<u32>,
+    output: Option<String>,
+    /// Values of each parameter, in the order of the file.
+    #[serde(default)]
+    parameters: serde_yaml::Mapping,
+}
+
+/// A combination of parameter values and its directory.
+#[derive(Debug)]
+struct Case {
+    name: String,
+    values: Vec<(String, String)>,
+    dir: PathBuf,
+}
+
+/// Run of a case, the last one when it was retried.
+#[derive(Debug)]
+struct CaseReport {
+    run_id: String,
+    duration_ms: u64,
+    /// Number of errors in the message file of the run.
+    errors: Option<usize>,
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap())
+}
+
+fn invalid(message: impl Into<String>) -> CaveError {
+    CaveError::InvalidSweep(message.into())
+}
+
+/// Parses a `NAME=V1,V2,...` option.
+fn parse_param(spec: &str) -> Result<(String, Vec<String>), CaveError> {
+    let (name, values) = spec
+        .split_once('=')
+        .ok_or_else(|| invalid(format!("'{}' is not of the form NAME=V1,V2,...", spec)))?;
+    Ok((
+        name.trim().to_string(),
+        values.split(',').map(|v| v.trim().to_string()).collect(),
+    ))
+}
+
+fn yaml_scalar(name: &str, value: &serde_yaml::Value) -> Result<String, CaveError> {
+    match value {
+        serde_yaml::Value::String(s) => Ok(s.clone()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(invalid(format!(
+            "the values of parameter '{}' must be strings, numbers or booleans",
+            name
+        ))),
+    }
+}
+
+/// Reads the parameters of a sweep file, in the order of the file.
+fn file_parameters(mapping: &serde_yaml::Mapping) -> Result<Vec<(String, Vec<String>)>, CaveError> {
+    let mut parameters = Vec::new();
+    for (name, values) in mapping {
+        let name = name
+            .as_str()
+            .ok_or_else(|| invalid("parameter names must be strings"))?
+            .to_string();
+        let values = match values {
+            serde_yaml::Value::Sequence(values) => values
+                .iter()
+                .map(|v| yaml_scalar(&name, v))
+                .collect::<Result<_, _>>()?,
+            value => vec![yaml_scalar(&name, value)?],
+        };
+        parameters.push((name, values));
+    }
+    Ok(parameters)
+}
+
+/// Returns every combination of values, the first parameter varying slowest.
+fn combinations(parameters: &[(String, Vec<String>)]) -> Vec<Vec<(String, String)>> {
+    let mut combinations = vec![Vec::new()];
+    for (name, values) in parameters {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.clone(), value.clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// Replaces the `{{ name }}` placeholders of `template` with the values of a case.
+fn render(template: &str, values: &[(String, String)], file: &str) -> Result<String, CaveError> {
+    let mut unknown = None;
+    let rendered = placeholder().replace_all(template, |caps: &regex::Captures| {
+        match values.iter().find(|(name, _)| *name == caps[1]) {
+            Some((_, value)) => value.clone(),
+            None => {
+                unknown.get_or_insert_with(|| caps[1].to_string());
+                caps[0].to_string()
+            }
+        }
+    });
+    match unknown {
+        Some(name) => Err(invalid(format!(
+            "{} uses the placeholder '{{{{ {} }}}}', which is not a parameter of the sweep",
+            file, name
+        ))),
+        None => Ok(rendered.into_owned()),
+    }
+}
+
+/// Returns the path of an export entry inside the case directory, or `None`
+/// for absolute paths, which are used as is.
+fn case_path(dir: &Path, path: &str) -> Result<Option<PathBuf>, CaveError> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Ok(None);
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(invalid(format!(
+            "'{}' is outside the study directory and cannot be copied to the cases",
+            path.display()
+        )));
+    }
+    Ok(Some(dir.join(path)))
+}
+
+/// Creates the directory of a case: the rendered export file, its inputs,
+/// with the `.comm` files rendered, and the directories of its outputs.
+fn prepare(case: &Case, export_file: &str, template: &str, force: bool) -> Result<(), CaveError> {
+    if case.dir.exists() {
+        if !force {
+            return Err(invalid(format!(
+                "{} already exists, use --force to replace it",
+                case.dir.display()
+            )));
+        }
+        fs::remove_dir_all(&case.dir)?;
+    }
+    fs::create_dir_all(&case.dir)?;
+
+    let content = render(template, &case.values, export_file)?;
+    fs::write(case.dir.join(export_file), &content)?;
+    let values: serde_json::Map<String, serde_json::Value> = case
+        .values
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone().into()))
+        .collect();
+    fs::write(
+        case.dir.join(PARAMETERS_FILE),
+        serde_json::to_string_pretty(&values)?,
+    )?;
+
+    let (export, _) = Export::parse(&content);
+    for entry in &export.entries {
+        let Some(target) = case_path(&case.dir, &entry.path)? else {
+            continue;
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let source = Path::new(&entry.path);
+        if !entry.input || !source.exists() {
+            continue;
+        }
+        if entry.directory {
+            copy_tree(source, &target)?;
+        } else if entry.kind == "comm" || entry.path.ends_with(".comm") {
+            let template = fs::read_to_string(source)?;
+            fs::write(&target, render(&template, &case.values, &entry.path)?)?;
+        } else {
+            fs::copy(source, &target)?;
+        }
+    }
+
+    let errors: Vec<String> = export
+        .validate(&case.dir)
+        .into_iter()
+        .filter(|p| p.severity == Severity::Error)
+        .map(|p| p.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(CaveError::InvalidExport {
+            file: case.dir.join(export_file).display().to_string(),
+            problems: errors,
+        });
+    }
+    Ok(())
+}
+
+/// Runs a case in its directory, holding its study lock.
+fn run_case(case: &Case, version: &str, export_file: &str, options: &RunOptions) -> (CaseReport, Result<(), CaveError>) {
+    let run_id = history::new_run_id();
+    let options = RunOptions {
+        host_dir: Some(case.dir.clone()),
+        run_id: Some(run_id.clone()),
+        ..options.clone()
+    };
+    let export = Some(export_file.to_string());
+    let result = lock::acquire(&case.dir, &container_name(&run_id, None), false).and_then(|_lock| {
+        docker_aster(version, DockerMode::RunAster { export_file: &export, args: &Vec::new() }, &options)
+    });
+    let record = history::find(&run_id).ok();
+    let report = CaseReport {
+        duration_ms: record.as_ref().map(|r| r.duration_ms).unwrap_or_default(),
+        errors: record.and_then(|r| r.results).map(|r| r.errors),
+        run_id,
+    };
+    (report, result)
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes the cases, their status and their run as CSV.
+fn write_summary(path: &Path, names: &[String], cases: &[Case], rows: &[(&str, u32, Option<&CaseReport>)]) -> Result<(), CaveError> {
+    let mut csv = String::from("case");
+    for name in names {
+        csv.push(',');
+        csv.push_str(&csv_field(name));
+    }
+    csv.push_str(",status,attempts,duration_ms,errors,run\n");
+    for (case, (status, attempts, report)) in cases.iter().zip(rows) {
+        let mut row = vec![case.name.clone()];
+        row.extend(case.values.iter().map(|(_, value)| csv_field(value)));
+        row.push(status.to_string());
+        row.push(attempts.to_string());
+        match report {
+            Some(report) => {
+                row.push(report.duration_ms.to_string());
+                row.push(report.errors.map(|e| e.to_string()).unwrap_or_default());
+                row.push(report.run_id.clone());
+            }
+            None => row.extend([String::new(), String::new(), String::new()]),
+        }
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    fs::write(path, csv)?;
+    Ok(())
+}
+
+/// Handler of `cave sweep`: runs a study once per combination of parameter
+/// values, in the directories of the cases, `jobs` at a time.
+///
+/// The parameters of the command line are added to those of the sweep file,
+/// replacing those with the same name.
+///
+/// # Errors
+/// - [`CaveError::InvalidSweep`] if the parameters or the sweep file are invalid,
+///   or a case directory already exists without `force`.
+/// - [`CaveError::InvalidExport`] if the export file of a case has errors.
+/// - [`CaveError::VersionNotInstalled`] if the configured version is not installed.
+/// - [`CaveError::SweepFailed`] if any case fails.
+pub fn run(options: SweepOptions) -> Result<(), CaveError> {
+    let SweepOptions { export, params, file, jobs, retries, fail_fast, output, force, yes } = options;
+    let sweep: SweepFile = match &file {
+        Some(path) => serde_yaml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| invalid(format!("{}: {}", path, e)))?,
+        None => SweepFile::default(),
+    };
+    let mut parameters = file_parameters(&sweep.parameters)?;
+    for spec in &params {
+        let (name, values) = parse_param(spec)?;
+        match parameters.iter_mut().find(|(n, _)| *n == name) {
+            Some(parameter) => parameter.1 = values,
+            None => parameters.push((name, values)),
+        }
+    }
+    if parameters.is_empty() {
+        return Err(invalid("no parameter, give them with --param or in the sweep file"));
+    }
+    let valid_name = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    if let Some((name, _)) = parameters.iter().find(|(n, _)| !valid_name.is_match(n)) {
+        return Err(invalid(format!(
+            "invalid parameter name '{}', use letters, digits and '_'",
+            name
+        )));
+    }
+    if let Some((name, _)) = parameters.iter().find(|(_, v)| v.is_empty()) {
+        return Err(invalid(format!("parameter '{}' has no value", name)));
+    }
+
+    let export_file = export
+        .or(sweep.export)
+        .ok_or_else(|| invalid("no export file, give it on the command line or in the sweep file"))?;
+    if Path::new(&export_file).components().count() != 1 || !export_file.ends_with(".export") {
+        return Err(invalid(format!(
+            "the export file must be a `.export` file of the current directory, got '{}'",
+            export_file
+        )));
+    }
+    let template = fs::read_to_string(&export_file).map_err(|_| {
+        CaveError::FileNotFound(format!("Export file '{}' not found or invalid.", export_file))
+    })?;
+    let jobs = jobs.or(sweep.jobs).unwrap_or(1).max(1);
+    let retries = retries.or(sweep.retries).unwrap_or(0);
+    let output_dir = std::path::absolute(output.or(sweep.output).unwrap_or_else(|| DEFAULT_OUTPUT.to_string()))?;
+
+    let combinations = combinations(&parameters);
+    let width = combinations.len().to_string().len().max(3);
+    let cases: Vec<Case> = combinations
+        .into_iter()
+        .enumerate()
+        .map(|(i, values)| {
+            let name = format!("case-{:0width$}", i + 1, width = width);
+            Case {
+                dir: output_dir.join(&name),
+                name,
+                values,
+            }
+        })
+        .collect();
+    let version = ensure_installed(read_cave_version()?, yes)?;
+
+    // Every case is prepared before the first run, so that a template error stops the sweep early
+    for case in &cases {
+        prepare(case, &export_file, &template, force)?;
+    }
+
+    let current_dir = env::current_dir()?;
+    let project_dir = find_project_dir(&current_dir).unwrap_or(current_dir);
+    let options = RunOptions {
+        extractors: read_project_config(&project_dir)?.extractors,
+        yes,
+        // The outputs of concurrent cases would be interleaved: they only go to the run logs
+        filter: (jobs > 1).then(output::silent_filter),
+        ..Default::default()
+    };
+    println!(
+        "{} {} case(s) of {} with code_aster {}, {} at a time, in {}",
+        "Sweeping".bold(),
+        cases.len(),
+        export_file,
+        version,
+        jobs.min(cases.len()),
+        output_dir.display()
+    );
+
+    let last_runs: Mutex<BTreeMap<String, CaseReport>> = Mutex::new(BTreeMap::new());
+    let outcomes = Orchestrator::new(jobs).retries(retries).fail_fast(fail_fast).run(
+        &cases,
+        |case| {
+            let values: Vec<String> = case.values.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+            format!("{} ({})", case.name, values.join(", "))
+        },
+        |case, attempt| {
+            // A retry starts from the inputs, not from the outputs of the failed attempt
+            if attempt > 1 {
+                prepare(case, &export_file, &template, true)?;
+            }
+            let (report, result) = run_case(case, &version, &export_file, &options);
+            last_runs.lock().unwrap_or_else(|e| e.into_inner()).insert(case.name.clone(), report);
+            result
+        },
+    );
+    let last_runs = last_runs.into_inner().unwrap_or_else(|e| e.into_inner());
+    let rows: Vec<(&str, u32, Option<&CaseReport>)> = cases
+        .iter()
+        .zip(&outcomes)
+        .map(|(case, outcome)| match outcome.outcome {
+            Outcome::Done(()) => ("ok", outcome.attempts, last_runs.get(&case.name)),
+            Outcome::Failed(_) => ("failed", outcome.attempts, last_runs.get(&case.name)),
+            Outcome::Skipped => ("not run", 0, None),
+        })
+        .collect();
+
+    let names: Vec<String> = parameters.iter().map(|(name, _)| name.clone()).collect();
+    let mut headers = vec!["Case"];
+    headers.extend(names.iter().map(String::as_str));
+    headers.extend(["Status", "Duration", "Errors", "Run"]);
+    let mut table = Table::new(&headers);
+    for (case, (status, _, report)) in cases.iter().zip(&rows) {
+        let mut row: Vec<Cell> = vec![case.name.as_str().into()];
+        row.extend(case.values.iter().map(|(_, value)| Cell::from(value.as_str())));
+        row.push(Cell::from(*status).color(match *status {
+            "ok" => Color::Green,
+            "failed" => Color::Red,
+            _ => Color::Yellow,
+        }));
+        if let Some(report) = report {
+            row.push(format::duration_ms(report.duration_ms).into());
+            row.push(report.errors.map(|e| format::count(e as u64)).unwrap_or_default().into());
+            row.push(report.run_id.as_str().into());
+        }
+        table.push(row);
+    }
+    println!();
+    table.print();
+
+    for (case, outcome) in cases.iter().zip(&outcomes) {
+        if let Outcome::Failed(e) = &outcome.outcome {
+            let run = last_runs.get(&case.name).map(|r| r.run_id.as_str()).unwrap_or_default();
+            println!();
+            println!("{} {} (run {}): {}", "Failed".red().bold(), case.name, run, e);
+        }
+    }
+
+    let summary = output_dir.join(SUMMARY_FILE);
+    write_summary(&summary, &names, &cases, &rows)?;
+    println!();
+    println!("Summary written to {}", summary.display());
+
+    if signals::cancelled() {
+        return Err(CaveError::Cancelled);
+    }
+    let failed = outcomes.iter().filter(|o| !o.succeeded()).count();
+    if failed > 0 {
+        return Err(CaveError::SweepFailed(failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_parameters() {
+        let (name, values) = parse_param(" E = 210e9, 70e9 ,1e9").unwrap();
+        assert_eq!(name, "E");
+        assert_eq!(values, ["210e9", "70e9", "1e9"]);
+        assert!(matches!(parse_param("E"), Err(CaveError::InvalidSweep(_))));
+    }
+
+    #[test]
+    fn lists_every_combination() {
+        let parameters = vec![
+            ("E".to_string(), vec!["1".to_string(), "2".to_string()]),
+            ("nu".to_string(), vec!["0.2".to_string(), "0.3".to_string(), "0.4".to_string()]),
+        ];
+        let cases = combinations(&parameters);
+        assert_eq!(cases.len(), 6);
+        assert_eq!(cases[0], values(&[("E", "1"), ("nu", "0.2")]));
+        assert_eq!(cases[1], values(&[("E", "1"), ("nu", "0.3")]));
+        assert_eq!(cases[5], values(&[("E", "2"), ("nu", "0.4")]));
+        assert_eq!(combinations(&[]), vec![Vec::new()]);
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let case = values(&[("E", "210e9"), ("nu", "0.3")]);
+        let rendered = render("E={{E}}, NU={{ nu }}, E again {{  E }}", &case, "study.comm").unwrap();
+        assert_eq!(rendered, "E=210e9, NU=0.3, E again 210e9");
+        assert_eq!(render("no placeholder {E}", &case, "study.comm").unwrap(), "no placeholder {E}");
+        match render("{{ rho }}", &case, "study.comm") {
+            Err(CaveError::InvalidSweep(message)) => {
+                assert!(message.contains("study.comm"));
+                assert!(message.contains("{{ rho }}"));
+            }
+            other => panic!("expected an invalid sweep, got {:?}", other),
+        }
+    }
+}
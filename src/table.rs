@@ -0,0 +1,155 @@
+//! Terminal table rendering shared by the list-style commands.
+//!
+//! Columns are aligned on the widest cell, the table shrinks its widest
+//! columns to fit the terminal width (truncating cells with `…`), and cells
+//! can be colored without breaking the alignment.
+
+use colored::*;
+
+const COLUMN_GAP: usize = 2;
+const MIN_COLUMN_WIDTH: usize = 4;
+
+/// A table cell: its text and optional style.
+#[derive(Debug, Clone, Default)]
+pub struct Cell {
+    text: String,
+    color: Option<Color>,
+    bold: bool,
+}
+
+impl Cell {
+    /// Sets the color of the cell.
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Makes the cell bold.
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    fn render(&self, width: usize) -> String {
+        let text = truncate(&self.text, width);
+        let padding = " ".repeat(width - text.chars().count());
+        let mut styled = text.normal();
+        if let Some(color) = self.color {
+            styled = styled.color(color);
+        }
+        if self.bold {
+            styled = styled.bold();
+        }
+        format!("{}{}", styled, padding)
+    }
+}
+
+impl<T: Into<String>> From<T> for Cell {
+    fn from(text: T) -> Self {
+        Cell {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// A table of rows under a header.
+#[derive(Debug, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    show_header: bool,
+}
+
+impl Table {
+    /// Creates an empty table with the given column headers.
+    pub fn new(headers: &[&str]) -> Self {
+        Table {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            show_header: true,
+        }
+    }
+
+    /// Shows or hides the header line (`--no-header`).
+    pub fn header(mut self, show: bool) -> Self {
+        self.show_header = show;
+        self
+    }
+
+    /// Appends a row. Missing cells are left empty.
+    pub fn push(&mut self, row: Vec<Cell>) {
+        self.rows.push(row);
+    }
+
+    /// Computes the column widths, shrunk to fit `max_width` when given.
+    fn widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .headers
+            .iter()
+            .map(|h| if self.show_header { h.chars().count() } else { 0 })
+            .collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(widths.len()) {
+                widths[i] = widths[i].max(cell.text.chars().count());
+            }
+        }
+
+        if let Some(max_width) = max_width {
+            let gaps = COLUMN_GAP * widths.len().saturating_sub(1);
+            while widths.iter().sum::<usize>() + gaps > max_width {
+                let Some((i, &w)) = widths.iter().enumerate().max_by_key(|(_, w)| **w) else {
+                    break;
+                };
+                if w <= MIN_COLUMN_WIDTH {
+                    break;
+                }
+                widths[i] = w - 1;
+            }
+        }
+        widths
+    }
+
+    /// Renders the table as lines, fitted to `max_width` columns when given.
+    pub fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let widths = self.widths(max_width);
+        let gap = " ".repeat(COLUMN_GAP);
+        let mut lines = Vec::new();
+
+        if self.show_header {
+            let header: Vec<String> = self
+                .headers
+                .iter()
+                .zip(&widths)
+                .map(|(h, w)| Cell::from(h.as_str()).bold().render(*w))
+                .collect();
+            lines.push(header.join(&gap).trim_end().to_string());
+        }
+        for row in &self.rows {
+            let line: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(i, w)| row.get(i).cloned().unwrap_or_default().render(*w))
+                .collect();
+            lines.push(line.join(&gap).trim_end().to_string());
+        }
+        lines
+    }
+
+    /// Prints the table, fitted to the terminal width when stdout is a terminal.
+    pub fn print(&self) {
+        let width = term_size::dimensions_stdout().map(|(w, _)| w);
+        for line in self.render(width) {
+            println!("{}", line);
+        }
+    }
+}
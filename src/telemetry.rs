@@ -1,22 +1,114 @@
+//! Telemetry of runs.
+//!
+//! The payload of a run goes to each sink of the `telemetry_sinks` setting
+//! (see [`TelemetrySink`]): the HTTP endpoint of Simvia, a JSON Lines file,
+//! or a command, so that sites disabling the remote telemetry can still keep
+//! their own metrics.
+
+use crate::answers;
+use crate::cgroup::ResourceUsage;
+use crate::config::{offline, Config, TelemetrySink};
+use crate::telemetry_schema::{Telemetry, SCHEMA_VERSION};
+use chrono::{DateTime, SecondsFormat, Utc};
+use colored::*;
 use log::debug;
-use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::time::Duration;
 
-#[derive(Serialize)]
-struct TelemetryPayload {
-    user_id: String,
-    time_execution: i64,
-    valid_result: bool,
-    timezone: String,
-    version: String,
-    id_docker: String,
-    r#type: i32,
+type SinkResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Sends the telemetry of a run to each sink of `config`, the HTTP endpoint
+/// only when usage tracking is enabled. A failing sink does not keep the
+/// others from receiving the payload.
+pub async fn dispatch(e: ExecutionData, config: &Config, local: bool) {
+    debug!("Données à envoyer: {:?}", e);
+    let payload = match payload(&e) {
+        Ok(payload) => payload,
+        Err(reason) => {
+            debug!("❌ Payload telemetry invalide, non envoyé: {}", reason);
+            return;
+        }
+    };
+    for sink in &config.telemetry_sinks {
+        let result = match TelemetrySink::parse(sink) {
+            Ok(TelemetrySink::Http) if offline() => {
+                debug!("Mode hors ligne, télémétrie HTTP non envoyée");
+                continue;
+            }
+            Ok(TelemetrySink::Http) if answers::usage_tracking().unwrap_or(config.version_tracking) => send_execution_data(&payload, local).await,
+            Ok(TelemetrySink::Http) => {
+                debug!("Suivi d'usage désactivé, télémétrie HTTP non envoyée");
+                continue;
+            }
+            Ok(TelemetrySink::File(path)) => append_line(&path, &payload),
+            Ok(TelemetrySink::Command(command)) => pipe_to_command(&command, &payload),
+            Err(e) => Err(e.to_string().into()),
+        };
+        match result {
+            Ok(()) => debug!("Télémétrie transmise à {}", sink),
+            // The endpoint is unreachable offline, which is not worth a warning
+            Err(e) if sink == "http" => debug!("Échec de la télémétrie HTTP: {}", e),
+            Err(e) => eprintln!("{} telemetry sink '{}' failed: {}", "Warning:".yellow().bold(), sink, e),
+        }
+    }
+}
+
+/// Builds and checks the payload of a run.
+fn payload(e: &ExecutionData) -> Result<Telemetry, String> {
+    let payload = Telemetry {
+        user_id: e.user_id.clone(),
+        time_execution: e.time_execution as i64,
+        valid_result: e.valid_result,
+        timezone: e.timezone.clone(),
+        version: e.version.clone(),
+        id_docker: e.id_docker.clone(),
+        r#type: 0, // 0 for cave, 1 for vs-code-aster
+        peak_memory: e.resource_usage.and_then(|u| u.peak_memory_bytes),
+        cpu_time: e.resource_usage.and_then(|u| u.cpu_usage_usec),
+        schema_version: SCHEMA_VERSION,
+        started_at: e.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        cave_version: e.cave_version.clone(),
+        mode: e.mode.to_string(),
+    };
+    payload.validate()?;
+    Ok(payload)
 }
 
-pub async fn send_execution_data(e: ExecutionData, local: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Appends the payload as a line of the JSON Lines file `path`.
+fn append_line(path: &Path, payload: &Telemetry) -> SinkResult {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(payload)?)?;
+    Ok(())
+}
+
+/// Runs `command` with `sh -c`, the payload as JSON on its standard input.
+fn pipe_to_command(command: &str, payload: &Telemetry) -> SinkResult {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", serde_json::to_string(payload)?)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("the command exited with {}", status).into());
+    }
+    Ok(())
+}
+
+/// Sends the payload to the telemetry endpoint of Simvia.
+pub async fn send_execution_data(payload: &Telemetry, local: bool) -> SinkResult {
     debug!("=== DÉBUT DE LA TÉLÉMÉTRIE ===");
     debug!("Initialisation du client HTTP pour la télémétrie");
-    debug!("Données à envoyer: {:?}", e);
 
     let endpoint = if local {
         debug!("=== CONNEXION EN LOCAL ===");
@@ -28,16 +120,6 @@ pub async fn send_execution_data(e: ExecutionData, local: bool) -> Result<(), Bo
 
     debug!("Endpoint: {}", endpoint);
 
-    let payload = TelemetryPayload {
-        user_id: e.user_id.clone(),
-        time_execution: e.time_execution as i64,
-        valid_result: e.valid_result,
-        timezone: e.timezone.clone(),
-        version: e.version.clone(),
-        id_docker: e.id_docker.clone(),
-        r#type: 0, // 0 for cave, 1 for vs-code-aster
-    };
-
     debug!("Construction de la requête Telemetry:");
     debug!("  - user_id: {}", payload.user_id);
     debug!("  - time_execution: {} ms", payload.time_execution);
@@ -46,13 +128,19 @@ pub async fn send_execution_data(e: ExecutionData, local: bool) -> Result<(), Bo
     debug!("  - version: {}", payload.version);
     debug!("  - id_docker: {}", payload.id_docker);
     debug!("  - type: {}", payload.r#type);
+    debug!("  - schema_version: {}", payload.schema_version);
+    debug!("  - started_at: {}", payload.started_at);
+    debug!("  - cave_version: {}", payload.cave_version);
+    debug!("  - mode: {}", payload.mode);
+    debug!("  - peak_memory: {:?} bytes", payload.peak_memory);
+    debug!("  - cpu_time: {:?} µs", payload.cpu_time);
 
     let client = reqwest::Client::builder()
         .timeout(Duration::from_millis(1000))
         .build()?;
 
     debug!("Envoi de la requête telemetry via HTTP POST...");
-    match client.post(endpoint).json(&payload).send().await {
+    match client.post(endpoint).json(payload).send().await {
         Ok(response) => {
             let status = response.status();
             if status.is_success() {
@@ -83,27 +171,23 @@ pub async fn send_execution_data(e: ExecutionData, local: bool) -> Result<(), Bo
     Ok(())
 }
 
-
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ExecutionData {
     pub user_id: String,
+    /// Duration of the run in milliseconds, measured with a monotonic clock.
     pub time_execution: u128,
     pub valid_result: bool,
+    /// UTC offset of the user, kept for schema 1 consumers.
     pub timezone: String,
+    /// Start of the run, in UTC.
+    pub started_at: DateTime<Utc>,
+    /// Version of cave.
+    pub cave_version: String,
+    /// Kind of run: `sequential`, `mpi` or `cluster`.
+    pub mode: &'static str,
     pub version: String,
     pub id_docker: String,
-}
-
-impl Default for ExecutionData {
-    fn default() -> Self {
-        Self { 
-            user_id: String::new(), 
-            time_execution: 0,
-            valid_result: false,
-            timezone: String::new(),
-            version: String::new(),
-            id_docker: String::new(),
-        }
-    }
+    /// cgroup resource usage of the run, only set when the user agreed to share it.
+    pub resource_usage: Option<ResourceUsage>,
 }
 
@@ -0,0 +1,63 @@
+//! Telemetry payload schema shared by every telemetry transport.
+//!
+//! The [`Telemetry`] struct is generated at build time from the `Telemetry`
+//! message of `proto/cave_telem.proto` (see `build.rs`), so the HTTP JSON
+//! payload always matches the proto definition. Any change to the schema
+//! must bump [`SCHEMA_VERSION`] so the backend can tell payloads apart.
+
+use regex::Regex;
+
+include!(concat!(env!("OUT_DIR"), "/cave_telem.rs"));
+
+/// Version of the telemetry payload schema.
+///
+/// - 1: initial schema.
+/// - 2: `started_at` (UTC start of the run), `cave_version` and `mode`.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Accepted values of the `mode` field.
+pub const MODES: &[&str] = &["sequential", "mpi", "cluster"];
+
+impl Telemetry {
+    /// Checks the payload before it is sent.
+    ///
+    /// # Errors
+    /// Returns a description of the first invalid field.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.schema_version != SCHEMA_VERSION {
+            return Err(format!("unexpected schema_version {}", self.schema_version));
+        }
+        if uuid::Uuid::parse_str(&self.user_id).is_err() {
+            return Err(format!("user_id '{}' is not a UUID", self.user_id));
+        }
+        if self.time_execution < 0 {
+            return Err(format!("negative time_execution {}", self.time_execution));
+        }
+        let timezone = Regex::new(r"^[+-]\d{2}:\d{2}$").unwrap();
+        if !timezone.is_match(&self.timezone) {
+            return Err(format!("timezone '{}' is not a UTC offset", self.timezone));
+        }
+        let utc = chrono::DateTime::parse_from_rfc3339(&self.started_at)
+            .map(|date| date.offset().local_minus_utc() == 0)
+            .unwrap_or(false);
+        if !utc {
+            return Err(format!("started_at '{}' is not an RFC 3339 UTC date", self.started_at));
+        }
+        if self.cave_version.is_empty() {
+            return Err("empty cave_version".to_string());
+        }
+        if !MODES.contains(&self.mode.as_str()) {
+            return Err(format!("unknown mode '{}'", self.mode));
+        }
+        if self.version.is_empty() {
+            return Err("empty version".to_string());
+        }
+        if self.id_docker.is_empty() || !self.id_docker.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("id_docker '{}' is not an image ID", self.id_docker));
+        }
+        if !(0..=1).contains(&self.r#type) {
+            return Err(format!("unknown type {}", self.r#type));
+        }
+        Ok(())
+    }
+}
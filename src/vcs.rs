@@ -0,0 +1,130 @@
+//! Version control hygiene for study directories.
+//!
+//! code_aster produces large binary artifacts (result databases, MED results,
+//! message files) that should never end up in a git repository. This module
+//! writes a `.gitignore` covering them and inspects the git index to warn
+//! about large or generated files staged for commit.
+
+use crate::format;
+use crate::manage::CaveError;
+use colored::*;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BLOCK_START: &str = "# >>> cave: code_aster artifacts";
+const BLOCK_END: &str = "# <<< cave";
+
+/// Patterns of files produced by code_aster runs.
+pub const ARTIFACT_PATTERNS: &[&str] = &[
+    "*.rmed", "*.resu", "*.mess", "*.erre", "*.base", "glob.*", "pick.*", "vola.*", "fort.*",
+    "REPE_OUT/", "base/", ".cave-run.lock", ".cave-logs/", ".cave-tmp/",
+];
+
+/// Adds the cave block of code_aster artifact patterns to `dir/.gitignore`.
+///
+/// Existing content is preserved, the block is only written when missing.
+/// Returns `true` if the file was modified.
+pub fn write_gitignore(dir: &Path) -> Result<bool, CaveError> {
+    let path = dir.join(".gitignore");
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(CaveError::IoError(e)),
+    };
+    if content.contains(BLOCK_START) {
+        return Ok(false);
+    }
+
+    let mut new_content = content;
+    if !new_content.is_empty() && !new_content.ends_with('\n') {
+        new_content.push('\n');
+    }
+    new_content.push_str(BLOCK_START);
+    new_content.push('\n');
+    for pattern in ARTIFACT_PATTERNS {
+        new_content.push_str(pattern);
+        new_content.push('\n');
+    }
+    new_content.push_str(BLOCK_END);
+    new_content.push('\n');
+    fs::write(&path, new_content)?;
+    Ok(true)
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the files staged in the git index that are larger than `max_bytes`
+/// or match a code_aster artifact pattern, with their size.
+///
+/// Returns an empty list when `dir` is not inside a git work tree.
+pub fn suspicious_staged_files(dir: &Path, max_bytes: u64) -> Vec<(PathBuf, u64)> {
+    let Some(root) = git(dir, &["rev-parse", "--show-toplevel"]) else {
+        return Vec::new();
+    };
+    let root = PathBuf::from(root.trim());
+    let Some(staged) = git(
+        dir,
+        &["diff", "--cached", "--name-only", "--diff-filter=AM", "-z"],
+    ) else {
+        return Vec::new();
+    };
+
+    staged
+        .split('\0')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| {
+            let path = PathBuf::from(p);
+            let size = fs::metadata(root.join(&path)).map(|m| m.len()).ok()?;
+            (size > max_bytes || is_artifact(&path)).then_some((path, size))
+        })
+        .collect()
+}
+
+fn is_artifact(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let in_dir = |d: &str| path.components().any(|c| c.as_os_str() == d);
+    ARTIFACT_PATTERNS.iter().any(|pattern| {
+        if let Some(d) = pattern.strip_suffix('/') {
+            in_dir(d)
+        } else if let Some(ext) = pattern.strip_prefix("*.") {
+            name.ends_with(&format!(".{}", ext))
+        } else if let Some(prefix) = pattern.strip_suffix('*') {
+            name.starts_with(prefix)
+        } else {
+            name == *pattern
+        }
+    })
+}
+
+/// Handler of `cave ignore`: writes the `.gitignore` (unless `check_only`)
+/// and warns about suspicious files staged in the git index.
+pub fn ignore(check_only: bool, max_size_mb: u64) -> Result<(), CaveError> {
+    let dir = std::env::current_dir()?;
+    if !check_only {
+        if write_gitignore(&dir)? {
+            println!("Added code_aster artifact patterns to .gitignore");
+        } else {
+            println!(".gitignore already contains the code_aster artifact patterns");
+        }
+    }
+
+    let files = suspicious_staged_files(&dir, max_size_mb * 1024 * 1024);
+    for (path, size) in &files {
+        eprintln!(
+            "{} '{}' ({}) is staged for commit and looks like a result file. Unstage it with `git restore --staged {}`.",
+            "Warning:".yellow().bold(),
+            path.display(),
+            format::size(*size),
+            path.display()
+        );
+    }
+    Ok(())
+}
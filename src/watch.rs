@@ -0,0 +1,260 @@
+//! Watch mode of `cave run` (`cave run --watch study.export`).
+//!
+//! The export file and the input files it references are watched with a
+//! filesystem watcher, and the study runs again when one of them changes.
+//! Changes are debounced, so that saving several files at once, or an editor
+//! writing a file in several steps, starts a single run. A change during a
+//! run stops its container, as `cave jobs kill` does: the run is recorded as
+//! cancelled and the next one starts once it has ended.
+//!
+//! The watch holds the study lock until it is interrupted with Ctrl-C.
+
+use crate::docker::RunOptions;
+use crate::export::Export;
+use crate::history;
+use crate::jobs;
+use crate::lock;
+use crate::manage::{find_export_file, run_aster, CaveError};
+use crate::signals;
+use colored::*;
+use log::debug;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Time without changes before the study runs again.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Interval at which a run is checked for its end.
+const POLL: Duration = Duration::from_millis(200);
+
+type Events = Receiver<notify::Result<notify::Event>>;
+
+/// Files of a study watched for changes.
+#[derive(Debug, Default)]
+struct Watched {
+    /// The export file and its input files, by absolute path.
+    files: HashSet<PathBuf>,
+    /// Input directories, all of whose files are watched.
+    dirs: Vec<PathBuf>,
+}
+
+impl Watched {
+    /// Returns the files to watch for `export`: itself and the inputs it
+    /// references, without the ones also written by the run.
+    fn of(export: &str) -> Result<Self, CaveError> {
+        let (parsed, _) = Export::parse(&fs::read_to_string(export)?);
+        let dir = Path::new(export).parent().unwrap_or(Path::new(""));
+        let mut watched = Watched::default();
+        watched.files.insert(absolute(Path::new(export)));
+        for entry in parsed.entries.iter().filter(|e| e.input && !e.output) {
+            let path = dir.join(&entry.path);
+            if entry.directory {
+                watched.dirs.push(fs::canonicalize(&path).unwrap_or(path));
+            } else {
+                watched.files.insert(absolute(&path));
+            }
+        }
+        Ok(watched)
+    }
+
+    /// Returns `true` if `path` is a watched file.
+    fn contains(&self, path: &Path) -> bool {
+        self.files.contains(path) || self.dirs.iter().any(|dir| path.starts_with(dir))
+    }
+
+    /// Returns the directories to give to the watcher, and whether to watch them recursively.
+    fn roots(&self) -> BTreeSet<(PathBuf, bool)> {
+        let parents = self.files.iter().filter_map(|f| f.parent()).map(|p| (p.to_path_buf(), false));
+        let dirs = self.dirs.iter().map(|d| (d.clone(), true));
+        parents.chain(dirs).filter(|(root, _)| root.is_dir()).collect()
+    }
+}
+
+/// Returns the absolute path of a file through its directory, which exists
+/// even while an editor replaces the file.
+fn absolute(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+    match path.file_name() {
+        Some(name) => parent.join(name),
+        None => parent,
+    }
+}
+
+/// Returns the watched files changed by an event.
+fn changed_files(event: notify::Result<notify::Event>, watched: &Watched) -> Vec<PathBuf> {
+    match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) => {
+            event.paths.into_iter().filter(|p| watched.contains(p)).collect()
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            debug!("Erreur de surveillance des fichiers: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Waits until no watched file changed for [`DEBOUNCE`], and returns the
+/// changed files, starting with `changed`.
+fn debounce(events: &Events, watched: &Watched, mut changed: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut deadline = Instant::now() + DEBOUNCE;
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(timeout) {
+            Ok(event) => {
+                let files = changed_files(event, watched);
+                if !files.is_empty() {
+                    changed.extend(files);
+                    deadline = Instant::now() + DEBOUNCE;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/// Stops the run `run_id` and waits for its end. The container is killed
+/// once it exists, a run can still be pulling its image.
+fn stop(run_id: &str, run: &JoinHandle<Result<(), CaveError>>) -> Result<(), CaveError> {
+    jobs::mark_aborted(run_id)?;
+    while !run.is_finished() {
+        if let Ok(job) = jobs::find(run_id) {
+            jobs::kill(&job)?;
+        }
+        thread::sleep(POLL);
+    }
+    Ok(())
+}
+
+/// Prints the end of a run, and returns `true` if the watch is interrupted.
+fn report(run_id: &str, result: thread::Result<Result<(), CaveError>>) -> bool {
+    match result {
+        Ok(Ok(())) => println!("{} run {}", "Finished".green().bold(), run_id),
+        Ok(Err(CaveError::Cancelled)) if !signals::cancelled() => {
+            println!("{} run {}", "Stopped".yellow().bold(), run_id)
+        }
+        Ok(Err(e)) => println!("{} run {}: {}", "Failed".red().bold(), run_id, e),
+        Err(_) => println!("{} run {}", "Failed".red().bold(), run_id),
+    }
+    signals::cancelled()
+}
+
+fn watch_roots(
+    watcher: &mut impl Watcher,
+    watched: &Watched,
+    roots: &mut BTreeSet<(PathBuf, bool)>,
+) -> Result<(), CaveError> {
+    for (root, recursive) in watched.roots() {
+        if roots.contains(&(root.clone(), recursive)) {
+            continue;
+        }
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(&root, mode)
+            .map_err(|e| CaveError::WatchFailed(format!("{}: {}", root.display(), e)))?;
+        roots.insert((root, recursive));
+    }
+    Ok(())
+}
+
+/// Handler of `cave run --watch`: runs the study with the run_aster
+/// arguments `args`, the export file last, and runs it again whenever the
+/// export file or one of its input files changes, until interrupted.
+///
+/// # Errors
+/// - [`CaveError::CodeAsterError`] if the arguments do not end with an export file.
+/// - [`CaveError::FileNotFound`] if the export file does not exist.
+/// - [`CaveError::RunInProgress`] if another run holds the study lock.
+/// - [`CaveError::WatchFailed`] if the files cannot be watched.
+/// - [`CaveError::Cancelled`] once interrupted.
+pub fn run(args: &[String], options: RunOptions) -> Result<(), CaveError> {
+    let Some(export) = args.last().filter(|a| a.ends_with(".export")) else {
+        return Err(CaveError::CodeAsterError(
+            "`--watch` needs the export file of the study as last argument".to_string(),
+        ));
+    };
+    find_export_file(export)?;
+    let current_dir = env::current_dir()?;
+    let _lock = lock::acquire(&current_dir, "cave-watch", options.force)?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| CaveError::WatchFailed(e.to_string()))?;
+    let mut roots = BTreeSet::new();
+    let mut watched = Watched::of(export)?;
+    watch_roots(&mut watcher, &watched, &mut roots)?;
+
+    loop {
+        let run_id = history::new_run_id();
+        let mut run = {
+            let args = args.to_vec();
+            let options = RunOptions {
+                run_id: Some(run_id.clone()),
+                ..options.clone()
+            };
+            Some(thread::spawn(move || run_aster(&args, options)))
+        };
+
+        let changed = loop {
+            match events.recv_timeout(POLL) {
+                Ok(event) => {
+                    let files = changed_files(event, &watched);
+                    if !files.is_empty() {
+                        break files;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(CaveError::WatchFailed("the watcher stopped".to_string()))
+                }
+            }
+            if let Some(handle) = run.take_if(|handle| handle.is_finished()) {
+                if report(&run_id, handle.join()) {
+                    return Err(CaveError::Cancelled);
+                }
+                println!(
+                    "{} {} files for changes, Ctrl-C to stop",
+                    "Watching".bold(),
+                    watched.files.len() + watched.dirs.len()
+                );
+            }
+        };
+        let changed = debounce(&events, &watched, changed);
+
+        if let Some(handle) = run {
+            println!("{} run {} after a change", "Stopping".yellow().bold(), run_id);
+            stop(&run_id, &handle)?;
+            let cancelled = report(&run_id, handle.join());
+            jobs::take_aborted(&run_id);
+            if cancelled {
+                return Err(CaveError::Cancelled);
+            }
+        }
+        let names: Vec<String> = changed
+            .iter()
+            .map(|p| p.strip_prefix(&current_dir).unwrap_or(p).display().to_string())
+            .collect();
+        println!("\n{} {}", "Changed".bold(), names.join(", "));
+
+        // The export file can reference other inputs now, and keeps its
+        // previous inputs while an editor replaces it
+        if let Ok(now) = Watched::of(export) {
+            watched = now;
+        }
+        watch_roots(&mut watcher, &watched, &mut roots)?;
+    }
+}
@@ -0,0 +1,199 @@
+//! Per-run temporary workspaces, for the modified copies of the study files.
+//!
+//! Features such as MPI runs give code_aster a rewritten copy of the export
+//! file rather than changing the user's one. The copies are written by a
+//! [`Workspace`] to `.cave-tmp/<run id>/` in the study directory, which is
+//! mounted in the container, so that parallel runs of a study never share a
+//! file. The directory is only created when a file is written to it, and is
+//! removed when the run ends, unless it is kept with `cave run --keep-temp`
+//! to inspect what code_aster was given.
+//!
+//! [`ExportEdit`] rewrites an export file line by line, keeping its comments
+//! and the order of its entries. Since relative paths are taken from the
+//! directory of the export file, the copies written by
+//! [`Workspace::write_export`] have them rebased onto the original files.
+
+use crate::manage::CaveError;
+use log::debug;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory of the workspaces, in the study directory.
+pub const WORKSPACE_DIR: &str = ".cave-tmp";
+
+/// Temporary directory of a run, removed when dropped unless kept.
+pub struct Workspace {
+    study_dir: PathBuf,
+    /// Path relative to the study directory.
+    relative: PathBuf,
+    keep: bool,
+    created: bool,
+}
+
+impl Workspace {
+    /// Returns the workspace of run `run_id` in `study_dir`, without creating it.
+    pub fn new(study_dir: &Path, run_id: &str, keep: bool) -> Self {
+        Workspace {
+            study_dir: study_dir.to_path_buf(),
+            relative: Path::new(WORKSPACE_DIR).join(run_id),
+            keep,
+            created: false,
+        }
+    }
+
+    /// Path of the workspace on the host.
+    pub fn path(&self) -> PathBuf {
+        self.study_dir.join(&self.relative)
+    }
+
+    fn create(&mut self) -> Result<(), CaveError> {
+        if !self.created {
+            fs::create_dir_all(self.path())?;
+            self.created = true;
+        }
+        Ok(())
+    }
+
+    /// Writes `name` in the workspace, and returns its path relative to the
+    /// study directory.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::IoError`] if the file cannot be written.
+    pub fn write(&mut self, name: &str, content: &str) -> Result<String, CaveError> {
+        self.create()?;
+        let relative = self.relative.join(name);
+        fs::write(self.study_dir.join(&relative), content)?;
+        debug!("Fichier temporaire {} écrit", relative.display());
+        Ok(relative.display().to_string())
+    }
+
+    /// Writes a copy of `export` (relative to the study directory) changed by
+    /// `edit`, with its relative paths rebased onto the original files, and
+    /// returns the path of the copy relative to the study directory.
+    ///
+    /// # Errors
+    /// Returns [`CaveError::IoError`] if the export file cannot be read or
+    /// its copy written.
+    pub fn write_export<F>(&mut self, export: &str, edit: F) -> Result<String, CaveError>
+    where
+        F: FnOnce(&mut ExportEdit),
+    {
+        let mut copy = ExportEdit::new(&fs::read_to_string(self.study_dir.join(export))?);
+        edit(&mut copy);
+        // From the copy back to the study directory, then to the directory of the original
+        let original = Path::new(export);
+        let mut base: PathBuf = self.relative.components().map(|_| "..").collect();
+        if let Some(parent) = original.parent() {
+            base.push(parent);
+        }
+        copy.rewrite_paths(|path| {
+            if Path::new(path).is_absolute() {
+                path.to_string()
+            } else {
+                base.join(path).display().to_string()
+            }
+        });
+        let name = original
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "study.export".to_string());
+        self.write(&name, &copy.content())
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
+        if self.keep {
+            eprintln!("Temporary files kept in {}", self.path().display());
+            return;
+        }
+        if let Err(e) = fs::remove_dir_all(self.path()) {
+            debug!("Impossible de supprimer {}: {}", self.path().display(), e);
+        }
+        // Only removed once the other runs of the study are done with it
+        let _ = fs::remove_dir(self.study_dir.join(WORKSPACE_DIR));
+    }
+}
+
+/// Lines of an export file being rewritten.
+#[derive(Debug, Clone)]
+pub struct ExportEdit {
+    lines: Vec<String>,
+}
+
+impl ExportEdit {
+    /// Starts rewriting the content of an export file.
+    pub fn new(content: &str) -> Self {
+        ExportEdit {
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    fn is_parameter(line: &str, name: &str) -> bool {
+        let mut words = line.split_whitespace();
+        matches!(words.next(), Some("P" | "A")) && words.next() == Some(name)
+    }
+
+    /// Sets the `P` parameter `name`, replacing its previous values.
+    pub fn set_parameter(&mut self, name: &str, value: impl Display) -> &mut Self {
+        self.remove_parameter(name);
+        self.lines.push(format!("P {} {}", name, value));
+        self
+    }
+
+    /// Removes the `P` parameter or `A` argument `name`.
+    pub fn remove_parameter(&mut self, name: &str) -> &mut Self {
+        self.lines.retain(|line| !Self::is_parameter(line, name));
+        self
+    }
+
+    /// Replaces the path of every `F` and `R` entry by `rewrite(path)`.
+    pub fn rewrite_paths<F>(&mut self, mut rewrite: F) -> &mut Self
+    where
+        F: FnMut(&str) -> String,
+    {
+        for line in &mut self.lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let [kind @ ("F" | "R"), file_type, path, flags, unit] = fields.as_slice() {
+                *line = format!("{} {} {} {} {}", kind, file_type, rewrite(path), flags, unit);
+            }
+        }
+        self
+    }
+
+    /// Returns the rewritten content.
+    pub fn content(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = "P time_limit 60\nP mpi_nbcpu 1\nA memjeveux 512\nF comm study.comm D 1\nR repe out R 0\n";
+
+    #[test]
+    fn replaces_parameters() {
+        let mut edit = ExportEdit::new(EXPORT);
+        edit.set_parameter("mpi_nbcpu", 4).remove_parameter("memjeveux");
+        assert_eq!(
+            edit.content(),
+            "P time_limit 60\nF comm study.comm D 1\nR repe out R 0\nP mpi_nbcpu 4\n"
+        );
+    }
+
+    #[test]
+    fn rewrites_the_paths_of_files_only() {
+        let mut edit = ExportEdit::new(EXPORT);
+        edit.rewrite_paths(|path| format!("../{}", path));
+        let content = edit.content();
+        assert!(content.contains("F comm ../study.comm D 1\n"));
+        assert!(content.contains("R repe ../out R 0\n"));
+        assert!(content.starts_with("P time_limit 60\nP mpi_nbcpu 1\nA memjeveux 512\n"));
+    }
+}
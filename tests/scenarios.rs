@@ -88,3 +88,132 @@ fn test_error_on_unknown_version_use_and_pin() {
 
 
 
+#[test]
+fn test_ignore_writes_gitignore_once() {
+    let temp_home = tempdir().expect("create temp dir");
+    let study = tempdir().expect("create temp dir");
+    fs::write(study.path().join(".gitignore"), "*.log\n").expect("write .gitignore");
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("cave").expect("binary built");
+        cmd.env("HOME", temp_home.path())
+            .current_dir(study.path())
+            .arg("ignore")
+            .assert()
+            .success();
+    }
+
+    let content = fs::read_to_string(study.path().join(".gitignore")).expect("read .gitignore");
+    assert!(content.starts_with("*.log\n"), "Existing patterns should be kept");
+    assert!(content.contains("*.rmed"), ".gitignore should ignore result files");
+    assert_eq!(content.matches("*.rmed").count(), 1, "Patterns should only be added once");
+}
+
+#[test]
+fn test_pin_from_global_and_unpin() {
+    let temp_home = tempdir().expect("create temp dir");
+    let study = tempdir().expect("create temp dir");
+    fs::write(temp_home.path().join(".cave"), "stable:17.2.24\n").expect("write global pin");
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .current_dir(study.path())
+        .arg("pin")
+        .arg("--from-global")
+        .assert()
+        .success();
+
+    let local = study.path().join(".cave");
+    let content = fs::read_to_string(&local).expect("read local .cave");
+    assert_eq!(content.trim(), "stable:17.2.24", "Local pin should copy the global one");
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .current_dir(study.path())
+        .arg("unpin")
+        .arg("--yes")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stable:17.2.24"));
+
+    assert!(!local.exists(), "Local .cave should be removed");
+}
+
+#[test]
+fn test_nested_cave_toml_keeps_parent_pin() {
+    let temp_home = tempdir().expect("create temp dir");
+    let project = tempdir().expect("create temp dir");
+    let study = project.path().join("study");
+    fs::create_dir(&study).expect("create study dir");
+    fs::write(project.path().join(".cave"), "17.2.24\n").expect("write parent pin");
+    fs::write(study.join("cave.toml"), "[run]\nargs = []\n").expect("write cave.toml");
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .current_dir(&study)
+        .arg("unpin")
+        .arg("--yes")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Local pin removed"));
+
+    assert!(!project.path().join(".cave").exists(), "Parent .cave should be the local pin");
+}
+
+#[test]
+fn test_explain_known_and_unknown_errors() {
+    let temp_home = tempdir().expect("create temp dir");
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .arg("explain")
+        .arg("aster-memory")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("memory_limit"));
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .arg("explain")
+        .arg("<F> ARRET PAR MANQUE DE TEMPS CPU")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("aster-cpu-time"));
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .arg("explain")
+        .arg("no-such-error")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_check_export_reports_missing_inputs() {
+    let temp_home = tempdir().expect("create temp dir");
+    let study = tempdir().expect("create study dir");
+    fs::write(study.path().join("study.comm"), "DEBUT()\nFIN()\n").expect("write comm");
+    fs::write(
+        study.path().join("study.export"),
+        "P time_limit 60\nP memory_limit 2048\nF comm study.comm D 1\nF mmed mesh.med D 20\n",
+    )
+    .expect("write export");
+
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .current_dir(study.path())
+        .arg("check")
+        .arg("study.export")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 4: input mmed 'mesh.med' not found"));
+
+    fs::write(study.path().join("mesh.med"), "").expect("write mesh");
+    let mut cmd = Command::cargo_bin("cave").expect("binary built");
+    cmd.env("HOME", temp_home.path())
+        .current_dir(study.path())
+        .arg("check")
+        .arg("study.export")
+        .assert()
+        .success();
+}